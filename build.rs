@@ -4,13 +4,39 @@ fn main() {
     parse_bm_net();
 }
 
+/*
+The network is only embedded with the nnue feature and when the file is a network,
+otherwise the engine is built with the basic evaluation
+*/
 fn parse_bm_net() {
+    if env::var_os("CARGO_FEATURE_NNUE").is_none() {
+        return;
+    }
     let nn_dir = env::var("EVALFILE").unwrap_or_else(|_| "./nn/default.bin".to_string());
     let out_dir = env::var_os("OUT_DIR").unwrap();
 
     let eval_path = Path::new(&out_dir).join("eval.bin");
-    let nn_bytes = std::fs::read(nn_dir).expect("nnue file doesn't exist");
+    let nn_bytes = match std::fs::read(&nn_dir) {
+        Ok(nn_bytes) => nn_bytes,
+        Err(err) => {
+            println!(
+                "cargo:warning=can't read network {} ({}), building with the basic evaluation",
+                nn_dir, err
+            );
+            return;
+        }
+    };
     let layers = parse_arch(&nn_bytes);
+    /*
+    Files that don't match their header, like git-lfs pointers, aren't networks
+    */
+    if network_len(layers) != Some(nn_bytes.len()) {
+        println!(
+            "cargo:warning=network {} doesn't match its header, building with the basic evaluation",
+            nn_dir
+        );
+        return;
+    }
 
     let arch_path = Path::new(&out_dir).join("arch.rs");
     let mut def_nodes = String::new();
@@ -21,6 +47,7 @@ fn parse_bm_net() {
 
     std::fs::write(&eval_path, nn_bytes).unwrap();
     std::fs::write(&arch_path, def_nodes).unwrap();
+    println!("cargo:rustc-cfg=embedded_net");
 }
 
 pub fn parse_arch(bytes: &[u8]) -> [usize; 3] {
@@ -30,3 +57,16 @@ pub fn parse_arch(bytes: &[u8]) -> [usize; 3] {
     }
     layers
 }
+
+/*
+Expected file length of a network with the given layers, see Network::from_bytes
+*/
+fn network_len([input, mid, output]: [usize; 3]) -> Option<usize> {
+    let incremental = input.checked_mul(mid)?.checked_mul(2)?;
+    let out = mid.checked_mul(output)?.checked_mul(2)?;
+    12usize
+        .checked_add(incremental)?
+        .checked_add(mid.checked_mul(2)?)?
+        .checked_add(out)?
+        .checked_add(output.checked_mul(2)?)
+}
@@ -1,6 +1,11 @@
+#[cfg_attr(embedded_net, allow(dead_code))]
+pub mod basic_eval;
 pub mod bm_console;
 pub mod bm_runner;
 pub mod bm_search;
 pub mod bm_util;
+pub mod evaluator;
+#[cfg(embedded_net)]
 pub mod nnue;
 pub mod uci;
+pub mod xboard;
@@ -0,0 +1,143 @@
+use cozy_chess::{Board, Color, Move, Piece, Square};
+
+use super::evaluator::{Evaluator, NnBreakdown, NnOutput, Perspectives};
+
+const PIECE_VALUES: [i32; 6] = [100, 320, 330, 500, 950, 0];
+
+/*
+Bonus per rank a pawn has advanced
+*/
+const PAWN_ADVANCE: i32 = 6;
+/*
+Bonus per step closer to the center for minor pieces
+*/
+const CENTRALIZATION: i32 = 8;
+
+/*
+Distance of the square to the edge of the board, 0 on the edge and 3 in the center
+*/
+fn centrality(sq: Square) -> i32 {
+    let file = sq.file() as i32;
+    let rank = sq.rank() as i32;
+    file.min(7 - file).min(rank).min(7 - rank)
+}
+
+fn piece_value(sq: Square, piece: Piece, color: Color) -> i32 {
+    let bonus = match piece {
+        Piece::Pawn => {
+            let rank = sq.rank().relative_to(color) as i32;
+            (rank - 1) * PAWN_ADVANCE
+        }
+        Piece::Knight | Piece::Bishop => centrality(sq) * CENTRALIZATION,
+        _ => 0,
+    };
+    PIECE_VALUES[piece as usize] + bonus
+}
+
+/*
+Material and piece placement score of each side
+*/
+fn side_scores(board: &Board) -> [i32; 2] {
+    let mut scores = [0; 2];
+    for sq in board.occupied() {
+        let piece = board.piece_on(sq).unwrap();
+        let color = board.color_on(sq).unwrap();
+        scores[color as usize] += piece_value(sq, piece, color);
+    }
+    scores
+}
+
+/*
+Hand written evaluation used when the engine is built without a network
+The boards are kept on a stack since the evaluation is computed from scratch
+*/
+#[derive(Debug, Clone)]
+pub struct BasicEval {
+    boards: Vec<Board>,
+}
+
+impl BasicEval {
+    fn output(&self, stm: Color) -> i32 {
+        let scores = side_scores(self.boards.last().unwrap());
+        scores[stm as usize] - scores[!stm as usize]
+    }
+}
+
+impl Evaluator for BasicEval {
+    fn new() -> Self {
+        Self {
+            boards: vec![Board::default()],
+        }
+    }
+
+    fn full_reset(&mut self, board: &Board) {
+        self.boards.clear();
+        self.boards.push(board.clone());
+    }
+
+    fn null_move(&mut self) {
+        let board = self.boards.last().unwrap().clone();
+        self.boards.push(board);
+    }
+
+    fn make_move(&mut self, board: &Board, make_move: Move) {
+        let mut board = board.clone();
+        board.play_unchecked(make_move);
+        self.boards.push(board);
+    }
+
+    fn unmake_move(&mut self) {
+        self.boards.pop();
+    }
+
+    fn feed_forward(&mut self, stm: Color) -> i16 {
+        Self::to_centipawns(self.output(stm))
+    }
+
+    fn feed_forward_both(&mut self) -> Perspectives {
+        let output = |stm| {
+            let raw = self.output(stm);
+            NnOutput {
+                raw,
+                eval: Self::to_centipawns(raw),
+            }
+        };
+        Perspectives {
+            white: output(Color::White),
+            black: output(Color::Black),
+        }
+    }
+
+    fn breakdown(&mut self, stm: Color) -> NnBreakdown {
+        let scores = side_scores(self.boards.last().unwrap());
+        NnBreakdown {
+            outputs: vec![self.output(stm)],
+            stm: scores[stm as usize],
+            nstm: -scores[!stm as usize],
+            bias: 0,
+        }
+    }
+
+    fn to_centipawns(raw: i32) -> i16 {
+        raw.clamp(i16::MIN as i32 + 1, i16::MAX as i32) as i16
+    }
+}
+
+#[test]
+fn basic_eval() {
+    let mut eval = BasicEval::new();
+    assert_eq!(eval.feed_forward(Color::White), 0);
+
+    let board: Board = "rnb1kbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+        .parse()
+        .unwrap();
+    eval.full_reset(&board);
+    let perspectives = eval.feed_forward_both();
+    assert!(perspectives.white.eval > 900);
+    assert_eq!(perspectives.white.eval, -perspectives.black.eval);
+
+    eval.make_move(&board, "e7e5".parse().unwrap());
+    let after = eval.feed_forward(Color::White);
+    eval.unmake_move();
+    assert_eq!(after, perspectives.white.eval - 2 * PAWN_ADVANCE as i16);
+}
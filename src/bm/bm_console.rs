@@ -1,25 +1,60 @@
+use super::bm_runner::params::SearchParams;
 use super::uci::UciAdapter;
+use super::xboard::XBoardAdapter;
 
+pub mod cli;
+#[cfg(feature = "data")]
+mod elo;
 #[cfg(feature = "data")]
 mod gen_eval;
 #[cfg(feature = "trace")]
 mod gen_fen;
 #[cfg(feature = "trace")]
 mod grad;
+mod input;
+mod interrupt;
+mod perft;
+#[cfg(feature = "data")]
+mod play_match;
 pub struct BmConsole {
     uci: UciAdapter,
+    xboard: Option<XBoardAdapter>,
 }
 
 impl BmConsole {
     pub fn new() -> Self {
         Self {
             uci: UciAdapter::new(),
+            xboard: None,
         }
     }
 
+    pub fn set_params(&mut self, params: SearchParams) {
+        self.uci.set_params(params);
+    }
+
+    pub fn handle_interrupts(&self) {
+        interrupt::install(self.uci.shutdown_handle());
+    }
+
+    pub fn run(&mut self) {
+        let commands = input::spawn(self.uci.shutdown_handle());
+        while self.input(commands.recv().unwrap_or_default()) {}
+    }
+
     pub fn input(&mut self, command: String) -> bool {
+        if let Some(xboard) = &mut self.xboard {
+            if command.is_empty() {
+                return xboard.input("quit".to_string());
+            }
+            return xboard.input(command);
+        }
         if command.is_empty() {
-            return false;
+            return self.uci.input("quit".to_string());
+        }
+        if command.trim() == "xboard" {
+            self.xboard = Some(self.uci.xboard());
+            return true;
         }
         #[cfg(any(feature = "trace", feature = "data"))]
         if command.starts_with("!") {
@@ -30,6 +65,8 @@ impl BmConsole {
                 "tune" => Self::tune(options),
                 #[cfg(feature = "data")]
                 "data" => Self::data(options),
+                #[cfg(feature = "data")]
+                "match" => Self::play_match(options),
                 _ => {}
             }
             return true;
@@ -45,10 +82,41 @@ impl BmConsole {
         gen_eval::gen_eval(
             options.get("depth").unwrap().parse::<u32>().unwrap(),
             options.get("threads").unwrap().parse::<u32>().unwrap(),
+            options
+                .get("noise")
+                .map_or(0, |noise| noise.parse::<i16>().unwrap()),
+            options
+                .get("seed")
+                .map_or(0, |seed| seed.parse::<u64>().unwrap()),
             options.get("path").unwrap(),
         );
     }
 
+    #[cfg(feature = "data")]
+    fn play_match(options: Vec<(String, String)>) {
+        use std::collections::HashMap;
+
+        let options = options.into_iter().collect::<HashMap<String, String>>();
+        let nodes = options
+            .get("nodes")
+            .map_or(5000, |nodes| nodes.parse::<u64>().unwrap());
+        play_match::play_match(
+            options
+                .get("games")
+                .map_or(100, |games| games.parse::<u32>().unwrap()),
+            options
+                .get("threads")
+                .map_or(1, |threads| threads.parse::<u32>().unwrap()),
+            nodes,
+            options
+                .get("opponent_nodes")
+                .map_or(nodes, |nodes| nodes.parse::<u64>().unwrap()),
+            options
+                .get("seed")
+                .map_or(0, |seed| seed.parse::<u64>().unwrap()),
+        );
+    }
+
     #[cfg(feature = "trace")]
     fn tune(options: Vec<(String, String)>) {
         use std::{collections::HashMap, str::FromStr};
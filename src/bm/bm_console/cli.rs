@@ -0,0 +1,184 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use cozy_chess::{Board, GameStatus};
+
+use crate::bm::bm_runner::ab_runner::AbRunner;
+use crate::bm::bm_runner::config::{Run, UciInfo};
+use crate::bm::bm_runner::params::SearchParams;
+use crate::bm::bm_runner::time::{SearchLimits, TimeManager};
+use crate::bm::evaluator;
+use crate::bm::uci;
+
+use super::perft;
+
+const DEFAULT_DEPTH: u32 = 12;
+
+/*
+Command line interface for one-shot runs:
+--fen <fen> --depth <n> --nodes <n> --threads <n> --hash <mb> --perft <n> --config <path>
+--captures restricts perft to capture sequences
+--bench or bench [depth] runs the bench and exits, OpenBench starts the engine this way
+--evalfile <path> loads a network instead of the embedded one
+Without any one-shot flags, the engine starts in UCI mode, unknown arguments are ignored
+*/
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    fen: Option<String>,
+    depth: Option<u32>,
+    nodes: Option<u64>,
+    threads: Option<usize>,
+    hash: Option<usize>,
+    perft: Option<u32>,
+    captures: bool,
+    config: Option<String>,
+    evalfile: Option<String>,
+    bench: bool,
+    bench_depth: Option<u32>,
+}
+
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, String> {
+    let value = args
+        .next()
+        .ok_or_else(|| format!("{} requires a value", flag))?;
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value {} for {}", value, flag))
+}
+
+impl CliArgs {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.peekable();
+        let mut cli = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.trim() {
+                "--fen" => cli.fen = Some(value(&mut args, "--fen")?),
+                "--depth" => cli.depth = Some(value(&mut args, "--depth")?),
+                "--nodes" => cli.nodes = Some(value(&mut args, "--nodes")?),
+                "--threads" => cli.threads = Some(value(&mut args, "--threads")?),
+                "--hash" => cli.hash = Some(value(&mut args, "--hash")?),
+                "--perft" => cli.perft = Some(value(&mut args, "--perft")?),
+                "--captures" => cli.captures = true,
+                "--config" => cli.config = Some(value(&mut args, "--config")?),
+                "--evalfile" => cli.evalfile = Some(value(&mut args, "--evalfile")?),
+                "--bench" | "bench" => {
+                    cli.bench = true;
+                    cli.bench_depth = args
+                        .next_if(|depth| depth.parse::<u32>().is_ok())
+                        .map(|depth| depth.parse().unwrap());
+                }
+                /*
+                GUIs and testing frameworks may pass arguments meant for other engines
+                */
+                _ => eprintln!("# ignoring unknown argument {}", arg),
+            }
+        }
+        Ok(cli)
+    }
+
+    pub fn params(&self) -> Result<SearchParams, String> {
+        match &self.config {
+            Some(path) => SearchParams::load(path),
+            None => Ok(SearchParams::new()),
+        }
+    }
+
+    pub fn bench(&self) -> Option<String> {
+        match (self.bench, self.bench_depth) {
+            (false, _) => None,
+            (true, None) => Some("bench".to_string()),
+            (true, Some(depth)) => Some(format!("bench {}", depth)),
+        }
+    }
+
+    pub fn uci_options(&self) -> Vec<String> {
+        let mut options = vec![];
+        if let Some(threads) = self.threads {
+            options.push(format!("setoption name Threads value {}", threads));
+        }
+        if let Some(hash) = self.hash {
+            options.push(format!("setoption name Hash value {}", hash));
+        }
+        if let Some(evalfile) = &self.evalfile {
+            options.push(format!("setoption name EvalFile value {}", evalfile));
+        }
+        options
+    }
+
+    pub fn one_shot(&self) -> bool {
+        self.fen.is_some() || self.depth.is_some() || self.nodes.is_some() || self.perft.is_some()
+    }
+
+    pub fn run(&self, params: SearchParams) -> Result<(), String> {
+        let board = match &self.fen {
+            Some(fen) => Board::from_str(fen).map_err(|err| format!("invalid fen: {:?}", err))?,
+            None => Board::default(),
+        };
+        if let Some(depth) = self.perft {
+            let start = Instant::now();
+            let mut total = 0;
+            for (make_move, nodes) in perft::divide(&board, depth, self.captures) {
+                println!("{}: {}", make_move, nodes);
+                total += nodes;
+            }
+            let elapsed = start.elapsed();
+            println!();
+            println!("Nodes searched: {}", total);
+            println!(
+                "{} nps",
+                (total as f64 / elapsed.as_secs_f64().max(0.001)) as u64
+            );
+            return Ok(());
+        }
+
+        /*
+        A mated or stalemated side has no move to search, it is answered like a GUI would expect
+        */
+        if board.status() != GameStatus::Ongoing {
+            println!("info string no legal moves");
+            println!("bestmove 0000");
+            return Ok(());
+        }
+        if let Some(evalfile) = &self.evalfile {
+            evaluator::load_network(evalfile)?;
+        }
+        let time_manager = Arc::new(TimeManager::new());
+        let mut runner = AbRunner::new(board.clone(), time_manager.clone());
+        runner.set_params(params);
+        if let Some(threads) = self.threads {
+            runner.set_threads(threads);
+        }
+        if let Some(hash) = self.hash {
+            runner.hash(hash);
+        }
+        let mut limits = SearchLimits::new();
+        if self.depth.is_some() || self.nodes.is_none() {
+            limits = limits.depth(self.depth.unwrap_or(DEFAULT_DEPTH));
+        }
+        if let Some(nodes) = self.nodes {
+            limits = limits.nodes(nodes);
+        }
+        time_manager.initiate_limits(&board, limits);
+        let (mut best_move, _, _, _) = runner.search::<Run, UciInfo>();
+        uci::convert_move_to_uci(&mut best_move, &board, false);
+        println!("bestmove {}", best_move);
+        Ok(())
+    }
+}
+
+#[test]
+fn no_legal_moves() {
+    let cli = |fen: &str| {
+        CliArgs::parse(
+            ["--fen", fen, "--depth", "4"]
+                .into_iter()
+                .map(str::to_string),
+        )
+        .unwrap()
+    };
+    let mated = cli("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+    assert!(mated.run(SearchParams::new()).is_ok());
+    let stalemated = cli("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1");
+    assert!(stalemated.run(SearchParams::new()).is_ok());
+}
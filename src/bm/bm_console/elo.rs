@@ -0,0 +1,107 @@
+/*
+Game results of a match from the first player's point of view
+*/
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MatchResult {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MatchResult {
+    pub fn add(&mut self, score: f32) {
+        if score > 0.5 {
+            self.wins += 1;
+        } else if score < 0.5 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: MatchResult) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+    }
+
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 * 0.5) / self.games() as f64
+    }
+
+    /*
+    Elo difference with the half width of its 95% confidence interval
+    */
+    pub fn elo(&self) -> (f64, f64) {
+        let games = self.games() as f64;
+        let score = self.score();
+        let deviation = ((self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / games)
+            .sqrt();
+        let margin = 1.959964 * deviation / games.sqrt();
+        let elo = score_to_elo(score);
+        let error = (score_to_elo(score + margin) - score_to_elo(score - margin)) / 2.0;
+        (elo, error)
+    }
+
+    /*
+    Likelihood of superiority, draws carry no information
+    */
+    pub fn los(&self) -> f64 {
+        let decisive = (self.wins + self.losses) as f64;
+        if decisive == 0.0 {
+            return 0.5;
+        }
+        let z = (self.wins as f64 - self.losses as f64) / (2.0 * decisive).sqrt();
+        0.5 * (1.0 + erf(z))
+    }
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/*
+Abramowitz and Stegun 7.1.26, accurate to ~1e-7
+*/
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    if x >= 0.0 {
+        y
+    } else {
+        -y
+    }
+}
+
+#[test]
+fn elo_estimation() {
+    let even = MatchResult {
+        wins: 30,
+        draws: 40,
+        losses: 30,
+    };
+    let (elo, error) = even.elo();
+    assert!(elo.abs() < 1e-6);
+    assert!(error > 0.0);
+    assert!((even.los() - 0.5).abs() < 1e-6);
+
+    let winning = MatchResult {
+        wins: 60,
+        draws: 20,
+        losses: 20,
+    };
+    let (elo, _) = winning.elo();
+    assert!((elo - 147.19).abs() < 0.1);
+    assert!(winning.los() > 0.99);
+}
@@ -2,29 +2,39 @@ use std::{
     fs::OpenOptions,
     io::{BufWriter, Write},
     sync::{mpsc::channel, Arc},
-    time::{Duration, Instant},
 };
 
 use arrayvec::ArrayVec;
 use cozy_chess::{BitBoard, Board, Move};
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::bm::{
     bm_runner::{
-        ab_runner::AbRunner,
+        ab_runner::{AbRunner, DEFAULT_STACK_MB},
         config::{NoInfo, Run},
         time::{TimeManagementInfo, TimeManager},
     },
-    bm_util::eval::Evaluation,
+    bm_util::{eval::Evaluation, noise::EvalNoise},
 };
 
-use threadpool::{self, ThreadPool};
+/*
+Search statistics of every labeled position,
+used to weight or filter low quality labels while training
+*/
+#[derive(Debug, Copy, Clone)]
+struct LabelStats {
+    depth: u32,
+    nodes: u64,
+    fail_highs: u32,
+}
 
 fn play_single(
+    rng: &mut StdRng,
     engine: &mut AbRunner,
     time_manager: &TimeManager,
     time_management_info: &[TimeManagementInfo],
-) -> Vec<(Board, Evaluation, f32)> {
+) -> Vec<(Board, Evaluation, LabelStats, f32)> {
     let mut evals = Vec::new();
     engine.set_board(Board::default());
     let mut result = 0.5;
@@ -38,7 +48,12 @@ fn play_single(
             cozy_chess::GameStatus::Ongoing => {}
         }
         time_manager.initiate(engine.get_board(), time_management_info);
-        let (mut make_move, eval, _, _) = engine.search::<Run, NoInfo>(1);
+        let (mut make_move, eval, depth, nodes) = engine.search::<Run, NoInfo>();
+        let stats = LabelStats {
+            depth: depth.saturating_sub(1),
+            nodes,
+            fail_highs: engine.get_fail_highs(),
+        };
         time_manager.clear();
         let turn = match engine.get_board().side_to_move() {
             cozy_chess::Color::White => 1,
@@ -53,7 +68,7 @@ fn play_single(
                 .has(make_move.to)
             && board.checkers() == BitBoard::EMPTY
         {
-            evals.push((engine.get_board().clone(), eval * turn));
+            evals.push((engine.get_board().clone(), eval * turn, stats));
         }
 
         if ply < 8 {
@@ -64,7 +79,7 @@ fn play_single(
                 }
                 false
             });
-            make_move = moves[rand::thread_rng().gen_range(0..moves.len())];
+            make_move = moves[rng.gen_range(0..moves.len())];
         }
         engine.make_move(make_move);
         if engine.get_position().forced_draw(0) {
@@ -74,18 +89,31 @@ fn play_single(
     }
     evals
         .into_iter()
-        .map(|(b, e)| (b, e, result))
+        .map(|(b, e, s)| (b, e, s, result))
         .collect::<Vec<_>>()
 }
 
-fn gen_games(duration: Duration, depth: u32) -> Vec<(Board, Evaluation, f32)> {
-    let start = Instant::now();
+/*
+Games played by every thread per batch, a fixed game count rather than a time budget
+keeps the output independent of machine load
+*/
+const GAMES_PER_BATCH: u32 = 16;
+
+fn gen_games(
+    games: u32,
+    depth: u32,
+    noise: i16,
+    seed: u64,
+) -> Vec<(Board, Evaluation, LabelStats, f32)> {
+    let mut rng = StdRng::seed_from_u64(seed);
     let mut evals = vec![];
     let time_management_options = TimeManagementInfo::MaxDepth(depth);
     let time_manager = Arc::new(TimeManager::new());
     let mut engine_0 = AbRunner::new(Board::default(), time_manager.clone());
-    while start.elapsed() < duration {
+    for _ in 0..games {
+        engine_0.set_eval_noise(EvalNoise::new(noise, rng.gen()));
         evals.extend(play_single(
+            &mut rng,
             &mut engine_0,
             &time_manager,
             &[time_management_options],
@@ -95,19 +123,40 @@ fn gen_games(duration: Duration, depth: u32) -> Vec<(Board, Evaluation, f32)> {
     evals
 }
 
-pub fn gen_eval(depth: u32, thread_cnt: u32, target_path: &str) {
-    let pool = ThreadPool::new(thread_cnt as usize);
-    loop {
+/*
+Every thread of every batch gets its own seed derived from the given seed,
+results are written in thread order so runs with the same seed are reproducible
+*/
+pub fn gen_eval(depth: u32, thread_cnt: u32, noise: i16, seed: u64, target_path: &str) {
+    let pool = threadpool::Builder::new()
+        .num_threads(thread_cnt as usize)
+        .thread_stack_size(DEFAULT_STACK_MB * 1024 * 1024)
+        .build();
+    for batch in 0_u64.. {
         let (tx, rx) = channel();
-        for _ in 0..thread_cnt {
+        for thread in 0..thread_cnt {
             let tx = tx.clone();
+            let seed = seed
+                .wrapping_mul(0x9E3779B97F4A7C15)
+                .wrapping_add(batch * thread_cnt as u64 + thread as u64);
             pool.execute(move || {
-                tx.send(gen_games(Duration::from_secs(30), depth)).unwrap();
+                tx.send((thread, gen_games(GAMES_PER_BATCH, depth, noise, seed)))
+                    .unwrap();
             });
         }
+        let mut games = rx.iter().take(thread_cnt as usize).collect::<Vec<_>>();
+        games.sort_by_key(|&(thread, _)| thread);
         let mut output = String::new();
-        for (board, eval, wdl) in rx.iter().take(thread_cnt as usize).flatten() {
-            output += &format!("{} | {} | {}\n", &board.to_string(), eval.raw(), wdl);
+        for (board, eval, stats, wdl) in games.into_iter().flat_map(|(_, games)| games) {
+            output += &format!(
+                "{} | {} | {} | {} | {} | {}\n",
+                &board.to_string(),
+                eval.raw(),
+                wdl,
+                stats.depth,
+                stats.nodes,
+                stats.fail_highs
+            );
         }
         let file = OpenOptions::new()
             .read(true)
@@ -116,6 +165,7 @@ pub fn gen_eval(depth: u32, thread_cnt: u32, target_path: &str) {
             .open(target_path)
             .unwrap();
         let mut write = BufWriter::new(file);
-        write.write(output.as_bytes()).unwrap();
+        write.write_all(output.as_bytes()).unwrap();
+        write.flush().unwrap();
     }
 }
@@ -0,0 +1,32 @@
+use std::io::BufRead;
+use std::sync::mpsc::{channel, Receiver};
+
+use crate::bm::uci::ShutdownHandle;
+
+/*
+Reads commands on a dedicated thread so a busy main thread doesn't delay them
+Stop, quit and xboard's move now (?) abort the running search right away,
+every command is still forwarded and handled in order
+Blank lines are ignored, input ends at quit or end of file and the closed channel
+then quits after the current command
+*/
+pub fn spawn(control: ShutdownHandle) -> Receiver<String> {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for command in stdin.lock().lines().map_while(Result::ok) {
+            let command_name = command.trim();
+            if command_name.is_empty() {
+                continue;
+            }
+            let quit = command_name == "quit";
+            if matches!(command_name, "stop" | "quit" | "?") {
+                control.stop();
+            }
+            if tx.send(command).is_err() || quit {
+                break;
+            }
+        }
+    });
+    rx
+}
@@ -0,0 +1,31 @@
+use crate::bm::uci::ShutdownHandle;
+
+/*
+SIGINT and SIGTERM stop the search and exit once it has reported and the game log is written
+signal-hook's handler only wakes a thread that does the actual work
+*/
+#[cfg(unix)]
+pub fn install(shutdown: ShutdownHandle) {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            println!("info string failed to install signal handlers: {}", err);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            shutdown.shutdown();
+            std::process::exit(0);
+        }
+    });
+}
+
+/*
+Elsewhere the engine is only shut down through quit or the end of input
+*/
+#[cfg(not(unix))]
+pub fn install(_: ShutdownHandle) {}
@@ -0,0 +1,77 @@
+use cozy_chess::{Board, Move, Piece};
+
+/*
+Capture-only perft only follows captures, including en passant, which exercises
+the capture sequences SEE and quiescence search deal with
+*/
+fn is_capture(board: &Board, make_move: Move) -> bool {
+    board.colors(!board.side_to_move()).has(make_move.to)
+        || (board.piece_on(make_move.from) == Some(Piece::Pawn)
+            && make_move.from.file() != make_move.to.file())
+}
+
+pub fn perft(board: &Board, depth: u32, captures: bool) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let mut nodes = 0;
+    board.generate_moves(|piece_moves| {
+        if depth == 1 && !captures {
+            nodes += piece_moves.len() as u64;
+        } else {
+            for make_move in piece_moves {
+                if captures && !is_capture(board, make_move) {
+                    continue;
+                }
+                let mut child = board.clone();
+                child.play_unchecked(make_move);
+                nodes += perft(&child, depth - 1, captures);
+            }
+        }
+        false
+    });
+    nodes
+}
+
+pub fn divide(board: &Board, depth: u32, captures: bool) -> Vec<(Move, u64)> {
+    let mut moves = vec![];
+    board.generate_moves(|piece_moves| {
+        for make_move in piece_moves {
+            if captures && !is_capture(board, make_move) {
+                continue;
+            }
+            let mut child = board.clone();
+            child.play_unchecked(make_move);
+            moves.push((make_move, perft(&child, depth.saturating_sub(1), captures)));
+        }
+        false
+    });
+    moves
+}
+
+#[test]
+fn perft_startpos() {
+    let board = Board::default();
+    assert_eq!(perft(&board, 1, false), 20);
+    assert_eq!(perft(&board, 2, false), 400);
+    assert_eq!(perft(&board, 3, false), 8902);
+    assert_eq!(
+        divide(&board, 3, false)
+            .iter()
+            .map(|&(_, nodes)| nodes)
+            .sum::<u64>(),
+        8902
+    );
+}
+
+#[test]
+fn capture_perft() {
+    let board = Board::default();
+    assert_eq!(perft(&board, 3, true), 0);
+    let board: Board = "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2"
+        .parse()
+        .unwrap();
+    assert_eq!(perft(&board, 1, true), 1);
+    assert_eq!(perft(&board, 2, true), 1);
+    assert_eq!(perft(&board, 3, true), 0);
+}
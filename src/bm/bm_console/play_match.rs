@@ -0,0 +1,169 @@
+use std::sync::{mpsc::channel, Arc};
+
+use arrayvec::ArrayVec;
+use cozy_chess::{Board, Color, GameStatus, Move};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::bm::bm_runner::{
+    ab_runner::{AbRunner, DEFAULT_STACK_MB},
+    config::{NoInfo, Run},
+    time::{TimeManagementInfo, TimeManager},
+};
+
+use super::elo::MatchResult;
+
+const OPENING_PLIES: usize = 8;
+
+struct Player {
+    engine: AbRunner,
+    time_manager: Arc<TimeManager>,
+    limits: [TimeManagementInfo; 1],
+}
+
+impl Player {
+    fn new(nodes: u64) -> Self {
+        let time_manager = Arc::new(TimeManager::new());
+        Self {
+            engine: AbRunner::new(Board::default(), time_manager.clone()),
+            time_manager,
+            limits: [TimeManagementInfo::MaxNodes(nodes)],
+        }
+    }
+
+    fn think(&mut self) -> Move {
+        self.time_manager
+            .initiate(self.engine.get_board(), &self.limits);
+        let (make_move, _, _, _) = self.engine.search::<Run, NoInfo>();
+        self.time_manager.clear();
+        make_move
+    }
+}
+
+fn random_opening(rng: &mut StdRng) -> Vec<Move> {
+    'retry: loop {
+        let mut board = Board::default();
+        let mut opening = vec![];
+        for _ in 0..OPENING_PLIES {
+            let mut moves = ArrayVec::<Move, 218>::new();
+            board.generate_moves(|piece_moves| {
+                moves.extend(piece_moves);
+                false
+            });
+            if moves.is_empty() {
+                continue 'retry;
+            }
+            let make_move = moves[rng.gen_range(0..moves.len())];
+            board.play_unchecked(make_move);
+            opening.push(make_move);
+        }
+        if board.status() == GameStatus::Ongoing {
+            return opening;
+        }
+    }
+}
+
+/*
+Returns the score of the white player
+*/
+fn play_game(opening: &[Move], white: &mut Player, black: &mut Player) -> f32 {
+    for player in [&mut *white, &mut *black] {
+        player.engine.new_game();
+        player.engine.set_board(Board::default());
+        for &make_move in opening {
+            player.engine.make_move(make_move);
+        }
+    }
+    loop {
+        let board = white.engine.get_board().clone();
+        match board.status() {
+            GameStatus::Won => {
+                return match board.side_to_move() {
+                    Color::White => 0.0,
+                    Color::Black => 1.0,
+                }
+            }
+            GameStatus::Drawn => return 0.5,
+            GameStatus::Ongoing => {}
+        }
+        let make_move = match board.side_to_move() {
+            Color::White => white.think(),
+            Color::Black => black.think(),
+        };
+        white.engine.make_move(make_move);
+        black.engine.make_move(make_move);
+        if white.engine.get_position().forced_draw(0) {
+            return 0.5;
+        }
+    }
+}
+
+/*
+Plays game pairs with swapped colors from random openings between
+a player searching `nodes` and one searching `opponent_nodes` per move
+*/
+fn play_pairs(pairs: u32, nodes: u64, opponent_nodes: u64, seed: u64) -> MatchResult {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut player = Player::new(nodes);
+    let mut opponent = Player::new(opponent_nodes);
+    let mut result = MatchResult::default();
+    for _ in 0..pairs {
+        let opening = random_opening(&mut rng);
+        result.add(play_game(&opening, &mut player, &mut opponent));
+        result.add(1.0 - play_game(&opening, &mut opponent, &mut player));
+    }
+    result
+}
+
+pub fn play_match(games: u32, thread_cnt: u32, nodes: u64, opponent_nodes: u64, seed: u64) {
+    let pool = threadpool::Builder::new()
+        .num_threads(thread_cnt as usize)
+        .thread_stack_size(DEFAULT_STACK_MB * 1024 * 1024)
+        .build();
+    let pairs = games.div_ceil(2);
+    let (tx, rx) = channel();
+    for thread in 0..thread_cnt {
+        let tx = tx.clone();
+        let thread_pairs = pairs / thread_cnt + u32::from(thread < pairs % thread_cnt);
+        let seed = seed
+            .wrapping_mul(0x9E3779B97F4A7C15)
+            .wrapping_add(thread as u64);
+        pool.execute(move || {
+            tx.send(play_pairs(thread_pairs, nodes, opponent_nodes, seed))
+                .unwrap();
+        });
+    }
+    let mut result = MatchResult::default();
+    for thread_result in rx.iter().take(thread_cnt as usize) {
+        result.merge(thread_result);
+    }
+
+    let (elo, error) = result.elo();
+    println!(
+        "games {} wins {} draws {} losses {}",
+        result.games(),
+        result.wins,
+        result.draws,
+        result.losses
+    );
+    println!(
+        "elo {:.1} +/- {:.1} los {:.1}%",
+        elo,
+        error,
+        result.los() * 100.0
+    );
+    /*
+    Normalizes this match's difference by its node odds so runs at different budgets compare,
+    it comes from the match result alone and not from bench node counts
+    */
+    if nodes != opponent_nodes {
+        let doublings = (nodes as f64 / opponent_nodes as f64).log2();
+        println!(
+            "elo per doubling of the node odds ({} vs {} nodes) {:.1} +/- {:.1}",
+            nodes,
+            opponent_nodes,
+            elo / doublings,
+            error / doublings.abs()
+        );
+    }
+}
@@ -1,3 +1,5 @@
 pub mod ab_runner;
 pub mod config;
+pub mod observer;
+pub mod params;
 pub mod time;
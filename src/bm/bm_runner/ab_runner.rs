@@ -1,70 +1,274 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use cozy_chess::{Board, Color, Move};
+use cozy_chess::{Board, Color, GameStatus, Move};
 
-use crate::bm::bm_runner::config::{GuiInfo, NoInfo, SearchMode, SearchStats};
-use crate::bm::bm_search::move_entry::MoveEntry;
+use crate::bm::bm_runner::config::{GuiInfo, NoInfo, ScoreBound, SearchMode, SearchStats};
+use crate::bm::bm_runner::observer::{IterationStats, Observers, PruneStats, SearchObserver};
+use crate::bm::bm_runner::params::SearchParams;
+use crate::bm::bm_search::move_entry::{MoveEntry, MAX_KILLERS};
 use crate::bm::bm_search::search;
 use crate::bm::bm_search::search::Pv;
 use crate::bm::bm_util::eval::Evaluation;
-use crate::bm::bm_util::h_table::{CounterMoveTable, DoubleMoveHistory, HistoryTable};
+use crate::bm::bm_util::h_table::{
+    CounterMoveTable, DoubleMoveHistory, HistoryTable, LosingCaptureHistory,
+};
 use crate::bm::bm_util::lookup::LookUp2d;
-use crate::bm::bm_util::position::Position;
-use crate::bm::bm_util::t_table::TranspositionTable;
+use crate::bm::bm_util::noise::EvalNoise;
+use crate::bm::bm_util::position::{EvalTerms, Position};
+use crate::bm::bm_util::t_table::{EntryType, TranspositionTable};
+use crate::bm::bm_util::tb::Tablebase;
+use crate::bm::bm_util::tension::Tension;
+use crate::bm::bm_util::wdl;
 use crate::bm::bm_util::window::Window;
+use crate::bm::evaluator::{Evaluator, NnBreakdown, Perspectives};
 use crate::bm::uci;
 
 use super::time::TimeManager;
 
 pub const MAX_PLY: u32 = 128;
 
-#[derive(Debug, Clone)]
+/*
+Search is deeply recursive, so search threads get more stack than the default
+*/
+pub const DEFAULT_STACK_MB: usize = 64;
+
+/*
+Upper limit of the Threads option
+*/
+pub const MAX_THREADS: usize = 512;
+
+/*
+Scores above this are considered clearly winning by the root move filter
+*/
+const WINNING_EVAL: i16 = 300;
+
+/*
+Root moves and aspiration failures are only reported once the search has run for this long
+*/
+const VERBOSE_INFO_DELAY: Duration = Duration::from_secs(1);
+
+/*
+Time between progress reports that don't wait for an iteration to complete
+*/
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/*
+Time between the timer thread's checks whether the search is out of time
+*/
+const TIMER_INTERVAL: Duration = Duration::from_millis(1);
+
+/*
+Nodes are added to the shared counts in batches, so counts read during the search
+are at most a batch per thread behind
+The search also checks its time and node limits once per batch
+*/
+const NODE_BATCH: u64 = 1024;
+
+/*
+Nodes searched by all threads together and by each thread
+*/
+#[derive(Debug, Clone, Default)]
 pub struct NodeCounter {
-    node_counters: Vec<Option<Arc<AtomicU64>>>,
+    total: Arc<AtomicU64>,
+    node_counters: Vec<Arc<AtomicU64>>,
 }
 
 impl NodeCounter {
     fn initialize_node_counters(&mut self, threads: usize) {
-        self.node_counters = vec![None; threads];
+        self.total = Arc::default();
+        self.node_counters = (0..threads).map(|_| Arc::default()).collect();
     }
 
-    fn add_node_counter(&mut self, thread: usize, node_counter: Arc<AtomicU64>) {
-        self.node_counters[thread] = Some(node_counter);
+    fn thread_nodes(&self, thread: usize) -> Nodes {
+        Nodes {
+            local: 0,
+            thread: self.node_counters[thread].clone(),
+            total: self.total.clone(),
+            batch_done: false,
+        }
     }
 
-    fn get_node_count(&self) -> u64 {
-        let mut total_nodes = 0;
-        for nodes in self.node_counters.iter() {
-            if let Some(nodes) = nodes {
-                total_nodes += nodes.load(Ordering::Relaxed);
-            }
+    /*
+    Threads only add their nodes to the total once per batch, the reporting thread's
+    nodes that aren't flushed yet are added so early and partial batches are counted
+    */
+    fn get_node_count(&self, unflushed: u64) -> u64 {
+        self.total.load(Ordering::Relaxed) + unflushed
+    }
+
+    fn get_thread_node_count(&self, thread: usize) -> u64 {
+        self.node_counters[thread].load(Ordering::Relaxed)
+    }
+}
+
+/*
+Message a thread panicked with, panics carry either a &str or a String
+*/
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic"
+    }
+}
+
+/*
+Result of a search thread taking part in the vote for the move to play
+*/
+#[derive(Debug, Copy, Clone)]
+struct ThreadVote {
+    best_move: Move,
+    ponder_move: Option<Move>,
+    eval: Evaluation,
+    depth: u32,
+    sel_depth: u32,
+}
+
+/*
+Added to every thread's score margin over the worst thread when weighing votes,
+so the thread with the worst score still has a vote proportional to its depth instead of none
+*/
+const VOTE_EVAL_BASE: i64 = 14;
+
+/*
+Every thread votes for its move weighted by its depth and how much better its score is
+than the worst one, the thread whose move got the most votes decides
+Threads that found a mate win the vote, the fastest mate among them decides
+*/
+fn vote(votes: &[ThreadVote]) -> usize {
+    let min_eval = votes.iter().map(|vote| vote.eval.raw() as i64).min();
+    let weight = |vote: &ThreadVote| {
+        (vote.eval.raw() as i64 - min_eval.unwrap() + VOTE_EVAL_BASE) * vote.depth as i64
+    };
+    let move_votes = |make_move: Move| -> i64 {
+        votes
+            .iter()
+            .filter(|vote| vote.best_move == make_move)
+            .map(weight)
+            .sum()
+    };
+    let mates = |vote: &ThreadVote| vote.eval.is_mate() && vote.eval.raw() > 0;
+    let mut best = 0;
+    for (thread, vote) in votes.iter().enumerate().skip(1) {
+        let best_vote = &votes[best];
+        let better = if mates(best_vote) {
+            vote.eval > best_vote.eval
+        } else {
+            mates(vote) || move_votes(vote.best_move) > move_votes(best_vote.best_move)
+        };
+        if better {
+            best = thread;
         }
-        total_nodes
     }
+    best
 }
 
-#[derive(Debug)]
-pub struct Nodes(Arc<AtomicU64>);
+/*
+Nodes searched by a thread, only the thread's own count is updated on every node
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Nodes {
+    local: u64,
+    thread: Arc<AtomicU64>,
+    total: Arc<AtomicU64>,
+    /*
+    Set when a batch is completed, until the search checks its limits
+    */
+    batch_done: bool,
+}
 
-impl Clone for Nodes {
-    fn clone(&self) -> Self {
-        Self(Arc::new(AtomicU64::new(0)))
+impl Nodes {
+    fn flush(&self, nodes: u64) {
+        self.thread.fetch_add(nodes, Ordering::Relaxed);
+        self.total.fetch_add(nodes, Ordering::Relaxed);
     }
 }
 
 type LmrLookup = LookUp2d<u32, 32, 64>;
 type LmpLookup = LookUp2d<usize, 16, 2>;
+type QLmpLookup = LookUp2d<usize, 8, 2>;
+type WindowLookup = LookUp2d<i16, 32, 16>;
+
+fn lmr_lookup(params: &SearchParams) -> LmrLookup {
+    LookUp2d::new(|depth, mv| {
+        if depth == 0 || mv == 0 {
+            0
+        } else {
+            (params.lmr_base + (depth as f32).ln() * (mv as f32).ln() / params.lmr_div) as u32
+        }
+    })
+}
+
+fn lmp_lookup(params: &SearchParams) -> LmpLookup {
+    LookUp2d::new(|depth, improving| {
+        let mut x = params.lmp_base + depth as f32 * depth as f32;
+        if improving == 0 {
+            x /= 2.0;
+        }
+        x as usize
+    })
+}
+
+/*
+Moves searched in quiescence search indexed by how many q_lmp_margins the
+stand pat is below alpha and whether quiet checks are searched
+Nodes within a margin of alpha search every move
+*/
+fn q_lmp_lookup(params: &SearchParams) -> QLmpLookup {
+    LookUp2d::new(|margins, checks| {
+        if margins == 0 {
+            return usize::MAX;
+        }
+        let moves = params.q_lmp_base * params.q_lmp_decay.powi(margins as i32 - 1);
+        moves.max(1.0) as usize + checks
+    })
+}
+
+/*
+Aspiration windows start narrower as depth increases
+and grow with every failed search
+*/
+fn window_lookup(params: &SearchParams) -> WindowLookup {
+    LookUp2d::new(|depth, fails| {
+        let mut window = (params.window_start - depth as f32 * params.window_depth_shrink)
+            .max(params.window_min);
+        for _ in 0..fails {
+            window = window * params.window_growth + params.window_add;
+        }
+        window as i16
+    })
+}
 
 #[derive(Debug, Clone)]
 pub struct SharedContext {
     start: Instant,
     time_manager: Arc<TimeManager>,
+    /*
+    Set once the search is out of time, by the timer thread or a search that read the clock
+    */
+    stop: Arc<AtomicBool>,
 
     t_table: Arc<TranspositionTable>,
+    search_moves: Arc<Vec<Move>>,
+    eval_noise: EvalNoise,
+    mate_search: bool,
+    analyse_mode: bool,
+    contempt: i16,
+    tablebase: Arc<Tablebase>,
+    tb_hits: Arc<AtomicU64>,
+    observers: Observers,
+    params: Arc<SearchParams>,
     lmr_lookup: Arc<LmrLookup>,
     lmp_lookup: Arc<LmpLookup>,
+    q_lmp_lookup: Arc<QLmpLookup>,
+    window_lookup: Arc<WindowLookup>,
 }
 
 #[derive(Debug, Clone)]
@@ -72,8 +276,13 @@ pub struct SearchStack {
     pub eval: Evaluation,
     pub skip_move: Option<Move>,
     pub move_played: Option<Move>,
+    pub threat_move: Option<Move>,
     pub pv: [Option<Move>; MAX_PLY as usize + 1],
     pub pv_len: usize,
+    /*
+    Plies of extensions along the line leading to the node
+    */
+    pub extensions: u32,
 }
 
 impl SearchStack {
@@ -86,31 +295,83 @@ impl SearchStack {
     }
 }
 
+type RootMoveCallback = dyn Fn(&Board, u32, Move, usize) + Send + Sync;
+
+/*
+Reports every root move as it is searched, only the main thread has one
+*/
+#[derive(Clone)]
+pub struct RootMoveReporter(Arc<RootMoveCallback>);
+
+impl Debug for RootMoveReporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RootMoveReporter")
+    }
+}
+
+type HeartbeatCallback = dyn Fn(u64) + Send + Sync;
+
+/*
+Reports search progress every HEARTBEAT_INTERVAL, only the main thread has one
+*/
+#[derive(Clone)]
+pub struct Heartbeat {
+    callback: Arc<HeartbeatCallback>,
+    next: Instant,
+}
+
+impl Debug for Heartbeat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Heartbeat")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LocalContext {
     window: Window,
     tt_hits: u32,
     tt_misses: u32,
+    prune_stats: PruneStats,
     eval: Evaluation,
     stm: Color,
     search_stack: Vec<SearchStack>,
     sel_depth: u32,
+    root_depth: u32,
     h_table: HistoryTable,
     ch_table: HistoryTable,
+    lc_table: LosingCaptureHistory,
     cm_table: CounterMoveTable,
     cm_hist: DoubleMoveHistory,
-    killer_moves: Vec<MoveEntry<2>>,
+    killer_moves: Vec<MoveEntry<MAX_KILLERS>>,
+    root_exclusions: Vec<Move>,
+    root_reporter: Option<RootMoveReporter>,
+    heartbeat: Option<Heartbeat>,
     nodes: Nodes,
+    thread: usize,
     abort: bool,
 }
 
 impl SharedContext {
+    /*
+    The clock is still read once per batch of nodes in case the timer thread oversleeps,
+    sleeps can take a whole scheduler tick (about 15.6 ms on Windows)
+    The node limit is checked at the same time
+    */
     #[inline]
-    pub fn abort_search(&self, node_cnt: u64) -> bool {
-        if node_cnt % 1024 != 0 {
-            return false;
+    pub fn abort_search(&self, batch_nodes: Option<u64>) -> bool {
+        if self.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        let nodes = match batch_nodes {
+            Some(nodes) => nodes,
+            None => return false,
+        };
+        let abort =
+            self.time_manager.abort_search(self.start) || self.time_manager.nodes_reached(nodes);
+        if abort {
+            self.stop.store(true, Ordering::Relaxed);
         }
-        self.time_manager.abort_search(self.start)
+        abort
     }
 
     #[inline]
@@ -123,6 +384,60 @@ impl SharedContext {
         &self.t_table
     }
 
+    /*
+    Root moves the search is restricted to, all moves are searched if empty
+    */
+    #[inline]
+    pub fn get_search_moves(&self) -> &Arc<Vec<Move>> {
+        &self.search_moves
+    }
+
+    #[inline]
+    pub fn mate_search(&self) -> bool {
+        self.mate_search
+    }
+
+    #[inline]
+    pub fn analyse_mode(&self) -> bool {
+        self.analyse_mode
+    }
+
+    /*
+    Contempt is left out of analysis so both sides are evaluated the same way
+    */
+    #[inline]
+    pub fn contempt(&self) -> i16 {
+        if self.analyse_mode {
+            0
+        } else {
+            self.contempt
+        }
+    }
+
+    #[inline]
+    pub fn get_eval_noise(&self) -> &EvalNoise {
+        &self.eval_noise
+    }
+
+    #[inline]
+    pub fn get_tablebase(&self) -> &Tablebase {
+        &self.tablebase
+    }
+
+    #[inline]
+    pub fn increment_tb_hits(&self) {
+        self.tb_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tb_hits(&self) -> u64 {
+        self.tb_hits.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn get_params(&self) -> &Arc<SearchParams> {
+        &self.params
+    }
+
     #[inline]
     pub fn get_lmr_lookup(&self) -> &Arc<LmrLookup> {
         &self.lmr_lookup
@@ -132,9 +447,124 @@ impl SharedContext {
     pub fn get_lmp_lookup(&self) -> &Arc<LmpLookup> {
         &self.lmp_lookup
     }
+
+    #[inline]
+    pub fn get_q_lmp_lookup(&self) -> &Arc<QLmpLookup> {
+        &self.q_lmp_lookup
+    }
+
+    #[inline]
+    pub fn get_window_lookup(&self) -> &Arc<WindowLookup> {
+        &self.window_lookup
+    }
 }
 
 impl LocalContext {
+    /*
+    History is kept between searches and aged at the start of each one
+    */
+    fn age_history(&mut self, div: i16) {
+        self.h_table.age(div);
+        self.ch_table.age(div);
+        self.lc_table.age(div);
+        self.cm_hist.age(div);
+    }
+
+    /*
+    Swaps the tables so the searched context keeps allocations it can be reused with
+    */
+    fn keep_history(&mut self, searched: &mut LocalContext) {
+        std::mem::swap(&mut self.h_table, &mut searched.h_table);
+        std::mem::swap(&mut self.ch_table, &mut searched.ch_table);
+        std::mem::swap(&mut self.lc_table, &mut searched.lc_table);
+        std::mem::swap(&mut self.cm_hist, &mut searched.cm_hist);
+    }
+
+    /*
+    Worker contexts are kept between searches and reset to the template
+    at the start of each one, copying into their tables instead of cloning them
+    */
+    fn prepare(&mut self, template: &LocalContext) {
+        let LocalContext {
+            window,
+            tt_hits,
+            tt_misses,
+            prune_stats,
+            eval,
+            stm,
+            search_stack,
+            sel_depth,
+            root_depth,
+            h_table,
+            ch_table,
+            lc_table,
+            cm_table,
+            cm_hist,
+            killer_moves,
+            root_exclusions,
+            root_reporter,
+            heartbeat,
+            nodes,
+            thread,
+            abort,
+        } = template;
+        self.window = window.clone();
+        self.tt_hits = *tt_hits;
+        self.tt_misses = *tt_misses;
+        self.prune_stats = *prune_stats;
+        self.eval = *eval;
+        self.stm = *stm;
+        self.search_stack.clone_from_slice(search_stack);
+        self.sel_depth = *sel_depth;
+        self.root_depth = *root_depth;
+        self.h_table.copy_from(h_table);
+        self.ch_table.copy_from(ch_table);
+        self.lc_table.copy_from(lc_table);
+        self.cm_table.copy_from(cm_table);
+        self.cm_hist.copy_from(cm_hist);
+        self.killer_moves.clone_from(killer_moves);
+        self.root_exclusions.clone_from(root_exclusions);
+        self.root_reporter = root_reporter.clone();
+        self.heartbeat = heartbeat.clone();
+        self.nodes = nodes.clone();
+        self.thread = *thread;
+        self.abort = *abort;
+    }
+
+    fn clear_history(&mut self) {
+        self.h_table = HistoryTable::new();
+        self.ch_table = HistoryTable::new();
+        self.lc_table = LosingCaptureHistory::new();
+        self.cm_table = CounterMoveTable::new();
+        self.cm_hist = DoubleMoveHistory::new();
+    }
+
+    #[inline]
+    pub fn report_root_move(
+        &self,
+        shared_context: &SharedContext,
+        board: &Board,
+        depth: u32,
+        make_move: Move,
+        move_number: usize,
+    ) {
+        if let Some(reporter) = &self.root_reporter {
+            if shared_context.start.elapsed() >= VERBOSE_INFO_DELAY {
+                (reporter.0)(board, depth, make_move, move_number);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn heartbeat(&mut self) {
+        if let Some(heartbeat) = &mut self.heartbeat {
+            if self.nodes.local.is_multiple_of(NODE_BATCH) && Instant::now() >= heartbeat.next {
+                (heartbeat.callback)(self.nodes.local % NODE_BATCH);
+                heartbeat.next = Instant::now() + HEARTBEAT_INTERVAL;
+            }
+        }
+    }
+
     #[inline]
     pub fn get_h_table(&self) -> &HistoryTable {
         &self.h_table
@@ -145,6 +575,11 @@ impl LocalContext {
         &self.ch_table
     }
 
+    #[inline]
+    pub fn get_lc_table(&self) -> &LosingCaptureHistory {
+        &self.lc_table
+    }
+
     #[inline]
     pub fn get_cm_table(&self) -> &CounterMoveTable {
         &self.cm_table
@@ -165,6 +600,11 @@ impl LocalContext {
         &mut self.ch_table
     }
 
+    #[inline]
+    pub fn get_lc_table_mut(&mut self) -> &mut LosingCaptureHistory {
+        &mut self.lc_table
+    }
+
     #[inline]
     pub fn get_cm_table_mut(&mut self) -> &mut CounterMoveTable {
         &mut self.cm_table
@@ -176,10 +616,20 @@ impl LocalContext {
     }
 
     #[inline]
-    pub fn get_k_table(&mut self) -> &mut Vec<MoveEntry<2>> {
+    pub fn get_k_table(&mut self) -> &mut Vec<MoveEntry<MAX_KILLERS>> {
         &mut self.killer_moves
     }
 
+    #[inline]
+    pub fn thread(&self) -> usize {
+        self.thread
+    }
+
+    #[inline]
+    pub fn root_exclusions(&self) -> &[Move] {
+        &self.root_exclusions
+    }
+
     #[inline]
     pub fn tt_hits(&mut self) -> &mut u32 {
         &mut self.tt_hits
@@ -190,6 +640,11 @@ impl LocalContext {
         &mut self.tt_misses
     }
 
+    #[inline]
+    pub fn prune_stats(&mut self) -> &mut PruneStats {
+        &mut self.prune_stats
+    }
+
     #[inline]
     pub fn search_stack(&self) -> &[SearchStack] {
         &self.search_stack
@@ -209,20 +664,57 @@ impl LocalContext {
         self.eval
     }
 
+    /*
+    Lines can be extended by at most extension_budget times the root depth
+    */
+    pub fn extension_budget(&self, params: &SearchParams) -> u32 {
+        (self.root_depth as f32 * params.extension_budget) as u32
+    }
+
+    pub fn set_root_depth(&mut self, depth: u32) {
+        self.root_depth = depth;
+    }
+
     pub fn stm(&self) -> Color {
         self.stm
     }
 
-    pub fn reset_nodes(&self) {
-        self.nodes.0.store(0, Ordering::Relaxed);
+    pub fn reset_nodes(&mut self) {
+        self.nodes.local = 0;
+        self.nodes.batch_done = false;
+    }
+
+    #[inline]
+    pub fn increment_nodes(&mut self) {
+        self.nodes.local += 1;
+        if self.nodes.local.is_multiple_of(NODE_BATCH) {
+            self.nodes.flush(NODE_BATCH);
+            self.nodes.batch_done = true;
+        }
+    }
+
+    /*
+    The node count if a batch was completed since the last call, qsearch nodes complete
+    batches too, so the search can't skip past one
+    */
+    #[inline]
+    pub fn batch_nodes(&mut self) -> Option<u64> {
+        std::mem::take(&mut self.nodes.batch_done).then_some(self.nodes.local)
     }
 
-    pub fn increment_nodes(&self) {
-        self.nodes.0.fetch_add(1, Ordering::Relaxed);
+    /*
+    Adds the nodes of the last incomplete batch to the shared counts once the search ends
+    */
+    fn flush_nodes(&self) {
+        self.nodes.flush(self.nodes.local % NODE_BATCH);
     }
 
     pub fn nodes(&self) -> u64 {
-        self.nodes.0.load(Ordering::Relaxed)
+        self.nodes.local
+    }
+
+    fn unflushed_nodes(&self) -> u64 {
+        self.nodes.local % NODE_BATCH
     }
 
     pub fn trigger_abort(&mut self) {
@@ -234,266 +726,1260 @@ impl LocalContext {
     }
 }
 
+/*
+Converts a line to UCI moves, limited to one more move than the search depth
+Lines of mate scores end with the mate, anything after it is stale
+The line is replayed and cut at the first illegal move, hash collisions can put one in it
+Lines cut short by TT cutoffs are extended with TT moves until a position repeats
+*/
+fn uci_pv<E: Evaluator>(
+    position: &mut Position<E>,
+    line: &[Option<Move>],
+    depth: u32,
+    eval: Evaluation,
+    chess960: bool,
+    t_table: Option<&TranspositionTable>,
+) -> Vec<Move> {
+    let max_len = eval
+        .mate_plies()
+        .map_or(depth as usize + 1, |plies| plies as usize);
+    let mut line = line.iter().map_while(|make_move| *make_move).fuse();
+    let mut hashes = vec![position.board().hash()];
+    let mut pv = vec![];
+    while pv.len() < max_len {
+        let (make_move, from_table) = match line.next() {
+            Some(make_move) => (make_move, false),
+            None => match t_table.and_then(|t_table| t_table.get(position.board(), 0)) {
+                Some(entry) => (entry.table_move(), true),
+                None => break,
+            },
+        };
+        if position.is_legal(make_move).is_err() {
+            break;
+        }
+        let mut uci_move = make_move;
+        uci::convert_move_to_uci(&mut uci_move, position.board(), chess960);
+        position.make_move(make_move);
+        pv.push(uci_move);
+
+        let hash = position.board().hash();
+        if from_table && hashes.contains(&hash) {
+            break;
+        }
+        hashes.push(hash);
+    }
+    for _ in 0..pv.len() {
+        position.unmake_move()
+    }
+    pv
+}
+
 pub struct AbRunner {
     shared_context: SharedContext,
     local_context: LocalContext,
     node_counter: NodeCounter,
     position: Position,
+    ponder_move: Option<Move>,
+    fail_highs: u32,
+    multi_pv: usize,
+    show_wdl: bool,
+    score_smoothing: i16,
+    pv_extension: bool,
     chess960: bool,
+    stack_size: usize,
+    threads: usize,
+    workers: Vec<Option<Worker>>,
+}
+
+/*
+What a search thread keeps between searches, so it's allocated once per thread
+instead of being cloned from the runner every search
+*/
+struct Worker {
+    local_context: LocalContext,
+    position: Position,
 }
 
 impl AbRunner {
-    fn launch_searcher<SM: 'static + SearchMode + Send, Info: 'static + GuiInfo + Send>(
+    fn launch_searcher<SM: 'static + SearchMode + Send, Info: 'static + GuiInfo + Send + Sync>(
         &mut self,
         search_start: Instant,
-        thread: u8,
+        thread: usize,
         chess960: bool,
-    ) -> impl FnMut() -> (Option<Move>, Evaluation, u32, u64) {
+    ) -> impl FnOnce() -> (
+        Option<Move>,
+        Option<Move>,
+        Option<Evaluation>,
+        u32,
+        u64,
+        u32,
+        Worker,
+    ) {
         let main_thread = thread == 0;
         let shared_context = self.shared_context.clone();
-        let mut local_context = self.local_context.clone();
-        self.node_counter
-            .add_node_counter(thread as usize, local_context.nodes.0.clone());
+        let Worker {
+            mut local_context,
+            mut position,
+        } = match self.workers.get_mut(thread).and_then(Option::take) {
+            Some(mut worker) => {
+                worker.local_context.prepare(&self.local_context);
+                worker.position.copy_from(&self.position);
+                worker
+            }
+            None => Worker {
+                local_context: self.local_context.clone(),
+                position: self.position.clone(),
+            },
+        };
+        local_context.thread = thread;
+        local_context.nodes = self.node_counter.thread_nodes(thread);
         let node_counter = if main_thread {
             Some(self.node_counter.clone())
         } else {
             None
         };
-        let mut position = self.position.clone();
         let mut debugger = SM::new(self.position.board());
         let gui_info = Info::new();
+        if main_thread {
+            let curr_move_info = Info::new();
+            local_context.root_reporter = Some(RootMoveReporter(Arc::new(
+                move |board: &Board, depth, make_move, move_number| {
+                    let mut uci_move = make_move;
+                    uci::convert_move_to_uci(&mut uci_move, board, chess960);
+                    curr_move_info.print_curr_move(depth, uci_move, move_number);
+                },
+            )));
+            let heartbeat_info = Info::new();
+            let node_counter = self.node_counter.clone();
+            let t_table = self.shared_context.t_table.clone();
+            let tb_hits = self.shared_context.tb_hits.clone();
+            local_context.heartbeat = Some(Heartbeat {
+                callback: Arc::new(move |unflushed| {
+                    heartbeat_info.print_heartbeat(
+                        search_start.elapsed(),
+                        node_counter.get_node_count(unflushed),
+                        tb_hits.load(Ordering::Relaxed),
+                        t_table.hash_full(),
+                    );
+                }),
+                next: search_start + HEARTBEAT_INTERVAL,
+            });
+        }
+        let mut legal_moves = 0;
+        self.position.board().generate_moves(|piece_moves| {
+            legal_moves += piece_moves.len();
+            false
+        });
+        if !self.shared_context.search_moves.is_empty() {
+            legal_moves = self.shared_context.search_moves.len();
+        }
+        let multi_pv = self.multi_pv.min(legal_moves).max(1);
+        let game_ply = self.show_wdl.then(|| wdl::game_ply(self.position.board()));
+        let score_smoothing = self.score_smoothing;
+        let pv_extension = self.pv_extension;
+        /*
+        In mate search, only scores of a mate within the limit are inside the window
+        The first iteration uses a full window so there is always a move to play
+        */
+        let mate_alpha = self
+            .shared_context
+            .time_manager
+            .mate_limit()
+            .map(|moves| Evaluation::new_checkmate(2 * moves.min(MAX_PLY / 2) as i16) - 1);
         move || {
             let mut nodes = 0;
             local_context.reset_nodes();
             local_context.stm = position.board().side_to_move();
             let start_time = Instant::now();
             let mut best_move = None;
+            let mut previous_best_move = None;
+            let mut ponder_move = None;
             let mut eval: Option<Evaluation> = None;
-            let mut depth = 1_u32;
+            /*
+            Helpers may skip the first iterations, a thread can only stop once it completed
+            its own first iteration so it always has a move to vote for
+            */
+            let start_depth = shared_context.get_params().start_depth(thread);
+            let mut depth = start_depth;
             let mut abort = false;
+            let mut fail_highs = 0;
+            let mut hash_nearly_full = false;
+            /*
+            With MultiPV, every iteration searches the root once per line,
+            excluding the root moves of the lines that were already found
+            */
+            let mut line_evals: Vec<Option<Evaluation>> = vec![None; multi_pv];
+            /*
+            Scores shown to the GUI, smoothed across reports when ScoreSmoothing is set
+            */
+            let mut reported_evals: Vec<Option<Evaluation>> = vec![None; multi_pv];
             'outer: loop {
-                let mut fail_cnt = 0;
-                local_context.window.reset();
-                loop {
-                    if abort {
-                        break 'outer;
-                    }
-                    let (alpha, beta) = if eval.is_some()
-                        && eval.unwrap().raw().abs() < 1000
-                        && depth > 4
-                        && fail_cnt < 10
-                    {
-                        local_context.window.get()
-                    } else {
-                        (Evaluation::min(), Evaluation::max())
-                    };
-                    local_context.sel_depth = 0;
-                    let score = search::search::<Pv>(
-                        &mut position,
-                        &mut local_context,
-                        &shared_context,
-                        0,
-                        depth,
-                        alpha,
-                        beta,
-                    );
-                    nodes = local_context.nodes();
-                    if depth > 1 && local_context.abort() {
-                        break 'outer;
+                local_context.root_exclusions.clear();
+                local_context.tt_hits = 0;
+                local_context.tt_misses = 0;
+                local_context.prune_stats = PruneStats::default();
+                let mut iteration_fail_highs = 0;
+                let mut iteration_fail_lows = 0;
+                for (multi_pv_index, line_eval) in line_evals.iter_mut().enumerate() {
+                    let mut fail_cnt = 0;
+                    let prev_eval = *line_eval;
+                    if let Some(prev_eval) = prev_eval {
+                        local_context.window.set(prev_eval);
                     }
-                    local_context.window.set(score);
-                    local_context.eval = score;
+                    let window = shared_context.get_window_lookup().get(depth as usize, 0);
+                    local_context
+                        .window
+                        .reset(shared_context.get_params().thread_window(window, thread));
+                    loop {
+                        if abort {
+                            break 'outer;
+                        }
+                        let (alpha, beta) =
+                            if let (Some(mate_alpha), true) = (mate_alpha, depth > start_depth) {
+                                (mate_alpha, Evaluation::max())
+                            } else if prev_eval.is_some()
+                                && prev_eval.unwrap().raw().abs() < 1000
+                                && depth > 4
+                                && fail_cnt < 10
+                            {
+                                local_context.window.get()
+                            } else {
+                                (Evaluation::min(), Evaluation::max())
+                            };
+                        local_context.sel_depth = 0;
+                        let score = search::search::<Pv>(
+                            &mut position,
+                            &mut local_context,
+                            &shared_context,
+                            0,
+                            depth,
+                            alpha,
+                            beta,
+                            false,
+                        );
+                        nodes = local_context.nodes();
+                        if depth > start_depth && local_context.abort() {
+                            break 'outer;
+                        }
+                        if mate_alpha.is_some() && depth > start_depth && score <= alpha {
+                            abort = shared_context.abort_deepening(depth, nodes);
+                            break;
+                        }
+                        local_context.window.set(score);
+                        if multi_pv_index == 0 {
+                            local_context.eval = score;
 
-                    shared_context.time_manager.deepen(
-                        thread,
-                        depth,
-                        nodes,
-                        local_context.eval,
-                        local_context.search_stack[0].pv[0].unwrap(),
-                        search_start.elapsed(),
-                    );
-                    abort = shared_context.abort_deepening(depth, nodes);
-                    if (score > alpha && score < beta) || score.is_mate() {
-                        best_move = local_context.search_stack[0].pv[0];
-                        eval = Some(score);
-                        break;
-                    } else {
-                        fail_cnt += 1;
-                        if score <= alpha {
-                            local_context.window.fail_low();
+                            let root_stack = &local_context.search_stack[0];
+                            let tension = if main_thread {
+                                Tension::of_line(
+                                    position.board(),
+                                    &root_stack.pv[..root_stack.pv_len],
+                                )
+                            } else {
+                                Tension::default()
+                            };
+                            shared_context.time_manager.deepen(
+                                thread,
+                                depth,
+                                nodes,
+                                local_context.eval,
+                                root_stack.pv[0].unwrap(),
+                                tension,
+                                search_start.elapsed(),
+                            );
+                        }
+                        abort = shared_context.abort_deepening(depth, nodes);
+                        if (score > alpha && score < beta) || score.is_mate() {
+                            if multi_pv_index == 0 {
+                                let root_stack = &local_context.search_stack[0];
+                                best_move = root_stack.pv[0];
+                                ponder_move = if root_stack.pv_len > 1 {
+                                    root_stack.pv[1]
+                                } else {
+                                    None
+                                };
+                                eval = Some(score);
+                            }
+                            *line_eval = Some(score);
+                            break;
                         } else {
-                            local_context.window.fail_high();
+                            fail_cnt += 1;
+                            let window = shared_context.get_params().thread_window(
+                                shared_context
+                                    .get_window_lookup()
+                                    .get(depth as usize, fail_cnt),
+                                thread,
+                            );
+                            if score <= alpha {
+                                local_context.window.fail_low(window);
+                                iteration_fail_lows += 1;
+                            } else {
+                                local_context.window.fail_high(window);
+                                fail_highs += 1;
+                                iteration_fail_highs += 1;
+                                if main_thread {
+                                    let fail_move = local_context.search_stack[0].pv[0];
+                                    for observer in shared_context.observers.iter() {
+                                        observer.on_fail_high(depth, fail_move, score);
+                                    }
+                                }
+                            }
+                            /*
+                            Long searches report bound scores so GUIs can show progress
+                            */
+                            let root_stack = &local_context.search_stack[0];
+                            if main_thread
+                                && root_stack.pv_len > 0
+                                && start_time.elapsed() >= VERBOSE_INFO_DELAY
+                            {
+                                let bound = if score <= alpha {
+                                    ScoreBound::Upper
+                                } else {
+                                    ScoreBound::Lower
+                                };
+                                let pv = uci_pv(
+                                    &mut position,
+                                    &root_stack.pv[..root_stack.pv_len],
+                                    depth,
+                                    score,
+                                    chess960,
+                                    pv_extension.then_some(&*shared_context.t_table),
+                                );
+                                let total_nodes = node_counter
+                                    .as_ref()
+                                    .unwrap()
+                                    .get_node_count(local_context.unflushed_nodes());
+                                let reported =
+                                    score.smoothed(reported_evals[multi_pv_index], score_smoothing);
+                                reported_evals[multi_pv_index] = Some(reported);
+                                gui_info.print_info(
+                                    local_context.sel_depth,
+                                    depth,
+                                    multi_pv_index + 1,
+                                    reported,
+                                    bound,
+                                    game_ply.map(|ply| wdl::wdl(reported, ply)),
+                                    start_time.elapsed(),
+                                    total_nodes,
+                                    shared_context.tb_hits(),
+                                    shared_context.t_table.hash_full(),
+                                    &pv,
+                                );
+                            }
                         }
                     }
+                    let root_stack = &local_context.search_stack[0];
+                    if root_stack.pv_len == 0 {
+                        break;
+                    }
+                    if let Some(line_move) = root_stack.pv[0] {
+                        local_context.root_exclusions.push(line_move);
+                    }
+                    if main_thread {
+                        let pv = uci_pv(
+                            &mut position,
+                            &root_stack.pv[..root_stack.pv_len],
+                            depth,
+                            line_eval.unwrap(),
+                            chess960,
+                            pv_extension.then_some(&*shared_context.t_table),
+                        );
+                        let total_nodes = node_counter
+                            .as_ref()
+                            .unwrap()
+                            .get_node_count(local_context.unflushed_nodes());
+                        let reported = line_eval
+                            .unwrap()
+                            .smoothed(reported_evals[multi_pv_index], score_smoothing);
+                        reported_evals[multi_pv_index] = Some(reported);
+                        gui_info.print_info(
+                            local_context.sel_depth,
+                            depth,
+                            multi_pv_index + 1,
+                            reported,
+                            ScoreBound::Exact,
+                            game_ply.map(|ply| wdl::wdl(reported, ply)),
+                            start_time.elapsed(),
+                            total_nodes,
+                            shared_context.tb_hits(),
+                            shared_context.t_table.hash_full(),
+                            &pv,
+                        );
+                    }
                 }
                 if main_thread {
+                    hash_nearly_full |= shared_context.t_table.update_replacement();
+                    let stats = IterationStats {
+                        tt_hits: local_context.tt_hits,
+                        tt_misses: local_context.tt_misses,
+                        fail_highs: iteration_fail_highs,
+                        fail_lows: iteration_fail_lows,
+                        prunes: local_context.prune_stats,
+                        best_move,
+                        previous_best_move,
+                    };
+                    previous_best_move = best_move;
+                    for observer in shared_context.observers.iter() {
+                        observer.on_iteration_stats(depth, &stats);
+                    }
+                    if let Some(eval) = eval {
+                        for observer in shared_context.observers.iter() {
+                            observer.on_depth_complete(depth, eval, nodes, start_time.elapsed());
+                        }
+                    }
                     debugger.push(SearchStats::new(
                         start_time.elapsed().as_millis(),
                         depth,
                         eval,
                         best_move,
                     ));
-
-                    let mut pv = vec![];
-                    let root_stack = &local_context.search_stack[0];
-                    for make_move in &root_stack.pv[..root_stack.pv_len] {
-                        if let Some(make_move) = *make_move {
-                            let mut uci_move = make_move;
-                            uci::convert_move_to_uci(&mut uci_move, position.board(), chess960);
-                            position.make_move(make_move);
-                            pv.push(uci_move);
-                            if pv.len() > depth as usize {
-                                break;
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                    for _ in 0..pv.len() {
-                        position.unmake_move()
-                    }
-                    let total_nodes = node_counter.as_ref().unwrap().get_node_count();
-                    gui_info.print_info(
-                        local_context.sel_depth,
-                        depth,
-                        eval.unwrap(),
-                        start_time.elapsed(),
-                        total_nodes,
-                        &pv,
-                    );
                 }
 
                 depth += 1;
-                if depth > 1 && shared_context.abort_deepening(depth, nodes) {
+                if depth > start_depth && shared_context.abort_deepening(depth, nodes) {
                     break 'outer;
                 }
             }
-            if let Some(evaluation) = eval {
+            local_context.flush_nodes();
+            if hash_nearly_full {
+                gui_info.print_hash_warning(shared_context.t_table.hash_full());
+            }
+            /*
+            A thread stopped before it completed an iteration has no move to vote for
+            */
+            if eval.is_some() {
                 debugger.complete();
-                (best_move, evaluation, depth, nodes)
-            } else {
-                panic!("# Search function has failed to evaluate the position");
             }
+            (
+                best_move,
+                ponder_move,
+                eval,
+                depth,
+                nodes,
+                fail_highs,
+                Worker {
+                    local_context,
+                    position,
+                },
+            )
         }
     }
 
+    /*
+    Searches check the stop flag at every node and the clock only every few nodes
+    The thread exits once the search is out of time or the flag is set after the search ends
+    */
+    fn start_timer(&self) -> JoinHandle<()> {
+        let stop = self.shared_context.stop.clone();
+        let time_manager = self.shared_context.time_manager.clone();
+        let start = self.shared_context.start;
+        stop.store(false, Ordering::Relaxed);
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                if time_manager.abort_search(start) {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+                std::thread::sleep(TIMER_INTERVAL);
+            }
+        })
+    }
+
     pub fn new(board: Board, time_manager: Arc<TimeManager>) -> Self {
         let mut position = Position::new(board);
+        let params = SearchParams::new();
+        let eval_params = params.eval_params();
         Self {
-            node_counter: NodeCounter {
-                node_counters: vec![],
-            },
+            node_counter: NodeCounter::default(),
             shared_context: SharedContext {
                 time_manager,
-                t_table: Arc::new(TranspositionTable::new(2_usize.pow(20))),
-                lmr_lookup: Arc::new(LookUp2d::new(|depth, mv| {
-                    if depth == 0 || mv == 0 {
-                        0
-                    } else {
-                        (2.0 + (depth as f32).ln() * (mv as f32).ln() / 1.75) as u32
-                    }
-                })),
-                lmp_lookup: Arc::new(LookUp2d::new(|depth, improving| {
-                    let mut x = 3.0 + depth as f32 * depth as f32;
-                    if improving == 0 {
-                        x /= 2.0;
-                    }
-                    x as usize
-                })),
+                stop: Arc::new(AtomicBool::new(false)),
+                t_table: Arc::new(TranspositionTable::new(2_usize.pow(20), 1)),
+                search_moves: Arc::new(vec![]),
+                eval_noise: EvalNoise::none(),
+                mate_search: false,
+                analyse_mode: false,
+                contempt: 0,
+                tablebase: Arc::new(Tablebase::new()),
+                tb_hits: Arc::new(AtomicU64::new(0)),
+                observers: Observers::default(),
+                lmr_lookup: Arc::new(lmr_lookup(&params)),
+                lmp_lookup: Arc::new(lmp_lookup(&params)),
+                q_lmp_lookup: Arc::new(q_lmp_lookup(&params)),
+                window_lookup: Arc::new(window_lookup(&params)),
+                params: Arc::new(params),
                 start: Instant::now(),
             },
             local_context: LocalContext {
-                window: Window::new(25, 1, 4, 5),
+                window: Window::new(),
                 tt_hits: 0,
                 tt_misses: 0,
-                eval: position.get_eval(Color::White, Evaluation::new(0)),
+                prune_stats: PruneStats::default(),
+                eval: position.get_eval(Color::White, Evaluation::new(0), &eval_params),
                 search_stack: vec![
                     SearchStack {
                         eval: Evaluation::new(0),
                         skip_move: None,
                         move_played: None,
+                        threat_move: None,
                         pv: [None; MAX_PLY as usize + 1],
                         pv_len: 0,
+                        extensions: 0,
                     };
                     MAX_PLY as usize + 1
                 ],
                 sel_depth: 0,
+                root_depth: 0,
                 h_table: HistoryTable::new(),
                 ch_table: HistoryTable::new(),
+                lc_table: LosingCaptureHistory::new(),
                 cm_table: CounterMoveTable::new(),
                 cm_hist: DoubleMoveHistory::new(),
                 killer_moves: vec![],
-                nodes: Nodes(Arc::new(AtomicU64::new(0))),
+                root_exclusions: vec![],
+                root_reporter: None,
+                heartbeat: None,
+                nodes: Nodes::default(),
+                thread: 0,
                 abort: false,
                 stm: Color::White,
             },
             position,
+            ponder_move: None,
+            fail_highs: 0,
+            multi_pv: 1,
+            show_wdl: false,
+            score_smoothing: 0,
+            pv_extension: true,
             chess960: false,
+            stack_size: DEFAULT_STACK_MB * 1024 * 1024,
+            threads: 1,
+            workers: vec![],
         }
     }
 
-    pub fn search<SM: 'static + SearchMode + Send, Info: 'static + GuiInfo + Send>(
+    pub fn search<SM: 'static + SearchMode + Send, Info: 'static + GuiInfo + Send + Sync>(
+        &mut self,
+    ) -> (Move, Evaluation, u32, u64) {
+        let result = self.search_position::<SM, Info>();
+        self.shared_context.t_table.age();
+        result
+    }
+
+    /*
+    Search without aging the TT afterwards
+    */
+    fn search_position<SM: 'static + SearchMode + Send, Info: 'static + GuiInfo + Send + Sync>(
         &mut self,
-        threads: u8,
     ) -> (Move, Evaluation, u32, u64) {
+        let threads = self.threads;
         let mut join_handlers = vec![];
         let search_start = Instant::now();
         self.shared_context.start = Instant::now();
-        self.node_counter.initialize_node_counters(threads as usize);
+        self.shared_context.mate_search = self.shared_context.time_manager.mate_limit().is_some();
+        self.shared_context.tb_hits.store(0, Ordering::Relaxed);
+        /*
+        In tablebase positions, only root moves that preserve the tablebase result are searched
+        */
+        let search_moves = self.shared_context.search_moves.clone();
+        let tablebase = self.shared_context.tablebase.clone();
+        if let Some(tb_moves) = tablebase.root_moves(&mut self.position) {
+            self.shared_context
+                .tb_hits
+                .fetch_add(tb_moves.len() as u64, Ordering::Relaxed);
+            let tb_moves = tb_moves
+                .into_iter()
+                .filter(|make_move| search_moves.is_empty() || search_moves.contains(make_move))
+                .collect::<Vec<_>>();
+            if !tb_moves.is_empty() {
+                self.shared_context.search_moves = Arc::new(tb_moves);
+            }
+        }
+        self.local_context
+            .age_history(self.shared_context.params.history_age_div);
+        self.node_counter.initialize_node_counters(threads);
+        self.workers.resize_with(threads, || None);
         //TODO: Research the effects of different depths
         self.position.reset();
+        let timer = self.start_timer();
         for i in 1..threads {
-            join_handlers.push(std::thread::spawn(self.launch_searcher::<SM, NoInfo>(
-                search_start,
-                i,
-                self.chess960,
-            )));
+            let searcher = self.launch_searcher::<SM, NoInfo>(search_start, i, self.chess960);
+            join_handlers.push(
+                std::thread::Builder::new()
+                    .stack_size(self.stack_size)
+                    .spawn(searcher)
+                    .unwrap(),
+            );
         }
-        let (final_move, final_eval, max_depth, mut node_count) =
+        let (final_move, ponder_move, final_eval, max_depth, _, fail_highs, mut searched) =
             self.launch_searcher::<SM, Info>(search_start, 0, self.chess960)();
-        for join_handler in join_handlers {
-            let (_, _, _, nodes) = join_handler.join().unwrap();
-            node_count += nodes;
+        let mut votes = vec![];
+        if let (Some(best_move), Some(eval)) = (final_move, final_eval) {
+            votes.push(ThreadVote {
+                best_move,
+                ponder_move,
+                eval,
+                depth: max_depth,
+                sel_depth: searched.local_context.sel_depth,
+            });
         }
-        if final_move.is_none() {
+        self.local_context.keep_history(&mut searched.local_context);
+        self.workers[0] = Some(searched);
+        /*
+        A helper that panicked only loses its own work, the main thread's result is still used
+        */
+        for (thread, join_handler) in (1..threads).zip(join_handlers) {
+            match join_handler.join() {
+                Ok((best_move, ponder_move, eval, depth, _, _, helper)) => {
+                    if let (Some(best_move), Some(eval)) = (best_move, eval) {
+                        votes.push(ThreadVote {
+                            best_move,
+                            ponder_move,
+                            eval,
+                            depth,
+                            sel_depth: helper.local_context.sel_depth,
+                        });
+                    }
+                    self.workers[thread] = Some(helper);
+                }
+                Err(payload) => {
+                    let nodes = self.node_counter.get_thread_node_count(thread);
+                    println!(
+                        "info string helper thread {} panicked after {} nodes: {} (position {})",
+                        thread,
+                        nodes,
+                        panic_message(payload.as_ref()),
+                        self.position.board()
+                    );
+                }
+            }
+        }
+        self.shared_context.stop.store(true, Ordering::Relaxed);
+        timer.join().unwrap();
+        /*
+        The tablebase restriction only lasts for this search,
+        it is lifted as soon as every searcher is done so no exit path keeps it
+        */
+        self.shared_context.search_moves = search_moves;
+        /*
+        Every thread flushed its last batch when it stopped
+        */
+        let node_count = self.node_counter.get_node_count(0);
+        /*
+        With helpers, the move is voted on and reported with the deepest depth any thread reached
+        */
+        let (searched_move, ponder_move, final_eval, max_depth) = if votes.len() > 1 {
+            let voted = votes[vote(&votes)];
+            let max_depth = votes.iter().map(|vote| vote.depth).max().unwrap();
+            let sel_depth = votes.iter().map(|vote| vote.sel_depth).max().unwrap();
+            let mut pv = vec![];
+            let mut pv_board = self.position.board().clone();
+            for make_move in [Some(voted.best_move), voted.ponder_move]
+                .into_iter()
+                .map_while(|make_move| make_move)
+            {
+                if !pv_board.is_legal(make_move) {
+                    break;
+                }
+                let mut uci_move = make_move;
+                uci::convert_move_to_uci(&mut uci_move, &pv_board, self.chess960);
+                pv_board.play_unchecked(make_move);
+                pv.push(uci_move);
+            }
+            let game_ply = self.show_wdl.then(|| wdl::game_ply(self.position.board()));
+            /*
+            Threads stop at the depth after the last one they completed
+            */
+            Info::new().print_info(
+                sel_depth,
+                max_depth.saturating_sub(1).max(1),
+                1,
+                voted.eval,
+                ScoreBound::Exact,
+                game_ply.map(|ply| wdl::wdl(voted.eval, ply)),
+                search_start.elapsed(),
+                node_count,
+                self.shared_context.tb_hits(),
+                self.shared_context.t_table.hash_full(),
+                &pv,
+            );
+            (voted.best_move, voted.ponder_move, voted.eval, max_depth)
+        } else if let Some(vote) = votes.first() {
+            (vote.best_move, vote.ponder_move, vote.eval, vote.depth)
+        } else {
             panic!("# All move generation has failed");
+        };
+        let final_move = self.safe_root_move(searched_move, final_eval);
+        let ponder_move = ponder_move.filter(|_| final_move == searched_move);
+        /*
+        The ponder move is the second move of the PV,
+        it is only kept if it's legal after the best move is played
+        */
+        let mut ponder_board = self.position.board().clone();
+        ponder_board.play_unchecked(final_move);
+        self.ponder_move = ponder_move.filter(|&ponder_move| ponder_board.is_legal(ponder_move));
+        self.fail_highs = fail_highs;
+        for observer in self.shared_context.observers.iter() {
+            observer.on_bestmove(final_move, self.ponder_move, final_eval);
         }
-        self.shared_context.t_table.age();
-        (final_move.unwrap(), final_eval, max_depth, node_count)
+        (final_move, final_eval, max_depth, node_count)
+    }
+
+    /*
+    Last guard before a move is played: an illegal move or a threefold repetition
+    in a clearly winning position is replaced unless there is no alternative
+    Replacements are picked by their TT score where the entry after the move bounds it from below,
+    ties and moves without such an entry are ordered by the main thread's history
+    */
+    fn safe_root_move(&self, best_move: Move, eval: Evaluation) -> Move {
+        let board = self.position.board();
+        let winning = eval.raw() > WINNING_EVAL;
+        if board.is_legal(best_move) && !(winning && self.position.threefold_after(best_move)) {
+            return best_move;
+        }
+        let mut moves = vec![];
+        board.generate_moves(|piece_moves| {
+            moves.extend(piece_moves);
+            false
+        });
+        let t_table = &self.shared_context.t_table;
+        let stm = board.side_to_move();
+        let child_score = |make_move: Move| {
+            let mut child = board.clone();
+            child.play_unchecked(make_move);
+            /*
+            Upper bounds of the opponent are lower bounds for us
+            */
+            t_table
+                .get(&child, 1)
+                .filter(|entry| entry.entry_type() != EntryType::LowerBound)
+                .map(|entry| -entry.score())
+        };
+        let history = |make_move: Move| {
+            let h_table = if board.colors(!stm).has(make_move.to) {
+                self.local_context.get_ch_table()
+            } else {
+                self.local_context.get_h_table()
+            };
+            h_table.get(stm, make_move.from, make_move.to)
+        };
+        moves
+            .iter()
+            .copied()
+            .filter(|&make_move| !winning || !self.position.threefold_after(make_move))
+            .max_by_key(|&make_move| (child_score(make_move), history(make_move)))
+            .or_else(|| Some(best_move).filter(|&make_move| board.is_legal(make_move)))
+            .or_else(|| moves.first().copied())
+            .unwrap_or(best_move)
+    }
+
+    /*
+    Single threaded search of the position after the given moves that only fills the TT,
+    the current position, ponder move and observers are left as they were
+    */
+    pub fn warm_up<SM: 'static + SearchMode + Send>(&mut self, moves: &[Move]) {
+        let position = self.position.clone();
+        let ponder_move = self.ponder_move;
+        let multi_pv = std::mem::replace(&mut self.multi_pv, 1);
+        let observers = std::mem::take(&mut self.shared_context.observers);
+        for &make_move in moves {
+            self.position.make_move(make_move);
+        }
+        if self.position.board().status() == GameStatus::Ongoing {
+            let threads = std::mem::replace(&mut self.threads, 1);
+            /*
+            The TT isn't aged, the warm-up belongs to the search that follows it
+            */
+            self.search_position::<SM, NoInfo>();
+            self.threads = threads;
+        }
+        self.shared_context.observers = observers;
+        self.multi_pv = multi_pv;
+        self.ponder_move = ponder_move;
+        self.position = position;
     }
 
     pub fn hash(&mut self, hash_mb: usize) {
         let entry_count = hash_mb * 65536;
-        self.shared_context.t_table = Arc::new(TranspositionTable::new(entry_count));
+        let diagnostics = self.shared_context.t_table.diagnostics();
+        self.shared_context.t_table = Arc::new(TranspositionTable::new(entry_count, self.threads));
+        self.shared_context.t_table.set_diagnostics(diagnostics);
+        for observer in self.shared_context.observers.iter() {
+            observer.on_tt_resize(entry_count);
+        }
+    }
+
+    pub fn reload_network(&mut self) {
+        self.position.reload_network();
     }
 
     pub fn raw_eval(&mut self) -> Evaluation {
-        self.position.get_eval(Color::White, Evaluation::new(0))
+        let eval_params = self.shared_context.params.eval_params();
+        self.position
+            .get_eval(Color::White, Evaluation::new(0), &eval_params)
+    }
+
+    pub fn eval_perspectives(&mut self) -> Perspectives {
+        self.position.eval_perspectives()
+    }
+
+    pub fn eval_breakdown(&mut self) -> (EvalTerms, NnBreakdown) {
+        let eval_params = self.shared_context.params.eval_params();
+        self.position.eval_breakdown(&eval_params)
+    }
+
+    pub fn new_game(&mut self) {
+        self.shared_context.t_table.clean(self.threads);
+        self.clear_history();
     }
 
-    pub fn new_game(&self) {
-        self.shared_context.t_table.clean();
+    /*
+    Per-game state that is reset even when the TT is kept
+    */
+    pub fn clear_history(&mut self) {
+        self.local_context.clear_history();
     }
 
     pub fn set_board(&mut self, board: Board) {
         self.position = Position::new(board);
+        self.shared_context.search_moves = Arc::new(vec![]);
     }
 
     pub fn make_move(&mut self, make_move: Move) {
         self.position.make_move(make_move);
         self.position.reset();
+        self.shared_context.search_moves = Arc::new(vec![]);
+    }
+
+    pub fn set_search_moves(&mut self, search_moves: Vec<Move>) {
+        let board = self.position.board();
+        let search_moves = search_moves
+            .into_iter()
+            .filter(|&make_move| board.is_legal(make_move))
+            .collect::<Vec<_>>();
+        self.shared_context.search_moves = Arc::new(search_moves);
+    }
+
+    pub fn get_search_moves(&self) -> &[Move] {
+        &self.shared_context.search_moves
     }
 
-    #[cfg(feature = "data")]
     pub fn get_position(&self) -> &Position {
         &self.position
     }
 
+    #[cfg(feature = "data")]
+    pub fn get_fail_highs(&self) -> u32 {
+        self.fail_highs
+    }
+
+    pub fn get_ponder_move(&self) -> Option<Move> {
+        self.ponder_move
+    }
+
+    pub fn get_t_table(&self) -> &TranspositionTable {
+        &self.shared_context.t_table
+    }
+
     pub fn get_board(&self) -> &Board {
         self.position.board()
     }
 
+    pub fn get_params(&self) -> &SearchParams {
+        &self.shared_context.params
+    }
+
+    pub fn set_params(&mut self, params: SearchParams) {
+        self.shared_context.lmr_lookup = Arc::new(lmr_lookup(&params));
+        self.shared_context.lmp_lookup = Arc::new(lmp_lookup(&params));
+        self.shared_context.q_lmp_lookup = Arc::new(q_lmp_lookup(&params));
+        self.shared_context.window_lookup = Arc::new(window_lookup(&params));
+        self.shared_context.time_manager.set_params(&params);
+        self.shared_context.params = Arc::new(params);
+        /*
+        Killer entries are sized when they're created
+        */
+        self.local_context.killer_moves.clear();
+    }
+
+    pub fn set_multi_pv(&mut self, multi_pv: usize) {
+        self.multi_pv = multi_pv;
+    }
+
+    pub fn set_show_wdl(&mut self, show_wdl: bool) {
+        self.show_wdl = show_wdl;
+    }
+
+    pub fn set_score_smoothing(&mut self, score_smoothing: i16) {
+        self.score_smoothing = score_smoothing;
+    }
+
+    pub fn set_pv_extension(&mut self, pv_extension: bool) {
+        self.pv_extension = pv_extension;
+    }
+
+    pub fn set_eval_noise(&mut self, eval_noise: EvalNoise) {
+        self.shared_context.eval_noise = eval_noise;
+    }
+
+    pub fn add_observer(&mut self, observer: Arc<dyn SearchObserver>) {
+        self.shared_context.observers.add(observer);
+    }
+
+    pub fn set_tablebase(&mut self, tablebase: Tablebase) {
+        self.shared_context.tablebase = Arc::new(tablebase);
+    }
+
+    pub fn set_stack_size(&mut self, stack_mb: usize) {
+        self.stack_size = stack_mb * 1024 * 1024;
+    }
+
+    /*
+    Threads searching in parallel, also used to zero the transposition table
+    */
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, MAX_THREADS);
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn get_stack_size(&self) -> usize {
+        self.stack_size
+    }
+
     pub fn set_chess960(&mut self, chess960: bool) {
         self.chess960 = chess960;
     }
+
+    /*
+    In analyse mode, mating lines are searched out in full instead of
+    being cut short by TT mate scores so the whole line can be shown
+    */
+    pub fn set_analyse_mode(&mut self, analyse_mode: bool) {
+        self.shared_context.analyse_mode = analyse_mode;
+    }
+
+    pub fn set_contempt(&mut self, contempt: i16) {
+        self.shared_context.contempt = contempt;
+    }
+}
+
+#[test]
+fn thread_votes() {
+    let thread = |best_move: &str, eval: i16, depth: u32| ThreadVote {
+        best_move: best_move.parse().unwrap(),
+        ponder_move: None,
+        eval: Evaluation::new(eval),
+        depth,
+        sel_depth: depth,
+    };
+    assert_eq!(vote(&[thread("e2e4", 30, 12)]), 0);
+    /*
+    Two helpers agreeing outvote a slightly deeper main thread
+    */
+    let votes = [
+        thread("e2e4", 30, 13),
+        thread("d2d4", 25, 12),
+        thread("d2d4", 28, 12),
+    ];
+    assert_eq!(votes[vote(&votes)].best_move, "d2d4".parse().unwrap());
+    /*
+    A mate decides even when the other threads agree
+    */
+    let mut votes = votes;
+    votes[0].eval = Evaluation::new_checkmate(7);
+    assert_eq!(vote(&votes), 0);
+    votes[2].eval = Evaluation::new_checkmate(5);
+    assert_eq!(vote(&votes), 2);
+}
+
+#[test]
+fn pv_from_line_and_tt() {
+    use crate::bm::basic_eval::BasicEval;
+
+    let moves = |moves: &[&str]| {
+        moves
+            .iter()
+            .map(|make_move| make_move.parse::<Move>().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let line = |line: &[&str]| moves(line).into_iter().map(Some).collect::<Vec<_>>();
+    let mut position = Position::<BasicEval>::with_evaluator(Board::default());
+    let hash = position.hash();
+    let t_table = TranspositionTable::new(1 << 16, 1);
+    let mut pv = |line: &[Option<Move>], depth: u32, t_table: Option<&TranspositionTable>| {
+        uci_pv(
+            &mut position,
+            line,
+            depth,
+            Evaluation::new(0),
+            false,
+            t_table,
+        )
+    };
+
+    /*
+    The line is cut at an illegal move, like one from a hash collision, and at the depth
+    */
+    assert_eq!(
+        pv(&line(&["e2e4", "e7e5", "e2e4", "g1f3"]), 10, None),
+        moves(&["e2e4", "e7e5"])
+    );
+    assert_eq!(
+        pv(&line(&["e2e4", "e7e5", "g1f3", "b8c6"]), 1, None),
+        moves(&["e2e4", "e7e5"])
+    );
+
+    /*
+    A line cut short by a TT cutoff is extended with TT moves up to an illegal TT move
+    */
+    let store = |line: &[&str]| {
+        let mut board = Board::default();
+        for make_move in moves(line) {
+            t_table.set(
+                &board,
+                0,
+                4,
+                EntryType::Exact,
+                Evaluation::new(0),
+                None,
+                make_move,
+            );
+            board.play_unchecked(make_move);
+        }
+    };
+    store(&["e2e4", "e7e5", "g1f3"]);
+    let mut board = Board::default();
+    for make_move in moves(&["e2e4", "e7e5", "g1f3"]) {
+        board.play_unchecked(make_move);
+    }
+    t_table.set(
+        &board,
+        0,
+        4,
+        EntryType::Exact,
+        Evaluation::new(0),
+        None,
+        "e5e4".parse().unwrap(),
+    );
+    assert_eq!(
+        pv(&line(&["e2e4"]), 10, Some(&t_table)),
+        moves(&["e2e4", "e7e5", "g1f3"])
+    );
+    assert_eq!(pv(&line(&["e2e4"]), 10, None), moves(&["e2e4"]));
+
+    /*
+    TT moves stop once they repeat a position
+    */
+    store(&["g1f3", "g8f6", "f3g1", "f6g8"]);
+    assert_eq!(
+        pv(&[], 20, Some(&t_table)),
+        moves(&["g1f3", "g8f6", "f3g1", "f6g8"])
+    );
+    assert_eq!(position.hash(), hash);
+}
+
+#[test]
+fn node_limit() {
+    use super::config::Run;
+    use super::time::TimeManagementInfo;
+
+    let time_manager = Arc::new(TimeManager::new());
+    let mut runner = AbRunner::new(Board::default(), time_manager.clone());
+    for max_nodes in [3000, 20000, 100000] {
+        time_manager.initiate(
+            runner.get_board(),
+            &[TimeManagementInfo::MaxNodes(max_nodes)],
+        );
+        let (_, _, _, nodes) = runner.search::<Run, NoInfo>();
+        assert!(
+            nodes <= max_nodes + NODE_BATCH,
+            "{} nodes searched with a limit of {}",
+            nodes,
+            max_nodes
+        );
+    }
+}
+
+#[test]
+fn pv_after_tt_cutoff() {
+    use super::config::{Run, ScoreBound};
+    use super::time::TimeManagementInfo;
+    use std::sync::Mutex;
+
+    static LAST_PV: Mutex<Vec<Move>> = Mutex::new(vec![]);
+
+    struct LastPv;
+
+    impl GuiInfo for LastPv {
+        fn new() -> Self {
+            Self
+        }
+
+        fn print_info(
+            &self,
+            _: u32,
+            _: u32,
+            _: usize,
+            _: Evaluation,
+            bound: ScoreBound,
+            _: Option<(u32, u32, u32)>,
+            _: Duration,
+            _: u64,
+            _: u64,
+            _: u32,
+            pv: &[Move],
+        ) {
+            if matches!(bound, ScoreBound::Exact) {
+                *LAST_PV.lock().unwrap() = pv.to_vec();
+            }
+        }
+
+        fn print_curr_move(&self, _: u32, _: Move, _: usize) {}
+
+        fn print_hash_warning(&self, _: u32) {}
+
+        fn print_heartbeat(&self, _: Duration, _: u64, _: u64, _: u32) {}
+    }
+
+    /*
+    A forced mate, later searches cut the PV nodes below the root on the mate scores
+    stored by the first, so only the TT extension can complete their PV
+    */
+    let board: Board = "k7/8/2K5/8/8/8/8/7R w - - 0 1".parse().unwrap();
+    let time_manager = Arc::new(TimeManager::new());
+    let mut runner = AbRunner::new(board.clone(), time_manager.clone());
+    let search = |runner: &mut AbRunner| {
+        time_manager.initiate(&board, &[TimeManagementInfo::MaxDepth(12)]);
+        let (_, eval, _, _) = runner.search::<Run, LastPv>();
+        (eval.mate_plies().unwrap(), LAST_PV.lock().unwrap().clone())
+    };
+    runner.set_pv_extension(false);
+    let (_, full) = search(&mut runner);
+    let (_, cut) = search(&mut runner);
+    assert!(cut.len() < full.len(), "{:?} wasn't cut", cut);
+
+    runner.set_pv_extension(true);
+    let (mate_plies, pv) = search(&mut runner);
+    assert_eq!(pv.len(), mate_plies as usize);
+    let mut child = board.clone();
+    for make_move in pv {
+        child.play(make_move);
+    }
+    assert_eq!(child.status(), GameStatus::Won);
+}
+
+#[test]
+fn info_nodes_before_first_batch() {
+    use super::config::{Run, ScoreBound};
+    use super::time::TimeManagementInfo;
+    use std::sync::Mutex;
+
+    static INFO_NODES: Mutex<Vec<u64>> = Mutex::new(vec![]);
+
+    struct InfoNodes;
+
+    impl GuiInfo for InfoNodes {
+        fn new() -> Self {
+            Self
+        }
+
+        fn print_info(
+            &self,
+            _: u32,
+            _: u32,
+            _: usize,
+            _: Evaluation,
+            _: ScoreBound,
+            _: Option<(u32, u32, u32)>,
+            _: Duration,
+            nodes: u64,
+            _: u64,
+            _: u32,
+            _: &[Move],
+        ) {
+            INFO_NODES.lock().unwrap().push(nodes);
+        }
+
+        fn print_curr_move(&self, _: u32, _: Move, _: usize) {}
+
+        fn print_hash_warning(&self, _: u32) {}
+
+        fn print_heartbeat(&self, _: Duration, _: u64, _: u64, _: u32) {}
+    }
+
+    /*
+    The first iterations search fewer nodes than a batch, they are reported anyway
+    */
+    let time_manager = Arc::new(TimeManager::new());
+    let mut runner = AbRunner::new(Board::default(), time_manager.clone());
+    time_manager.initiate(runner.get_board(), &[TimeManagementInfo::MaxDepth(2)]);
+    let (_, _, _, searched) = runner.search::<Run, InfoNodes>();
+    let reported = INFO_NODES.lock().unwrap().clone();
+    assert!(searched < NODE_BATCH, "{} nodes searched", searched);
+    assert!(reported.iter().all(|&nodes| nodes > 0), "{:?}", reported);
+    assert!(
+        reported.windows(2).all(|pair| pair[0] <= pair[1]),
+        "{:?}",
+        reported
+    );
+    assert_eq!(*reported.last().unwrap(), searched);
+}
+
+#[test]
+fn short_multi_thread_search() {
+    use super::config::Run;
+    use super::time::TimeManagementInfo;
+
+    /*
+    Helpers starting deeper than the main thread are stopped during their first iteration
+    */
+    let time_manager = Arc::new(TimeManager::new());
+    let mut runner = AbRunner::new(Board::default(), time_manager.clone());
+    runner.set_threads(4);
+    for _ in 0..20 {
+        time_manager.initiate(
+            runner.get_board(),
+            &[TimeManagementInfo::MoveTime(Duration::from_millis(1))],
+        );
+        runner.search::<Run, NoInfo>();
+        assert!(
+            runner.workers.iter().all(Option::is_some),
+            "a helper panicked"
+        );
+    }
+}
+
+#[test]
+fn stop_multi_thread_search() {
+    use super::config::Run;
+    use super::time::TimeManagementInfo;
+
+    /*
+    Helpers stopped right away still return normally instead of panicking
+    */
+    let time_manager = Arc::new(TimeManager::new());
+    let mut runner = AbRunner::new(Board::default(), time_manager.clone());
+    runner.set_threads(2);
+    for _ in 0..20 {
+        time_manager.initiate(
+            runner.get_board(),
+            &[
+                TimeManagementInfo::WTime(Duration::from_secs(3)),
+                TimeManagementInfo::BTime(Duration::from_secs(3)),
+            ],
+        );
+        let stopper = {
+            let time_manager = time_manager.clone();
+            std::thread::spawn(move || time_manager.abort_now())
+        };
+        runner.search::<Run, NoInfo>();
+        stopper.join().unwrap();
+        assert!(
+            runner.workers.iter().all(Option::is_some),
+            "a helper panicked"
+        );
+    }
 }
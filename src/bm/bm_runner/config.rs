@@ -5,6 +5,7 @@ use std::fs::OpenOptions;
 use std::io::Write;
 use std::time::Duration;
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct SearchStats {
     delta_time: u128,
@@ -49,6 +50,7 @@ impl SearchMode for Run {
     fn complete(&mut self) {}
 }
 
+#[allow(dead_code)]
 pub struct Debug {
     fen: String,
     info: Vec<SearchStats>,
@@ -113,30 +115,54 @@ impl SearchMode for Debug {
     }
 }
 
+#[allow(dead_code)]
 fn format<T: Display>(value: T) -> String {
     let mut string = format!("{} ", value);
     extend(&mut string, 15);
     string
 }
 
+#[allow(dead_code)]
 fn extend(string: &mut String, len: usize) {
     if string.len() < len {
         string.push_str(&" ".repeat(len - string.len()));
     }
 }
 
+/*
+Whether a reported score is exact or only a bound from a failed aspiration search
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScoreBound {
+    Exact,
+    Lower,
+    Upper,
+}
+
 pub trait GuiInfo {
     fn new() -> Self;
 
+    #[allow(clippy::too_many_arguments)]
     fn print_info(
         &self,
         sel_depth: u32,
         depth: u32,
+        multi_pv: usize,
         eval: Evaluation,
+        bound: ScoreBound,
+        wdl: Option<(u32, u32, u32)>,
         elapsed: Duration,
         node_cnt: u64,
+        tb_hits: u64,
+        hash_full: u32,
         pv: &[Move],
     );
+
+    fn print_curr_move(&self, depth: u32, make_move: Move, move_number: usize);
+
+    fn print_hash_warning(&self, hash_full: u32);
+
+    fn print_heartbeat(&self, elapsed: Duration, node_cnt: u64, tb_hits: u64, hash_full: u32);
 }
 
 #[derive(Debug, Clone)]
@@ -147,7 +173,27 @@ impl GuiInfo for NoInfo {
         Self {}
     }
 
-    fn print_info(&self, _: u32, _: u32, _: Evaluation, _: Duration, _: u64, _: &[Move]) {}
+    fn print_info(
+        &self,
+        _: u32,
+        _: u32,
+        _: usize,
+        _: Evaluation,
+        _: ScoreBound,
+        _: Option<(u32, u32, u32)>,
+        _: Duration,
+        _: u64,
+        _: u64,
+        _: u32,
+        _: &[Move],
+    ) {
+    }
+
+    fn print_curr_move(&self, _: u32, _: Move, _: usize) {}
+
+    fn print_hash_warning(&self, _: u32) {}
+
+    fn print_heartbeat(&self, _: Duration, _: u64, _: u64, _: u32) {}
 }
 
 #[derive(Debug, Clone)]
@@ -158,13 +204,19 @@ impl GuiInfo for UciInfo {
         Self {}
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn print_info(
         &self,
         seldepth: u32,
         depth: u32,
+        multi_pv: usize,
         eval: Evaluation,
+        bound: ScoreBound,
+        wdl: Option<(u32, u32, u32)>,
         elapsed: Duration,
         node_cnt: u64,
+        tb_hits: u64,
+        hash_full: u32,
         pv: &[Move],
     ) {
         let eval_str = if eval.is_mate() {
@@ -172,20 +224,116 @@ impl GuiInfo for UciInfo {
         } else {
             format!("cp {}", eval.raw())
         };
+        let eval_str = match bound {
+            ScoreBound::Exact => eval_str,
+            ScoreBound::Lower => format!("{} lowerbound", eval_str),
+            ScoreBound::Upper => format!("{} upperbound", eval_str),
+        };
+        let eval_str = match wdl {
+            Some((win, draw, loss)) => format!("{} wdl {} {} {}", eval_str, win, draw, loss),
+            None => eval_str,
+        };
         let nps = (node_cnt as u128 * 1000) / elapsed.as_millis().max(1);
         let mut buffer = String::new();
         buffer += &format!(
-            "info depth {} seldepth {} score {} time {} nodes {} nps {} pv",
+            "info depth {} seldepth {} multipv {} score {} time {} nodes {} nps {} tbhits {} hashfull {} pv",
             depth,
             seldepth,
+            multi_pv,
             eval_str,
             elapsed.as_millis(),
             node_cnt,
-            nps
+            nps,
+            tb_hits,
+            hash_full
+        );
+        for make_move in pv {
+            buffer += &format!(" {}", make_move);
+        }
+        println!("{}", buffer);
+    }
+
+    fn print_curr_move(&self, depth: u32, make_move: Move, move_number: usize) {
+        println!(
+            "info depth {} currmove {} currmovenumber {}",
+            depth, make_move, move_number
+        );
+    }
+
+    fn print_hash_warning(&self, hash_full: u32) {
+        println!(
+            "info string hash is {}% full, consider increasing Hash",
+            hash_full / 10
+        );
+    }
+
+    fn print_heartbeat(&self, elapsed: Duration, node_cnt: u64, tb_hits: u64, hash_full: u32) {
+        let nps = (node_cnt as u128 * 1000) / elapsed.as_millis().max(1);
+        println!(
+            "info time {} nodes {} nps {} tbhits {} hashfull {}",
+            elapsed.as_millis(),
+            node_cnt,
+            nps,
+            tb_hits,
+            hash_full
+        );
+    }
+}
+
+/*
+Thinking output of the Chess Engine Communication Protocol,
+times are in centiseconds and mates are reported as 100000 + moves to mate
+*/
+#[derive(Debug, Clone)]
+pub struct XBoardInfo;
+
+impl GuiInfo for XBoardInfo {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn print_info(
+        &self,
+        _: u32,
+        depth: u32,
+        multi_pv: usize,
+        eval: Evaluation,
+        bound: ScoreBound,
+        _: Option<(u32, u32, u32)>,
+        elapsed: Duration,
+        node_cnt: u64,
+        _: u64,
+        _: u32,
+        pv: &[Move],
+    ) {
+        if multi_pv != 1 || bound != ScoreBound::Exact {
+            return;
+        }
+        let score = match eval.mate_in() {
+            Some(mate_in) => mate_in.signum() as i32 * (100000 + mate_in.abs() as i32),
+            None => eval.raw() as i32,
+        };
+        let mut buffer = format!(
+            "{} {} {} {}",
+            depth,
+            score,
+            elapsed.as_millis() / 10,
+            node_cnt
         );
         for make_move in pv {
             buffer += &format!(" {}", make_move);
         }
         println!("{}", buffer);
     }
+
+    fn print_curr_move(&self, _: u32, _: Move, _: usize) {}
+
+    fn print_hash_warning(&self, hash_full: u32) {
+        println!(
+            "# hash is {}% full, consider increasing memory",
+            hash_full / 10
+        );
+    }
+
+    fn print_heartbeat(&self, _: Duration, _: u64, _: u64, _: u32) {}
 }
@@ -0,0 +1,228 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use cozy_chess::Move;
+
+use crate::bm::bm_util::eval::Evaluation;
+
+/*
+Number of moves or nodes cut by each pruning technique
+*/
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PruneStats {
+    pub rev_fp: u64,
+    pub nmp: u64,
+    pub fp: u64,
+    pub lmp: u64,
+    pub hp: u64,
+    pub see_fp: u64,
+}
+
+/*
+Counters of the main thread over a single iteration
+*/
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IterationStats {
+    pub tt_hits: u32,
+    pub tt_misses: u32,
+    pub fail_highs: u32,
+    pub fail_lows: u32,
+    pub prunes: PruneStats,
+    pub best_move: Option<Move>,
+    pub previous_best_move: Option<Move>,
+}
+
+impl IterationStats {
+    /*
+    Whether the iteration changed the best move of the previous one
+    */
+    pub fn best_move_changed(&self) -> bool {
+        self.previous_best_move.is_some() && self.previous_best_move != self.best_move
+    }
+
+    pub fn tt_hit_rate(&self) -> f32 {
+        let probes = self.tt_hits + self.tt_misses;
+        if probes == 0 {
+            0.0
+        } else {
+            self.tt_hits as f32 / probes as f32
+        }
+    }
+}
+
+/*
+Callbacks for instrumentation that shouldn't go through GuiInfo printing
+Search events are only reported by the main search thread
+*/
+pub trait SearchObserver: Send + Sync {
+    fn on_depth_complete(&self, _depth: u32, _eval: Evaluation, _nodes: u64, _elapsed: Duration) {}
+
+    fn on_iteration_stats(&self, _depth: u32, _stats: &IterationStats) {}
+
+    fn on_fail_high(&self, _depth: u32, _make_move: Option<Move>, _score: Evaluation) {}
+
+    fn on_bestmove(&self, _best_move: Move, _ponder_move: Option<Move>, _eval: Evaluation) {}
+
+    fn on_tt_resize(&self, _entries: usize) {}
+}
+
+#[derive(Clone, Default)]
+pub struct Observers(Vec<Arc<dyn SearchObserver>>);
+
+impl Observers {
+    pub fn add(&mut self, observer: Arc<dyn SearchObserver>) {
+        self.0.push(observer);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn SearchObserver>> {
+        self.0.iter()
+    }
+}
+
+impl Debug for Observers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+/*
+Reports search events as info strings while UCI debug mode is on
+*/
+#[derive(Debug, Default)]
+pub struct DebugObserver {
+    enabled: AtomicBool,
+}
+
+impl DebugObserver {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl SearchObserver for DebugObserver {
+    fn on_fail_high(&self, depth: u32, make_move: Option<Move>, score: Evaluation) {
+        if self.enabled() {
+            match make_move {
+                Some(make_move) => println!(
+                    "info string fail high at depth {} on {} with {}",
+                    depth,
+                    make_move,
+                    score.raw()
+                ),
+                None => println!(
+                    "info string fail high at depth {} with {}",
+                    depth,
+                    score.raw()
+                ),
+            }
+        }
+    }
+
+    fn on_iteration_stats(&self, depth: u32, stats: &IterationStats) {
+        if self.enabled() {
+            let prunes = &stats.prunes;
+            println!(
+                "info string depth {} tt hit rate {:.1}% fail highs {} fail lows {} \
+                 pruned rfp {} nmp {} fp {} lmp {} hp {} see {}",
+                depth,
+                stats.tt_hit_rate() * 100.0,
+                stats.fail_highs,
+                stats.fail_lows,
+                prunes.rev_fp,
+                prunes.nmp,
+                prunes.fp,
+                prunes.lmp,
+                prunes.hp,
+                prunes.see_fp
+            );
+        }
+    }
+
+    /*
+    Raw score of the iteration, the reported one may be smoothed
+    */
+    fn on_depth_complete(&self, depth: u32, eval: Evaluation, _nodes: u64, _elapsed: Duration) {
+        if self.enabled() {
+            println!("info string depth {} raw score {}", depth, eval.raw());
+        }
+    }
+
+    fn on_tt_resize(&self, entries: usize) {
+        if self.enabled() {
+            println!("info string hash resized to {} entries", entries);
+        }
+    }
+}
+
+/*
+Reports every depth that changed the best move as an info string,
+frequent changes mean the engine isn't confident in its choice yet
+*/
+#[derive(Debug, Default)]
+pub struct BestMoveChangeObserver {
+    enabled: AtomicBool,
+}
+
+impl BestMoveChangeObserver {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+impl SearchObserver for BestMoveChangeObserver {
+    fn on_iteration_stats(&self, depth: u32, stats: &IterationStats) {
+        if !self.enabled.load(Ordering::Relaxed) || !stats.best_move_changed() {
+            return;
+        }
+        if let (Some(from), Some(to)) = (stats.previous_best_move, stats.best_move) {
+            println!(
+                "info string depth {} best move changed from {} to {}",
+                depth, from, to
+            );
+        }
+    }
+}
+
+/*
+Records when each depth of a search completed, time to depth measures search speed
+in a way nps doesn't since changes to pruning change how many nodes a depth takes
+*/
+#[derive(Debug, Default)]
+pub struct DepthTimeObserver {
+    enabled: AtomicBool,
+    times: Mutex<Vec<Duration>>,
+}
+
+impl DepthTimeObserver {
+    /*
+    Enabling clears the times of the previous search
+    */
+    pub fn set_enabled(&self, enabled: bool) {
+        self.times.lock().unwrap().clear();
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /*
+    times()[n] is the time it took to complete depth n + 1
+    */
+    pub fn times(&self) -> Vec<Duration> {
+        self.times.lock().unwrap().clone()
+    }
+}
+
+impl SearchObserver for DepthTimeObserver {
+    fn on_depth_complete(&self, depth: u32, _eval: Evaluation, _nodes: u64, elapsed: Duration) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let times = &mut *self.times.lock().unwrap();
+        times.resize(times.len().max(depth as usize), elapsed);
+        times[depth as usize - 1] = elapsed;
+    }
+}
@@ -0,0 +1,312 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::bm::bm_util::position::EvalParams;
+
+macro_rules! search_params {
+    ($($name:ident: $ty:ty = $default:expr;)*) => {
+        /*
+        Parameters missing from a config file keep their compiled defaults
+        */
+        #[derive(Debug, Clone, Deserialize)]
+        #[serde(default, deny_unknown_fields)]
+        pub struct SearchParams {
+            $(pub $name: $ty,)*
+        }
+
+        impl Default for SearchParams {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl SearchParams {
+            pub fn new() -> Self {
+                Self {
+                    $($name: $default,)*
+                }
+            }
+
+            pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+                let mut params = self.clone();
+                match name {
+                    $(stringify!($name) => params.$name = parse(name, value)?,)*
+                    _ => return Err(format!("unknown parameter {}", name)),
+                }
+                params.check_divisors()?;
+                *self = params;
+                Ok(())
+            }
+        }
+    };
+}
+
+search_params! {
+    rev_fp_depth: u32 = 7;
+    rev_fp_margin: i16 = 50;
+    rev_fp_improving: i16 = 50;
+    rev_fp_threat: i16 = 40;
+    rev_fp_odd_depth: i16 = 10;
+    nmp_depth: u32 = 4;
+    nmp_base_reduction: u32 = 3;
+    nmp_depth_div: u32 = 4;
+    nmp_eval_div: i16 = 200;
+    nmp_verify_depth: u32 = 10;
+    nmp_min_pieces: u32 = 1;
+    iir_depth: u32 = 4;
+    extension_budget: f32 = 1.0;
+    fp_depth: u32 = 7;
+    fp_margin: i16 = 100;
+    see_fp_depth: u32 = 7;
+    see_fp_margin: i16 = 100;
+    hp_depth: u32 = 8;
+    hp_div: i32 = 64;
+    history_lmr_div: i16 = 80;
+    lmr_eval_gap_bucket: i16 = 200;
+    lmr_eval_gap_max: i16 = 2;
+    capture_history_lmr_div: i16 = 80;
+    losing_capture_lmr: i16 = 1;
+    cut_node_lmr: i16 = 1;
+    busy_depth: u32 = 6;
+    busy_lmr: i16 = 1;
+    helper_depth_skip: u32 = 1;
+    helper_window_step: f32 = 0.25;
+    killer_slots: usize = 2;
+    history_age_div: i16 = 2;
+    mvv_lva_order: bool = false;
+    q_see_threshold: i16 = 200;
+    q_check_plies: u32 = 1;
+    q_see_margin: i16 = 50;
+    q_see_margin_div: i16 = 4;
+    q_lmp_margin: i16 = 300;
+    q_lmp_base: f32 = 4.0;
+    q_lmp_decay: f32 = 0.5;
+    see_order_pawn: i16 = 100;
+    see_order_knight: i16 = 300;
+    see_order_bishop: i16 = 300;
+    see_order_rook: i16 = 500;
+    see_order_queen: i16 = 900;
+    see_prune_pawn: i16 = 100;
+    see_prune_knight: i16 = 300;
+    see_prune_bishop: i16 = 300;
+    see_prune_rook: i16 = 500;
+    see_prune_queen: i16 = 900;
+    lmr_base: f32 = 2.0;
+    lmr_div: f32 = 1.75;
+    lmp_base: f32 = 3.0;
+    endgame_pieces: u32 = 4;
+    endgame_lmp_scale: f32 = 1.5;
+    window_start: f32 = 30.0;
+    window_min: f32 = 15.0;
+    window_depth_shrink: f32 = 0.75;
+    window_growth: f32 = 1.25;
+    window_add: f32 = 5.0;
+    expected_moves: u32 = 40;
+    move_change_margin: u32 = 9;
+    nn_scale: i32 = 100;
+    lone_minor_scale: i32 = 8;
+    opposite_bishop_scale: i32 = 64;
+    rook_endgame_scale: i32 = 96;
+    corner_bishop_penalty: i16 = 50;
+    root_bonus_max: i16 = 100;
+    root_bonus_div: i16 = 50;
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value {} for {}", value, name))
+}
+
+/*
+The king can't be exchanged, its value only has to exceed every other piece
+*/
+const SEE_KING: i16 = 20000;
+
+/*
+Piece values used by static exchange evaluation, indexed by piece
+*/
+pub type SeeValues = [i16; 6];
+
+impl SearchParams {
+    /*
+    SEE values for move ordering
+    */
+    pub fn see_order_values(&self) -> SeeValues {
+        [
+            self.see_order_pawn,
+            self.see_order_knight,
+            self.see_order_bishop,
+            self.see_order_rook,
+            self.see_order_queen,
+            SEE_KING,
+        ]
+    }
+
+    /*
+    SEE values for pruning decisions, these interact with the pruning margins
+    */
+    pub fn see_prune_values(&self) -> SeeValues {
+        [
+            self.see_prune_pawn,
+            self.see_prune_knight,
+            self.see_prune_bishop,
+            self.see_prune_rook,
+            self.see_prune_queen,
+            SEE_KING,
+        ]
+    }
+
+    /*
+    Static evaluation terms
+    */
+    pub fn eval_params(&self) -> EvalParams {
+        EvalParams {
+            nn_scale: self.nn_scale,
+            lone_minor_scale: self.lone_minor_scale,
+            opposite_bishop_scale: self.opposite_bishop_scale,
+            rook_endgame_scale: self.rook_endgame_scale,
+            corner_bishop_penalty: self.corner_bishop_penalty,
+            root_bonus_max: self.root_bonus_max,
+            root_bonus_div: self.root_bonus_div.max(1),
+        }
+    }
+
+    /*
+    LMR adjustment for a node whose static eval is gap above alpha, one ply less
+    reduction for every lmr_eval_gap_bucket and one more when below alpha
+    */
+    pub fn eval_gap_lmr(&self, gap: i32) -> i16 {
+        let buckets = gap / self.lmr_eval_gap_bucket.max(1) as i32;
+        buckets.clamp(-self.lmr_eval_gap_max as i32, self.lmr_eval_gap_max as i32) as i16
+    }
+
+    /*
+    Depth the iterative deepening of a thread starts at, every other helper skips
+    the first iterations so the threads aren't all at the same depth
+    */
+    pub fn start_depth(&self, thread: usize) -> u32 {
+        1 + (thread % 2) as u32 * self.helper_depth_skip
+    }
+
+    /*
+    Aspiration window of a thread, helpers cycle through wider windows so they
+    resolve fail highs and lows differently from the main thread
+    */
+    pub fn thread_window(&self, window: i16, thread: usize) -> i16 {
+        (window as f32 * (1.0 + self.helper_window_step * (thread % 4) as f32)) as i16
+    }
+
+    /*
+    The search divides by these, a zero is rejected instead of panicking mid-search
+    */
+    fn check_divisors(&self) -> Result<(), String> {
+        let divisors = [
+            ("nmp_depth_div", self.nmp_depth_div == 0),
+            ("nmp_eval_div", self.nmp_eval_div == 0),
+            ("hp_div", self.hp_div == 0),
+            ("history_lmr_div", self.history_lmr_div == 0),
+            ("capture_history_lmr_div", self.capture_history_lmr_div == 0),
+            ("q_see_margin_div", self.q_see_margin_div == 0),
+        ];
+        match divisors.iter().find(|(_, zero)| *zero) {
+            Some((name, _)) => Err(format!("invalid value 0 for {}", name)),
+            None => Ok(()),
+        }
+    }
+
+    /*
+    Loads parameters from a JSON object ({"name": value}) or a flat TOML (name = value) file,
+    parameters that aren't in the file keep their compiled defaults
+    */
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        if content.trim_start().starts_with('{') {
+            let params: Self =
+                serde_json::from_str(&content).map_err(|err| format!("{}: {}", path, err))?;
+            params
+                .check_divisors()
+                .map_err(|err| format!("{}: {}", path, err))?;
+            return Ok(params);
+        }
+        let mut params = Self::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("invalid line {}", line))?;
+            params.set(name.trim(), value.trim())?;
+        }
+        Ok(params)
+    }
+}
+
+#[test]
+fn eval_gap_buckets() {
+    let params = SearchParams::new();
+    let bucket = params.lmr_eval_gap_bucket as i32;
+    assert_eq!(params.eval_gap_lmr(0), 0);
+    assert_eq!(params.eval_gap_lmr(bucket - 1), 0);
+    assert_eq!(params.eval_gap_lmr(bucket), 1);
+    assert_eq!(params.eval_gap_lmr(-bucket), -1);
+    assert_eq!(
+        params.eval_gap_lmr(i16::MAX as i32),
+        params.lmr_eval_gap_max
+    );
+    assert_eq!(
+        params.eval_gap_lmr(i16::MIN as i32),
+        -params.lmr_eval_gap_max
+    );
+}
+
+#[test]
+fn helper_diversification() {
+    let params = SearchParams::new();
+    assert_eq!(params.start_depth(0), 1);
+    assert_eq!(params.start_depth(1), 1 + params.helper_depth_skip);
+    assert_eq!(params.start_depth(2), 1);
+    assert_eq!(params.thread_window(20, 0), 20);
+    assert!(params.thread_window(20, 3) > params.thread_window(20, 1));
+    assert_eq!(params.thread_window(20, 4), 20);
+}
+
+#[test]
+fn json_config() {
+    let params: SearchParams = serde_json::from_str(
+        r#"{"rev_fp_margin": 60, "lmr_div": 2.5, "mvv_lva_order": true, "nn_scale": 90}"#,
+    )
+    .unwrap();
+    assert_eq!(params.rev_fp_margin, 60);
+    assert_eq!(params.lmr_div, 2.5);
+    assert!(params.mvv_lva_order);
+    assert_eq!(params.eval_params().nn_scale, 90);
+    assert_eq!(params.nmp_depth, SearchParams::new().nmp_depth);
+
+    assert!(serde_json::from_str::<SearchParams>(r#"{"no_such_param": 1}"#).is_err());
+    assert!(serde_json::from_str::<SearchParams>(r#"{"nmp_depth": -1}"#).is_err());
+}
+
+#[test]
+fn zero_divisors() {
+    let mut params = SearchParams::new();
+    assert!(params.set("hp_div", "0").is_err());
+    assert_eq!(params.hp_div, SearchParams::new().hp_div);
+    assert!(params.set("hp_div", "32").is_ok());
+    assert_eq!(params.hp_div, 32);
+
+    let dir = std::env::temp_dir();
+    let json = dir.join(format!("bm_zero_div_{}.json", std::process::id()));
+    let toml = dir.join(format!("bm_zero_div_{}.toml", std::process::id()));
+    std::fs::write(&json, r#"{"nmp_depth_div": 0}"#).unwrap();
+    std::fs::write(&toml, "q_see_margin_div = 0\n").unwrap();
+    let json_err = SearchParams::load(json.to_str().unwrap()).unwrap_err();
+    let toml_err = SearchParams::load(toml.to_str().unwrap()).unwrap_err();
+    std::fs::remove_file(&json).unwrap();
+    std::fs::remove_file(&toml).unwrap();
+    assert!(json_err.contains("nmp_depth_div"), "{}", json_err);
+    assert!(toml_err.contains("q_see_margin_div"), "{}", toml_err);
+}
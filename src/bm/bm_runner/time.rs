@@ -1,14 +1,13 @@
 use crate::bm::bm_util::eval::Evaluation;
-use cozy_chess::{Board, Move};
+use crate::bm::bm_util::tension::Tension;
+use cozy_chess::{Board, Color, Move};
 use std::fmt::Debug;
-use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI16, AtomicI32, AtomicU32, AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use super::ab_runner::MAX_PLY;
-
-const EXPECTED_MOVES: u32 = 40;
-const MOVE_CHANGE_MARGIN: u32 = 9;
+use super::params::SearchParams;
 
 const TIME_DEFAULT: Duration = Duration::from_secs(0);
 const INC_DEFAULT: Duration = Duration::from_secs(0);
@@ -30,13 +29,114 @@ pub enum TimeManagementInfo {
     MaxNodes(u64),
     MovesToGo(u32),
     MoveTime(Duration),
+    Mate(u32),
+    Ponder,
     Unknown,
 }
 
+/*
+All limits of a single search gathered in one place.
+Precedence:
+- An explicit abort (stop/quit) always ends the search
+- Depth, node and mate limits are hard limits, whichever is reached first ends the search
+- A fixed move time overrides the clock
+- Without a move time or a clock the search is infinite
+- While pondering, no limit ends the search until the ponder move is played
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct SearchLimits {
+    depth: u32,
+    nodes: u64,
+    mate: Option<u32>,
+    move_time: Option<Duration>,
+    time: Option<Duration>,
+    inc: Duration,
+    moves_to_go: Option<u32>,
+    ponder: bool,
+}
+
+impl SearchLimits {
+    pub fn new() -> Self {
+        Self {
+            depth: DEPTH_DEFAULT,
+            nodes: NODES_DEFAULT,
+            mate: None,
+            move_time: None,
+            time: None,
+            inc: INC_DEFAULT,
+            moves_to_go: MOVES_TO_GO_DEFAULT,
+            ponder: false,
+        }
+    }
+
+    pub fn from_info(stm: Color, info: &[TimeManagementInfo]) -> Self {
+        let mut limits = Self::new();
+        for info in info {
+            match (*info, stm) {
+                (TimeManagementInfo::WTime(time), Color::White)
+                | (TimeManagementInfo::BTime(time), Color::Black) => {
+                    limits.time = Some(time);
+                }
+                (TimeManagementInfo::WInc(inc), Color::White)
+                | (TimeManagementInfo::BInc(inc), Color::Black) => {
+                    limits.inc = inc;
+                }
+                (TimeManagementInfo::MaxDepth(depth), _) => limits.depth = depth,
+                (TimeManagementInfo::MaxNodes(nodes), _) => limits.nodes = limits.nodes.min(nodes),
+                (TimeManagementInfo::Mate(mate), _) => limits.mate = Some(mate),
+                (TimeManagementInfo::MovesToGo(moves), _) => limits.moves_to_go = Some(moves),
+                (TimeManagementInfo::MoveTime(time), _) => limits.move_time = Some(time),
+                (TimeManagementInfo::Ponder, _) => limits.ponder = true,
+                _ => {}
+            }
+        }
+        limits
+    }
+
+    pub fn depth(mut self, depth: u32) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn nodes(mut self, nodes: u64) -> Self {
+        self.nodes = nodes;
+        self
+    }
+
+    pub fn mate(&self) -> Option<u32> {
+        self.mate
+    }
+
+    pub fn infinite(&self) -> bool {
+        self.move_time.is_none() && self.time.is_none()
+    }
+
+    pub fn managed(&self) -> bool {
+        self.move_time.is_none() && self.time.is_some()
+    }
+
+    pub fn depth_reached(&self, depth: u32) -> bool {
+        self.depth < depth
+    }
+
+    pub fn nodes_reached(&self, nodes: u64) -> bool {
+        self.nodes <= nodes
+    }
+
+    pub fn mate_found(&self, eval: Evaluation) -> bool {
+        match (self.mate, eval.mate_in()) {
+            (Some(limit), Some(mate_in)) => mate_in > 0 && mate_in as u32 <= limit,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TimeManager {
-    expected_moves: AtomicU32,
+    moves_to_go_default: AtomicU32,
+    move_change_margin: AtomicU32,
     last_eval: AtomicI16,
+    last_king_danger: AtomicI32,
     max_duration: AtomicU32,
     normal_duration: AtomicU32,
     target_duration: AtomicU32,
@@ -49,15 +149,25 @@ pub struct TimeManager {
     abort_now: AtomicBool,
     no_manage: AtomicBool,
 
-    max_depth: AtomicU32,
+    pondering: AtomicBool,
+    search_start: Mutex<Instant>,
+    ponder_offset: AtomicU32,
+
+    limits: Mutex<SearchLimits>,
+    /*
+    Node limit of the search, kept outside the limits so it can be checked while searching
+    */
     max_nodes: AtomicU64,
 }
 
 impl TimeManager {
     pub fn new() -> Self {
+        let params = SearchParams::new();
         Self {
-            expected_moves: AtomicU32::new(EXPECTED_MOVES),
+            moves_to_go_default: AtomicU32::new(params.expected_moves),
+            move_change_margin: AtomicU32::new(params.move_change_margin),
             last_eval: AtomicI16::new(0),
+            last_king_danger: AtomicI32::new(0),
             max_duration: AtomicU32::new(0),
             normal_duration: AtomicU32::new(0),
             target_duration: AtomicU32::new(0),
@@ -67,23 +177,43 @@ impl TimeManager {
             abort_now: AtomicBool::new(false),
             infinite: AtomicBool::new(true),
             no_manage: AtomicBool::new(true),
-            max_depth: AtomicU32::new(DEPTH_DEFAULT),
+            pondering: AtomicBool::new(false),
+            search_start: Mutex::new(Instant::now()),
+            ponder_offset: AtomicU32::new(0),
+            limits: Mutex::new(SearchLimits::new()),
             max_nodes: AtomicU64::new(NODES_DEFAULT),
         }
     }
 }
 
 impl TimeManager {
+    pub fn set_params(&self, params: &SearchParams) {
+        self.moves_to_go_default
+            .store(params.expected_moves, Ordering::SeqCst);
+        self.move_change_margin
+            .store(params.move_change_margin, Ordering::SeqCst);
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn deepen(
         &self,
-        thread: u8,
+        thread: usize,
         depth: u32,
         _: u64,
         eval: Evaluation,
         current_move: Move,
+        tension: Tension,
         _: Duration,
     ) {
-        if thread != 0 || depth <= 4 || self.no_manage.load(Ordering::SeqCst) {
+        if thread != 0 {
+            return;
+        }
+        if self.limits.lock().unwrap().mate_found(eval) {
+            self.wait_for_ponder_hit();
+            self.abort_now();
+            return;
+        }
+        if depth <= 4 || self.no_manage.load(Ordering::SeqCst) {
             return;
         }
 
@@ -111,21 +241,47 @@ impl TimeManager {
 
         time *= 1.05_f32.powf(eval_diff.min(1.0));
 
+        /*
+        Sharp swings in king danger between iterations mean the position is
+        still tactically unsettled, so it's worth thinking a bit longer
+        */
+        let last_king_danger = self.last_king_danger.load(Ordering::SeqCst);
+        let danger_diff = (tension.king_danger - last_king_danger).abs() as f32 / 8.0;
+        time *= 1.05_f32.powf(danger_diff.min(1.0));
+
         let move_change_factor = 1.05_f32
-            .powf(MOVE_CHANGE_MARGIN as f32 - move_change_depth as f32)
+            .powf(self.move_change_margin.load(Ordering::SeqCst) as f32 - move_change_depth as f32)
             .max(0.4);
 
+        /*
+        Sacrifices in the best line are often refuted a few plies deeper
+        */
+        let sacrifice_factor = if tension.sacrifice { 1.15 } else { 1.0 };
+
         let time = time.min(self.max_duration.load(Ordering::SeqCst) as f32 * 1000.0);
         self.normal_duration
             .store((time * 0.001) as u32, Ordering::SeqCst);
-        self.target_duration
-            .store((time * 0.001 * move_change_factor) as u32, Ordering::SeqCst);
+        self.target_duration.store(
+            (time * 0.001 * move_change_factor * sacrifice_factor) as u32,
+            Ordering::SeqCst,
+        );
         self.last_eval.store(current_eval, Ordering::SeqCst);
+        self.last_king_danger
+            .store(tension.king_danger, Ordering::SeqCst);
     }
 
     pub fn initiate(&self, board: &Board, info: &[TimeManagementInfo]) {
+        self.initiate_limits(board, SearchLimits::from_info(board.side_to_move(), info));
+    }
+
+    pub fn initiate_limits(&self, board: &Board, limits: SearchLimits) {
         self.abort_now.store(false, Ordering::SeqCst);
+        self.pondering.store(limits.ponder, Ordering::SeqCst);
+        self.ponder_offset.store(0, Ordering::SeqCst);
+        *self.search_start.lock().unwrap() = Instant::now();
         *self.board.lock().unwrap() = board.clone();
+        *self.limits.lock().unwrap() = limits;
+        self.max_nodes.store(limits.nodes, Ordering::SeqCst);
 
         let mut move_cnt = 0;
         board.generate_moves(|piece_moves| {
@@ -133,70 +289,24 @@ impl TimeManager {
             false
         });
 
-        let mut infinite = true;
-
-        let mut w_time = TIME_DEFAULT;
-        let mut b_time = TIME_DEFAULT;
-        let mut w_inc = INC_DEFAULT;
-        let mut b_inc = INC_DEFAULT;
-        let mut max_depth = DEPTH_DEFAULT;
-        let mut max_nodes = NODES_DEFAULT;
-        let mut moves_to_go = MOVES_TO_GO_DEFAULT;
-        let mut move_time = None;
-
-        for info in info {
-            match info {
-                TimeManagementInfo::WTime(time) => {
-                    w_time = *time;
-                    infinite = false;
-                }
-                TimeManagementInfo::BTime(time) => {
-                    b_time = *time;
-                    infinite = false;
-                }
-                TimeManagementInfo::WInc(time) => {
-                    w_inc = *time;
-                }
-                TimeManagementInfo::BInc(time) => {
-                    b_inc = *time;
-                }
-                TimeManagementInfo::MaxDepth(depth) => {
-                    max_depth = *depth;
-                }
-                TimeManagementInfo::MaxNodes(nodes) => {
-                    max_nodes = *nodes;
-                }
-                TimeManagementInfo::MovesToGo(moves) => {
-                    moves_to_go = Some(*moves);
-                }
-                TimeManagementInfo::MoveTime(time) => {
-                    move_time = Some(*time);
-                    infinite = false;
-                }
-                _ => {}
-            }
-        }
-        self.infinite.store(infinite, Ordering::SeqCst);
-        self.max_depth.store(max_depth, Ordering::SeqCst);
-        self.max_nodes.store(max_nodes, Ordering::SeqCst);
-
-        let (time, inc) = match board.side_to_move() {
-            cozy_chess::Color::White => (w_time, w_inc),
-            cozy_chess::Color::Black => (b_time, b_inc),
-        };
-
-        let no_manage = infinite || move_time.is_some();
-        self.no_manage.store(no_manage, Ordering::SeqCst);
+        self.infinite.store(limits.infinite(), Ordering::SeqCst);
+        self.no_manage.store(!limits.managed(), Ordering::SeqCst);
 
         if move_cnt == 0 {
             self.target_duration.store(0, Ordering::SeqCst);
-        } else if let Some(move_time) = move_time {
+        } else if let Some(move_time) = limits.move_time {
             self.target_duration
                 .store(move_time.as_millis() as u32, Ordering::SeqCst);
+            self.max_duration
+                .store(move_time.as_millis() as u32, Ordering::SeqCst);
         } else {
-            let expected_moves = moves_to_go.unwrap_or(EXPECTED_MOVES) + 1;
+            let time = limits.time.unwrap_or(TIME_DEFAULT);
+            let expected_moves = limits
+                .moves_to_go
+                .unwrap_or(self.moves_to_go_default.load(Ordering::SeqCst))
+                + 1;
             let default = if move_cnt > 1 {
-                inc.as_millis() as u32 + time.as_millis() as u32 / expected_moves
+                limits.inc.as_millis() as u32 + time.as_millis() as u32 / expected_moves
             } else {
                 0
             };
@@ -207,30 +317,83 @@ impl TimeManager {
         };
     }
 
+    /*
+    Hard and soft time limits in milliseconds, None if the search isn't timed
+    */
+    pub fn allocated(&self) -> Option<(u32, u32)> {
+        if self.infinite.load(Ordering::SeqCst) {
+            None
+        } else {
+            Some((
+                self.max_duration.load(Ordering::SeqCst),
+                self.target_duration.load(Ordering::SeqCst),
+            ))
+        }
+    }
+
+    pub fn mate_limit(&self) -> Option<u32> {
+        self.limits.lock().unwrap().mate()
+    }
+
     pub fn abort_now(&self) {
         self.abort_now.store(true, Ordering::SeqCst);
     }
 
+    /*
+    The opponent played the expected move, the ponder search turns into a regular search
+    Time spent pondering isn't counted against our own clock
+    */
+    pub fn ponder_hit(&self) {
+        let elapsed = self.search_start.lock().unwrap().elapsed().as_millis() as u32;
+        self.ponder_offset.store(elapsed, Ordering::SeqCst);
+        self.pondering.store(false, Ordering::SeqCst);
+    }
+
+    fn pondering(&self) -> bool {
+        self.pondering.load(Ordering::SeqCst)
+    }
+
+    fn wait_for_ponder_hit(&self) {
+        while self.pondering() && !self.abort_now.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn elapsed(&self, start: Instant) -> u32 {
+        (start.elapsed().as_millis() as u32)
+            .saturating_sub(self.ponder_offset.load(Ordering::SeqCst))
+    }
+
     pub fn abort_search(&self, start: Instant) -> bool {
         if self.abort_now.load(Ordering::SeqCst) {
             true
         } else {
-            self.target_duration.load(Ordering::SeqCst) < start.elapsed().as_millis() as u32
+            self.target_duration.load(Ordering::SeqCst) < self.elapsed(start)
                 && !self.infinite.load(Ordering::SeqCst)
+                && !self.pondering()
         }
     }
 
+    /*
+    The node limit is checked during the search so it isn't overshot by an iteration,
+    while pondering it's left to abort_deepening, which waits for the ponder hit
+    */
+    pub fn nodes_reached(&self, nodes: u64) -> bool {
+        self.max_nodes.load(Ordering::SeqCst) <= nodes && !self.pondering()
+    }
+
     pub fn abort_deepening(&self, start: Instant, depth: u32, nodes: u64) -> bool {
         if self.abort_now.load(Ordering::SeqCst) {
-            true
-        } else {
-            let abort_std = self.target_duration.load(Ordering::SeqCst)
-                < (start.elapsed().as_millis() * 8 / 10) as u32
-                && !self.infinite.load(Ordering::SeqCst);
-            abort_std
-                || self.max_depth.load(Ordering::SeqCst) < depth
-                || self.max_nodes.load(Ordering::SeqCst) <= nodes
+            return true;
+        }
+        let limits = *self.limits.lock().unwrap();
+        if limits.depth_reached(depth) || limits.nodes_reached(nodes) {
+            self.wait_for_ponder_hit();
+            return true;
         }
+        self.target_duration.load(Ordering::SeqCst) < self.elapsed(start) * 8 / 10
+            && !self.infinite.load(Ordering::SeqCst)
+            && !self.pondering()
     }
 
     pub fn clear(&self) {
@@ -238,8 +401,14 @@ impl TimeManager {
         self.same_move_depth.store(0, Ordering::SeqCst);
         self.abort_now.store(false, Ordering::SeqCst);
         self.no_manage.store(false, Ordering::SeqCst);
-        let expected_moves = self.expected_moves.load(Ordering::SeqCst);
-        self.expected_moves
-            .store(expected_moves.saturating_sub(1), Ordering::SeqCst);
+    }
+
+    /*
+    The eval and king danger trends of the previous game don't carry over
+    */
+    pub fn new_game(&self) {
+        self.clear();
+        self.last_eval.store(0, Ordering::SeqCst);
+        self.last_king_danger.store(0, Ordering::SeqCst);
     }
 }
@@ -1,3 +1,4 @@
 pub mod move_entry;
 pub mod move_gen;
 pub mod search;
+pub mod see_check;
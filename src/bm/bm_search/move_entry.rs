@@ -3,15 +3,24 @@ use std::iter::{IntoIterator, Take};
 
 use cozy_chess::{Move, Square};
 
+/*
+Most killer moves a ply can hold, the killer_slots parameter picks how many are used
+*/
+pub const MAX_KILLERS: usize = 4;
+
 #[derive(Debug, Copy, Clone)]
 pub struct MoveEntry<const N: usize> {
     moves: [Move; N],
     index: usize,
     size: usize,
+    slots: usize,
 }
 
 impl<const N: usize> MoveEntry<N> {
-    pub fn new() -> Self {
+    /*
+    Keeps the latest moves pushed, up to slots of them
+    */
+    pub fn new(slots: usize) -> Self {
         Self {
             moves: [Move {
                 from: Square::A1,
@@ -20,6 +29,7 @@ impl<const N: usize> MoveEntry<N> {
             }; N],
             index: 0,
             size: 0,
+            slots: slots.min(N),
         }
     }
 
@@ -29,13 +39,13 @@ impl<const N: usize> MoveEntry<N> {
     }
 
     pub fn push(&mut self, killer_move: Move) {
-        if N == 0 {
+        if self.slots == 0 {
             return;
         }
         if self.size == 0 || !self.moves.contains(&killer_move) {
             self.moves[self.index] = killer_move;
-            self.size += 1;
-            self.index = (self.index + 1) % N;
+            self.size = (self.size + 1).min(self.slots);
+            self.index = (self.index + 1) % self.slots;
         }
     }
 }
@@ -1,6 +1,13 @@
-use cozy_chess::{Board, Move, Piece, PieceMoves};
+use cozy_chess::{
+    get_between_rays, get_bishop_moves, get_bishop_rays, get_king_moves, get_knight_moves,
+    get_line_rays, get_pawn_attacks, get_pawn_quiets, get_rook_moves, get_rook_rays, BitBoard,
+    Board, Move, Piece, PieceMoves, Rank, Square,
+};
 
-use crate::bm::bm_util::h_table::{DoubleMoveHistory, HistoryTable};
+use crate::bm::bm_runner::params::SeeValues;
+use crate::bm::bm_util::eval::Evaluation;
+use crate::bm::bm_util::h_table::{DoubleMoveHistory, HistoryTable, LosingCaptureHistory};
+use crate::bm::bm_util::zobrist;
 use arrayvec::ArrayVec;
 
 use super::move_entry::MoveEntryIterator;
@@ -9,57 +16,223 @@ use super::search;
 const MAX_MOVES: usize = 218;
 const THRESHOLD: i16 = -(2_i16.pow(10));
 const LOSING_CAPTURE: i16 = -(2_i16.pow(12));
+const THREAT_RESPONSE: i16 = 2_i16.pow(8);
+const REPETITION_BONUS: i16 = 2_i16.pow(8);
+const THREATENED_SQUARE: i16 = 2_i16.pow(9);
+const THREAT_ESCAPE: i16 = 2_i16.pow(8);
+
+/*
+Positions this far from equal prefer avoiding or forcing repetitions
+*/
+const REPETITION_MARGIN: i16 = 50;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum GenType {
     PvMove,
     CalcCaptures,
     Captures,
+    Killer,
     GenQuiet,
     CounterMove,
-    Killer,
     Quiet,
     BadCaptures,
 }
 
 type LazySee = Option<i16>;
 
+/*
+Squares the opponent attacks with pawns, with pieces up to minors and with pieces up to rooks
+A piece on a square attacked by a less valuable piece is threatened
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct Threats {
+    pawn: BitBoard,
+    minor: BitBoard,
+    rook: BitBoard,
+}
+
+impl Threats {
+    pub fn new(board: &Board) -> Self {
+        let stm = board.side_to_move();
+        let theirs = board.colors(!stm);
+        let occupied = board.occupied();
+
+        let mut pawn = BitBoard::EMPTY;
+        for sq in theirs & board.pieces(Piece::Pawn) {
+            pawn |= get_pawn_attacks(sq, !stm);
+        }
+        let mut minor = pawn;
+        for sq in theirs & board.pieces(Piece::Knight) {
+            minor |= get_knight_moves(sq);
+        }
+        for sq in theirs & board.pieces(Piece::Bishop) {
+            minor |= get_bishop_moves(sq, occupied);
+        }
+        let mut rook = minor;
+        for sq in theirs & board.pieces(Piece::Rook) {
+            rook |= get_rook_moves(sq, occupied);
+        }
+        Self { pawn, minor, rook }
+    }
+
+    /*
+    Squares where the piece can be taken by a less valuable piece
+    */
+    pub fn against(&self, piece: Piece) -> BitBoard {
+        match piece {
+            Piece::Knight | Piece::Bishop => self.pawn,
+            Piece::Rook => self.minor,
+            Piece::Queen => self.rook,
+            Piece::Pawn | Piece::King => BitBoard::EMPTY,
+        }
+    }
+
+    /*
+    Whether any piece of the side to move is threatened
+    */
+    pub fn any(&self, board: &Board) -> bool {
+        let ours = board.colors(board.side_to_move());
+        [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen]
+            .into_iter()
+            .any(|piece| !(self.against(piece) & ours & board.pieces(piece)).is_empty())
+    }
+}
+
+/*
+Board::is_legal doesn't check whether castling moves are out of check,
+castling is a king move onto its own rook
+*/
+fn is_legal(board: &Board, make_move: Move) -> bool {
+    let castles = board.colors(board.side_to_move()).has(make_move.to);
+    (!castles || board.checkers().is_empty()) && board.is_legal(make_move)
+}
+
+/*
+Our pieces that attack an enemy piece, the capture stages only generate moves for these
+*/
+fn capturers(board: &Board) -> BitBoard {
+    let stm = board.side_to_move();
+    let ours = board.colors(stm);
+    let theirs = board.colors(!stm);
+    let occupied = board.occupied();
+    let attacks = |piece: Piece, sq| match piece {
+        Piece::Pawn => get_pawn_attacks(sq, stm),
+        Piece::Knight => get_knight_moves(sq),
+        Piece::Bishop => get_bishop_moves(sq, occupied),
+        Piece::Rook => get_rook_moves(sq, occupied),
+        Piece::Queen => get_bishop_moves(sq, occupied) | get_rook_moves(sq, occupied),
+        Piece::King => get_king_moves(sq),
+    };
+    let mut capturers = BitBoard::EMPTY;
+    for piece in Piece::ALL {
+        for sq in ours & board.pieces(piece) {
+            if !(attacks(piece, sq) & theirs).is_empty() {
+                capturers |= sq.bitboard();
+            }
+        }
+    }
+    capturers
+}
+
+/*
+Sorts the moves scored at least limit to the front from best to worst,
+the rest are left behind them unsorted since they're rarely reached
+*/
+fn partial_insertion_sort(moves: &mut [(Move, i16)], limit: i16) {
+    let mut sorted = 0;
+    for index in 0..moves.len() {
+        let entry = moves[index];
+        if entry.1 < limit {
+            continue;
+        }
+        moves[index] = moves[sorted];
+        let mut insert = sorted;
+        while insert > 0 && moves[insert - 1].1 < entry.1 {
+            moves[insert] = moves[insert - 1];
+            insert -= 1;
+        }
+        moves[insert] = entry;
+        sorted += 1;
+    }
+}
+
 pub struct OrderedMoveGen<const K: usize> {
-    move_list: ArrayVec<PieceMoves, 18>,
     pv_move: Option<Move>,
     killer_entry: MoveEntryIterator<K>,
+    killers: ArrayVec<Move, K>,
     counter_move: Option<Move>,
     prev_move: Option<Move>,
+    threat_move: Option<Move>,
     gen_type: GenType,
 
     captures: ArrayVec<(Move, i16, LazySee), MAX_MOVES>,
     quiets: ArrayVec<(Move, i16), MAX_MOVES>,
+    quiet_index: usize,
     skip_quiets: bool,
+    mvv_lva: bool,
+
+    repetition_hashes: ArrayVec<u64, 64>,
+    repetition_bonus: i16,
+    see_values: SeeValues,
 }
 
 impl<const K: usize> OrderedMoveGen<K> {
     pub fn new(
-        board: &Board,
         pv_move: Option<Move>,
         counter_move: Option<Move>,
         prev_move: Option<Move>,
+        threat_move: Option<Move>,
         killer_entry: MoveEntryIterator<K>,
+        see_values: SeeValues,
     ) -> Self {
-        let mut move_list = ArrayVec::new();
-        board.generate_moves(|piece_moves| {
-            move_list.push(piece_moves);
-            false
-        });
         Self {
             gen_type: GenType::PvMove,
-            move_list,
             counter_move,
             prev_move,
+            threat_move,
             pv_move,
             killer_entry,
+            killers: ArrayVec::new(),
             captures: ArrayVec::new(),
             quiets: ArrayVec::new(),
+            quiet_index: 0,
             skip_quiets: false,
+            mvv_lva: false,
+            repetition_hashes: ArrayVec::new(),
+            repetition_bonus: 0,
+            see_values,
+        }
+    }
+
+    /*
+    Quiet moves that repeat a position are ordered later when we are better
+    and earlier when we are worse
+    */
+    pub fn set_repetitions(&mut self, hashes: impl Iterator<Item = u64>, eval: Evaluation) {
+        if eval.is_mate() {
+            return;
+        }
+        self.repetition_bonus = if eval.raw() > REPETITION_MARGIN {
+            -REPETITION_BONUS
+        } else if eval.raw() < -REPETITION_MARGIN {
+            REPETITION_BONUS
+        } else {
+            return;
+        };
+        for hash in hashes {
+            if self.repetition_hashes.try_push(hash).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn repetition(&self, board: &Board, make_move: Move) -> i16 {
+        if self.repetition_hashes.is_empty() {
+            return 0;
+        }
+        match zobrist::reversible_child_hash(board, make_move) {
+            Some(hash) if self.repetition_hashes.contains(&hash) => self.repetition_bonus,
+            _ => 0,
         }
     }
 
@@ -71,6 +244,38 @@ impl<const K: usize> OrderedMoveGen<K> {
         self.skip_quiets
     }
 
+    /*
+    Orders captures by the captured piece, then the capturing piece, without SEE
+    Losing captures are no longer held back until after the quiets
+    */
+    pub fn set_mvv_lva(&mut self, value: bool) {
+        self.mvv_lva = value;
+    }
+
+    /*
+    Moves that respond to the threat found by null move pruning get a bonus:
+    moving the threatened piece away, capturing the threatening piece or
+    blocking the threat's path
+    */
+    fn threat_response(&self, make_move: Move) -> i16 {
+        if let Some(threat) = self.threat_move {
+            if make_move.from == threat.to
+                || make_move.to == threat.from
+                || get_between_rays(threat.from, threat.to).has(make_move.to)
+            {
+                return THREAT_RESPONSE;
+            }
+        }
+        0
+    }
+
+    /*
+    Quiet moves are everything the quiet phases generate, castling and en passant included
+    */
+    fn is_legal_quiet(board: &Board, make_move: Move) -> bool {
+        !board.colors(!board.side_to_move()).has(make_move.to) && is_legal(board, make_move)
+    }
+
     fn set_phase(&mut self) {
         if self.skip_quiets {
             match self.gen_type {
@@ -87,36 +292,42 @@ impl<const K: usize> OrderedMoveGen<K> {
         board: &Board,
         hist: &HistoryTable,
         c_hist: &HistoryTable,
+        lc_hist: &LosingCaptureHistory,
         cm_hist: &DoubleMoveHistory,
     ) -> Option<Move> {
         self.set_phase();
         if self.gen_type == GenType::PvMove {
             self.gen_type = GenType::CalcCaptures;
+            /*
+            Nodes that cut off on the TT move never generate their moves
+            */
             if let Some(pv_move) = self.pv_move {
-                for &piece_moves in &self.move_list {
-                    if piece_moves.from != pv_move.from {
-                        continue;
-                    }
-                    for mv in piece_moves {
-                        if mv == pv_move {
-                            return Some(pv_move);
-                        }
-                    }
+                if is_legal(board, pv_move) {
+                    return Some(pv_move);
                 }
                 self.pv_move = None;
             }
         }
         if self.gen_type == GenType::CalcCaptures {
-            for &piece_moves in &self.move_list {
+            let mut move_list = ArrayVec::<PieceMoves, 18>::new();
+            board.generate_moves_for(capturers(board), |piece_moves| {
+                move_list.push(piece_moves);
+                false
+            });
+            for piece_moves in move_list {
                 let mut piece_moves = piece_moves;
                 piece_moves.to &= board.colors(!board.side_to_move());
                 for make_move in piece_moves {
                     if Some(make_move) == self.pv_move {
                         continue;
                     }
-                    let expected_gain =
+                    let mut expected_gain =
                         c_hist.get(board.side_to_move(), make_move.from, make_move.to)
-                            + search::see::<1>(&board, make_move) * 32;
+                            + search::see::<1>(board, make_move, &self.see_values) * 32
+                            + self.threat_response(make_move);
+                    if self.mvv_lva {
+                        expected_gain -= self.see_values[piece_moves.piece as usize];
+                    }
                     self.captures.push((make_move, expected_gain, None));
                 }
             }
@@ -127,13 +338,16 @@ impl<const K: usize> OrderedMoveGen<K> {
             let mut max = THRESHOLD;
             let mut best_index = None;
             for (index, (make_move, score, see)) in self.captures.iter_mut().enumerate() {
-                if *score > max {
-                    let see_score = see.unwrap_or_else(|| search::see::<16>(&board, *make_move));
+                if *score > max && !self.mvv_lva {
+                    let see_score = see
+                        .unwrap_or_else(|| search::see::<16>(board, *make_move, &self.see_values));
                     *see = Some(see_score);
-                    if see_score < 0 {
+                    if see_score < 0 && !lc_hist.rescues(board, *make_move) {
                         *score += LOSING_CAPTURE;
                         continue;
                     }
+                }
+                if *score > max {
                     max = *score;
                     best_index = Some(index);
                 }
@@ -144,16 +358,40 @@ impl<const K: usize> OrderedMoveGen<K> {
                 self.gen_type = if self.skip_quiets {
                     GenType::BadCaptures
                 } else {
-                    GenType::GenQuiet
+                    GenType::Killer
                 }
             }
         }
+        /*
+        Killers come from sibling nodes, so they're only played if they're
+        legal quiet moves here, the quiets they're played before skip them
+        */
+        if self.gen_type == GenType::Killer {
+            for make_move in self.killer_entry.by_ref() {
+                if Some(make_move) != self.pv_move && Self::is_legal_quiet(board, make_move) {
+                    self.killers.push(make_move);
+                    return Some(make_move);
+                }
+            }
+            self.gen_type = GenType::GenQuiet;
+        }
         if self.gen_type == GenType::GenQuiet {
-            for &piece_moves in &self.move_list {
+            let cont_entry = self.prev_move.map(|prev_move| {
+                let prev_move_piece = board.piece_on(prev_move.to).unwrap_or(Piece::King);
+                cm_hist.entry(board.side_to_move(), prev_move_piece, prev_move.to)
+            });
+            let threats = Threats::new(board);
+            let mut move_list = ArrayVec::<PieceMoves, 18>::new();
+            board.generate_moves(|piece_moves| {
+                move_list.push(piece_moves);
+                false
+            });
+            for piece_moves in move_list {
                 let mut piece_moves = piece_moves;
                 piece_moves.to &= !board.colors(!board.side_to_move());
+                let threatened = threats.against(piece_moves.piece);
                 for make_move in piece_moves {
-                    if Some(make_move) == self.pv_move {
+                    if Some(make_move) == self.pv_move || self.killers.contains(&make_move) {
                         continue;
                     }
                     if let Some(piece) = make_move.promotion {
@@ -167,38 +405,28 @@ impl<const K: usize> OrderedMoveGen<K> {
                         };
                         continue;
                     }
-                    let mut score = 0;
-                    let piece = board.piece_on(make_move.from).unwrap();
+                    let mut score =
+                        self.threat_response(make_move) + self.repetition(board, make_move);
+                    /*
+                    Quiets that put a piece where a less valuable piece can take it are
+                    ordered later, ones that move a threatened piece to safety earlier
+                    */
+                    if threatened.has(make_move.to) {
+                        score -= THREATENED_SQUARE;
+                    } else if threatened.has(make_move.from) {
+                        score += THREAT_ESCAPE;
+                    }
+                    let piece = piece_moves.piece;
 
                     score += hist.get(board.side_to_move(), make_move.from, make_move.to);
-                    if let Some(prev_move) = self.prev_move {
-                        let prev_move_piece = board.piece_on(prev_move.to).unwrap_or(Piece::King);
-                        score += cm_hist.get(
-                            board.side_to_move(),
-                            prev_move_piece,
-                            prev_move.to,
-                            piece,
-                            make_move.to,
-                        );
+                    if let Some(cont_entry) = cont_entry {
+                        score += cont_entry[piece as usize][make_move.to as usize];
                     }
 
                     self.quiets.push((make_move, score));
                 }
             }
-            self.gen_type = GenType::Killer;
-        }
-        //Assumes Killer Moves won't repeat
-        if self.gen_type == GenType::Killer {
-            for make_move in self.killer_entry.clone() {
-                let position = self
-                    .quiets
-                    .iter()
-                    .position(|(cmp_move, _)| make_move == *cmp_move);
-                if let Some(position) = position {
-                    self.quiets.swap_remove(position);
-                    return Some(make_move);
-                }
-            }
+            partial_insertion_sort(&mut self.quiets, THRESHOLD);
             self.gen_type = GenType::CounterMove;
         }
         if self.gen_type == GenType::CounterMove {
@@ -209,22 +437,15 @@ impl<const K: usize> OrderedMoveGen<K> {
                     .iter()
                     .position(|(cmp_move, _)| counter_move == *cmp_move);
                 if let Some(position) = position {
-                    self.quiets.swap_remove(position);
+                    self.quiets.remove(position);
                     return Some(counter_move);
                 }
             }
         }
         if self.gen_type == GenType::Quiet {
-            let mut max = 0;
-            let mut best_index = None;
-            for (index, &(_, score)) in self.quiets.iter().enumerate() {
-                if best_index.is_none() || score > max {
-                    max = score;
-                    best_index = Some(index);
-                }
-            }
-            if let Some(index) = best_index {
-                return Some(self.quiets.swap_remove(index).0);
+            if let Some(&(make_move, _)) = self.quiets.get(self.quiet_index) {
+                self.quiet_index += 1;
+                return Some(make_move);
             } else {
                 self.gen_type = GenType::BadCaptures;
             };
@@ -245,46 +466,193 @@ impl<const K: usize> OrderedMoveGen<K> {
     }
 }
 
+/*
+Generates quiet moves that give check from the squares that check the enemy king,
+without generating every legal move:
+- Direct checks land on a square that attacks the enemy king
+- Discovered checks move the only piece between one of our sliders and the enemy king off the line
+- Castling checks with the rook on its castled square, the king can't give check itself
+Promotions and en passant are left to the captures
+*/
+pub fn quiet_checks(board: &Board) -> ArrayVec<Move, MAX_MOVES> {
+    let stm = board.side_to_move();
+    let king = board.king(!stm);
+    let occupied = board.occupied();
+    let empty = !occupied;
+    let ours = board.colors(stm);
+
+    let diagonal = get_bishop_moves(king, occupied);
+    let orthogonal = get_rook_moves(king, occupied);
+    let check_squares = |piece: Piece| match piece {
+        Piece::Pawn => get_pawn_attacks(king, !stm),
+        Piece::Knight => get_knight_moves(king),
+        Piece::Bishop => diagonal,
+        Piece::Rook => orthogonal,
+        Piece::Queen => diagonal | orthogonal,
+        Piece::King => BitBoard::EMPTY,
+    };
+
+    let diagonal_sliders = board.pieces(Piece::Bishop) | board.pieces(Piece::Queen);
+    let orthogonal_sliders = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    let sliders = ours
+        & ((diagonal_sliders & get_bishop_rays(king)) | (orthogonal_sliders & get_rook_rays(king)));
+    let mut discoverers = BitBoard::EMPTY;
+    for slider in sliders {
+        let blockers = get_between_rays(slider, king) & occupied;
+        if blockers.popcnt() == 1 && blockers & ours == blockers {
+            discoverers |= blockers;
+        }
+    }
+
+    let promotion_rank = Rank::Eighth.relative_to(stm).bitboard();
+    let mut checks = ArrayVec::new();
+    for piece in Piece::ALL {
+        for from in ours & board.pieces(piece) {
+            let moves = match piece {
+                Piece::Pawn => get_pawn_quiets(from, stm, occupied) & !promotion_rank,
+                Piece::Knight => get_knight_moves(from),
+                Piece::Bishop => get_bishop_moves(from, occupied),
+                Piece::Rook => get_rook_moves(from, occupied),
+                Piece::Queen => get_bishop_moves(from, occupied) | get_rook_moves(from, occupied),
+                Piece::King => get_king_moves(from),
+            } & empty;
+            let targets = if discoverers.has(from) {
+                moves & (check_squares(piece) | !get_line_rays(from, king))
+            } else {
+                moves & check_squares(piece)
+            };
+            for to in targets {
+                let make_move = Move {
+                    from,
+                    to,
+                    promotion: None,
+                };
+                if board.is_legal(make_move) {
+                    checks.push(make_move);
+                }
+            }
+        }
+    }
+
+    let castle_rights = board.castle_rights(stm);
+    let back_rank = Rank::First.relative_to(stm);
+    for rook in [castle_rights.short, castle_rights.long]
+        .into_iter()
+        .flatten()
+    {
+        let make_move = Move {
+            from: board.king(stm),
+            to: Square::new(rook, back_rank),
+            promotion: None,
+        };
+        if is_legal(board, make_move) {
+            let mut child = board.clone();
+            child.play_unchecked(make_move);
+            if !child.checkers().is_empty() {
+                checks.push(make_move);
+            }
+        }
+    }
+    checks
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum QSearchGenType {
     CalcCaptures,
     Captures,
+    QuietChecks,
 }
 
 pub struct QuiescenceSearchMoveGen {
     gen_type: QSearchGenType,
     queue: ArrayVec<(Move, i16, LazySee), MAX_MOVES>,
+    see_values: SeeValues,
+    evasions: bool,
+    quiet_checks: bool,
+    see_threshold: i16,
 }
 
 impl QuiescenceSearchMoveGen {
-    pub fn new() -> Self {
+    /*
+    The SEE scores of quiescence moves are used for pruning,
+    so the pruning values are expected here
+    */
+    pub fn new(see_values: SeeValues) -> Self {
         Self {
             gen_type: QSearchGenType::CalcCaptures,
             queue: ArrayVec::new(),
+            see_values,
+            evasions: false,
+            quiet_checks: false,
+            see_threshold: 0,
         }
     }
 
+    /*
+    Moves with a lower SEE are skipped instead of searched
+    */
+    pub fn set_see_threshold(&mut self, value: i16) {
+        self.see_threshold = value;
+    }
+
+    /*
+    Quiet moves that give check are tried once the captures run out
+    */
+    pub fn set_quiet_checks(&mut self, value: bool) {
+        self.quiet_checks = value;
+    }
+
+    /*
+    In check, every legal move is an evasion and is generated regardless of its SEE
+    */
     pub fn next(&mut self, board: &Board, c_hist: &HistoryTable) -> Option<(Move, i16)> {
         if self.gen_type == QSearchGenType::CalcCaptures {
-            board.generate_moves(|mut piece_moves| {
-                piece_moves.to &= board.colors(!board.side_to_move());
+            self.evasions = !board.checkers().is_empty();
+            let (pieces, targets) = if self.evasions {
+                (BitBoard::FULL, BitBoard::FULL)
+            } else {
+                (capturers(board), board.colors(!board.side_to_move()))
+            };
+            board.generate_moves_for(pieces, |mut piece_moves| {
+                piece_moves.to &= targets;
                 for make_move in piece_moves {
                     let expected_gain =
                         c_hist.get(board.side_to_move(), make_move.from, make_move.to)
-                            + search::see::<1>(&board, make_move) * 32;
+                            + search::see::<1>(board, make_move, &self.see_values) * 32;
                     self.queue.push((make_move, expected_gain, None));
                 }
                 false
             });
             self.gen_type = QSearchGenType::Captures;
         }
+        if let Some(next) = self.pick(board) {
+            return Some(next);
+        }
+        if self.gen_type == QSearchGenType::Captures && self.quiet_checks && !self.evasions {
+            self.gen_type = QSearchGenType::QuietChecks;
+            self.queue.clear();
+            /*
+            Castling is a king move onto our own rook, it doesn't exchange any material
+            */
+            let ours = board.colors(board.side_to_move());
+            for make_move in quiet_checks(board) {
+                let castles = ours.has(make_move.to);
+                self.queue.push((make_move, 0, castles.then_some(0)));
+            }
+            return self.pick(board);
+        }
+        None
+    }
+
+    fn pick(&mut self, board: &Board) -> Option<(Move, i16)> {
         let mut max = 0;
         let mut best_index = None;
         for (index, (make_move, score, see)) in self.queue.iter_mut().enumerate() {
             if best_index.is_none() || *score > max {
-                let see_score = see.unwrap_or_else(|| search::see::<16>(&board, *make_move));
+                let see_score =
+                    see.unwrap_or_else(|| search::see::<16>(board, *make_move, &self.see_values));
                 *see = Some(see_score);
-                if see_score < 0 {
+                if see_score < self.see_threshold && !self.evasions {
                     continue;
                 }
                 max = *score;
@@ -299,3 +667,302 @@ impl QuiescenceSearchMoveGen {
         }
     }
 }
+
+#[test]
+fn quiet_check_generation() {
+    use std::str::FromStr;
+
+    const FENS: &[&str] = &[
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "4k3/8/8/8/8/8/3N4/3RK2B w - - 0 1",
+        "4k3/8/2N5/1B6/8/8/8/R3K3 w Q - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "3k4/8/8/8/3Q4/8/8/3RK3 b - - 0 1",
+        "5k2/8/8/8/8/8/8/4K2R w K - 0 1",
+        "3k4/8/8/8/8/8/8/R3K3 w Q - 0 1",
+        "r3k3/8/8/8/8/8/8/3K4 b q - 0 1",
+        "5k2/8/8/8/8/8/5P2/4K2R w K - 0 1",
+    ];
+    for fen in FENS {
+        let board = Board::from_str(fen).unwrap();
+        let mut expected = vec![];
+        board.generate_moves(|piece_moves| {
+            for make_move in piece_moves {
+                let mut child = board.clone();
+                child.play_unchecked(make_move);
+                let quiet = board.color_on(make_move.to) != Some(!board.side_to_move())
+                    && !(piece_moves.piece == Piece::Pawn
+                        && make_move.from.file() != make_move.to.file())
+                    && make_move.promotion.is_none();
+                if quiet && !child.checkers().is_empty() {
+                    expected.push(make_move);
+                }
+            }
+            false
+        });
+        let mut checks = quiet_checks(&board).to_vec();
+        let key = |make_move: &Move| (make_move.from as u8, make_move.to as u8);
+        expected.sort_by_key(key);
+        checks.sort_by_key(key);
+        assert_eq!(checks, expected, "{}", fen);
+    }
+
+    /*
+    Castling moves the king onto its rook, the rook gives the check
+    */
+    let castling_checks = |fen: &str| quiet_checks(&Board::from_str(fen).unwrap()).to_vec();
+    let castles = |uci: &str| uci.parse::<Move>().unwrap();
+    assert!(castling_checks(FENS[6]).contains(&castles("e1h1")));
+    assert!(castling_checks(FENS[7]).contains(&castles("e1a1")));
+    assert!(castling_checks(FENS[8]).contains(&castles("e8a8")));
+    assert!(!castling_checks(FENS[9]).contains(&castles("e1h1")));
+}
+
+#[test]
+fn quiescence_moves() {
+    use crate::bm::bm_runner::params::SearchParams;
+    use std::str::FromStr;
+
+    let c_hist = HistoryTable::new();
+    let see_values = SearchParams::new().see_prune_values();
+    let generated = |fen: &str, checks: bool| {
+        let board = Board::from_str(fen).unwrap();
+        let mut move_gen = QuiescenceSearchMoveGen::new(see_values);
+        move_gen.set_quiet_checks(checks);
+        let mut moves = vec![];
+        while let Some((make_move, _)) = move_gen.next(&board, &c_hist) {
+            moves.push(make_move.to_string());
+        }
+        moves
+    };
+
+    /*
+    Every evasion is generated in check, even the ones that hang the king's defender
+    */
+    let evasions = generated("4k3/8/8/8/8/8/3q4/R3K3 w - - 0 1", false);
+    assert_eq!(evasions.len(), 2);
+    assert!(evasions.contains(&"e1d2".to_string()));
+    assert!(evasions.contains(&"e1f1".to_string()));
+
+    /*
+    Quiet checks follow the captures, the ones that hang a piece are left out
+    */
+    let fen = "6k1/5ppp/8/2q5/8/8/8/R3K3 w - - 0 1";
+    assert!(generated(fen, false).is_empty());
+    assert_eq!(generated(fen, true), vec!["a1a8"]);
+    assert!(generated("6k1/5ppp/2q5/8/8/8/8/R3K3 w - - 0 1", true).is_empty());
+}
+
+#[test]
+fn killer_moves() {
+    use super::move_entry::{MoveEntry, MAX_KILLERS};
+    use crate::bm::bm_runner::params::SearchParams;
+    use std::str::FromStr;
+
+    let hist = HistoryTable::new();
+    let c_hist = HistoryTable::new();
+    let lc_hist = LosingCaptureHistory::new();
+    let cm_hist = DoubleMoveHistory::new();
+    let board = Board::from_str("r3k2r/8/8/8/8/8/3q4/R3K2R w KQkq - 0 1").unwrap();
+    let mut killers = MoveEntry::<MAX_KILLERS>::new(2);
+    for killer in ["e1h1", "e1f1"] {
+        killers.push(killer.parse().unwrap());
+    }
+    let mut move_gen = OrderedMoveGen::new(
+        None,
+        None,
+        None,
+        None,
+        killers.into_iter(),
+        SearchParams::new().see_order_values(),
+    );
+    let mut moves = vec![];
+    while let Some(make_move) = move_gen.next(&board, &hist, &c_hist, &lc_hist, &cm_hist) {
+        moves.push(make_move.to_string());
+    }
+
+    /*
+    Castling out of check is skipped, the legal killer follows the winning capture
+    */
+    let mut legal_moves = 0;
+    board.generate_moves(|piece_moves| {
+        legal_moves += piece_moves.len();
+        false
+    });
+    assert_eq!(moves[..2], ["e1d2", "e1f1"]);
+    assert_eq!(moves.len(), legal_moves);
+    assert!(!moves.contains(&"e1h1".to_string()));
+}
+
+#[test]
+fn threats() {
+    use cozy_chess::Square;
+    use std::str::FromStr;
+
+    let board = Board::from_str("4k3/8/8/3p4/8/2n5/8/R3K2Q w - - 0 1").unwrap();
+    let threats = Threats::new(&board);
+    assert_eq!(
+        threats.against(Piece::Bishop),
+        Square::C4.bitboard() | Square::E4.bitboard()
+    );
+    assert!(threats.against(Piece::Rook).has(Square::D1));
+    assert!(!threats.against(Piece::Knight).has(Square::D1));
+    assert!(threats.against(Piece::King).is_empty());
+    assert!(!threats.any(&board));
+
+    let board = Board::from_str("4k3/8/8/3p4/8/2n5/8/R2RK2Q w - - 0 1").unwrap();
+    assert!(Threats::new(&board).any(&board));
+}
+
+#[test]
+fn legality_matches_generation() {
+    use cozy_chess::Square;
+
+    const FENS: &[&str] = &[
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/8/8/8/8/8/3q4/R3K2R w KQkq - 0 1",
+        "r3k2r/8/8/8/8/5q2/8/R3K2R w KQkq - 0 1",
+        "8/8/8/KPp4r/8/8/8/4k3 w - c6 0 2",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        /*
+        Chess960 castling with the king next to a rook
+        */
+        "1r2k1r1/8/8/8/8/8/8/RK4R1 w GAgb - 0 1",
+    ];
+    for fen in FENS {
+        let board = Board::from_fen(fen, fen.contains("GA")).unwrap();
+        let mut legal = vec![];
+        board.generate_moves(|piece_moves| {
+            legal.extend(piece_moves);
+            false
+        });
+        for from in Square::ALL {
+            for to in Square::ALL {
+                for promotion in [None, Some(Piece::Knight), Some(Piece::Queen)] {
+                    let make_move = Move {
+                        from,
+                        to,
+                        promotion,
+                    };
+                    assert_eq!(
+                        is_legal(&board, make_move),
+                        legal.contains(&make_move),
+                        "{} {}",
+                        fen,
+                        make_move
+                    );
+                }
+            }
+        }
+        assert!(!is_legal(
+            &board,
+            Move {
+                from: Square::A1,
+                to: Square::A1,
+                promotion: None
+            }
+        ));
+    }
+}
+
+#[test]
+fn partial_sort() {
+    let make_move = |index: usize| Move {
+        from: cozy_chess::Square::index(index),
+        to: cozy_chess::Square::A1,
+        promotion: None,
+    };
+    let scores = [5, -2000, 7, 5, -3000, 0, 12];
+    let mut moves = scores
+        .iter()
+        .enumerate()
+        .map(|(index, &score)| (make_move(index), score))
+        .collect::<Vec<_>>();
+    partial_insertion_sort(&mut moves, THRESHOLD);
+
+    /*
+    Equal scores keep their order, moves below the limit come last
+    */
+    let order = moves
+        .iter()
+        .map(|(make_move, _)| make_move.from as usize)
+        .collect::<Vec<_>>();
+    assert_eq!(order[..5], [6, 2, 0, 3, 5]);
+    let mut unsorted = order[5..].to_vec();
+    unsorted.sort();
+    assert_eq!(unsorted, [1, 4]);
+}
+
+#[test]
+fn mvv_lva_order() {
+    use super::move_entry::{MoveEntry, MAX_KILLERS};
+    use crate::bm::bm_runner::params::SearchParams;
+    use std::str::FromStr;
+
+    let hist = HistoryTable::new();
+    let c_hist = HistoryTable::new();
+    let lc_hist = LosingCaptureHistory::new();
+    let cm_hist = DoubleMoveHistory::new();
+    let board = Board::from_str("k7/8/2p5/3p4/8/8/8/K2Q4 w - - 0 1").unwrap();
+    let generated = |mvv_lva: bool| {
+        let killers = MoveEntry::<MAX_KILLERS>::new(2);
+        let see_values = SearchParams::new().see_order_values();
+        let mut move_gen =
+            OrderedMoveGen::new(None, None, None, None, killers.into_iter(), see_values);
+        move_gen.set_mvv_lva(mvv_lva);
+        let mut moves = vec![];
+        while let Some(make_move) = move_gen.next(&board, &hist, &c_hist, &lc_hist, &cm_hist) {
+            moves.push(make_move.to_string());
+        }
+        moves
+    };
+
+    /*
+    The queen capture loses the queen, only SEE holds it back until after the quiets
+    */
+    assert_eq!(generated(false).last().unwrap(), "d1d5");
+    assert_eq!(generated(true)[0], "d1d5");
+}
+
+#[test]
+fn capture_generation() {
+    use super::move_entry::{MoveEntry, MAX_KILLERS};
+    use crate::bm::bm_runner::params::SearchParams;
+    use std::str::FromStr;
+
+    const FENS: &[&str] = &[
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "4k3/8/8/8/1b6/8/3N4/4K2r w - - 0 1",
+        "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+    ];
+    let hist = HistoryTable::new();
+    let c_hist = HistoryTable::new();
+    let lc_hist = LosingCaptureHistory::new();
+    let cm_hist = DoubleMoveHistory::new();
+    for fen in FENS {
+        let board = Board::from_str(fen).unwrap();
+        let killers = MoveEntry::<MAX_KILLERS>::new(2);
+        let see_values = SearchParams::new().see_order_values();
+        let mut move_gen =
+            OrderedMoveGen::new(None, None, None, None, killers.into_iter(), see_values);
+        move_gen.set_skip_quiets(true);
+        let mut captures = vec![];
+        while let Some(make_move) = move_gen.next(&board, &hist, &c_hist, &lc_hist, &cm_hist) {
+            captures.push(make_move);
+        }
+        let mut expected = vec![];
+        board.generate_moves(|mut piece_moves| {
+            piece_moves.to &= board.colors(!board.side_to_move());
+            expected.extend(piece_moves);
+            false
+        });
+        let key = |make_move: &Move| make_move.to_string();
+        captures.sort_by_key(key);
+        expected.sort_by_key(key);
+        assert_eq!(captures, expected, "{}", fen);
+    }
+}
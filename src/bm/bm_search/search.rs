@@ -1,17 +1,20 @@
 use arrayvec::ArrayVec;
-use cozy_chess::{BitBoard, Board, Move, Piece};
+use cozy_chess::{BitBoard, Board, Color, Move, Piece, Rank};
 
 use crate::bm::bm_runner::ab_runner::{LocalContext, SharedContext, MAX_PLY};
+use crate::bm::bm_runner::params::{SearchParams, SeeValues};
 use crate::bm::bm_search::move_entry::MoveEntry;
 use crate::bm::bm_util::eval::Depth::Next;
 use crate::bm::bm_util::eval::Evaluation;
 use crate::bm::bm_util::h_table;
+use crate::bm::bm_util::phase::Phase;
 use crate::bm::bm_util::position::Position;
-use crate::bm::bm_util::t_table::EntryType;
 use crate::bm::bm_util::t_table::EntryType::{Exact, LowerBound, UpperBound};
+use crate::bm::bm_util::t_table::{Analysis, EntryType};
+use crate::bm::bm_util::tb::Wdl;
 
-use super::move_gen::OrderedMoveGen;
 use super::move_gen::QuiescenceSearchMoveGen;
+use super::move_gen::{OrderedMoveGen, Threats};
 
 pub trait SearchType {
     const NM: bool;
@@ -42,33 +45,101 @@ impl SearchType for NoNm {
 }
 
 #[inline]
-const fn do_rev_fp(depth: u32) -> bool {
-    depth < 7
+fn do_rev_fp(params: &SearchParams, depth: u32) -> bool {
+    depth < params.rev_fp_depth
 }
 
+/*
+The margin grows when the side to move has a piece under threat since the static eval
+doesn't see it being lost, and at odd depths where the side to move gets the last move
+*/
 #[inline]
-const fn rev_fp(depth: u32, improving: bool) -> i16 {
-    (depth as i16 - improving as i16) * 50
+fn rev_fp(params: &SearchParams, depth: u32, improving: bool, threatened: bool) -> i16 {
+    depth as i16 * params.rev_fp_margin - improving as i16 * params.rev_fp_improving
+        + threatened as i16 * params.rev_fp_threat
+        + (depth % 2) as i16 * params.rev_fp_odd_depth
 }
 
+/*
+The TT score is a better estimate of the position than the static eval
+whenever its bound is on the right side of the static eval
+*/
 #[inline]
-fn do_nmp<Search: SearchType>(board: &Board, depth: u32, eval: i16, beta: i16) -> bool {
+fn tt_eval(tt_entry: Option<Analysis>, eval: Evaluation) -> Evaluation {
+    match tt_entry {
+        Some(entry) if !entry.score().is_mate() => match entry.entry_type() {
+            Exact => entry.score(),
+            LowerBound if entry.score() > eval => entry.score(),
+            UpperBound if entry.score() < eval => entry.score(),
+            _ => eval,
+        },
+        _ => eval,
+    }
+}
+
+#[inline]
+fn do_nmp<Search: SearchType>(
+    params: &SearchParams,
+    phase: Phase,
+    depth: u32,
+    eval: i16,
+    beta: i16,
+) -> bool {
     Search::NM
-        && depth > 4
+        && depth > params.nmp_depth
         && eval >= beta
-        && (board.pieces(Piece::Pawn) | board.pieces(Piece::King)) != board.occupied()
+        && phase.pieces() >= params.nmp_min_pieces
 }
 
+/*
+With few pieces left, zugzwang and horizon effects are more likely so pruning is relaxed
+*/
 #[inline]
-fn nmp_depth(depth: u32, eval: i16, beta: i16) -> u32 {
+fn lmp_limit(params: &SearchParams, limit: usize, phase: Phase) -> usize {
+    if phase.pieces() <= params.endgame_pieces {
+        (limit as f32 * params.endgame_lmp_scale) as usize
+    } else {
+        limit
+    }
+}
+
+/*
+Positions where the side to move is likely to be in zugzwang:
+no major pieces and either at most one minor piece or only blocked pawns
+Null move pruning is always verified in these positions
+*/
+#[inline]
+fn zugzwang_prone(board: &Board) -> bool {
+    let stm = board.side_to_move();
+    let own = board.colors(stm);
+    let majors = (board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) & own;
+    if !majors.is_empty() {
+        return false;
+    }
+    let minors = (board.pieces(Piece::Knight) | board.pieces(Piece::Bishop)) & own;
+    if minors.popcnt() <= 1 {
+        return true;
+    }
+    let pawns = board.pieces(Piece::Pawn) & own;
+    let pushes = match stm {
+        Color::White => BitBoard(pawns.0 << 8),
+        Color::Black => BitBoard(pawns.0 >> 8),
+    };
+    !pawns.is_empty() && (pushes & board.occupied()).popcnt() == pawns.popcnt()
+}
+
+#[inline]
+fn nmp_depth(params: &SearchParams, depth: u32, eval: i16, beta: i16) -> u32 {
     assert!(eval >= beta);
-    let r = 3 + depth / 4 + ((eval - beta) / 200) as u32;
+    let r = params.nmp_base_reduction
+        + depth / params.nmp_depth_div
+        + ((eval - beta) / params.nmp_eval_div) as u32;
     depth.saturating_sub(r).max(1)
 }
 
 #[inline]
-const fn iir(depth: u32) -> u32 {
-    if depth >= 4 {
+fn iir(params: &SearchParams, depth: u32) -> u32 {
+    if depth >= params.iir_depth {
         1
     } else {
         0
@@ -76,30 +147,117 @@ const fn iir(depth: u32) -> u32 {
 }
 
 #[inline]
-const fn fp(depth: u32) -> i16 {
-    depth as i16 * 100
+fn fp(params: &SearchParams, depth: u32) -> i16 {
+    depth as i16 * params.fp_margin
+}
+
+#[inline]
+fn see_fp(params: &SearchParams, depth: u32) -> i16 {
+    depth as i16 * params.see_fp_margin
 }
 
 #[inline]
-const fn see_fp(depth: u32) -> i16 {
-    depth as i16 * 100
+fn hp(params: &SearchParams, depth: u32) -> i32 {
+    -h_table::MAX_VALUE * ((depth * depth) as i32) / params.hp_div
 }
 
 #[inline]
-const fn hp(depth: u32) -> i32 {
-    -h_table::MAX_VALUE * ((depth * depth) as i32) / 64
+fn history_lmr(params: &SearchParams, history: i16) -> i16 {
+    history / params.history_lmr_div
 }
 
 #[inline]
-const fn history_lmr(history: i16) -> i16 {
-    history / 80
+fn capture_history_lmr(params: &SearchParams, history: i16) -> i16 {
+    history / params.capture_history_lmr_div
 }
 
+/*
+The most a single capture can gain in this position:
+the value of the highest valued enemy piece and a possible promotion
+*/
 #[inline]
-const fn q_see_threshold() -> i16 {
-    200
+fn q_delta(params: &SearchParams, board: &Board) -> i16 {
+    let stm = board.side_to_move();
+    let enemy = board.colors(!stm);
+    let values = params.see_prune_values();
+    let max_capture = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight]
+        .into_iter()
+        .find(|&piece| !(board.pieces(piece) & enemy).is_empty())
+        .map_or(values[Piece::Pawn as usize], |piece| values[piece as usize]);
+    let promotion_rank = Rank::Seventh.relative_to(stm).bitboard();
+    let promotion = if (board.pieces(Piece::Pawn) & board.colors(stm) & promotion_rank).is_empty() {
+        0
+    } else {
+        values[Piece::Queen as usize] - values[Piece::Pawn as usize]
+    };
+    max_capture + promotion + params.q_see_threshold
 }
 
+/*
+Least SEE a quiescence move needs to be searched, captures may lose up to a margin
+but the further the stand pat score is below alpha, the more they have to win
+Mate scores aren't a distance in material so they don't tighten the threshold
+*/
+#[inline]
+fn q_see_threshold(params: &SearchParams, stand_pat: Evaluation, alpha: Evaluation) -> i16 {
+    if alpha.is_mate() || stand_pat.is_mate() {
+        return -params.q_see_margin;
+    }
+    let gap = (alpha.raw() as i32 - stand_pat.raw() as i32).max(0);
+    let tightening = (gap / params.q_see_margin_div as i32).min(i16::MAX as i32) as i16;
+    tightening - params.q_see_margin
+}
+
+/*
+Root moves can be excluded by MultiPV or restricted by searchmoves
+*/
+#[inline]
+fn searched_root_move(
+    local_context: &LocalContext,
+    shared_context: &SharedContext,
+    make_move: Move,
+) -> bool {
+    let search_moves = shared_context.get_search_moves();
+    !local_context.root_exclusions().contains(&make_move)
+        && (search_moves.is_empty() || search_moves.contains(&make_move))
+}
+
+/*
+In PV nodes, TT cutoffs are only sound if a deeper search can't change the score
+This is the case for exact mate and tablebase scores
+Exact draw scores are left out, the TT can't tell them apart from an evaluation of 0
+(tablebase draws aren't stored and contempt moves the others away from 0),
+and repetition and fifty move draws depend on the path to the position,
+reusing them in another line would report draws that aren't there
+These cutoffs truncate the PV, so they're skipped in analyse mode
+*/
+#[inline]
+fn pv_tt_cutoff(entry_type: EntryType, score: Evaluation, analyse_mode: bool) -> bool {
+    entry_type == Exact && (score.is_mate() || score.is_tb_score()) && !analyse_mode
+}
+
+/*
+With contempt, the side to move at the root scores draws as slightly lost
+*/
+#[inline]
+fn draw_score(
+    shared_context: &SharedContext,
+    local_context: &LocalContext,
+    pos: &Position,
+) -> Evaluation {
+    let contempt = shared_context.contempt();
+    if pos.board().side_to_move() == local_context.stm() {
+        Evaluation::new(-contempt)
+    } else {
+        Evaluation::new(contempt)
+    }
+}
+
+/*
+Cut nodes are non-PV nodes that are expected to fail high,
+children of cut nodes are expected to fail low and vice versa
+*/
+#[allow(clippy::too_many_arguments)]
 pub fn search<Search: SearchType>(
     pos: &mut Position,
     local_context: &mut LocalContext,
@@ -107,33 +265,46 @@ pub fn search<Search: SearchType>(
     ply: u32,
     mut depth: u32,
     mut alpha: Evaluation,
-    beta: Evaluation,
+    mut beta: Evaluation,
+    cut_node: bool,
 ) -> Evaluation {
+    debug_assert!(!(Search::PV && cut_node));
+    let params = shared_context.get_params();
     local_context.search_stack_mut()[ply as usize].pv_len = 0;
+    local_context.search_stack_mut()[ply as usize].threat_move = None;
 
-    if ply != 0 && shared_context.abort_search(local_context.nodes()) {
+    if ply != 0 && shared_context.abort_search(local_context.batch_nodes()) {
         local_context.trigger_abort();
         return Evaluation::min();
     }
+    local_context.heartbeat();
 
     local_context.update_sel_depth(ply);
     if ply != 0 && pos.forced_draw(ply) {
         local_context.increment_nodes();
-        return Evaluation::new(0);
+        return draw_score(shared_context, local_context, pos);
     }
 
     /*
     At depth 0, we run Quiescence Search
     */
     if depth == 0 || ply >= MAX_PLY {
-        return q_search(pos, local_context, shared_context, ply, alpha, beta);
+        return q_search(
+            pos,
+            local_context,
+            shared_context,
+            ply,
+            alpha,
+            beta,
+            params.q_check_plies,
+        );
     }
 
     let skip_move = local_context.search_stack()[ply as usize].skip_move;
     let tt_entry = if skip_move.is_some() {
         None
     } else {
-        shared_context.get_t_table().get(pos.board())
+        shared_context.get_t_table().get(pos.board(), ply)
     };
 
     local_context.increment_nodes();
@@ -169,17 +340,82 @@ pub fn search<Search: SearchType>(
                     }
                 }
             }
+        } else if Search::PV
+            && ply != 0
+            && entry.depth() >= depth
+            && pv_tt_cutoff(
+                entry.entry_type(),
+                entry.score(),
+                shared_context.analyse_mode(),
+            )
+            && pos.board().is_legal(entry.table_move())
+        {
+            /*
+            The PV is cut short at the TT move so it stays legal
+            */
+            local_context.search_stack_mut()[ply as usize].update_pv(entry.table_move(), &[]);
+            return entry.score();
         }
     } else {
         *local_context.tt_misses() += 1;
     }
 
+    /*
+    Tablebases
+    Positions covered by the tablebases get their WDL score,
+    wins and losses are only bounds since shorter wins may still be found
+    */
+    if ply != 0 && skip_move.is_none() && pos.half_ply() == 0 {
+        if let Some(wdl) = shared_context.get_tablebase().probe_wdl(pos.board()) {
+            shared_context.increment_tb_hits();
+            let (score, entry_type) = match wdl {
+                Wdl::Win => (Evaluation::new_tb_win(ply), LowerBound),
+                Wdl::Loss => (-Evaluation::new_tb_win(ply), UpperBound),
+                _ => (Evaluation::new(0), Exact),
+            };
+            match entry_type {
+                Exact => return score,
+                LowerBound if score >= beta => return score,
+                UpperBound if score <= alpha => return score,
+                _ => {}
+            }
+        }
+    }
+
+    /*
+    With MultiPV, the root TT move of secondary lines is usually
+    the best move of a previous line which is excluded from the search
+    */
+    if ply == 0 {
+        best_move = best_move
+            .filter(|&make_move| searched_root_move(local_context, shared_context, make_move));
+        local_context.set_root_depth(depth);
+        local_context.search_stack_mut()[0].extensions = 0;
+    }
+
+    /*
+    Children searched before the move loop (null move and its verification)
+    inherit the node's extensions, the move loop adds each move's own extension
+    */
+    let extensions = local_context.search_stack()[ply as usize].extensions;
+    local_context.search_stack_mut()[ply as usize + 1].extensions = extensions;
+
     let in_check = pos.board().checkers() != BitBoard::EMPTY;
 
-    let eval = if skip_move.is_none() {
-        pos.get_eval(local_context.stm(), local_context.eval())
-    } else {
-        local_context.search_stack()[ply as usize].eval
+    /*
+    The static eval is taken from the TT when it's there instead of being evaluated again
+    */
+    let eval_params = params.eval_params();
+    let static_eval = skip_move.is_none().then(|| {
+        tt_entry
+            .and_then(|entry| entry.static_eval())
+            .unwrap_or_else(|| pos.static_eval(&eval_params))
+    });
+    let eval = match static_eval {
+        Some(static_eval) => {
+            static_eval + pos.eval_bonus(local_context.stm(), local_context.eval(), &eval_params)
+        }
+        None => local_context.search_stack()[ply as usize].eval,
     };
 
     local_context.search_stack_mut()[ply as usize].eval = eval;
@@ -188,15 +424,28 @@ pub fn search<Search: SearchType>(
     } else {
         eval > local_context.search_stack()[ply as usize - 2].eval
     };
+    let phase = pos.phase();
 
-    if !Search::PV && !in_check && skip_move.is_none() {
+    if !Search::PV && !in_check && skip_move.is_none() && !shared_context.mate_search() {
         /*
         Reverse Futility Pruning:
         If in a non PV node and evaluation is higher than beta + a depth dependent margin
         we assume we can at least achieve beta
         */
-        if do_rev_fp(depth) && eval - rev_fp(depth, improving) >= beta {
-            return eval;
+        let rev_fp_eval = tt_eval(tt_entry, eval);
+        if do_rev_fp(params, depth)
+            && rev_fp_eval >= beta
+            && rev_fp_eval
+                - rev_fp(
+                    params,
+                    depth,
+                    improving,
+                    Threats::new(pos.board()).any(pos.board()),
+                )
+                >= beta
+        {
+            local_context.prune_stats().rev_fp += 1;
+            return rev_fp_eval;
         }
 
         /*
@@ -204,13 +453,13 @@ pub fn search<Search: SearchType>(
         If in a non PV node and we can still achieve beta at a reduced depth after
         giving the opponent the side to move we can prune this node and return the evaluation
         While doing null move pruning, we also get the "best move" for the opponent in case
-        This is seen as the major threat in the current position and can be used in
-        move ordering for the next ply
+        This is seen as the major threat in the current position and is used in
+        move ordering to prefer moves that respond to the threat
         */
-        if do_nmp::<Search>(pos.board(), depth, eval.raw(), beta.raw()) && pos.null_move() {
+        if do_nmp::<Search>(params, phase, depth, eval.raw(), beta.raw()) && pos.null_move() {
             local_context.search_stack_mut()[ply as usize].move_played = None;
 
-            let nmp_depth = nmp_depth(depth, eval.raw(), beta.raw());
+            let nmp_depth = nmp_depth(params, depth, eval.raw(), beta.raw());
             let zw = beta >> Next;
             let search_score = search::<NoNm>(
                 pos,
@@ -220,11 +469,20 @@ pub fn search<Search: SearchType>(
                 nmp_depth,
                 zw,
                 zw + 1,
+                !cut_node,
             );
+            let threat_move = shared_context
+                .get_t_table()
+                .get(pos.board(), ply + 1)
+                .map(|entry| entry.table_move())
+                .filter(|threat| {
+                    pos.board().color_on(threat.from) == Some(pos.board().side_to_move())
+                });
             pos.unmake_move();
+            local_context.search_stack_mut()[ply as usize].threat_move = threat_move;
             let score = search_score << Next;
             if score >= beta {
-                let mut verified = depth < 10;
+                let mut verified = depth < params.nmp_verify_depth && !zugzwang_prone(pos.board());
                 if !verified {
                     let verification = search::<NoNm>(
                         pos,
@@ -234,22 +492,31 @@ pub fn search<Search: SearchType>(
                         nmp_depth,
                         alpha,
                         beta,
+                        true,
                     );
                     verified = verification >= beta;
                 }
                 if verified {
+                    local_context.prune_stats().nmp += 1;
                     return score;
                 }
             }
         }
     }
 
-    if tt_entry.is_none() {
-        depth -= iir(depth)
+    /*
+    Internal Iterative Reductions:
+    Without a TT move, PV and cut nodes are searched at a lower depth since their
+    move ordering is poor, all nodes search every move anyway
+    */
+    if tt_entry.is_none() && (Search::PV || cut_node) {
+        depth -= iir(params, depth)
     }
 
     while local_context.get_k_table().len() <= ply as usize {
-        local_context.get_k_table().push(MoveEntry::new());
+        local_context
+            .get_k_table()
+            .push(MoveEntry::new(params.killer_slots));
     }
 
     if let Some(entry) = local_context.get_k_table().get_mut(ply as usize + 1) {
@@ -276,28 +543,65 @@ pub fn search<Search: SearchType>(
 
     let killers = local_context.get_k_table()[ply as usize];
     let mut move_gen = OrderedMoveGen::new(
-        pos.board(),
         best_move,
         counter_move,
         prev_move.unwrap_or(None),
+        local_context.search_stack()[ply as usize].threat_move,
         killers.into_iter(),
+        params.see_order_values(),
     );
+    move_gen.set_repetitions(pos.repetition_hashes(), eval);
+    move_gen.set_mvv_lva(params.mvv_lva_order);
+
+    /*
+    Busy Markers:
+    Other threads reduce this node while we search it, the marker is
+    released when it goes out of scope
+    */
+    let _busy = (ply != 0 && skip_move.is_none() && depth >= params.busy_depth)
+        .then(|| {
+            shared_context
+                .get_t_table()
+                .mark_busy(pos.hash(), depth, local_context.thread())
+        })
+        .flatten();
 
     let mut moves_seen = 0;
     let mut move_exists = false;
 
     let mut quiets = ArrayVec::<Move, 64>::new();
+    let lmp_limit = lmp_limit(
+        params,
+        shared_context
+            .get_lmp_lookup()
+            .get(depth as usize, improving as usize),
+        phase,
+    );
     let mut captures = ArrayVec::<Move, 64>::new();
+    let mut losing_captures = ArrayVec::<Move, 64>::new();
 
     while let Some(make_move) = move_gen.next(
         pos.board(),
         local_context.get_h_table(),
         local_context.get_ch_table(),
+        local_context.get_lc_table(),
         local_context.get_cm_hist(),
     ) {
         if Some(make_move) == skip_move {
             continue;
         }
+        if ply == 0 && !searched_root_move(local_context, shared_context, make_move) {
+            continue;
+        }
+        if ply == 0 {
+            local_context.report_root_move(
+                shared_context,
+                pos.board(),
+                depth,
+                make_move,
+                moves_seen + 1,
+            );
+        }
         local_context.search_stack_mut()[ply as usize + 1].pv_len = 0;
 
         move_exists = true;
@@ -320,7 +624,7 @@ pub fn search<Search: SearchType>(
             )
         };
 
-        let mut extension = 0;
+        let mut extension: i16 = 0;
         let mut score;
 
         /*
@@ -350,6 +654,7 @@ pub fn search<Search: SearchType>(
                         depth / 2 - 1,
                         s_beta - 1,
                         s_beta,
+                        cut_node,
                     )
                 } else {
                     eval
@@ -368,18 +673,31 @@ pub fn search<Search: SearchType>(
                     our singular beta is above beta, we assume the move is good enough to beat beta
                     */
                     return s_beta;
+                } else if multi_cut && entry.score() >= beta {
+                    /*
+                    Negative Extensions:
+                    The move isn't singular but the TT entry still expects a cutoff,
+                    so the move is searched with reduced depth
+                    */
+                    extension = -1;
                 }
             }
         }
 
-        let non_mate_line = highest_score.map_or(false, |s: Evaluation| !s.is_mate());
+        let non_mate_line = !shared_context.mate_search()
+            && highest_score.is_some_and(|s: Evaluation| !s.is_mate());
         /*
         In non-PV nodes If a move isn't good enough to beat alpha - a static margin
         we assume it's safe to prune this move
         */
-        let do_fp = !Search::PV && non_mate_line && moves_seen > 0 && !is_capture && depth <= 7;
+        let do_fp = !Search::PV
+            && non_mate_line
+            && moves_seen > 0
+            && !is_capture
+            && depth <= params.fp_depth;
 
-        if do_fp && eval + fp(depth) <= alpha {
+        if do_fp && eval + fp(params, depth) <= alpha {
+            local_context.prune_stats().fp += 1;
             move_gen.set_skip_quiets(true);
             continue;
         }
@@ -387,14 +705,8 @@ pub fn search<Search: SearchType>(
         /*
         If a move is placed late in move ordering, we can safely prune it based on a depth related margin
         */
-        if !move_gen.skip_quiets()
-            && non_mate_line
-            && !is_capture
-            && quiets.len()
-                >= shared_context
-                    .get_lmp_lookup()
-                    .get(depth as usize, improving as usize)
-        {
+        if !move_gen.skip_quiets() && non_mate_line && !is_capture && quiets.len() >= lmp_limit {
+            local_context.prune_stats().lmp += 1;
             move_gen.set_skip_quiets(true);
             continue;
         }
@@ -403,9 +715,14 @@ pub fn search<Search: SearchType>(
         In low depth, non-PV nodes, we assume it's safe to prune a move
         if it has very low history
         */
-        let do_hp = !Search::PV && non_mate_line && moves_seen > 0 && depth <= 8 && eval <= alpha;
+        let do_hp = !Search::PV
+            && non_mate_line
+            && moves_seen > 0
+            && depth <= params.hp_depth
+            && eval <= alpha;
 
-        if do_hp && (h_score as i32) < hp(depth) {
+        if do_hp && (h_score as i32) < hp(params, depth) {
+            local_context.prune_stats().hp += 1;
             continue;
         }
 
@@ -413,11 +730,25 @@ pub fn search<Search: SearchType>(
         In non-PV nodes If a move evaluated by SEE isn't good enough to beat alpha - a static margin
         we assume it's safe to prune this move
         */
-        let do_see_prune = !Search::PV && non_mate_line && moves_seen > 0 && depth <= 7;
-        if do_see_prune && eval + see::<16>(pos.board(), make_move) + see_fp(depth) <= alpha {
+        let sacrifice = is_capture && local_context.get_lc_table().rescues(pos.board(), make_move);
+        let do_see_prune = !Search::PV
+            && non_mate_line
+            && moves_seen > 0
+            && !sacrifice
+            && depth <= params.see_fp_depth;
+        if do_see_prune
+            && eval
+                + see::<16>(pos.board(), make_move, &params.see_prune_values())
+                + see_fp(params, depth)
+                <= alpha
+        {
+            local_context.prune_stats().see_fp += 1;
             continue;
         }
 
+        let losing_capture =
+            is_capture && see::<16>(pos.board(), make_move, &params.see_prune_values()) < 0;
+
         pos.make_move(make_move);
         shared_context.get_t_table().prefetch(pos.board());
         local_context.search_stack_mut()[ply as usize].move_played = Some(make_move);
@@ -438,18 +769,46 @@ pub fn search<Search: SearchType>(
 
         if moves_seen > 0 {
             /*
-            If a move is quiet, we already have information on this move
-            in the history table. If history score is high, we reduce
+            We already have information on this move in the history table
+            (capture history for captures). If history score is high, we reduce
             less and if history score is low we reduce more.
+            Captures that lose material by SEE are reduced further
             */
-
-            reduction -= history_lmr(h_score);
+            if is_capture {
+                reduction -= capture_history_lmr(params, h_score);
+                if losing_capture && !sacrifice {
+                    reduction += params.losing_capture_lmr;
+                }
+            } else {
+                reduction -= history_lmr(params, h_score);
+            }
             if Search::PV {
                 reduction -= 1;
             };
+            if cut_node {
+                reduction += params.cut_node_lmr;
+            }
             if improving {
                 reduction -= 1;
             }
+            /*
+            Another thread is already searching the position at least as deep,
+            we move on to positions nobody is searching yet
+            */
+            if depth > params.busy_depth
+                && shared_context
+                    .get_t_table()
+                    .busy(pos.hash(), depth - 1, local_context.thread())
+            {
+                reduction += params.busy_lmr;
+            }
+            /*
+            Nodes far below alpha are unlikely to have a move that raises it,
+            nodes far above alpha are expected to
+            */
+            if !in_check && !alpha.is_mate() {
+                reduction -= params.eval_gap_lmr(eval.raw() as i32 - alpha.raw() as i32);
+            }
             if Some(make_move) == counter_move
                 || killers.into_iter().any(|killer| killer == make_move)
             {
@@ -458,7 +817,32 @@ pub fn search<Search: SearchType>(
             reduction = reduction.min(depth as i16 - 2).max(0);
         }
 
-        let lmr_depth = (depth as i16 - reduction) as u32;
+        /*
+        Extension Budget:
+        Once a line has been extended too much it isn't extended any further,
+        otherwise lines of checks and singular moves can blow up the search
+        */
+        let extensions = local_context.search_stack()[ply as usize].extensions;
+        if extension > 0 && extensions + extension as u32 > local_context.extension_budget(params) {
+            extension = 0;
+        }
+        local_context.search_stack_mut()[ply as usize + 1].extensions =
+            extensions + extension.max(0) as u32;
+
+        let new_depth = (depth as i16 - 1 + extension) as u32;
+        let lmr_depth = (new_depth as i16 - reduction) as u32;
+
+        /*
+        Root eval noise is applied by shifting the window of the move
+        so the bounds returned by zero window searches stay valid
+        */
+        let noise = if ply == 0 {
+            shared_context.get_eval_noise().get(pos.hash())
+        } else {
+            0
+        };
+        alpha = alpha - noise;
+        beta = beta - noise;
 
         if moves_seen == 0 {
             let search_score = search::<Search>(
@@ -466,9 +850,10 @@ pub fn search<Search: SearchType>(
                 local_context,
                 shared_context,
                 ply + 1,
-                depth - 1 + extension,
+                new_depth,
                 beta >> Next,
                 alpha >> Next,
+                !Search::PV && !cut_node,
             );
             score = search_score << Next;
         } else {
@@ -480,9 +865,10 @@ pub fn search<Search: SearchType>(
                 local_context,
                 shared_context,
                 ply + 1,
-                lmr_depth - 1 + extension,
+                lmr_depth,
                 zw - 1,
                 zw,
+                true,
             );
             score = lmr_score << Next;
 
@@ -490,15 +876,16 @@ pub fn search<Search: SearchType>(
             If no reductions occured in LMR we don't waste time re-searching
             otherwise, we run a full depth search to attempt a fail low
             */
-            if lmr_depth < depth && score > alpha {
+            if lmr_depth < new_depth && score > alpha {
                 let zw_score = search::<Search::Zw>(
                     pos,
                     local_context,
                     shared_context,
                     ply + 1,
-                    depth - 1 + extension,
+                    new_depth,
                     zw - 1,
                     zw,
+                    !cut_node,
                 );
                 score = zw_score << Next;
             }
@@ -511,14 +898,21 @@ pub fn search<Search: SearchType>(
                     local_context,
                     shared_context,
                     ply + 1,
-                    depth - 1 + extension,
+                    new_depth,
                     beta >> Next,
                     alpha >> Next,
+                    false,
                 );
                 score = search_score << Next;
             }
         }
 
+        alpha = alpha + noise;
+        beta = beta + noise;
+        if !score.is_mate() {
+            score = score + noise;
+        }
+
         pos.unmake_move();
         moves_seen += 1;
 
@@ -536,7 +930,7 @@ pub fn search<Search: SearchType>(
                 }
                 if score >= beta {
                     if !local_context.abort() {
-                        let amt = depth + extension;
+                        let amt = new_depth + 1;
                         if !is_capture {
                             let killer_table = local_context.get_k_table();
                             killer_table[ply as usize].push(make_move);
@@ -568,6 +962,14 @@ pub fn search<Search: SearchType>(
                                 &captures,
                                 amt,
                             );
+                            if losing_capture {
+                                local_context.get_lc_table_mut().cutoff(
+                                    pos.board(),
+                                    make_move,
+                                    &losing_captures,
+                                    amt,
+                                );
+                            }
                         }
                     }
                     break;
@@ -579,20 +981,30 @@ pub fn search<Search: SearchType>(
             if !captures.is_full() {
                 captures.push(make_move);
             }
+            if losing_capture && !losing_captures.is_full() {
+                losing_captures.push(make_move);
+            }
         } else if !quiets.is_full() {
             quiets.push(make_move);
         }
     }
     if !move_exists {
         return if pos.board().checkers() == BitBoard::EMPTY {
-            Evaluation::new(0)
+            draw_score(shared_context, local_context, pos)
         } else {
             Evaluation::new_checkmate(-1)
         };
     }
     let highest_score = highest_score.unwrap();
 
-    if skip_move.is_none() && !local_context.abort() {
+    /*
+    Root searches with excluded moves don't store their results
+    as the best move might not be among the moves searched
+    */
+    let excluded_root = ply == 0
+        && (!local_context.root_exclusions().is_empty()
+            || !shared_context.get_search_moves().is_empty());
+    if skip_move.is_none() && !excluded_root && !local_context.abort() {
         if let Some(final_move) = &best_move {
             let entry_type = if highest_score > initial_alpha {
                 if highest_score >= beta {
@@ -605,9 +1017,11 @@ pub fn search<Search: SearchType>(
             };
             shared_context.get_t_table().set(
                 pos.board(),
+                ply,
                 depth,
                 entry_type,
                 highest_score,
+                static_eval,
                 *final_move,
             );
         }
@@ -627,16 +1041,19 @@ pub fn q_search(
     ply: u32,
     mut alpha: Evaluation,
     beta: Evaluation,
+    check_plies: u32,
 ) -> Evaluation {
+    let params = shared_context.get_params();
+    let eval_params = params.eval_params();
     local_context.increment_nodes();
 
     local_context.update_sel_depth(ply);
     if ply >= MAX_PLY {
-        return pos.get_eval(local_context.stm(), local_context.eval());
+        return pos.get_eval(local_context.stm(), local_context.eval(), &eval_params);
     }
 
     let initial_alpha = alpha;
-    let tt_entry = shared_context.get_t_table().get(pos.board());
+    let tt_entry = shared_context.get_t_table().get(pos.board(), ply);
     if let Some(entry) = tt_entry {
         match entry.entry_type() {
             LowerBound => {
@@ -657,7 +1074,11 @@ pub fn q_search(
     let mut best_move = None;
     let in_check = pos.board().checkers() != BitBoard::EMPTY;
 
-    let stand_pat = pos.get_eval(local_context.stm(), local_context.eval());
+    let static_eval = tt_entry
+        .and_then(|entry| entry.static_eval())
+        .unwrap_or_else(|| pos.static_eval(&eval_params));
+    let stand_pat =
+        static_eval + pos.eval_bonus(local_context.stm(), local_context.eval(), &eval_params);
     /*
     If not in check, we have a stand pat score which is the static eval of the current position.
     This is done as captures aren't necessarily the best moves.
@@ -670,46 +1091,76 @@ pub fn q_search(
         }
     }
 
-    let mut move_gen = QuiescenceSearchMoveGen::new();
+    /*
+    Delta Pruning:
+    If even capturing the most valuable piece on the board can't bring the evaluation
+    close to alpha, no capture will and we can return the stand pat score
+    */
+    if !in_check
+        && !shared_context.mate_search()
+        && stand_pat + q_delta(params, pos.board()) <= alpha
+    {
+        return stand_pat;
+    }
+
+    /*
+    In check all evasions are searched, otherwise captures and
+    quiet checks in the first plies of quiescence search
+    */
+    let mut move_gen = QuiescenceSearchMoveGen::new(params.see_prune_values());
+    move_gen.set_quiet_checks(check_plies > 0);
+    move_gen.set_see_threshold(q_see_threshold(params, stand_pat, alpha));
+    let q_lmp_limit = q_lmp_limit(shared_context, stand_pat, alpha, in_check, check_plies);
+    let mut move_exists = false;
+    let mut moves_searched = 0;
     while let Some((make_move, see)) = move_gen.next(pos.board(), local_context.get_ch_table()) {
-        let is_capture = pos
-            .board()
-            .colors(!pos.board().side_to_move())
-            .has(make_move.to);
-        if in_check || is_capture {
-            /*
-            SEE beta cutoff: (Koivisto)
-            If SEE considerably improves evaluation above beta, we can return beta early
-            */
-            if stand_pat + see - q_see_threshold() >= beta {
-                return beta;
-            }
-            if stand_pat + see + q_see_threshold() <= alpha {
-                continue;
-            }
-            pos.make_move(make_move);
-            let search_score = q_search(
-                pos,
-                local_context,
-                shared_context,
-                ply + 1,
-                beta >> Next,
-                alpha >> Next,
-            );
-            let score = search_score << Next;
-            if highest_score.is_none() || score > highest_score.unwrap() {
-                highest_score = Some(score);
-                best_move = Some(make_move);
-            }
-            if score > alpha {
-                alpha = score;
-                if score >= beta {
-                    pos.unmake_move();
-                    break;
-                }
+        move_exists = true;
+        /*
+        Quiescence Late Move Pruning:
+        Far below alpha only the first few moves, the ones most likely to win
+        back enough material, are searched
+        */
+        if moves_searched >= q_lmp_limit {
+            break;
+        }
+        /*
+        SEE beta cutoff: (Koivisto)
+        If SEE considerably improves evaluation above beta, we can return beta early
+        The stand pat score can't be trusted in check where we might be getting mated
+        */
+        if !in_check && stand_pat + see - params.q_see_threshold >= beta {
+            return beta;
+        }
+        if !in_check && stand_pat + see + params.q_see_threshold <= alpha {
+            continue;
+        }
+        moves_searched += 1;
+        pos.make_move(make_move);
+        let search_score = q_search(
+            pos,
+            local_context,
+            shared_context,
+            ply + 1,
+            beta >> Next,
+            alpha >> Next,
+            check_plies.saturating_sub(1),
+        );
+        let score = search_score << Next;
+        if highest_score.is_none() || score > highest_score.unwrap() {
+            highest_score = Some(score);
+            best_move = Some(make_move);
+        }
+        if score > alpha {
+            alpha = score;
+            if score >= beta {
+                pos.unmake_move();
+                break;
             }
-            pos.unmake_move();
         }
+        pos.unmake_move();
+    }
+    if in_check && !move_exists {
+        return Evaluation::new_checkmate(-1);
     }
     if let (Some(best_move), Some(highest_score)) = (best_move, highest_score) {
         let entry_type = if highest_score > initial_alpha {
@@ -722,20 +1173,48 @@ pub fn q_search(
             UpperBound
         };
 
-        shared_context
-            .get_t_table()
-            .set(pos.board(), 0, entry_type, highest_score, best_move);
+        shared_context.get_t_table().set(
+            pos.board(),
+            ply,
+            0,
+            entry_type,
+            highest_score,
+            Some(static_eval),
+            best_move,
+        );
     }
     highest_score.unwrap_or(alpha)
 }
 
-pub fn see<const N: usize>(board: &Board, make_move: Move) -> i16 {
+/*
+Moves searched in quiescence search before the rest are pruned,
+evasions and mate searches search every move
+*/
+fn q_lmp_limit(
+    shared_context: &SharedContext,
+    stand_pat: Evaluation,
+    alpha: Evaluation,
+    in_check: bool,
+    check_plies: u32,
+) -> usize {
+    if in_check || shared_context.mate_search() || alpha.is_mate() {
+        return usize::MAX;
+    }
+    let params = shared_context.get_params();
+    let gap = (alpha.raw() as i32 - stand_pat.raw() as i32).max(0);
+    let margins = gap / params.q_lmp_margin.max(1) as i32;
+    shared_context
+        .get_q_lmp_lookup()
+        .get(margins as usize, (check_plies > 0) as usize)
+}
+
+pub fn see<const N: usize>(board: &Board, make_move: Move, values: &SeeValues) -> i16 {
     let mut index = 0;
     let mut gains = [0_i16; N];
     let target_square = make_move.to;
     let move_piece = board.piece_on(make_move.from).unwrap();
     gains[0] = if let Some(piece) = board.piece_on(target_square) {
-        piece_pts(piece)
+        values[piece as usize]
     } else {
         if move_piece == Piece::King {
             return 0;
@@ -744,12 +1223,12 @@ pub fn see<const N: usize>(board: &Board, make_move: Move) -> i16 {
     };
     let mut color = !board.side_to_move();
     let mut blockers = board.occupied() & !make_move.from.bitboard();
-    let mut last_piece_pts = piece_pts(move_piece);
+    let mut last_piece_pts = values[move_piece as usize];
     'outer: for i in 1..N {
         gains[i] = last_piece_pts - gains[i - 1];
         let defenders = board.colors(color) & blockers;
         for &piece in &Piece::ALL {
-            last_piece_pts = piece_pts(piece);
+            last_piece_pts = values[piece as usize];
             let mut potential = match piece {
                 Piece::Pawn => cozy_chess::get_pawn_attacks(target_square, !color),
                 Piece::Knight => cozy_chess::get_knight_moves(target_square),
@@ -778,13 +1257,30 @@ pub fn see<const N: usize>(board: &Board, make_move: Move) -> i16 {
     gains[0]
 }
 
-fn piece_pts(piece: Piece) -> i16 {
-    match piece {
-        Piece::Pawn => 100,
-        Piece::Knight => 300,
-        Piece::Bishop => 300,
-        Piece::Rook => 500,
-        Piece::Queen => 900,
-        Piece::King => 20000,
-    }
+#[test]
+fn pv_tt_cutoffs() {
+    assert!(pv_tt_cutoff(Exact, Evaluation::new_checkmate(5), false));
+    assert!(pv_tt_cutoff(Exact, Evaluation::new_checkmate(-4), false));
+    assert!(pv_tt_cutoff(Exact, Evaluation::new_tb_win(12), false));
+    assert!(pv_tt_cutoff(Exact, -Evaluation::new_tb_win(7), false));
+    assert!(!pv_tt_cutoff(Exact, Evaluation::new(0), false));
+    assert!(!pv_tt_cutoff(Exact, Evaluation::new(35), false));
+    assert!(!pv_tt_cutoff(
+        LowerBound,
+        Evaluation::new_checkmate(5),
+        false
+    ));
+    assert!(!pv_tt_cutoff(UpperBound, Evaluation::new(0), false));
+    assert!(!pv_tt_cutoff(Exact, Evaluation::new_checkmate(5), true));
+    assert!(!pv_tt_cutoff(Exact, Evaluation::new_tb_win(12), true));
+}
+
+#[test]
+fn zugzwang_signatures() {
+    let prone = |fen: &str| zugzwang_prone(&fen.parse::<Board>().unwrap());
+    assert!(prone("8/5k2/8/3p4/3P4/2N5/5K2/8 w - - 0 1"));
+    assert!(prone("8/5k2/8/3p4/3P4/8/5K2/8 b - - 0 1"));
+    assert!(prone("8/2b2k2/8/2pp4/2PP4/2NB4/5K2/8 w - - 0 1"));
+    assert!(!prone("8/2b2k2/8/3p4/2PP4/2NB4/5K2/8 w - - 0 1"));
+    assert!(!prone("8/5k2/8/3p4/3P4/8/5K2/R7 w - - 0 1"));
 }
@@ -0,0 +1,116 @@
+use cozy_chess::{Board, Move, Rank, Square};
+
+use crate::bm::bm_runner::params::SeeValues;
+
+use super::search;
+
+const EXCHANGE_DEPTH: u32 = 16;
+
+/*
+Best material gain of the side to move from capturing on the square,
+every legal capture is tried and either side can stop capturing at any point
+*/
+fn exchange(board: &Board, sq: Square, values: &SeeValues, depth: u32) -> i16 {
+    if depth == 0 {
+        return 0;
+    }
+    let captured = values[board.piece_on(sq).unwrap() as usize];
+    let mut best = 0;
+    board.generate_moves(|piece_moves| {
+        for make_move in piece_moves {
+            if make_move.to != sq {
+                continue;
+            }
+            let mut child = board.clone();
+            child.play_unchecked(make_move);
+            best = best.max(captured - exchange(&child, sq, values, depth - 1));
+        }
+        false
+    });
+    best
+}
+
+/*
+Exact exchange value of a move, unlike SEE it respects pins and tries every recapture order
+*/
+pub fn exchange_value(board: &Board, make_move: Move, values: &SeeValues) -> i16 {
+    let gain = board
+        .piece_on(make_move.to)
+        .filter(|_| board.color_on(make_move.to) != Some(board.side_to_move()))
+        .map_or(0, |piece| values[piece as usize]);
+    let mut child = board.clone();
+    child.play_unchecked(make_move);
+    gain - exchange(&child, make_move.to, values, EXCHANGE_DEPTH)
+}
+
+/*
+SEE result that disagrees with the exhaustive exchange search
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct SeeMismatch {
+    pub make_move: Move,
+    pub see: i16,
+    pub exact: i16,
+}
+
+impl SeeMismatch {
+    /*
+    Whether SEE gets the sign wrong, which is what ordering and pruning thresholds depend on
+    */
+    pub fn sign_differs(&self) -> bool {
+        (self.see >= 0) != (self.exact >= 0)
+    }
+}
+
+/*
+Compares SEE with the exhaustive exchange search on every legal move of the position
+Promotions and captures on the back ranks are skipped, SEE doesn't score promotions
+Returns the number of moves compared along with the mismatches
+*/
+pub fn check(board: &Board, values: &SeeValues) -> (usize, Vec<SeeMismatch>) {
+    let mut checked = 0;
+    let mut mismatches = vec![];
+    board.generate_moves(|piece_moves| {
+        for make_move in piece_moves {
+            let castles = board.color_on(make_move.to) == Some(board.side_to_move());
+            if castles || matches!(make_move.to.rank(), Rank::First | Rank::Eighth) {
+                continue;
+            }
+            checked += 1;
+            let see = search::see::<16>(board, make_move, values);
+            let exact = exchange_value(board, make_move, values);
+            if see != exact {
+                mismatches.push(SeeMismatch {
+                    make_move,
+                    see,
+                    exact,
+                });
+            }
+        }
+        false
+    });
+    (checked, mismatches)
+}
+
+#[test]
+fn see_matches_exchanges() {
+    let values = [100, 300, 300, 500, 900, 20000];
+    let board: Board = "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1"
+        .parse()
+        .unwrap();
+    let (checked, mismatches) = check(&board, &values);
+    assert!(checked > 0);
+    assert!(mismatches.is_empty());
+    assert_eq!(
+        exchange_value(&board, "e1e5".parse().unwrap(), &values),
+        100
+    );
+
+    /*
+    The knight is pinned, SEE doesn't know it can't recapture
+    */
+    let board: Board = "4k3/4r3/8/3p4/2P5/4N3/8/4K3 b - - 0 1".parse().unwrap();
+    let pinned = "d5c4".parse().unwrap();
+    assert_eq!(exchange_value(&board, pinned, &values), 100);
+    assert_eq!(search::see::<16>(&board, pinned, &values), 0);
+}
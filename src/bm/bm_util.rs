@@ -1,7 +1,19 @@
+pub mod endgame;
 pub mod eval;
+pub mod frc;
 pub mod h_table;
 pub mod lookup;
+pub mod mirror;
+pub mod noise;
+pub mod opponent;
+pub mod pgn;
+pub mod phase;
 pub mod position;
+pub mod render;
 pub mod t_table;
+pub mod tb;
+pub mod tension;
+pub mod ttd;
+pub mod wdl;
 pub mod window;
-pub mod frc;
\ No newline at end of file
+pub mod zobrist;
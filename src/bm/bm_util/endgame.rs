@@ -0,0 +1,107 @@
+use cozy_chess::{BitBoard, Board, Color, Piece};
+
+use super::phase::Phase;
+use super::position::EvalParams;
+
+pub const SCALE: i32 = 128;
+
+const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA_55AA_55AA_55AA);
+
+/*
+Returns how much of the evaluation the side that is ahead can expect to convert,
+out of SCALE, in endgames that are known to be drawish
+*/
+pub fn scale(board: &Board, strong_side: Color, params: &EvalParams) -> i32 {
+    let pawns = board.pieces(Piece::Pawn);
+    let bishops = board.pieces(Piece::Bishop);
+    let rooks = board.pieces(Piece::Rook);
+    let phase = Phase::new(board);
+    let strong_phase = Phase::side(board, strong_side);
+
+    let white = board.colors(Color::White);
+    let black = board.colors(Color::Black);
+    let strong = board.colors(strong_side);
+
+    /*
+    Without pawns, a single minor piece can't force a win
+    */
+    if (pawns & strong).is_empty() && strong_phase.pieces() <= 1 && strong_phase.weight() <= 1 {
+        return params.lone_minor_scale;
+    }
+
+    /*
+    Opposite colored bishops are notoriously hard to win even with extra pawns
+    */
+    if phase.pieces() == 2
+        && (bishops & white).popcnt() == 1
+        && (bishops & black).popcnt() == 1
+        && (bishops & LIGHT_SQUARES).popcnt() == 1
+    {
+        return params.opposite_bishop_scale;
+    }
+
+    /*
+    Rook endgames with at most a single extra pawn are often drawn
+    */
+    if phase.pieces() == 2
+        && (rooks & white).popcnt() == 1
+        && (rooks & black).popcnt() == 1
+        && ((pawns & white).popcnt() as i32 - (pawns & black).popcnt() as i32).abs() <= 1
+    {
+        return params.rook_endgame_scale;
+    }
+    SCALE
+}
+
+#[test]
+fn endgame_scaling() {
+    use crate::bm::bm_runner::params::SearchParams;
+    use std::str::FromStr;
+
+    let params = SearchParams::new().eval_params();
+    let scale_of =
+        |fen: &str, strong_side: Color| scale(&Board::from_str(fen).unwrap(), strong_side, &params);
+
+    assert_eq!(
+        scale_of(Board::default().to_string().as_str(), Color::White),
+        SCALE
+    );
+
+    //Lone minor pieces
+    assert_eq!(
+        scale_of("8/8/4k3/8/8/2N5/4K3/8 w - - 0 1", Color::White),
+        params.lone_minor_scale
+    );
+    assert_eq!(
+        scale_of("8/8/4k3/8/8/2B5/4K3/8 b - - 0 1", Color::White),
+        params.lone_minor_scale
+    );
+    assert_eq!(
+        scale_of("8/8/4k3/8/8/2BB4/4K3/8 w - - 0 1", Color::White),
+        SCALE
+    );
+    assert_eq!(
+        scale_of("8/8/4k3/8/8/2N5/4KP2/8 w - - 0 1", Color::White),
+        SCALE
+    );
+
+    //Opposite colored bishops
+    assert_eq!(
+        scale_of("8/5k2/4b3/3p4/3P1P2/2B5/4K3/8 w - - 0 1", Color::White),
+        params.opposite_bishop_scale
+    );
+    assert_eq!(
+        scale_of("8/5k2/5b2/3p4/3P1P2/2B5/4K3/8 w - - 0 1", Color::White),
+        SCALE
+    );
+
+    //Rook endgames
+    assert_eq!(
+        scale_of("8/5k2/4r3/3p4/3P1P2/8/2R1K3/8 w - - 0 1", Color::White),
+        params.rook_endgame_scale
+    );
+    assert_eq!(
+        scale_of("8/5k2/4r3/8/3P1P2/8/2R1K3/8 w - - 0 1", Color::White),
+        SCALE
+    );
+}
@@ -1,6 +1,11 @@
 const CHECKMATE: i16 = 64;
 const CHECKMATE_EVAL: i16 = i16::MAX - 1024;
 const MAX_EVAL: i16 = CHECKMATE_EVAL - CHECKMATE;
+const TB_WIN: i16 = MAX_EVAL - 256;
+/*
+Tablebase wins are at most this many plies below TB_WIN
+*/
+const TB_PLY_RANGE: i16 = 256;
 
 pub enum Depth {
     Next,
@@ -28,6 +33,16 @@ impl Evaluation {
         }
     }
 
+    /*
+    Tablebase wins are below the mate range, closer wins are preferred
+    */
+    #[inline]
+    pub fn new_tb_win(ply: u32) -> Self {
+        Self {
+            score: TB_WIN - ply as i16,
+        }
+    }
+
     #[inline]
     pub const fn is_mate(&self) -> bool {
         self.score.saturating_abs() > MAX_EVAL
@@ -42,6 +57,65 @@ impl Evaluation {
         }
     }
 
+    /*
+    Plies from the root until the mate is delivered, for either side
+    */
+    #[inline]
+    pub const fn mate_plies(&self) -> Option<u32> {
+        if self.is_mate() {
+            Some((CHECKMATE_EVAL - self.score.abs()) as u32)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub const fn is_tb_score(&self) -> bool {
+        let score = self.score.saturating_abs();
+        score > TB_WIN - TB_PLY_RANGE && score <= TB_WIN
+    }
+
+    /*
+    Tablebase scores count plies from the root, so they are stored relative to the node
+    and converted back on a TT hit, a hit at another ply then gets the right distance
+    Mate scores need no adjustment, they're already relative to the node
+    as every ply they're propagated through moves them one ply further
+    */
+    #[inline]
+    pub fn node_relative(self, ply: u32) -> Self {
+        if self.is_tb_score() {
+            Self::new(self.score + self.score.signum() * ply as i16)
+        } else {
+            self
+        }
+    }
+
+    #[inline]
+    pub fn root_relative(self, ply: u32) -> Self {
+        if self.is_tb_score() {
+            Self::new(self.score - self.score.signum() * ply as i16)
+        } else {
+            self
+        }
+    }
+
+    /*
+    Exponential moving average with the previously reported score for display,
+    weight is the percentage of the previous score that is kept
+    Mate scores are never smoothed
+    */
+    pub fn smoothed(self, previous: Option<Evaluation>, weight: i16) -> Self {
+        match previous {
+            Some(previous) if !self.is_mate() && !previous.is_mate() => {
+                let score = (previous.score as i32 * weight as i32
+                    + self.score as i32 * (100 - weight) as i32)
+                    / 100;
+                Self::new(score as i16)
+            }
+            _ => self,
+        }
+    }
+
     #[inline]
     pub const fn raw(&self) -> i16 {
         self.score
@@ -146,12 +220,51 @@ impl_eval_ops! {
     Div, div, div;
 }
 
+#[test]
+fn mate_distances() {
+    let mate_in_1 = Evaluation::min() << Depth::Next;
+    let mated_in_1 = mate_in_1 << Depth::Next;
+    assert_eq!(mate_in_1.mate_in(), Some(1));
+    assert_eq!(mate_in_1.mate_plies(), Some(1));
+    assert_eq!(mated_in_1.mate_in(), Some(-1));
+    assert_eq!(mated_in_1.mate_plies(), Some(2));
+    assert_eq!(Evaluation::new(300).mate_plies(), None);
+}
+
+#[test]
+fn tt_scores() {
+    let tb_win = Evaluation::new_tb_win(10);
+    assert_eq!(tb_win.node_relative(4), Evaluation::new_tb_win(6));
+    assert_eq!(
+        tb_win.node_relative(4).root_relative(2),
+        Evaluation::new_tb_win(8)
+    );
+    assert_eq!((-tb_win).node_relative(4), -Evaluation::new_tb_win(6));
+    assert_eq!((-tb_win).node_relative(4).root_relative(4), -tb_win);
+
+    let mate = Evaluation::new_checkmate(7);
+    assert_eq!(mate.node_relative(4), mate);
+    assert_eq!(mate.root_relative(4), mate);
+    assert_eq!(Evaluation::new(300).node_relative(4).raw(), 300);
+}
+
+#[test]
+fn smoothing() {
+    let previous = Some(Evaluation::new(100));
+    assert_eq!(Evaluation::new(200).smoothed(previous, 0).raw(), 200);
+    assert_eq!(Evaluation::new(200).smoothed(previous, 75).raw(), 125);
+    assert_eq!(Evaluation::new(200).smoothed(None, 75).raw(), 200);
+    let mate = Evaluation::new_checkmate(3);
+    assert_eq!(mate.smoothed(previous, 75), mate);
+    assert_eq!(Evaluation::new(200).smoothed(Some(mate), 75).raw(), 200);
+}
+
 #[test]
 fn mate_comparisons() {
-    let w_checkmate_in_2 = Evaluation::new_checkmate(2);
-    let w_checkmate_in_1 = Evaluation::new_checkmate(1);
-    let b_checkmate_in_1 = Evaluation::new_checkmate(-1);
-    let b_checkmate_in_2 = Evaluation::new_checkmate(-2);
+    let w_checkmate_in_2 = Evaluation::new_checkmate(4);
+    let w_checkmate_in_1 = Evaluation::new_checkmate(2);
+    let b_checkmate_in_1 = Evaluation::new_checkmate(-3);
+    let b_checkmate_in_2 = Evaluation::new_checkmate(-5);
 
     assert_eq!(w_checkmate_in_2.mate_in().unwrap(), 2);
     assert_eq!(w_checkmate_in_1.mate_in().unwrap(), 1);
@@ -1,8 +1,9 @@
 use cozy_chess::{Board, Color, Piece, Square};
 
-const CORNER_BISHOP_FACTOR: i16 = 50;
-
-pub fn frc_corner_bishop(board: &Board) -> i16 {
+/*
+Bishops blocked in by a pawn in their corner, penalty is per blocked bishop
+*/
+pub fn frc_corner_bishop(board: &Board, penalty: i16) -> i16 {
     let mut score = 0;
 
     let white = board.colors(Color::White);
@@ -14,17 +15,17 @@ pub fn frc_corner_bishop(board: &Board) -> i16 {
     let b_bishops = board.pieces(Piece::Bishop) & black;
 
     if w_bishops.has(Square::A1) && w_pawns.has(Square::B2) {
-        score -= CORNER_BISHOP_FACTOR;
+        score -= penalty;
     }
     if w_bishops.has(Square::H1) && w_pawns.has(Square::G2) {
-        score -= CORNER_BISHOP_FACTOR;
+        score -= penalty;
     }
 
     if b_bishops.has(Square::A8) && b_pawns.has(Square::B7) {
-        score += CORNER_BISHOP_FACTOR;
+        score += penalty;
     }
     if b_bishops.has(Square::H8) && b_pawns.has(Square::G7) {
-        score += CORNER_BISHOP_FACTOR;
+        score += penalty;
     }
 
     match board.side_to_move() {
@@ -3,6 +3,10 @@ use cozy_chess::{Board, Color, Move, Piece, Square};
 pub const MAX_VALUE: i32 = 512;
 const SQUARE_COUNT: usize = 64;
 const PIECE_COUNT: usize = 12;
+/*
+Losing captures with at least this much history are treated like winning captures
+*/
+const RESCUE_THRESHOLD: i16 = 128;
 
 #[derive(Debug, Clone)]
 pub struct HistoryTable {
@@ -16,12 +20,27 @@ impl HistoryTable {
         }
     }
 
+    /*
+    Copies the table without reallocating it
+    */
+    pub fn copy_from(&mut self, other: &Self) {
+        self.table.copy_from_slice(&other.table[..]);
+    }
+
     pub fn get(&self, color: Color, from: Square, to: Square) -> i16 {
         let from_index = sq_index(color, from);
         let to_index = to as usize;
         self.table[from_index][to_index]
     }
 
+    /*
+    Scales history down between searches so the previous search's ordering is
+    a hint rather than a fact
+    */
+    pub fn age(&mut self, div: i16) {
+        age(self.table.iter_mut().flatten(), div);
+    }
+
     pub fn cutoff(&mut self, board: &Board, make_move: Move, fails: &[Move], amt: u32) {
         let index = sq_index(board.side_to_move(), make_move.from);
         let to_index = make_move.to as usize;
@@ -58,6 +77,10 @@ impl CounterMoveTable {
         }
     }
 
+    pub fn copy_from(&mut self, other: &Self) {
+        self.table.copy_from_slice(&other.table[..]);
+    }
+
     pub fn get(&self, color: Color, piece: Piece, to: Square) -> Option<Move> {
         let piece_index = piece_index(color, piece);
         let to_index = to as usize;
@@ -75,9 +98,14 @@ impl CounterMoveTable {
     }
 }
 
+/*
+History of every move following one previous move, indexed by piece and destination
+*/
+pub type ContinuationEntry = [[i16; SQUARE_COUNT]; PIECE_COUNT / 2];
+
 #[derive(Debug, Clone)]
 pub struct DoubleMoveHistory {
-    table: Box<[[[[i16; SQUARE_COUNT]; PIECE_COUNT / 2]; SQUARE_COUNT]; PIECE_COUNT]>,
+    table: Box<[[ContinuationEntry; SQUARE_COUNT]; PIECE_COUNT]>,
 }
 
 impl DoubleMoveHistory {
@@ -87,19 +115,20 @@ impl DoubleMoveHistory {
         }
     }
 
-    pub fn get(
-        &self,
-        color: Color,
-        piece_0: Piece,
-        to_0: Square,
-        piece_1: Piece,
-        to_1: Square,
-    ) -> i16 {
-        let piece_0_index = piece_index(color, piece_0);
-        let to_0_index = to_0 as usize;
-        let piece_1_index = piece_1 as usize;
-        let to_1_index = to_1 as usize;
-        self.table[piece_0_index][to_0_index][piece_1_index][to_1_index]
+    pub fn copy_from(&mut self, other: &Self) {
+        self.table.copy_from_slice(&other.table[..]);
+    }
+
+    /*
+    Moves ordered after the same previous move share an entry, it's looked up once per node
+    */
+    #[inline]
+    pub fn entry(&self, color: Color, prev_piece: Piece, prev_to: Square) -> &ContinuationEntry {
+        &self.table[piece_index(color, prev_piece)][prev_to as usize]
+    }
+
+    pub fn age(&mut self, div: i16) {
+        age(self.table.iter_mut().flatten().flatten().flatten(), div);
     }
 
     pub fn cutoff(
@@ -139,6 +168,74 @@ impl DoubleMoveHistory {
     }
 }
 
+/*
+History of captures that lose material by SEE, indexed by piece, destination and captured piece
+Only captures that turned out to be sacrifices by causing a cutoff gain history
+*/
+#[derive(Debug, Clone)]
+pub struct LosingCaptureHistory {
+    table: Box<[[[i16; PIECE_COUNT / 2]; SQUARE_COUNT]; PIECE_COUNT]>,
+}
+
+impl LosingCaptureHistory {
+    pub fn new() -> Self {
+        Self {
+            table: Box::new([[[0; PIECE_COUNT / 2]; SQUARE_COUNT]; PIECE_COUNT]),
+        }
+    }
+
+    pub fn copy_from(&mut self, other: &Self) {
+        self.table.copy_from_slice(&other.table[..]);
+    }
+
+    fn index(board: &Board, make_move: Move) -> (usize, usize, usize) {
+        let piece = board.piece_on(make_move.from).unwrap();
+        let captured = board.piece_on(make_move.to).unwrap();
+        (
+            piece_index(board.side_to_move(), piece),
+            make_move.to as usize,
+            captured as usize,
+        )
+    }
+
+    pub fn get(&self, board: &Board, make_move: Move) -> i16 {
+        let (piece, to, captured) = Self::index(board, make_move);
+        self.table[piece][to][captured]
+    }
+
+    pub fn age(&mut self, div: i16) {
+        age(self.table.iter_mut().flatten().flatten(), div);
+    }
+
+    /*
+    Losing captures that have often been sacrifices aren't ordered last or pruned
+    */
+    pub fn rescues(&self, board: &Board, make_move: Move) -> bool {
+        self.get(board, make_move) >= RESCUE_THRESHOLD
+    }
+
+    pub fn cutoff(&mut self, board: &Board, make_move: Move, fails: &[Move], amt: u32) {
+        let change = (amt * amt) as i16;
+        let (piece, to, captured) = Self::index(board, make_move);
+        let value = &mut self.table[piece][to][captured];
+        let decay = (change as i32 * *value as i32 / MAX_VALUE) as i16;
+        *value += change - decay;
+
+        for &fail in fails {
+            let (piece, to, captured) = Self::index(board, fail);
+            let value = &mut self.table[piece][to][captured];
+            let decay = (change as i32 * *value as i32 / MAX_VALUE) as i16;
+            *value -= change + decay;
+        }
+    }
+}
+
+fn age<'a>(values: impl Iterator<Item = &'a mut i16>, div: i16) {
+    for value in values {
+        *value /= div.max(1);
+    }
+}
+
 fn piece_index(color: Color, piece: Piece) -> usize {
     color as usize * PIECE_COUNT / 2 + piece as usize
 }
@@ -146,3 +243,24 @@ fn piece_index(color: Color, piece: Piece) -> usize {
 fn sq_index(color: Color, sq: Square) -> usize {
     color as usize * SQUARE_COUNT + sq as usize
 }
+
+#[test]
+fn history_aging() {
+    let board = Board::default();
+    let make_move = "e2e4".parse().unwrap();
+    let fail = "d2d4".parse().unwrap();
+    let mut table = HistoryTable::new();
+    table.cutoff(&board, make_move, &[fail], 8);
+    let (good, bad) = (
+        table.get(Color::White, Square::E2, Square::E4),
+        table.get(Color::White, Square::D2, Square::D4),
+    );
+    assert!(good > 0 && bad < 0);
+
+    table.age(2);
+    assert_eq!(table.get(Color::White, Square::E2, Square::E4), good / 2);
+    assert_eq!(table.get(Color::White, Square::D2, Square::D4), bad / 2);
+    table.age(i16::MAX);
+    assert_eq!(table.get(Color::White, Square::E2, Square::E4), 0);
+    assert_eq!(table.get(Color::White, Square::D2, Square::D4), 0);
+}
@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+use cozy_chess::{Board, Move, Square};
+
+/*
+Flips the board vertically and swaps the colors of all pieces,
+the result is the same position with the other side to move
+*/
+pub fn color_flip(board: &Board) -> Board {
+    let fen = board.to_string();
+    let mut fields = fen.split(' ');
+    let placement = fields
+        .next()
+        .unwrap()
+        .split('/')
+        .rev()
+        .map(swap_case)
+        .collect::<Vec<_>>()
+        .join("/");
+    let stm = match fields.next().unwrap() {
+        "w" => "b",
+        _ => "w",
+    };
+    let castling = fields.next().unwrap();
+    let mut castling = swap_case(castling).chars().collect::<Vec<_>>();
+    castling.sort_by_key(|c| c.is_ascii_lowercase());
+    let castling = castling.into_iter().collect::<String>();
+    let ep = fields.next().unwrap();
+    let ep = if ep == "-" {
+        ep.to_string()
+    } else {
+        Square::from_str(ep).unwrap().flip_rank().to_string()
+    };
+    let rest = fields.collect::<Vec<_>>().join(" ");
+    Board::from_str(&format!(
+        "{} {} {} {} {}",
+        placement, stm, castling, ep, rest
+    ))
+    .unwrap()
+}
+
+pub fn flip_move(make_move: Move) -> Move {
+    Move {
+        from: make_move.from.flip_rank(),
+        to: make_move.to.flip_rank(),
+        promotion: make_move.promotion,
+    }
+}
+
+fn swap_case(field: &str) -> String {
+    field
+        .chars()
+        .map(|c| {
+            if c.is_ascii_uppercase() {
+                c.to_ascii_lowercase()
+            } else {
+                c.to_ascii_uppercase()
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn color_flips() {
+    let start = Board::default();
+    assert_eq!(
+        color_flip(&start).to_string().split(' ').next(),
+        start.to_string().split(' ').next()
+    );
+
+    let board = Board::from_str("r3k2r/pp1n1ppp/8/2pP4/8/8/PPP2PPP/R3K2R w KQq c6 0 12").unwrap();
+    let flipped = color_flip(&board);
+    assert_eq!(
+        flipped.to_string(),
+        "r3k2r/ppp2ppp/8/8/2Pp4/8/PP1N1PPP/R3K2R b Qkq c3 0 12"
+    );
+    assert_eq!(color_flip(&flipped), board);
+}
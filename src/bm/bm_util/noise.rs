@@ -0,0 +1,34 @@
+/*
+Deterministic noise for root move scores
+The noise only depends on the seed and the position so repeated searches agree
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct EvalNoise {
+    amplitude: i16,
+    seed: u64,
+}
+
+impl EvalNoise {
+    pub const fn new(amplitude: i16, seed: u64) -> Self {
+        Self { amplitude, seed }
+    }
+
+    pub const fn none() -> Self {
+        Self::new(0, 0)
+    }
+
+    pub fn get(&self, hash: u64) -> i16 {
+        if self.amplitude <= 0 {
+            return 0;
+        }
+        let range = 2 * self.amplitude as u64 + 1;
+        (splitmix64(self.seed ^ hash) % range) as i16 - self.amplitude
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,95 @@
+/*
+Rating the engine assumes for itself at full strength when comparing with the opponent
+*/
+pub const ENGINE_RATING: u32 = 3000;
+
+/*
+Rating difference worth one centipawn of contempt
+*/
+const CONTEMPT_STEP: u32 = 40;
+const MAX_CONTEMPT: i16 = 30;
+/*
+Root move noise in the opening as a fraction of the contempt
+*/
+const OPENING_NOISE_DIV: i16 = 2;
+
+/*
+Opponent declared by the GUI with UCI_Opponent as "<title> <rating> <computer|human> <name>",
+the title and rating can be "none"
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opponent {
+    pub title: Option<String>,
+    pub rating: Option<u32>,
+    pub computer: bool,
+    pub name: String,
+}
+
+impl Opponent {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut split = value.split_ascii_whitespace();
+        let title = split.next()?;
+        let rating = split.next()?;
+        let kind = split.next()?;
+        Some(Self {
+            title: Some(title.to_uppercase()).filter(|title| title != "NONE"),
+            rating: rating.parse().ok(),
+            computer: kind.eq_ignore_ascii_case("computer"),
+            name: split.collect::<Vec<_>>().join(" "),
+        })
+    }
+
+    /*
+    Declared rating, or the least a player with the title is expected to have
+    */
+    pub fn estimated_rating(&self) -> Option<u32> {
+        self.rating.or_else(|| {
+            Some(match self.title.as_deref()? {
+                "GM" => 2500,
+                "IM" | "WGM" => 2400,
+                "FM" | "WIM" => 2300,
+                "CM" | "WFM" => 2200,
+                "WCM" | "NM" => 2100,
+                _ => return None,
+            })
+        })
+    }
+
+    /*
+    Centipawns a draw is worth to the opponent, the engine avoids draws against weaker players
+    */
+    pub fn contempt(&self, engine_rating: u32) -> i16 {
+        self.estimated_rating().map_or(0, |rating| {
+            let gap = engine_rating.saturating_sub(rating) / CONTEMPT_STEP;
+            gap.min(MAX_CONTEMPT as u32) as i16
+        })
+    }
+
+    /*
+    Noise added to root moves in the opening so games against the same opponent vary
+    */
+    pub fn opening_noise(&self, engine_rating: u32) -> i16 {
+        self.contempt(engine_rating) / OPENING_NOISE_DIV
+    }
+}
+
+#[test]
+fn opponents() {
+    let gm = Opponent::parse("GM none human Garry Kasparov").unwrap();
+    assert_eq!(gm.title.as_deref(), Some("GM"));
+    assert_eq!(gm.name, "Garry Kasparov");
+    assert!(!gm.computer);
+    assert_eq!(gm.estimated_rating(), Some(2500));
+    assert_eq!(gm.contempt(ENGINE_RATING), 12);
+    assert_eq!(gm.opening_noise(ENGINE_RATING), 6);
+
+    let engine = Opponent::parse("none 3400 computer Stockfish").unwrap();
+    assert!(engine.computer);
+    assert_eq!(engine.contempt(ENGINE_RATING), 0);
+
+    let unknown = Opponent::parse("none none human").unwrap();
+    assert_eq!(unknown.contempt(ENGINE_RATING), 0);
+    let beginner = Opponent::parse("none 1000 human").unwrap();
+    assert_eq!(beginner.contempt(ENGINE_RATING), MAX_CONTEMPT);
+    assert_eq!(Opponent::parse("<empty>"), None);
+}
@@ -0,0 +1,410 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use cozy_chess::{Board, Color, Move, Piece};
+
+use super::eval::Evaluation;
+use super::render;
+
+const ENGINE_NAME: &str = "Black Marlin 6.0";
+/*
+Moves are wrapped once a line reaches this length
+*/
+const LINE_LEN: usize = 80;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Win(Color),
+    Draw,
+}
+
+impl Outcome {
+    pub fn parse(token: &str) -> Option<Self> {
+        match token {
+            "1-0" => Some(Outcome::Win(Color::White)),
+            "0-1" => Some(Outcome::Win(Color::Black)),
+            "1/2-1/2" => Some(Outcome::Draw),
+            _ => None,
+        }
+    }
+
+    pub fn score(&self) -> &'static str {
+        match self {
+            Outcome::Win(Color::White) => "1-0",
+            Outcome::Win(Color::Black) => "0-1",
+            Outcome::Draw => "1/2-1/2",
+        }
+    }
+}
+
+/*
+Result of the game after the moves along with the reason,
+draws by rule count as soon as they happen
+*/
+pub fn adjudicate(start: &Board, moves: &[Move]) -> Option<(Outcome, &'static str)> {
+    let mut board = start.clone();
+    let mut hashes = vec![board.hash()];
+    for &make_move in moves {
+        board.play_unchecked(make_move);
+        if board.halfmove_clock() == 0 {
+            hashes.clear();
+        }
+        hashes.push(board.hash());
+    }
+    let stm = board.side_to_move();
+    if !board.generate_moves(|_| true) {
+        return Some(if board.checkers().is_empty() {
+            (Outcome::Draw, "Stalemate")
+        } else {
+            match stm {
+                Color::White => (Outcome::Win(Color::Black), "Black mates"),
+                Color::Black => (Outcome::Win(Color::White), "White mates"),
+            }
+        });
+    }
+    let repetitions = hashes.iter().filter(|&&hash| hash == board.hash()).count();
+    if repetitions >= 3 {
+        Some((Outcome::Draw, "Draw by repetition"))
+    } else if board.halfmove_clock() >= 100 {
+        Some((Outcome::Draw, "Draw by fifty move rule"))
+    } else if insufficient_material(&board) {
+        Some((Outcome::Draw, "Draw by insufficient material"))
+    } else {
+        None
+    }
+}
+
+/*
+Positions neither side can win in, a lone minor piece against a bare king
+*/
+fn insufficient_material(board: &Board) -> bool {
+    let minors = board.pieces(Piece::Knight) | board.pieces(Piece::Bishop);
+    board.occupied().len() == 2 || (board.occupied().len() == 3 && minors.len() == 1)
+}
+
+/*
+Standard algebraic notation of a legal move, castling is encoded as the king capturing its rook
+*/
+pub fn san(board: &Board, make_move: Move) -> String {
+    let stm = board.side_to_move();
+    let piece = board.piece_on(make_move.from).unwrap();
+    let mut san = if piece == Piece::King && board.colors(stm).has(make_move.to) {
+        if make_move.to.file() > make_move.from.file() {
+            "O-O".to_string()
+        } else {
+            "O-O-O".to_string()
+        }
+    } else {
+        let capture = board.colors(!stm).has(make_move.to)
+            || (piece == Piece::Pawn && make_move.from.file() != make_move.to.file());
+        let mut san = String::new();
+        if piece == Piece::Pawn {
+            if capture {
+                san += &make_move.from.file().to_string();
+            }
+        } else {
+            san += &piece.to_string().to_uppercase();
+            let mut rivals = vec![];
+            board.generate_moves_for(board.colors(stm) & board.pieces(piece), |piece_moves| {
+                if piece_moves.from != make_move.from && piece_moves.to.has(make_move.to) {
+                    rivals.push(piece_moves.from);
+                }
+                false
+            });
+            if !rivals.is_empty() {
+                let file = make_move.from.file();
+                let rank = make_move.from.rank();
+                if rivals.iter().all(|rival| rival.file() != file) {
+                    san += &file.to_string();
+                } else if rivals.iter().all(|rival| rival.rank() != rank) {
+                    san += &rank.to_string();
+                } else {
+                    san += &make_move.from.to_string();
+                }
+            }
+        }
+        if capture {
+            san.push('x');
+        }
+        san += &make_move.to.to_string();
+        if let Some(promotion) = make_move.promotion {
+            san.push('=');
+            san += &promotion.to_string().to_uppercase();
+        }
+        san
+    };
+    let mut child = board.clone();
+    child.play_unchecked(make_move);
+    if !child.checkers().is_empty() {
+        san.push(if child.generate_moves(|_| true) {
+            '+'
+        } else {
+            '#'
+        });
+    }
+    san
+}
+
+/*
+What the engine knew when it played a move,
+clock is the time left on its clock after the move if the game is timed
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MoveComment {
+    pub eval: Evaluation,
+    pub depth: u32,
+    pub time: Duration,
+    pub clock: Option<Duration>,
+}
+
+impl MoveComment {
+    fn format(&self) -> String {
+        let eval = match self.eval.mate_in() {
+            Some(mate_in) if mate_in < 0 => format!("-M{}", -mate_in),
+            Some(mate_in) => format!("+M{}", mate_in),
+            None => format!("{:+.2}", self.eval.raw() as f64 / 100.0),
+        };
+        let mut comment = format!("{}/{} {:.1}s", eval, self.depth, self.time.as_secs_f64());
+        if let Some(clock) = self.clock {
+            let secs = clock.as_secs();
+            comment += &format!(
+                " [%clk {}:{:02}:{:02}]",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60
+            );
+        }
+        comment
+    }
+}
+
+/*
+Civil date of a unix timestamp as year, month and day
+*/
+fn civil_date(unix_secs: u64) -> (i64, u64, u64) {
+    let days = (unix_secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097) as u64;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era as i64 + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/*
+Records the game being played and appends it to a PGN file once it's over
+The adapters keep it in sync with the moves of the game,
+games the engine hasn't played a timed move in aren't written
+*/
+#[derive(Debug, Clone)]
+pub struct GameLog {
+    path: Option<String>,
+    round: u32,
+    opponent: Option<String>,
+    start: Board,
+    chess960: bool,
+    moves: Vec<Move>,
+    comments: Vec<Option<(Move, MoveComment)>>,
+    engine: Option<Color>,
+}
+
+impl GameLog {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            round: 0,
+            opponent: None,
+            start: Board::default(),
+            chess960: false,
+            moves: vec![],
+            comments: vec![],
+            engine: None,
+        }
+    }
+
+    pub fn set_path(&mut self, path: Option<String>) {
+        self.path = path;
+    }
+
+    pub fn set_opponent(&mut self, opponent: Option<String>) {
+        self.opponent = opponent;
+    }
+
+    /*
+    Follows the game to the given moves, a position that doesn't continue
+    the current game ends it and starts a new one
+    */
+    pub fn sync(&mut self, start: &Board, moves: &[Move], chess960: bool) {
+        let same_game =
+            start == &self.start && chess960 == self.chess960 && moves.starts_with(&self.moves);
+        if !same_game {
+            self.finish(None);
+            self.start = start.clone();
+            self.chess960 = chess960;
+        }
+        self.moves = moves.to_vec();
+    }
+
+    pub fn take_back(&mut self, plies: usize) {
+        let len = self.moves.len().saturating_sub(plies);
+        self.moves.truncate(len);
+        self.comments.truncate(len);
+    }
+
+    /*
+    Comment on the move the engine is about to play after the current moves
+    */
+    pub fn annotate(&mut self, make_move: Move, comment: MoveComment) {
+        let ply = self.moves.len();
+        if self.comments.len() <= ply {
+            self.comments.resize(ply + 1, None);
+        }
+        self.comments[ply] = Some((make_move, comment));
+        self.engine = Some(match ply % 2 {
+            0 => self.start.side_to_move(),
+            _ => !self.start.side_to_move(),
+        });
+    }
+
+    /*
+    Writes the game if there is one and clears the moves,
+    without an outcome the result is adjudicated from the final position
+    The GUI doesn't send back the move that ended the game, it's taken from its comment
+    */
+    pub fn finish(&mut self, outcome: Option<Outcome>) {
+        if let Some(Some((make_move, _))) = self.comments.get(self.moves.len()) {
+            self.moves.push(*make_move);
+        }
+        if let (Some(path), Some(engine)) = (&self.path, self.engine) {
+            if !self.moves.is_empty() {
+                self.round += 1;
+                let outcome =
+                    outcome.or_else(|| adjudicate(&self.start, &self.moves).map(|(o, _)| o));
+                let unix_secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs());
+                let pgn = self.to_pgn(engine, outcome, unix_secs);
+                if let Err(err) = append(path, &pgn) {
+                    println!("info string failed to write game to {}: {}", path, err);
+                }
+            }
+        }
+        self.moves.clear();
+        self.comments.clear();
+        self.engine = None;
+    }
+
+    fn to_pgn(&self, engine: Color, outcome: Option<Outcome>, unix_secs: u64) -> String {
+        let result = outcome.map_or("*", |outcome| outcome.score());
+        let opponent = self.opponent.as_deref().unwrap_or("?");
+        let (white, black) = match engine {
+            Color::White => (ENGINE_NAME, opponent),
+            Color::Black => (opponent, ENGINE_NAME),
+        };
+        let (year, month, day) = civil_date(unix_secs);
+        let mut pgn = String::new();
+        for (tag, value) in [
+            ("Event", "?".to_string()),
+            ("Site", "?".to_string()),
+            ("Date", format!("{}.{:02}.{:02}", year, month, day)),
+            ("Round", self.round.to_string()),
+            ("White", white.to_string()),
+            ("Black", black.to_string()),
+            ("Result", result.to_string()),
+        ] {
+            pgn += &format!("[{} \"{}\"]\n", tag, value);
+        }
+        if self.chess960 {
+            pgn += "[Variant \"Chess960\"]\n";
+        }
+        if self.chess960 || self.start != Board::default() {
+            pgn += "[SetUp \"1\"]\n";
+            pgn += &format!("[FEN \"{}\"]\n", render::fen(&self.start, self.chess960));
+        }
+        pgn.push('\n');
+
+        let mut tokens = vec![];
+        let mut board = self.start.clone();
+        for (ply, &make_move) in self.moves.iter().enumerate() {
+            let number = board.fullmove_number();
+            if board.side_to_move() == Color::White {
+                tokens.push(format!("{}.", number));
+            } else if ply == 0 {
+                tokens.push(format!("{}...", number));
+            }
+            tokens.push(san(&board, make_move));
+            if let Some(Some((commented, comment))) = self.comments.get(ply) {
+                if *commented == make_move {
+                    tokens.push(format!("{{{}}}", comment.format()));
+                }
+            }
+            board.play_unchecked(make_move);
+        }
+        tokens.push(result.to_string());
+
+        let mut line_len = 0;
+        for token in tokens {
+            if line_len > 0 && line_len + 1 + token.len() > LINE_LEN {
+                pgn.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                pgn.push(' ');
+                line_len += 1;
+            }
+            line_len += token.len();
+            pgn += &token;
+        }
+        pgn += "\n\n";
+        pgn
+    }
+}
+
+fn append(path: &str, pgn: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(pgn.as_bytes())
+}
+
+#[test]
+fn pgn_records() {
+    let board = Board::default();
+    let moves = ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "g8f6", "e1h1"]
+        .iter()
+        .map(|make_move| make_move.parse().unwrap())
+        .collect::<Vec<Move>>();
+    let mut log = GameLog::new();
+    log.sync(&board, &moves[..4], false);
+    log.annotate(
+        moves[4],
+        MoveComment {
+            eval: Evaluation::new(31),
+            depth: 14,
+            time: Duration::from_millis(1300),
+            clock: Some(Duration::from_secs(298)),
+        },
+    );
+    log.sync(&board, &moves, false);
+    assert_eq!(
+        log.to_pgn(Color::White, None, 1_700_000_000),
+        "[Event \"?\"]\n[Site \"?\"]\n[Date \"2023.11.14\"]\n[Round \"0\"]\n\
+         [White \"Black Marlin 6.0\"]\n[Black \"?\"]\n[Result \"*\"]\n\n\
+         1. e4 e5 2. Nf3 Nc6 3. Bb5 {+0.31/14 1.3s [%clk 0:04:58]} Nf6 4. O-O *\n\n"
+    );
+
+    let board: Board = "3k4/1R6/8/8/8/8/8/R3K3 w - - 0 1".parse().unwrap();
+    assert_eq!(san(&board, "a1a8".parse().unwrap()), "Ra8#");
+    let board: Board = "4k3/8/8/8/8/R7/8/R3K3 w - - 0 1".parse().unwrap();
+    assert_eq!(san(&board, "a1a2".parse().unwrap()), "R1a2");
+    let board: Board = "4k3/1P6/8/8/8/8/8/N3K2N w - - 0 1".parse().unwrap();
+    assert_eq!(san(&board, "b7b8q".parse().unwrap()), "b8=Q+");
+    assert_eq!(san(&board, "h1g3".parse().unwrap()), "Ng3");
+    assert_eq!(civil_date(951_782_400), (2000, 2, 29));
+}
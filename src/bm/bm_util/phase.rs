@@ -0,0 +1,107 @@
+use cozy_chess::{BitBoard, Board, Color, Piece};
+
+/*
+Contribution of each piece to the phase, pawns and kings don't count
+*/
+const PHASE_WEIGHTS: [u32; 6] = [0, 1, 1, 2, 4, 0];
+/*
+Phase of the starting position, promotions can't push the phase past this
+*/
+pub const MAX_PHASE: u32 = 24;
+
+/*
+How far the game has progressed judging by the material left on the board
+Shared by everything that behaves differently in the endgame so they agree on what it is
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Phase {
+    occupied: u32,
+    pieces: u32,
+    weight: u32,
+}
+
+impl Phase {
+    pub fn new(board: &Board) -> Self {
+        Self::of(board, board.occupied())
+    }
+
+    /*
+    Phase counting only the pieces of one side
+    */
+    pub fn side(board: &Board, color: Color) -> Self {
+        Self::of(board, board.colors(color))
+    }
+
+    fn of(board: &Board, mask: BitBoard) -> Self {
+        let mut weight = 0;
+        for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+            weight += (board.pieces(piece) & mask).popcnt() * PHASE_WEIGHTS[piece as usize];
+        }
+        let pawns_and_kings = board.pieces(Piece::Pawn) | board.pieces(Piece::King);
+        Self {
+            occupied: mask.popcnt(),
+            pieces: (mask & !pawns_and_kings).popcnt(),
+            weight: weight.min(MAX_PHASE),
+        }
+    }
+
+    /*
+    Number of occupied squares, pawns and kings included
+    */
+    pub fn occupied(&self) -> u32 {
+        self.occupied
+    }
+
+    /*
+    Number of pieces other than pawns and kings, few pieces mean zugzwang and
+    horizon effects are more likely
+    */
+    pub fn pieces(&self) -> u32 {
+        self.pieces
+    }
+
+    /*
+    MAX_PHASE in the opening down to 0 when only pawns and kings are left
+    */
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+#[test]
+fn phases() {
+    let phase = |fen: &str| Phase::new(&fen.parse().unwrap());
+    let start = phase("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+    assert_eq!(start.occupied(), 32);
+    assert_eq!(start.pieces(), 14);
+    assert_eq!(start.weight(), MAX_PHASE);
+
+    let rook_endgame = phase("8/5k2/3r4/4p3/4P3/3R4/5K2/8 w - - 0 1");
+    assert_eq!(rook_endgame.pieces(), 2);
+    assert_eq!(rook_endgame.weight(), 4);
+
+    let pawn_endgame = phase("8/5k2/8/4p3/4P3/8/5K2/8 w - - 0 1");
+    assert_eq!(pawn_endgame.occupied(), 4);
+    assert_eq!(pawn_endgame.pieces(), 0);
+    assert_eq!(pawn_endgame.weight(), 0);
+
+    let promoted = phase("4k3/8/8/8/8/8/8/QQQQKQQQ w - - 0 1");
+    assert_eq!(promoted.weight(), MAX_PHASE);
+
+    let board = "4k3/8/2n5/8/8/8/8/3RKB2 w - - 0 1".parse().unwrap();
+    assert_eq!(Phase::side(&board, Color::White).weight(), 3);
+    assert_eq!(Phase::side(&board, Color::Black).pieces(), 1);
+
+    /*
+    NMP and LMP count pieces the way search always has
+    */
+    for fen in [
+        "r1bqk2r/pp2bppp/2n1pn2/3p4/3P4/2NBPN2/PP3PPP/R2QK2R w KQkq - 0 1",
+        "8/8/3k4/8/8/2N2B2/3K4/8 w - - 0 1",
+        "4k3/8/8/8/8/8/8/QQQQKQQQ w - - 0 1",
+    ] {
+        let board: Board = fen.parse().unwrap();
+        let pieces = board.occupied() & !board.pieces(Piece::Pawn) & !board.pieces(Piece::King);
+        assert_eq!(Phase::new(&board).pieces(), pieces.popcnt());
+    }
+}
@@ -1,23 +1,99 @@
 use cozy_chess::{BitBoard, Board, Color, GameStatus, Move, Piece};
 
-use crate::bm::nnue::Nnue;
+use crate::bm::evaluator::{DefaultEvaluator, Evaluator, NnBreakdown, Perspectives};
 
-use super::{eval::Evaluation, frc};
+use super::{endgame, eval::Evaluation, frc, phase::Phase, render};
+
+/*
+Why a move sent by the GUI can't be played
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IllegalMove {
+    NoPiece,
+    WrongColor,
+    Illegal,
+}
+
+impl std::fmt::Display for IllegalMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            IllegalMove::NoPiece => "no piece on the from square",
+            IllegalMove::WrongColor => "piece of the side not to move",
+            IllegalMove::Illegal => "not a legal move",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/*
+Tunable terms of the static evaluation, nn_scale is the percentage of the network output
+that is used and the endgame scales are out of endgame::SCALE
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct EvalParams {
+    pub nn_scale: i32,
+    pub lone_minor_scale: i32,
+    pub opposite_bishop_scale: i32,
+    pub rook_endgame_scale: i32,
+    pub corner_bishop_penalty: i16,
+    pub root_bonus_max: i16,
+    pub root_bonus_div: i16,
+}
+
+/*
+Terms of the static evaluation of the side to move
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct EvalTerms {
+    pub nn_eval: i32,
+    pub scale: i32,
+    pub frc: i16,
+}
+
+impl EvalTerms {
+    #[inline]
+    pub fn scaled_nn_eval(&self) -> i16 {
+        (self.nn_eval * self.scale / endgame::SCALE) as i16
+    }
+}
+
+fn legality(board: &Board, make_move: Move) -> Result<(), IllegalMove> {
+    match board.color_on(make_move.from) {
+        None => Err(IllegalMove::NoPiece),
+        Some(color) if color != board.side_to_move() => Err(IllegalMove::WrongColor),
+        _ if !board.is_legal(make_move) => Err(IllegalMove::Illegal),
+        _ => Ok(()),
+    }
+}
 
 #[derive(Debug, Clone)]
-pub struct Position {
+pub struct Position<E: Evaluator = DefaultEvaluator> {
     current: Board,
     boards: Vec<Board>,
-    evaluator: Nnue,
+    /*
+    Index of the first board after the last irreversible move along with
+    the previous indices, earlier boards can't be repeated anymore
+    */
+    last_irreversible: usize,
+    irreversible_history: Vec<usize>,
+    evaluator: E,
 }
 
 impl Position {
     pub fn new(board: Board) -> Self {
-        let mut evaluator = Nnue::new();
+        Self::with_evaluator(board)
+    }
+}
+
+impl<E: Evaluator> Position<E> {
+    pub fn with_evaluator(board: Board) -> Self {
+        let mut evaluator = E::new();
         evaluator.full_reset(&board);
         Self {
             current: board,
             boards: vec![],
+            last_irreversible: 0,
+            irreversible_history: vec![],
             evaluator,
         }
     }
@@ -26,6 +102,26 @@ impl Position {
         self.evaluator.full_reset(&self.current);
     }
 
+    /*
+    Same as cloning the position but reuses the board history and evaluator allocations
+    */
+    pub fn copy_from(&mut self, other: &Self) {
+        self.current.clone_from(&other.current);
+        self.boards.clone_from(&other.boards);
+        self.last_irreversible = other.last_irreversible;
+        self.irreversible_history
+            .clone_from(&other.irreversible_history);
+        self.evaluator.clone_from(&other.evaluator);
+    }
+
+    /*
+    Picks up the currently loaded network
+    */
+    pub fn reload_network(&mut self) {
+        self.evaluator = E::new();
+        self.reset();
+    }
+
     #[inline]
     pub fn forced_draw(&self, ply: u32) -> bool {
         if self.insufficient_material()
@@ -35,14 +131,14 @@ impl Position {
             return true;
         }
         let hash = self.hash();
-        self.boards
+        let reversible = self.reversible_boards();
+        reversible
             .iter()
             .rev()
             .skip(1)
             .take(ply as usize)
             .any(|board| board.hash() == hash)
-            || self
-                .boards
+            || reversible
                 .iter()
                 .rev()
                 .skip(ply as usize + 1)
@@ -51,11 +147,74 @@ impl Position {
                 >= 2
     }
 
+    /*
+    Whether playing the move repeats the resulting position for the third time
+    */
+    pub fn threefold_after(&self, make_move: Move) -> bool {
+        let mut board = self.current.clone();
+        board.play_unchecked(make_move);
+        let hash = board.hash();
+        self.reversible_boards()
+            .iter()
+            .chain(std::iter::once(&self.current))
+            .filter(|board| board.hash() == hash)
+            .count()
+            >= 2
+    }
+
+    /*
+    Whether any position repeated since the last zeroing move
+    */
+    pub fn has_repeated(&self) -> bool {
+        let mut hashes = self
+            .reversible_boards()
+            .iter()
+            .map(|board| board.hash())
+            .chain(std::iter::once(self.hash()))
+            .collect::<Vec<_>>();
+        let len = hashes.len();
+        hashes.sort_unstable();
+        hashes.dedup();
+        hashes.len() != len
+    }
+
+    /*
+    Hashes of earlier positions since the last zeroing move with the opponent to move,
+    a move that leads to one of these repeats a position
+    */
+    pub fn repetition_hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        self.reversible_boards()
+            .iter()
+            .rev()
+            .step_by(2)
+            .map(|board| board.hash())
+    }
+
+    /*
+    Moves from the GUI have to be checked before they're played,
+    make_move doesn't validate them and an illegal one corrupts the evaluator
+    */
+    pub fn is_legal(&self, make_move: Move) -> Result<(), IllegalMove> {
+        legality(&self.current, make_move)
+    }
+
+    /*
+    Boards since the last capture, pawn move or change of castling rights
+    */
+    #[inline]
+    fn reversible_boards(&self) -> &[Board] {
+        &self.boards[self.last_irreversible..]
+    }
+
     #[inline]
     pub fn board(&self) -> &Board {
         &self.current
     }
 
+    pub fn to_fen(&self, chess960: bool) -> String {
+        render::fen(&self.current, chess960)
+    }
+
     #[inline]
     pub fn half_ply(&self) -> u8 {
         self.current.halfmove_clock()
@@ -66,6 +225,7 @@ impl Position {
         if let Some(new_board) = self.board().null_move() {
             self.evaluator.null_move();
             self.boards.push(self.current.clone());
+            self.irreversible_history.push(self.last_irreversible);
             self.current = new_board;
             true
         } else {
@@ -77,12 +237,22 @@ impl Position {
     pub fn make_move(&mut self, make_move: Move) {
         self.evaluator.make_move(&self.current, make_move);
         self.boards.push(self.current.clone());
+        self.irreversible_history.push(self.last_irreversible);
+        let prev = self.boards.last().unwrap();
         self.current.play_unchecked(make_move);
+        let irreversible = self.current.halfmove_clock() == 0
+            || Color::ALL
+                .iter()
+                .any(|&color| self.current.castle_rights(color) != prev.castle_rights(color));
+        if irreversible {
+            self.last_irreversible = self.boards.len();
+        }
     }
 
     #[inline]
     pub fn unmake_move(&mut self) {
         self.evaluator.unmake_move();
+        self.last_irreversible = self.irreversible_history.pop().unwrap();
         let current = self.boards.pop().unwrap();
         self.current = current;
     }
@@ -92,21 +262,70 @@ impl Position {
         self.board().hash()
     }
 
-    pub fn get_eval(&mut self, stm: Color, root_eval: Evaluation) -> Evaluation {
-        let piece_cnt = self.board().occupied().popcnt() as i16;
+    pub fn get_eval(
+        &mut self,
+        stm: Color,
+        root_eval: Evaluation,
+        params: &EvalParams,
+    ) -> Evaluation {
+        self.static_eval(params) + self.eval_bonus(stm, root_eval, params)
+    }
+
+    /*
+    Evaluation of the position alone, this is what the TT stores
+    */
+    pub fn static_eval(&mut self, params: &EvalParams) -> Evaluation {
+        let terms = self.eval_terms(params);
+        Evaluation::new(terms.scaled_nn_eval() + terms.frc)
+    }
+
+    #[inline]
+    pub fn phase(&self) -> Phase {
+        Phase::new(self.board())
+    }
 
-        let clamped_eval = root_eval.raw().clamp(-100, 100);
-        let eval_bonus = if self.board().side_to_move() == stm {
-            piece_cnt * clamped_eval / 50
+    /*
+    Bonus for the side that was better at the root, scaled by the material left
+    */
+    pub fn eval_bonus(&self, stm: Color, root_eval: Evaluation, params: &EvalParams) -> i16 {
+        let piece_cnt = self.phase().occupied() as i16;
+
+        let clamped_eval = root_eval
+            .raw()
+            .clamp(-params.root_bonus_max, params.root_bonus_max);
+        if self.board().side_to_move() == stm {
+            piece_cnt * clamped_eval / params.root_bonus_div
         } else {
-            -piece_cnt * clamped_eval / 50
+            -piece_cnt * clamped_eval / params.root_bonus_div
+        }
+    }
+
+    #[inline]
+    fn eval_terms(&mut self, params: &EvalParams) -> EvalTerms {
+        let side_to_move = self.board().side_to_move();
+        let nn_eval = self.evaluator.feed_forward(side_to_move) as i32 * params.nn_scale / 100;
+        let strong_side = if nn_eval >= 0 {
+            side_to_move
+        } else {
+            !side_to_move
         };
+        EvalTerms {
+            nn_eval,
+            scale: endgame::scale(self.board(), strong_side, params),
+            frc: frc::frc_corner_bishop(self.board(), params.corner_bishop_penalty),
+        }
+    }
 
-        let frc_score = frc::frc_corner_bishop(self.board());
+    /*
+    Every part of the static evaluation of the side to move, without the root eval bonus
+    */
+    pub fn eval_breakdown(&mut self, params: &EvalParams) -> (EvalTerms, NnBreakdown) {
+        let stm = self.board().side_to_move();
+        (self.eval_terms(params), self.evaluator.breakdown(stm))
+    }
 
-        Evaluation::new(
-            self.evaluator.feed_forward(self.board().side_to_move()) + frc_score + eval_bonus,
-        )
+    pub fn eval_perspectives(&mut self) -> Perspectives {
+        self.evaluator.feed_forward_both()
     }
 
     pub fn insufficient_material(&self) -> bool {
@@ -122,3 +341,13 @@ impl Position {
         }
     }
 }
+
+#[test]
+fn illegal_moves() {
+    let board = Board::default();
+    let legality = |make_move: &str| legality(&board, make_move.parse().unwrap());
+    assert_eq!(legality("e2e4"), Ok(()));
+    assert_eq!(legality("e3e4"), Err(IllegalMove::NoPiece));
+    assert_eq!(legality("e7e5"), Err(IllegalMove::WrongColor));
+    assert_eq!(legality("e1e2"), Err(IllegalMove::Illegal));
+}
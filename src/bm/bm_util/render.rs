@@ -0,0 +1,82 @@
+use cozy_chess::{Board, Color, File, Piece, Rank, Square};
+
+/*
+How pieces are drawn in a board diagram
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PieceStyle {
+    Ascii,
+    Unicode,
+}
+
+impl PieceStyle {
+    pub fn piece_char(&self, piece: Piece, color: Color) -> char {
+        match self {
+            PieceStyle::Ascii => match color {
+                Color::White => char::from(piece).to_ascii_uppercase(),
+                Color::Black => char::from(piece),
+            },
+            PieceStyle::Unicode => {
+                let white = ['♙', '♘', '♗', '♖', '♕', '♔'];
+                let black = ['♟', '♞', '♝', '♜', '♛', '♚'];
+                match color {
+                    Color::White => white[piece as usize],
+                    Color::Black => black[piece as usize],
+                }
+            }
+        }
+    }
+}
+
+/*
+Board diagram from white's point of view with rank and file coordinates
+*/
+pub fn board_diagram(board: &Board, style: PieceStyle) -> String {
+    let separator = " +---+---+---+---+---+---+---+---+\n";
+    let mut diagram = separator.to_string();
+    for &rank in Rank::ALL.iter().rev() {
+        for &file in &File::ALL {
+            let sq = Square::new(file, rank);
+            let piece = match (board.piece_on(sq), board.color_on(sq)) {
+                (Some(piece), Some(color)) => style.piece_char(piece, color),
+                _ => ' ',
+            };
+            diagram += &format!(" | {}", piece);
+        }
+        diagram += &format!(" | {}\n", char::from(rank));
+        diagram += separator;
+    }
+    diagram += "   a   b   c   d   e   f   g   h\n";
+    diagram
+}
+
+/*
+FEN of the board, castling rights are written as Shredder-FEN files in Chess960
+*/
+pub fn fen(board: &Board, chess960: bool) -> String {
+    if chess960 {
+        format!("{:#}", board)
+    } else {
+        format!("{}", board)
+    }
+}
+
+#[test]
+fn diagrams() {
+    let board = Board::default();
+    let ascii = board_diagram(&board, PieceStyle::Ascii);
+    let unicode = board_diagram(&board, PieceStyle::Unicode);
+    assert!(ascii.contains(" | r | n | b | q | k | b | n | r | 8\n"));
+    assert!(ascii.contains(" | P | P | P | P | P | P | P | P | 2\n"));
+    assert!(unicode.contains(" | ♖ | ♘ | ♗ | ♕ | ♔ | ♗ | ♘ | ♖ | 1\n"));
+    assert!(unicode.ends_with("   a   b   c   d   e   f   g   h\n"));
+
+    assert_eq!(
+        fen(&board, false),
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+    );
+    assert_eq!(
+        fen(&board, true),
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+    );
+}
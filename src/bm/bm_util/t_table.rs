@@ -1,9 +1,46 @@
-use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 
 use cozy_chess::{Board, Move, Piece, Square};
 
 use crate::bm::bm_util::eval::Evaluation;
 
+/*
+Number of entries sampled for the hashfull estimate
+*/
+const HASH_FULL_SAMPLE: usize = 1000;
+
+/*
+Once this many permille of the entries are from the current search,
+depth preferred replacement mostly rejects new entries, so every entry is replaced
+*/
+const ALWAYS_REPLACE_FULL: u32 = 900;
+
+/*
+Entries per bucket, a bucket fills a 64 byte cache line
+*/
+const BUCKET_SIZE: usize = 4;
+
+/*
+Slots for busy markers, kept apart from the entries since an entry's hash check covers
+all of its analysis and a bucket already fills its cache line, so a flag in the entries
+would rewrite both words of one whenever a node starts or finishes
+Only nodes being searched at the moment hold a marker, at most one per ply of each thread,
+so the table doesn't need to grow with the hash size
+A position whose slot is taken by another one goes unmarked, markers keep the upper half
+of the hash so positions sharing a slot don't see each other as busy
+*/
+const BUSY_SLOTS: usize = 1 << 14;
+
+/*
+Consecutive entries whose occupancy is reported together in diagnostics,
+an index function that spreads positions evenly fills every region at the same rate
+*/
+pub const UTILIZATION_REGION: usize = 64;
+/*
+Plies per bin of the depth histogram
+*/
+pub const DEPTH_BIN: usize = 8;
+
 #[derive(Debug, Copy, Clone)]
 struct TTMove(u16);
 
@@ -54,12 +91,27 @@ pub enum EntryType {
     UpperBound,
 }
 
+/*
+Analysis is packed into 64 bits so an entry can be read and written atomically
+- Bits 0-15: TT move
+- Bits 16-31: Score
+- Bits 32-47: Static eval, NO_EVAL if there is none
+- Bits 48-54: Depth
+- Bits 55-60: Age
+- Bits 61-62: Entry type + 1, 0 for empty entries
+*/
+const NO_EVAL: i16 = i16::MIN;
+const MAX_DEPTH: u32 = 127;
+const AGE_MASK: u8 = 0b111111;
+const AGE_DEPTH: i32 = 8;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Analysis {
     exists: bool,
     depth: u8,
     entry_type: EntryType,
     score: Evaluation,
+    static_eval: Option<Evaluation>,
     table_move: TTMove,
     age: u8,
 }
@@ -69,31 +121,50 @@ impl Analysis {
         depth: u32,
         entry_type: EntryType,
         score: Evaluation,
+        static_eval: Option<Evaluation>,
         table_move: Move,
         age: u8,
     ) -> Self {
         Self {
             exists: true,
-            depth: depth as u8,
+            depth: depth.min(MAX_DEPTH) as u8,
             entry_type,
             score,
+            static_eval,
             table_move: TTMove::new(table_move),
-            age,
+            age: age & AGE_MASK,
         }
     }
 
-    fn zero() -> Self {
+    fn to_bits(self) -> u64 {
+        if !self.exists {
+            return 0;
+        }
+        let static_eval = self.static_eval.map_or(NO_EVAL, |eval| eval.raw());
+        self.table_move.0 as u64
+            | (self.score.raw() as u16 as u64) << 16
+            | (static_eval as u16 as u64) << 32
+            | (self.depth as u64) << 48
+            | (self.age as u64) << 55
+            | (self.entry_type as u64 + 1) << 61
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        let entry_type = match (bits >> 61) & 0b11 {
+            1 => Some(EntryType::LowerBound),
+            2 => Some(EntryType::Exact),
+            3 => Some(EntryType::UpperBound),
+            _ => None,
+        };
+        let static_eval = (bits >> 32) as u16 as i16;
         Self {
-            exists: false,
-            depth: 0,
-            entry_type: EntryType::LowerBound,
-            score: Evaluation::new(0),
-            table_move: TTMove::new(Move {
-                from: Square::A1,
-                to: Square::A1,
-                promotion: None,
-            }),
-            age: 0,
+            exists: entry_type.is_some(),
+            depth: ((bits >> 48) & MAX_DEPTH as u64) as u8,
+            entry_type: entry_type.unwrap_or(EntryType::LowerBound),
+            score: Evaluation::new((bits >> 16) as u16 as i16),
+            static_eval: Some(Evaluation::new(static_eval)).filter(|_| static_eval != NO_EVAL),
+            table_move: TTMove(bits as u16),
+            age: (bits >> 55) as u8 & AGE_MASK,
         }
     }
 
@@ -112,10 +183,47 @@ impl Analysis {
         self.score
     }
 
+    /*
+    Static eval of the position without the root eval bonus
+    */
+    #[inline]
+    pub fn static_eval(&self) -> Option<Evaluation> {
+        self.static_eval
+    }
+
     #[inline]
     pub fn table_move(&self) -> Move {
         self.table_move.to_move()
     }
+
+    /*
+    Exact and lower bound entries can cause cutoffs, they're kept over upper bounds of equal depth
+    */
+    fn extra_depth(&self) -> u8 {
+        matches!(self.entry_type, EntryType::Exact | EntryType::LowerBound) as u8
+    }
+}
+
+#[test]
+fn packed_analysis() {
+    let table_move = "e7e8q".parse().unwrap();
+    for (entry_type, static_eval) in [
+        (EntryType::LowerBound, Some(Evaluation::new(-35))),
+        (EntryType::Exact, None),
+        (EntryType::UpperBound, Some(Evaluation::new(1200))),
+    ] {
+        let score = Evaluation::new_checkmate(-5);
+        let analysis = Analysis::new(200, entry_type, score, static_eval, table_move, 70);
+        let unpacked = Analysis::from_bits(analysis.to_bits());
+        assert!(unpacked.exists);
+        assert_eq!(unpacked.depth(), MAX_DEPTH);
+        assert_eq!(unpacked.entry_type(), entry_type);
+        assert_eq!(unpacked.score(), score);
+        assert_eq!(unpacked.static_eval(), static_eval);
+        assert_eq!(unpacked.table_move(), table_move);
+        assert_eq!(unpacked.age, 70 & AGE_MASK);
+    }
+    assert!(!Analysis::from_bits(0).exists);
 }
 
 #[derive(Debug)]
@@ -126,43 +234,205 @@ pub struct Entry {
 
 impl Entry {
     fn zeroed() -> Self {
-        unsafe {
-            Self {
-                hash: AtomicU64::new(std::mem::transmute(Analysis::zero())),
-                analysis: AtomicU64::new(std::mem::transmute(Analysis::zero())),
-            }
+        Self {
+            hash: AtomicU64::new(0),
+            analysis: AtomicU64::new(0),
         }
     }
+
     fn zero(&self) {
-        unsafe {
-            self.hash
-                .store(std::mem::transmute(Analysis::zero()), Ordering::Relaxed);
-            self.analysis
-                .store(std::mem::transmute(Analysis::zero()), Ordering::Relaxed);
-        }
+        self.hash.store(0, Ordering::Relaxed);
+        self.analysis.store(0, Ordering::Relaxed);
     }
 
     fn set_new(&self, hash: u64, entry: u64) {
         self.hash.store(hash, Ordering::Relaxed);
         self.analysis.store(entry, Ordering::Relaxed);
     }
+
+    /*
+    Analysis of the entry if it holds the position with the given hash
+    */
+    #[inline]
+    fn matches(&self, hash: u64) -> Option<u64> {
+        let entry_u64 = self.analysis.load(Ordering::Relaxed);
+        (entry_u64 ^ hash == self.hash.load(Ordering::Relaxed)).then_some(entry_u64)
+    }
+}
+
+/*
+Positions map to a bucket and can be stored in any of its entries
+*/
+#[derive(Debug)]
+#[repr(align(64))]
+struct Bucket {
+    entries: [Entry; BUCKET_SIZE],
+}
+
+impl Bucket {
+    fn zeroed() -> Self {
+        Self {
+            entries: std::array::from_fn(|_| Entry::zeroed()),
+        }
+    }
+}
+
+/*
+Probe and store counts, only kept while diagnostics are enabled
+- A collision is a probe that misses while the bucket holds other positions
+- An overwrite is a store that replaces the entry of another position
+*/
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TtCounters {
+    pub probes: u64,
+    pub hits: u64,
+    pub collisions: u64,
+    pub stores: u64,
+    pub overwrites: u64,
+    pub rejected: u64,
+}
+
+impl TtCounters {
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / self.probes.max(1) as f64
+    }
+
+    pub fn collision_rate(&self) -> f64 {
+        self.collisions as f64 / self.probes.max(1) as f64
+    }
+
+    pub fn overwrite_rate(&self) -> f64 {
+        self.overwrites as f64 / self.stores.max(1) as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct AtomicCounters {
+    probes: AtomicU64,
+    hits: AtomicU64,
+    collisions: AtomicU64,
+    stores: AtomicU64,
+    overwrites: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl AtomicCounters {
+    fn load(&self) -> TtCounters {
+        TtCounters {
+            probes: self.probes.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            collisions: self.collisions.load(Ordering::Relaxed),
+            stores: self.stores.load(Ordering::Relaxed),
+            overwrites: self.overwrites.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    fn clear(&self) {
+        for counter in [
+            &self.probes,
+            &self.hits,
+            &self.collisions,
+            &self.stores,
+            &self.overwrites,
+            &self.rejected,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[inline]
+fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/*
+Contents of evenly spaced regions of the table
+utilization[n] is the number of regions with up to n/8 of their entries in use,
+but more than (n - 1)/8
+last_search counts the entries written by the last finished search, the age is bumped after it
+*/
+#[derive(Debug, Clone, Default)]
+pub struct TtSample {
+    pub sampled: usize,
+    pub used: usize,
+    pub last_search: usize,
+    pub utilization: [usize; 9],
+    pub depths: [usize; 8],
+    pub entry_types: [usize; 3],
+}
+
+/*
+Marks a node as being searched by a thread until it is dropped
+Markers are packed as the upper half of the hash, the depth and the thread + 1,
+a zero slot is free
+*/
+#[derive(Debug)]
+pub struct BusyMarker<'a> {
+    slot: &'a AtomicU64,
+    marker: u64,
+}
+
+impl BusyMarker<'_> {
+    #[inline]
+    fn pack(hash: u64, depth: u32, thread: usize) -> u64 {
+        (hash & !0xFFFF_FFFF) | (depth.min(0xFFFF) as u64) << 16 | (thread as u64 + 1) & 0xFFFF
+    }
+}
+
+impl Drop for BusyMarker<'_> {
+    fn drop(&mut self) {
+        let _ = self
+            .slot
+            .compare_exchange(self.marker, 0, Ordering::Relaxed, Ordering::Relaxed);
+    }
 }
 
 #[derive(Debug)]
 pub struct TranspositionTable {
-    table: Box<[Entry]>,
+    table: Box<[Bucket]>,
     mask: usize,
+    busy: Box<[AtomicU64]>,
     age: AtomicU8,
+    always_replace: AtomicBool,
+    diagnostics: AtomicBool,
+    counters: AtomicCounters,
 }
 
 impl TranspositionTable {
-    pub fn new(size: usize) -> Self {
-        let size = size.next_power_of_two();
-        let table = (0..size).map(|_| Entry::zeroed()).collect::<Box<_>>();
+    /*
+    Size is the number of entries, rounded to a power of two number of buckets
+    Buckets are zeroed by the given number of threads, large tables take a while otherwise
+    */
+    pub fn new(size: usize, threads: usize) -> Self {
+        let buckets = (size / BUCKET_SIZE).max(1).next_power_of_two();
+        let mut table = Vec::<Bucket>::with_capacity(buckets);
+        let chunk_len = buckets.div_ceil(threads.max(1));
+        std::thread::scope(|scope| {
+            for chunk in table.spare_capacity_mut().chunks_mut(chunk_len) {
+                scope.spawn(|| {
+                    for bucket in chunk {
+                        bucket.write(Bucket::zeroed());
+                    }
+                });
+            }
+        });
+        /*
+        Every bucket was written by one of the threads
+        */
+        unsafe {
+            table.set_len(buckets);
+        }
+        let table = table.into_boxed_slice();
         Self {
             table,
-            mask: size - 1,
+            mask: buckets - 1,
+            busy: (0..BUSY_SLOTS).map(|_| AtomicU64::new(0)).collect(),
             age: AtomicU8::new(0),
+            always_replace: AtomicBool::new(false),
+            diagnostics: AtomicBool::new(false),
+            counters: AtomicCounters::default(),
         }
     }
 
@@ -171,6 +441,19 @@ impl TranspositionTable {
         (hash as usize) & self.mask
     }
 
+    #[inline]
+    fn entry_cnt(&self) -> usize {
+        self.table.len() * BUCKET_SIZE
+    }
+
+    /*
+    Entries are numbered consecutively across buckets
+    */
+    #[inline]
+    fn entry(&self, index: usize) -> &Entry {
+        &self.table[index / BUCKET_SIZE].entries[index % BUCKET_SIZE]
+    }
+
     #[cfg(not(target_feature = "sse"))]
     pub fn prefetch(&self, _: &Board) {}
 
@@ -180,72 +463,455 @@ impl TranspositionTable {
         let hash = board.hash();
         let index = self.index(hash);
         unsafe {
-            let ptr = self.table.as_ptr().offset(index as isize);
+            let ptr = self.table.as_ptr().add(index);
             _mm_prefetch(ptr as *const i8, _MM_HINT_T0);
         }
     }
 
-    pub fn get(&self, board: &Board) -> Option<Analysis> {
+    /*
+    Scores are stored relative to the node and returned relative to the root
+    */
+    pub fn get(&self, board: &Board, ply: u32) -> Option<Analysis> {
         let hash = board.hash();
-        let index = self.index(hash);
+        let bucket = &self.table[self.index(hash)];
 
-        let entry = &self.table[index];
-        let hash_u64 = entry.hash.load(Ordering::Relaxed);
-        let entry_u64 = entry.analysis.load(Ordering::Relaxed);
-        if entry_u64 ^ hash == hash_u64 {
-            let analysis: Analysis = unsafe { std::mem::transmute(entry_u64) };
-            if analysis.exists {
-                Some(analysis)
-            } else {
-                None
+        let result = bucket
+            .entries
+            .iter()
+            .filter_map(|entry| entry.matches(hash))
+            .map(Analysis::from_bits)
+            .find(|analysis| analysis.exists)
+            .map(|mut analysis| {
+                analysis.score = analysis.score.root_relative(ply);
+                analysis
+            });
+        if self.diagnostics.load(Ordering::Relaxed) {
+            increment(&self.counters.probes);
+            if result.is_some() {
+                increment(&self.counters.hits);
+            } else if bucket
+                .entries
+                .iter()
+                .any(|entry| Analysis::from_bits(entry.analysis.load(Ordering::Relaxed)).exists)
+            {
+                increment(&self.counters.collisions);
             }
-        } else {
-            None
         }
+        result
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn set(
         &self,
         board: &Board,
+        ply: u32,
         depth: u32,
         entry_type: EntryType,
         score: Evaluation,
+        static_eval: Option<Evaluation>,
         table_move: Move,
     ) {
         let entry = Analysis::new(
             depth,
             entry_type,
-            score,
+            score.node_relative(ply),
+            static_eval,
             table_move,
             self.age.load(Ordering::Relaxed),
         );
         let hash = board.hash();
-        let index = self.index(hash);
-        let fetched_entry = &self.table[index];
-        let analysis: Analysis =
-            unsafe { std::mem::transmute(fetched_entry.analysis.load(Ordering::Relaxed)) };
-        if !analysis.exists || self.do_replace(&entry, &analysis) {
-            let analysis_u64 = unsafe { std::mem::transmute::<Analysis, u64>(entry) };
+        let bucket = &self.table[self.index(hash)];
+        let (fetched_entry, same_position) = self.victim(bucket, hash);
+        let analysis = Analysis::from_bits(fetched_entry.analysis.load(Ordering::Relaxed));
+        let replace = !analysis.exists || self.do_replace(&entry, &analysis);
+        if self.diagnostics.load(Ordering::Relaxed) {
+            increment(&self.counters.stores);
+            if !replace {
+                increment(&self.counters.rejected);
+            } else if analysis.exists && !same_position {
+                increment(&self.counters.overwrites);
+            }
+        }
+        if replace {
+            let analysis_u64 = entry.to_bits();
             fetched_entry.set_new(hash ^ analysis_u64, analysis_u64);
         }
     }
 
+    /*
+    Entry to store the position in, either its own entry, an empty one or the one least worth
+    keeping by its aged depth
+    Returns whether the entry already holds the position
+    */
+    fn victim<'a>(&self, bucket: &'a Bucket, hash: u64) -> (&'a Entry, bool) {
+        if let Some(entry) = bucket
+            .entries
+            .iter()
+            .find(|entry| entry.matches(hash).is_some())
+        {
+            return (entry, true);
+        }
+        let victim = bucket
+            .entries
+            .iter()
+            .min_by_key(|entry| {
+                let analysis = Analysis::from_bits(entry.analysis.load(Ordering::Relaxed));
+                (analysis.exists, self.aged_depth(&analysis))
+            })
+            .unwrap();
+        (victim, false)
+    }
+
+    /*
+    Entries lose AGE_DEPTH plies of depth for every search since they were stored,
+    deep entries of the previous move are reused while analysis left over from
+    long ago gives way to the current search
+    */
+    #[inline]
+    fn aged_depth(&self, analysis: &Analysis) -> i32 {
+        let age_diff = self.current_age().wrapping_sub(analysis.age) & AGE_MASK;
+        (analysis.depth + analysis.extra_depth()) as i32 - AGE_DEPTH * age_diff as i32
+    }
+
+    /*
+    The new entry has to be at least half as deep as the aged depth of the stored one
+    */
     fn do_replace(&self, a: &Analysis, b: &Analysis) -> bool {
-        let current_age = self.age.load(Ordering::Relaxed);
-        let a_extra_depth =
-            matches!(a.entry_type(), EntryType::Exact | EntryType::LowerBound) as u8;
-        let b_extra_depth =
-            matches!(b.entry_type(), EntryType::Exact | EntryType::LowerBound) as u8;
-        ((a.depth + a_extra_depth).saturating_add(current_age.wrapping_sub(b.age) / 2))
-            >= (b.depth + b_extra_depth) / 2
+        self.always_replace.load(Ordering::Relaxed) || self.aged_depth(a) >= self.aged_depth(b) / 2
+    }
+
+    #[inline]
+    fn busy_slot(&self, hash: u64) -> &AtomicU64 {
+        &self.busy[hash as usize & (BUSY_SLOTS - 1)]
+    }
+
+    /*
+    ABDADA style marker for a node a thread has started searching
+    None if the slot is taken, either by another node or by the same node higher up
+    in the thread's own search
+    */
+    pub fn mark_busy(&self, hash: u64, depth: u32, thread: usize) -> Option<BusyMarker<'_>> {
+        let slot = self.busy_slot(hash);
+        let marker = BusyMarker::pack(hash, depth, thread);
+        slot.compare_exchange(0, marker, Ordering::Relaxed, Ordering::Relaxed)
+            .ok()
+            .map(|_| BusyMarker { slot, marker })
+    }
+
+    /*
+    Whether another thread is searching the position at least as deep
+    */
+    pub fn busy(&self, hash: u64, depth: u32, thread: usize) -> bool {
+        let marker = self.busy_slot(hash).load(Ordering::Relaxed);
+        let ours = BusyMarker::pack(hash, depth, thread);
+        marker != 0
+            && marker >> 32 == ours >> 32
+            && marker & 0xFFFF != ours & 0xFFFF
+            && (marker >> 16) & 0xFFFF >= (ours >> 16) & 0xFFFF
+    }
+
+    /*
+    Permille of the sampled entries written during the current search
+    */
+    pub fn hash_full(&self) -> u32 {
+        let age = self.current_age();
+        let sample = self.entry_cnt().min(HASH_FULL_SAMPLE);
+        let used = (0..sample)
+            .filter(|&index| {
+                let entry = self.entry(index);
+                let analysis = Analysis::from_bits(entry.analysis.load(Ordering::Relaxed));
+                analysis.exists && analysis.age == age
+            })
+            .count();
+        (used * 1000 / sample) as u32
+    }
+
+    /*
+    Switches to always replace while the table is nearly full, returns whether it did
+    */
+    pub fn update_replacement(&self) -> bool {
+        let nearly_full = self.hash_full() >= ALWAYS_REPLACE_FULL;
+        self.always_replace.store(nearly_full, Ordering::Relaxed);
+        nearly_full
+    }
+
+    /*
+    Counting probes and stores costs an atomic increment on every access,
+    counters restart whenever diagnostics are switched on
+    */
+    pub fn set_diagnostics(&self, enabled: bool) {
+        if enabled {
+            self.counters.clear();
+        }
+        self.diagnostics.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn diagnostics(&self) -> bool {
+        self.diagnostics.load(Ordering::Relaxed)
     }
 
-    pub fn clean(&self) {
+    pub fn counters(&self) -> TtCounters {
+        self.counters.load()
+    }
+
+    /*
+    Samples the given number of evenly spaced regions of UTILIZATION_REGION entries
+    */
+    pub fn sample(&self, regions: usize) -> TtSample {
+        let region_len = UTILIZATION_REGION.min(self.entry_cnt());
+        let region_cnt = regions.clamp(1, self.entry_cnt() / region_len);
+        let stride = self.entry_cnt() / region_cnt;
+        let last_age = self.current_age().wrapping_sub(1) & AGE_MASK;
+        let mut sample = TtSample::default();
+        for region in 0..region_cnt {
+            let start = region * stride;
+            let mut region_used = 0;
+            for index in start..start + region_len {
+                let entry = self.entry(index);
+                let analysis = Analysis::from_bits(entry.analysis.load(Ordering::Relaxed));
+                sample.sampled += 1;
+                if !analysis.exists {
+                    continue;
+                }
+                region_used += 1;
+                if analysis.age == last_age {
+                    sample.last_search += 1;
+                }
+                sample.depths[(analysis.depth() as usize / DEPTH_BIN).min(7)] += 1;
+                sample.entry_types[analysis.entry_type as usize] += 1;
+            }
+            sample.used += region_used;
+            sample.utilization[(region_used * 8).div_ceil(region_len)] += 1;
+        }
+        sample
+    }
+
+    /*
+    Entries are zeroed by the given number of threads
+    */
+    pub fn clean(&self, threads: usize) {
         self.age.store(0, Ordering::Relaxed);
-        self.table.iter().for_each(|entry| entry.zero());
+        self.counters.clear();
+        self.always_replace.store(false, Ordering::Relaxed);
+        let chunk_len = self.table.len().div_ceil(threads.max(1));
+        std::thread::scope(|scope| {
+            for chunk in self.table.chunks(chunk_len) {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .flat_map(|bucket| bucket.entries.iter())
+                        .for_each(|entry| entry.zero());
+                });
+            }
+        });
+        self.busy
+            .iter()
+            .for_each(|slot| slot.store(0, Ordering::Relaxed));
+    }
+
+    #[inline]
+    fn current_age(&self) -> u8 {
+        self.age.load(Ordering::Relaxed) & AGE_MASK
     }
 
     pub fn age(&self) {
         self.age.fetch_add(1, Ordering::Relaxed);
+        self.always_replace.store(false, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn diagnostics() {
+    let t_table = TranspositionTable::new(UTILIZATION_REGION * 4, 1);
+    t_table.set_diagnostics(true);
+    let board = Board::default();
+    assert!(t_table.get(&board, 0).is_none());
+
+    let mut boards = vec![board.clone()];
+    board.generate_moves(|piece_moves| {
+        for make_move in piece_moves {
+            let mut child = board.clone();
+            child.play_unchecked(make_move);
+            boards.push(child);
+        }
+        false
+    });
+    for board in &boards {
+        let table_move = Move {
+            from: Square::A1,
+            to: Square::A2,
+            promotion: None,
+        };
+        t_table.set(
+            board,
+            0,
+            4,
+            EntryType::Exact,
+            Evaluation::new(0),
+            None,
+            table_move,
+        );
+    }
+    for board in &boards {
+        t_table.get(board, 0);
+    }
+    let counters = t_table.counters();
+    assert_eq!(counters.probes, boards.len() as u64 + 1);
+    assert_eq!(counters.stores, boards.len() as u64);
+    assert_eq!(counters.hits + counters.collisions + 1, counters.probes);
+    assert!(counters.hits > 0);
+
+    let sample = t_table.sample(4);
+    assert_eq!(sample.sampled, UTILIZATION_REGION * 4);
+    assert_eq!(
+        sample.used,
+        counters.stores as usize - counters.overwrites as usize
+    );
+    assert_eq!(sample.utilization.iter().sum::<usize>(), 4);
+    assert_eq!(sample.depths[0], sample.used);
+    assert_eq!(sample.entry_types[EntryType::Exact as usize], sample.used);
+}
+
+#[test]
+fn stale_entries() {
+    let t_table = TranspositionTable::new(1, 1);
+    let mut boards = vec![];
+    Board::default().generate_moves(|piece_moves| {
+        for make_move in piece_moves {
+            let mut child = Board::default();
+            child.play_unchecked(make_move);
+            boards.push(child);
+        }
+        false
+    });
+    let table_move = Move {
+        from: Square::A1,
+        to: Square::A2,
+        promotion: None,
+    };
+    let set = |board: &Board, depth: u32| {
+        t_table.set(
+            board,
+            0,
+            depth,
+            EntryType::UpperBound,
+            Evaluation::new(0),
+            None,
+            table_move,
+        );
+    };
+    for board in &boards[..BUCKET_SIZE] {
+        set(board, 30);
+    }
+    set(&boards[BUCKET_SIZE], 2);
+    let stored = |boards: &[Board]| {
+        boards
+            .iter()
+            .filter(|board| t_table.get(board, 0).is_some())
+            .count()
+    };
+    assert_eq!(stored(&boards[..BUCKET_SIZE]), BUCKET_SIZE);
+    assert!(t_table.get(&boards[BUCKET_SIZE], 0).is_none());
+
+    /*
+    Deep entries of the previous search are kept over shallow ones of the current search
+    */
+    t_table.age();
+    set(&boards[BUCKET_SIZE], 2);
+    assert_eq!(stored(&boards[..BUCKET_SIZE]), BUCKET_SIZE);
+    set(&boards[BUCKET_SIZE], 11);
+    assert_eq!(stored(&boards[..BUCKET_SIZE]), BUCKET_SIZE - 1);
+
+    /*
+    Old enough entries are replaced by anything
+    */
+    for _ in 0..3 {
+        t_table.age();
+    }
+    for (board, depth) in boards[BUCKET_SIZE..].iter().zip([2, 1, 4, 3]) {
+        set(board, depth);
+    }
+    assert_eq!(stored(&boards[..BUCKET_SIZE]), 0);
+    /*
+    Every entry is from the current search, the shallowest one is replaced
+    */
+    set(&boards[BUCKET_SIZE * 2], 2);
+    assert!(t_table.get(&boards[BUCKET_SIZE + 1], 0).is_none());
+    assert_eq!(
+        stored(&boards[BUCKET_SIZE..BUCKET_SIZE * 2 + 1]),
+        BUCKET_SIZE
+    );
+}
+
+#[test]
+fn busy_markers() {
+    let t_table = TranspositionTable::new(1, 1);
+    let hash = Board::default().hash();
+    let marker = t_table.mark_busy(hash, 8, 0).unwrap();
+    assert!(t_table.mark_busy(hash, 8, 1).is_none());
+    assert!(!t_table.busy(hash, 8, 0));
+    assert!(t_table.busy(hash, 8, 1));
+    assert!(t_table.busy(hash, 6, 1));
+    assert!(!t_table.busy(hash, 9, 1));
+    assert!(!t_table.busy(hash ^ 1 << 40, 8, 1));
+    drop(marker);
+    assert!(!t_table.busy(hash, 8, 1));
+    assert!(t_table.mark_busy(hash, 8, 1).is_some());
+}
+
+#[test]
+fn busy_slot_collisions() {
+    let t_table = TranspositionTable::new(1, 1);
+    let hash = Board::default().hash();
+    let other = hash ^ (BUSY_SLOTS as u64) << 20;
+    assert!(std::ptr::eq(
+        t_table.busy_slot(hash),
+        t_table.busy_slot(other)
+    ));
+
+    let marker = t_table.mark_busy(hash, 8, 0).unwrap();
+    assert!(t_table.mark_busy(other, 8, 1).is_none());
+    assert!(!t_table.busy(other, 8, 1));
+    assert!(!t_table.busy(other, 1, 2));
+    assert!(t_table.busy(hash, 8, 1));
+    drop(marker);
+
+    let other_marker = t_table.mark_busy(other, 8, 1).unwrap();
+    assert!(t_table.busy(other, 8, 0));
+    assert!(!t_table.busy(hash, 8, 0));
+    drop(other_marker);
+    assert!(!t_table.busy(other, 8, 0));
+}
+
+#[test]
+fn parallel_clean() {
+    let t_table = TranspositionTable::new(BUCKET_SIZE * 5, 3);
+    let mut boards = vec![];
+    Board::default().generate_moves(|piece_moves| {
+        for make_move in piece_moves {
+            let mut child = Board::default();
+            child.play_unchecked(make_move);
+            boards.push(child);
+        }
+        false
+    });
+    let table_move = Move {
+        from: Square::A1,
+        to: Square::A2,
+        promotion: None,
+    };
+    for board in &boards {
+        t_table.set(
+            board,
+            0,
+            1,
+            EntryType::Exact,
+            Evaluation::new(0),
+            None,
+            table_move,
+        );
     }
+    assert!(boards.iter().any(|board| t_table.get(board, 0).is_some()));
+    t_table.clean(3);
+    assert!(boards.iter().all(|board| t_table.get(board, 0).is_none()));
 }
@@ -0,0 +1,1311 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use cozy_chess::{BitBoard, Board, CastleRights, Color, GameStatus, Move, Piece, Rank, Square};
+use memmap2::Mmap;
+
+use super::position::Position;
+
+/*
+Syzygy tablebase prober
+Tables are discovered from SyzygyPath and loaded lazily on their first probe
+The decoding follows the reference format used by Fathom and Stockfish
+*/
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const WDL_SUFFIX: &str = ".rtbw";
+const DTZ_SUFFIX: &str = ".rtbz";
+
+const TB_PIECES: usize = 7;
+
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+const MAX_DTZ: i32 = 1 << 18;
+
+const PIECE_CHARS: [(Piece, char); 5] = [
+    (Piece::Queen, 'Q'),
+    (Piece::Rook, 'R'),
+    (Piece::Bishop, 'B'),
+    (Piece::Knight, 'N'),
+    (Piece::Pawn, 'P'),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+enum Probe {
+    Value(i32),
+    ChangeStm,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+}
+
+struct Maps {
+    a1d1d4: [usize; 64],
+    b1h1h7: [usize; 64],
+    kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; TB_PIECES],
+    pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn off_a1h8(sq: usize) -> i32 {
+    (sq >> 3) as i32 - (sq & 7) as i32
+}
+
+impl Maps {
+    fn new() -> Self {
+        let mut maps = Self {
+            a1d1d4: [0; 64],
+            b1h1h7: [0; 64],
+            kk: [[0; 64]; 10],
+            binomial: [[0; 64]; TB_PIECES],
+            pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                maps.b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        /*
+        Squares below the diagonal in the a1-d1-d4 triangle come first,
+        the diagonal squares are encoded last
+        */
+        let mut diagonal = vec![];
+        let mut code = 0;
+        for sq in 0..=Square::D4 as usize {
+            if off_a1h8(sq) < 0 && sq & 7 <= 3 {
+                maps.a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 && sq & 7 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            maps.a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        /*
+        All 462 legal placements of two kings with the first one in the a1-d1-d4 triangle
+        */
+        let mut both_on_diagonal = vec![];
+        let mut code = 0;
+        for idx in 0..10 {
+            for sq_1 in 0..=Square::D4 as usize {
+                if maps.a1d1d4[sq_1] != idx || (idx == 0 && sq_1 != Square::B1 as usize) {
+                    continue;
+                }
+                let king = Square::index(sq_1);
+                let illegal = cozy_chess::get_king_moves(king) | king.bitboard();
+                for sq_2 in 0..64 {
+                    if illegal.has(Square::index(sq_2))
+                        || (off_a1h8(sq_1) == 0 && off_a1h8(sq_2) > 0)
+                    {
+                        continue;
+                    }
+                    if off_a1h8(sq_1) == 0 && off_a1h8(sq_2) == 0 {
+                        both_on_diagonal.push((idx, sq_2));
+                    } else {
+                        maps.kk[idx][sq_2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, sq) in both_on_diagonal {
+            maps.kk[idx][sq] = code;
+            code += 1;
+        }
+
+        maps.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..TB_PIECES.min(n + 1) {
+                let with = if k > 0 {
+                    maps.binomial[k - 1][n - 1]
+                } else {
+                    0
+                };
+                let without = if k < n { maps.binomial[k][n - 1] } else { 0 };
+                maps.binomial[k][n] = with + without;
+            }
+        }
+
+        /*
+        The leading pawn is the one with the highest pawn map value,
+        which is the one closest to the edge with the lowest rank
+        */
+        let mut available = 47;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        maps.pawns[sq] = available;
+                        maps.pawns[sq ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    maps.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += maps.binomial[lead_pawns - 1][maps.pawns[sq]];
+                }
+                maps.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        maps
+    }
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(Maps::new)
+}
+
+/*
+Reads return None past the end of the table, so a truncated or corrupt file
+fails to load or probe instead of panicking
+*/
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+    bytes.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    read(bytes, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    read(bytes, offset).map(u32::from_le_bytes)
+}
+
+fn read_u32_be(bytes: &[u8], offset: usize) -> Option<u32> {
+    read(bytes, offset).map(u32::from_be_bytes)
+}
+
+fn read_u64_be(bytes: &[u8], offset: usize) -> Option<u64> {
+    read(bytes, offset).map(u64::from_be_bytes)
+}
+
+fn piece_code(piece: Piece, color: Color) -> u8 {
+    piece as u8 + 1 + 8 * color as u8
+}
+
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    min_sym_len: usize,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    base64: Vec<u64>,
+    sym_len: Vec<u8>,
+    map_idx: [usize; 4],
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+}
+
+/*
+Piece counts of both sides excluding kings, in the order of PIECE_CHARS
+Also identifies the table the material is stored in
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Material {
+    counts: [[usize; 5]; 2],
+    symmetric: bool,
+    piece_cnt: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pawn_cnt: [usize; 2],
+}
+
+impl Material {
+    fn new(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let mut counts = [[0_usize; 5]; 2];
+        for (side, material) in [white, black].iter().enumerate() {
+            if !material.starts_with('K') {
+                return None;
+            }
+            for chr in material.chars().skip(1) {
+                let index = PIECE_CHARS.iter().position(|&(_, c)| c == chr)?;
+                counts[side][index] += 1;
+            }
+        }
+        let material = Self::from_counts(counts);
+        (material.piece_cnt <= TB_PIECES).then_some(material)
+    }
+
+    /*
+    Material of the board with the given color as the first side
+    */
+    fn from_board(board: &Board, first: Color) -> Self {
+        let mut counts = [[0_usize; 5]; 2];
+        for (side, color) in [first, !first].into_iter().enumerate() {
+            for (index, &(piece, _)) in PIECE_CHARS.iter().enumerate() {
+                counts[side][index] = (board.pieces(piece) & board.colors(color)).popcnt() as usize;
+            }
+        }
+        Self::from_counts(counts)
+    }
+
+    /*
+    Sorted piece codes of the material, kings included, as the table stores them
+    */
+    fn piece_codes(&self) -> Vec<u8> {
+        let mut codes = vec![
+            piece_code(Piece::King, Color::White),
+            piece_code(Piece::King, Color::Black),
+        ];
+        for (side, color) in [Color::White, Color::Black].into_iter().enumerate() {
+            for (index, &(piece, _)) in PIECE_CHARS.iter().enumerate() {
+                codes.extend(std::iter::repeat_n(
+                    piece_code(piece, color),
+                    self.counts[side][index],
+                ));
+            }
+        }
+        codes.sort_unstable();
+        codes
+    }
+
+    fn from_counts(counts: [[usize; 5]; 2]) -> Self {
+        let pawns = [counts[0][4], counts[1][4]];
+        let white_leads = pawns[1] == 0 || (pawns[0] != 0 && pawns[1] >= pawns[0]);
+        Self {
+            counts,
+            symmetric: counts[0] == counts[1],
+            piece_cnt: 2 + counts.iter().flatten().sum::<usize>(),
+            has_pawns: pawns[0] + pawns[1] > 0,
+            has_unique_pieces: counts.iter().flatten().any(|&count| count == 1),
+            pawn_cnt: if white_leads {
+                pawns
+            } else {
+                [pawns[1], pawns[0]]
+            },
+        }
+    }
+}
+
+/*
+WDL tables store both sides to move, DTZ tables only one of them
+*/
+#[derive(Debug)]
+struct Table {
+    kind: TableKind,
+    bytes: Mmap,
+    material: Material,
+    sides: usize,
+    pairs: Vec<PairsData>,
+    map: usize,
+}
+
+impl Table {
+    /*
+    Tables are mapped rather than read so only the pages that are probed are loaded,
+    they can be several gigabytes
+    */
+    fn load(path: &Path, material: Material, kind: TableKind) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        /*
+        Safety: tables are never written to, the mapping is only unsound if another
+        process modifies the file while it's in use
+        */
+        let bytes = unsafe { Mmap::map(&file) }.ok()?;
+        let magic = match kind {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC,
+        };
+        if bytes.len() < 5 || bytes[..4] != magic {
+            return None;
+        }
+        let sides = if material.symmetric || kind == TableKind::Dtz {
+            1
+        } else {
+            2
+        };
+        let files = if material.has_pawns { 4 } else { 1 };
+        let mut table = Self {
+            kind,
+            bytes,
+            material,
+            sides,
+            pairs: vec![PairsData::default(); 2 * files],
+            map: 0,
+        };
+        table.parse(files)?;
+        Some(table)
+    }
+
+    fn pairs(&self, stm: usize, file: usize) -> &PairsData {
+        &self.pairs[file * 2 + stm % self.sides]
+    }
+
+    fn parse(&mut self, files: usize) -> Option<()> {
+        let bytes = &self.bytes[..];
+        let material = &self.material;
+        let both_pawns = material.has_pawns && material.pawn_cnt[1] > 0;
+        let mut data = 4;
+        if bytes[data] & 2 != 0 && !material.has_pawns {
+            return None;
+        }
+        data += 1;
+
+        for file in 0..files {
+            let first = *bytes.get(data)?;
+            let second = if both_pawns {
+                *bytes.get(data + 1)?
+            } else {
+                0xFF
+            };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            data += 1 + both_pawns as usize;
+
+            for k in 0..material.piece_cnt {
+                let pieces = *bytes.get(data)?;
+                for side in 0..self.sides {
+                    self.pairs[file * 2 + side].pieces[k] =
+                        if side == 1 { pieces >> 4 } else { pieces & 0xF };
+                }
+                data += 1;
+            }
+            for (side, &order) in order.iter().enumerate().take(self.sides) {
+                let pairs = &mut self.pairs[file * 2 + side];
+                /*
+                The grouping and indexing rely on the pieces being those of the material
+                */
+                let mut pieces = pairs.pieces[..material.piece_cnt].to_vec();
+                pieces.sort_unstable();
+                if pieces != material.piece_codes() {
+                    return None;
+                }
+                set_groups(material, pairs, order, file);
+                if material.has_pawns && !pawn_groups(material, pairs) {
+                    return None;
+                }
+            }
+        }
+
+        data += data & 1;
+
+        for file in 0..files {
+            for side in 0..self.sides {
+                data = set_sizes(bytes, &mut self.pairs[file * 2 + side], data)?;
+            }
+        }
+        if self.kind == TableKind::Dtz {
+            self.map = data;
+            data = set_dtz_map(bytes, &mut self.pairs, data, files)?;
+        }
+        for file in 0..files {
+            for side in 0..self.sides {
+                let pairs = &mut self.pairs[file * 2 + side];
+                pairs.sparse_index = data;
+                data = data.checked_add(pairs.sparse_index_size.checked_mul(6)?)?;
+            }
+        }
+        for file in 0..files {
+            for side in 0..self.sides {
+                let pairs = &mut self.pairs[file * 2 + side];
+                pairs.block_length = data;
+                data = data.checked_add(pairs.block_length_size.checked_mul(2)?)?;
+            }
+        }
+        for file in 0..files {
+            for side in 0..self.sides {
+                let pairs = &mut self.pairs[file * 2 + side];
+                data = data.checked_add(0x3F)? & !0x3F;
+                pairs.data = data;
+                data = data.checked_add(pairs.num_blocks.checked_mul(pairs.block_size)?)?;
+            }
+        }
+        if data > bytes.len() {
+            return None;
+        }
+        Some(())
+    }
+
+    fn map_score(&self, file: usize, value: i32, wdl: i32) -> Option<i32> {
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        let pairs = self.pairs(0, file);
+        let flags = pairs.flags;
+        let mut value = value;
+        if flags & MAPPED != 0 {
+            let index = pairs.map_idx[WDL_MAP[(wdl + 2) as usize]] + value as usize;
+            value = if flags & WIDE != 0 {
+                read_u16(&self.bytes, self.map + index * 2)? as i32
+            } else {
+                *self.bytes.get(self.map + index)? as i32
+            };
+        }
+        /*
+        Values are stored in moves unless the plies flag is set, cursed results are always in moves
+        */
+        if (wdl == 2 && flags & WIN_PLIES == 0)
+            || (wdl == -2 && flags & LOSS_PLIES == 0)
+            || wdl.abs() == 1
+        {
+            value *= 2;
+        }
+        Some(value + 1)
+    }
+
+    fn left(&self, pairs: &PairsData, sym: usize) -> usize {
+        let lr = pairs.btree + sym * 3;
+        ((self.bytes[lr + 1] as usize & 0xF) << 8) | self.bytes[lr] as usize
+    }
+
+    fn right(&self, pairs: &PairsData, sym: usize) -> usize {
+        let lr = pairs.btree + sym * 3;
+        ((self.bytes[lr + 2] as usize) << 4) | (self.bytes[lr + 1] as usize >> 4)
+    }
+
+    /*
+    Offsets and symbols come from the block data, which is only checked while decoding
+    */
+    fn decompress_pairs(&self, pairs: &PairsData, idx: u64) -> Option<i32> {
+        if pairs.flags & SINGLE_VALUE != 0 {
+            return Some(pairs.min_sym_len as i32);
+        }
+        let bytes = &self.bytes;
+
+        /*
+        Sparse index entries point at a known block and offset for every span values
+        */
+        let k = (idx / pairs.span) as usize;
+        let entry = pairs.sparse_index + k * 6;
+        let mut block = read_u32(bytes, entry)? as usize;
+        let mut offset = read_u16(bytes, entry + 4)? as i64;
+        offset += (idx % pairs.span) as i64 - (pairs.span / 2) as i64;
+
+        let block_length = |block: usize| {
+            if block >= pairs.block_length_size {
+                return None;
+            }
+            read_u16(bytes, pairs.block_length + block * 2).map(i64::from)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        /*
+        Walk the canonical Huffman symbols of the block until the one covering the offset
+        */
+        let mut ptr = pairs
+            .data
+            .checked_add(block.checked_mul(pairs.block_size)?)?;
+        let mut buf = read_u64_be(bytes, ptr)?;
+        ptr += 8;
+        let mut buf_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < pairs.base64[len] {
+                len += 1;
+            }
+            sym = ((buf - pairs.base64[len]) >> (64 - len - pairs.min_sym_len)) as usize;
+            sym += read_u16(bytes, pairs.lowest_sym + len * 2)? as usize;
+            let sym_len = *pairs.sym_len.get(sym)? as i64;
+            if offset < sym_len + 1 {
+                break;
+            }
+            offset -= sym_len + 1;
+            len += pairs.min_sym_len;
+            buf = buf.checked_shl(len as u32)?;
+            buf_size = usize::checked_sub(buf_size, len)?;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= (read_u32_be(bytes, ptr)? as u64) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        /*
+        Expand the pair symbol until the leaf holding the offset,
+        every step has to get to a shorter symbol or the pairs are cyclic
+        */
+        while pairs.sym_len[sym] != 0 {
+            let left = self.left(pairs, sym);
+            let left_len = pairs.sym_len[left] as i64;
+            let next = if offset < left_len + 1 {
+                left
+            } else {
+                offset -= left_len + 1;
+                self.right(pairs, sym)
+            };
+            if pairs.sym_len[next] >= pairs.sym_len[sym] {
+                return None;
+            }
+            sym = next;
+        }
+        Some(self.left(pairs, sym) as i32)
+    }
+
+    fn probe(&self, board: &Board, flipped: bool, wdl: i32) -> Option<Probe> {
+        let maps = maps();
+        let material = &self.material;
+        let symmetric_black = material.symmetric && board.side_to_move() == Color::Black;
+        let flip = symmetric_black || flipped;
+        let flip_color = flip as u8 * 8;
+        let flip_squares = flip as usize * 56;
+        let stm = flip as usize ^ board.side_to_move() as usize;
+
+        let mut squares = [0_usize; TB_PIECES];
+        let mut pieces = [0_u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = BitBoard::EMPTY;
+        let mut lead_pawn_cnt = 0;
+        let mut file = 0;
+
+        if material.has_pawns {
+            let lead = self.pairs(0, 0).pieces[0] ^ flip_color;
+            let color = if lead >> 3 == 0 {
+                Color::White
+            } else {
+                Color::Black
+            };
+            lead_pawns = board.pieces(Piece::Pawn) & board.colors(color);
+            for sq in lead_pawns {
+                squares[size] = sq as usize ^ flip_squares;
+                size += 1;
+            }
+            lead_pawn_cnt = size;
+            let mut lead_index = 0;
+            for i in 1..lead_pawn_cnt {
+                if maps.pawns[squares[i]] > maps.pawns[squares[lead_index]] {
+                    lead_index = i;
+                }
+            }
+            squares.swap(0, lead_index);
+            file = (squares[0] & 7).min(7 - (squares[0] & 7));
+        }
+
+        for sq in board.occupied() ^ lead_pawns {
+            squares[size] = sq as usize ^ flip_squares;
+            pieces[size] =
+                piece_code(board.piece_on(sq).unwrap(), board.color_on(sq).unwrap()) ^ flip_color;
+            size += 1;
+        }
+
+        let pairs = self.pairs(stm, file);
+        if self.kind == TableKind::Dtz
+            && (pairs.flags & STM) as usize != stm
+            && (!material.symmetric || material.has_pawns)
+        {
+            return Some(Probe::ChangeStm);
+        }
+
+        /*
+        Reorder the pieces to the sequence stored in the table
+        */
+        for i in lead_pawn_cnt..size - 1 {
+            for j in i + 1..size {
+                if pairs.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        if squares[0] & 7 > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx;
+        if material.has_pawns {
+            idx = maps.lead_pawn_idx[lead_pawn_cnt][squares[0]];
+            squares[1..lead_pawn_cnt].sort_by_key(|&sq| maps.pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_pawn_cnt).skip(1) {
+                idx += maps.binomial[i][maps.pawns[sq]];
+            }
+        } else {
+            if squares[0] >> 3 > 3 {
+                for sq in &mut squares[..size] {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..pairs.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in &mut squares[i..size] {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+            idx = if material.has_unique_pieces {
+                unique_pieces_index(&squares)
+            } else {
+                maps.kk[maps.a1d1d4[squares[0]]][squares[1]]
+            };
+        }
+
+        /*
+        Remaining groups are encoded as combinations of the squares left over
+        */
+        idx *= pairs.group_idx[0];
+        let mut group_start = pairs.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawn_cnt[1] > 0;
+        let mut next = 1;
+        while pairs.group_len[next] != 0 {
+            let group_end = group_start + pairs.group_len[next];
+            squares[group_start..group_end].sort_unstable();
+            let mut n = 0;
+            for i in group_start..group_end {
+                let adjust = squares[..group_start]
+                    .iter()
+                    .filter(|&&sq| squares[i] > sq)
+                    .count();
+                n += maps.binomial[i - group_start + 1]
+                    [squares[i] - adjust - 8 * remaining_pawns as usize];
+            }
+            remaining_pawns = false;
+            idx += n * pairs.group_idx[next];
+            group_start = group_end;
+            next += 1;
+        }
+
+        let value = self.decompress_pairs(pairs, idx)?;
+        Some(Probe::Value(match self.kind {
+            TableKind::Wdl => value - 2,
+            TableKind::Dtz => self.map_score(file, value, wdl)?,
+        }))
+    }
+}
+
+fn unique_pieces_index(squares: &[usize; TB_PIECES]) -> u64 {
+    let maps = maps();
+    let rank = |sq: usize| (sq >> 3) as u64;
+    let adjust_1 = (squares[1] > squares[0]) as u64;
+    let adjust_2 = (squares[2] > squares[0]) as u64 + (squares[2] > squares[1]) as u64;
+    if off_a1h8(squares[0]) != 0 {
+        (maps.a1d1d4[squares[0]] as u64 * 63 + (squares[1] as u64 - adjust_1)) * 62
+            + squares[2] as u64
+            - adjust_2
+    } else if off_a1h8(squares[1]) != 0 {
+        (6 * 63 + rank(squares[0]) * 28 + maps.b1h1h7[squares[1]] as u64) * 62 + squares[2] as u64
+            - adjust_2
+    } else if off_a1h8(squares[2]) != 0 {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + rank(squares[0]) * 7 * 28
+            + (rank(squares[1]) - adjust_1) * 28
+            + maps.b1h1h7[squares[2]] as u64
+    } else {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + 4 * 7 * 28
+            + rank(squares[0]) * 7 * 6
+            + (rank(squares[1]) - adjust_1) * 6
+            + (rank(squares[2]) - adjust_2)
+    }
+}
+
+fn set_groups(material: &Material, pairs: &mut PairsData, order: [u8; 2], file: usize) {
+    let maps = maps();
+    let mut n = 0;
+    let mut first_len: i32 = if material.has_pawns {
+        0
+    } else if material.has_unique_pieces {
+        3
+    } else {
+        2
+    };
+    pairs.group_len[0] = 1;
+    for i in 1..material.piece_cnt {
+        first_len -= 1;
+        if first_len > 0 || pairs.pieces[i] == pairs.pieces[i - 1] {
+            pairs.group_len[n] += 1;
+        } else {
+            n += 1;
+            pairs.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    pairs.group_len[n] = 0;
+
+    let both_pawns = material.has_pawns && material.pawn_cnt[1] > 0;
+    let mut next = if both_pawns { 2 } else { 1 };
+    let mut free_squares =
+        64 - pairs.group_len[0] - if both_pawns { pairs.group_len[1] } else { 0 };
+    let mut idx = 1;
+    let mut k = 0;
+    while next < n || k == order[0] || k == order[1] {
+        if k == order[0] {
+            pairs.group_idx[0] = idx;
+            idx *= if material.has_pawns {
+                maps.lead_pawns_size[pairs.group_len[0]][file]
+            } else if material.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] {
+            pairs.group_idx[1] = idx;
+            idx *= maps.binomial[pairs.group_len[1]][48 - pairs.group_len[0]];
+        } else {
+            pairs.group_idx[next] = idx;
+            idx *= maps.binomial[pairs.group_len[next]][free_squares];
+            free_squares -= pairs.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    pairs.group_idx[n] = idx;
+}
+
+/*
+Pawn tables index the leading pawns first and, with pawns on both sides,
+the other side's pawns next, so these groups have to hold exactly those pawns
+*/
+fn pawn_groups(material: &Material, pairs: &PairsData) -> bool {
+    let pawn_cnt = |code: u8| {
+        let side = (code >> 3) as usize;
+        material.counts[side][4]
+    };
+    let is_pawn = |code: u8| code & 7 == piece_code(Piece::Pawn, Color::White);
+    let lead = pairs.pieces[0];
+    if !is_pawn(lead) || pairs.group_len[0] != pawn_cnt(lead) {
+        return false;
+    }
+    if material.pawn_cnt[1] == 0 {
+        return true;
+    }
+    let other = pairs.pieces[pairs.group_len[0]];
+    is_pawn(other) && other != lead && pairs.group_len[1] == pawn_cnt(other)
+}
+
+/*
+DTZ values can be stored through a per WDL result map of byte or word entries
+*/
+fn set_dtz_map(
+    bytes: &[u8],
+    pairs: &mut [PairsData],
+    mut data: usize,
+    files: usize,
+) -> Option<usize> {
+    let map = data;
+    for file in 0..files {
+        let pairs = &mut pairs[file * 2];
+        if pairs.flags & MAPPED == 0 {
+            continue;
+        }
+        if pairs.flags & WIDE != 0 {
+            data += data & 1;
+            for map_idx in &mut pairs.map_idx {
+                *map_idx = (data - map) / 2 + 1;
+                data += 2 * read_u16(bytes, data)? as usize + 2;
+            }
+        } else {
+            for map_idx in &mut pairs.map_idx {
+                *map_idx = data - map + 1;
+                data += *bytes.get(data)? as usize + 1;
+            }
+        }
+    }
+    Some(data + (data & 1))
+}
+
+fn set_sizes(bytes: &[u8], pairs: &mut PairsData, mut data: usize) -> Option<usize> {
+    pairs.flags = *bytes.get(data)?;
+    data += 1;
+    if pairs.flags & SINGLE_VALUE != 0 {
+        pairs.min_sym_len = *bytes.get(data)? as usize;
+        return Some(data + 1);
+    }
+
+    let groups = pairs.group_len.iter().position(|&len| len == 0)?;
+    let tb_size = pairs.group_idx[groups];
+
+    pairs.block_size = 1_usize.checked_shl(*bytes.get(data)? as u32)?;
+    pairs.span = 1_u64.checked_shl(*bytes.get(data + 1)? as u32)?;
+    pairs.sparse_index_size = tb_size.div_ceil(pairs.span) as usize;
+    let padding = *bytes.get(data + 2)? as usize;
+    pairs.num_blocks = read_u32(bytes, data + 3)? as usize;
+    pairs.block_length_size = pairs.num_blocks + padding;
+    let max_sym_len = *bytes.get(data + 7)? as usize;
+    pairs.min_sym_len = *bytes.get(data + 8)? as usize;
+    data += 9;
+    if max_sym_len < pairs.min_sym_len || pairs.min_sym_len == 0 || max_sym_len > 64 {
+        return None;
+    }
+    pairs.lowest_sym = data;
+
+    /*
+    base64[l] is the lowest symbol of length l left aligned to 64 bits,
+    so a symbol's length is found by walking down the table
+    */
+    let lowest = |i: usize| read_u16(bytes, pairs.lowest_sym + i * 2).map(u64::from);
+    let len = max_sym_len - pairs.min_sym_len + 1;
+    let mut base64 = vec![0; len];
+    for i in (0..len - 1).rev() {
+        base64[i] = (base64[i + 1] + lowest(i)?).checked_sub(lowest(i + 1)?)? / 2;
+    }
+    for (i, base) in base64.iter_mut().enumerate() {
+        *base <<= 64 - i - pairs.min_sym_len;
+    }
+    pairs.base64 = base64;
+    data += len * 2;
+
+    let sym_cnt = read_u16(bytes, data)? as usize;
+    data += 2;
+    pairs.btree = data;
+    if data + sym_cnt * 3 > bytes.len() {
+        return None;
+    }
+
+    pairs.sym_len = vec![0; sym_cnt];
+    let mut visited = vec![false; sym_cnt];
+    for sym in 0..sym_cnt {
+        if !visited[sym] {
+            pairs.sym_len[sym] = set_sym_len(bytes, pairs, sym, &mut visited)?;
+        }
+    }
+    Some(data + sym_cnt * 3 + (sym_cnt & 1))
+}
+
+/*
+Pairs outside of the symbol table make the table invalid,
+probes expand symbols through them without checking
+*/
+fn set_sym_len(
+    bytes: &[u8],
+    pairs: &mut PairsData,
+    sym: usize,
+    visited: &mut [bool],
+) -> Option<u8> {
+    visited[sym] = true;
+    let lr = pairs.btree + sym * 3;
+    let right = ((bytes[lr + 2] as usize) << 4) | (bytes[lr + 1] as usize >> 4);
+    if right == 0xFFF {
+        return Some(0);
+    }
+    let left = ((bytes[lr + 1] as usize & 0xF) << 8) | bytes[lr] as usize;
+    if left >= visited.len() || right >= visited.len() {
+        return None;
+    }
+    if !visited[left] {
+        pairs.sym_len[left] = set_sym_len(bytes, pairs, left, visited)?;
+    }
+    if !visited[right] {
+        pairs.sym_len[right] = set_sym_len(bytes, pairs, right, visited)?;
+    }
+    Some(
+        pairs.sym_len[left]
+            .wrapping_add(pairs.sym_len[right])
+            .wrapping_add(1),
+    )
+}
+
+#[derive(Debug)]
+struct TableEntry {
+    path: PathBuf,
+    table: OnceLock<Option<Table>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Tablebase {
+    wdl: HashMap<Material, TableEntry>,
+    dtz: HashMap<Material, TableEntry>,
+    max_pieces: u32,
+}
+
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+impl Tablebase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+    Scans every directory of the path list for WDL and DTZ tables
+    */
+    pub fn load(paths: &str) -> Self {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let mut tablebase = Self::new();
+        for dir in paths.split(separator).filter(|dir| !dir.is_empty()) {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                let file_name = match file_name.to_str() {
+                    Some(file_name) => file_name,
+                    None => continue,
+                };
+                let (name, kind) = if let Some(name) = file_name.strip_suffix(WDL_SUFFIX) {
+                    (name, TableKind::Wdl)
+                } else if let Some(name) = file_name.strip_suffix(DTZ_SUFFIX) {
+                    (name, TableKind::Dtz)
+                } else {
+                    continue;
+                };
+                let material = match Material::new(name) {
+                    Some(material) => material,
+                    None => continue,
+                };
+                let tables = match kind {
+                    TableKind::Wdl => {
+                        tablebase.max_pieces = tablebase.max_pieces.max(material.piece_cnt as u32);
+                        &mut tablebase.wdl
+                    }
+                    TableKind::Dtz => &mut tablebase.dtz,
+                };
+                tables.insert(
+                    material,
+                    TableEntry {
+                        path: entry.path(),
+                        table: OnceLock::new(),
+                    },
+                );
+            }
+        }
+        tablebase
+    }
+
+    pub fn table_cnt(&self) -> usize {
+        self.wdl.len() + self.dtz.len()
+    }
+
+    fn in_range(&self, board: &Board) -> bool {
+        board.occupied().popcnt() <= self.max_pieces
+            && board.castle_rights(Color::White) == &CastleRights::EMPTY
+            && board.castle_rights(Color::Black) == &CastleRights::EMPTY
+    }
+
+    fn probe_table(&self, kind: TableKind, board: &Board, wdl: i32) -> Option<Probe> {
+        let tables = match kind {
+            TableKind::Wdl => &self.wdl,
+            TableKind::Dtz => &self.dtz,
+        };
+        let material = Material::from_board(board, Color::White);
+        let (flipped, (&material, entry)) = match tables.get_key_value(&material) {
+            Some(entry) => (false, entry),
+            None => (
+                true,
+                tables.get_key_value(&Material::from_board(board, Color::Black))?,
+            ),
+        };
+        let table = entry
+            .table
+            .get_or_init(|| Table::load(&entry.path, material, kind));
+        table.as_ref()?.probe(board, flipped, wdl)
+    }
+
+    /*
+    Tables don't store en passant and assume captures are resolved,
+    so zeroing moves are searched before the table value is trusted
+    Also returns whether the best move is a zeroing move
+    */
+    fn search(&self, board: &Board, check_pawn_moves: bool) -> Option<(i32, bool)> {
+        let enemy = board.colors(!board.side_to_move());
+        let ep_square = board.en_passant().map(|file| {
+            Square::new(
+                file,
+                match board.side_to_move() {
+                    Color::White => Rank::Sixth,
+                    Color::Black => Rank::Third,
+                },
+            )
+        });
+        let mut zeroing: Vec<Move> = vec![];
+        let mut total = 0;
+        board.generate_moves(|piece_moves| {
+            for make_move in piece_moves {
+                total += 1;
+                let pawn = piece_moves.piece == Piece::Pawn;
+                let is_ep = pawn && Some(make_move.to) == ep_square;
+                if enemy.has(make_move.to) || is_ep || (check_pawn_moves && pawn) {
+                    zeroing.push(make_move);
+                }
+            }
+            false
+        });
+
+        let mut best = -2;
+        for &make_move in &zeroing {
+            let mut child = board.clone();
+            child.play_unchecked(make_move);
+            let value = -self.search(&child, false)?.0;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = !zeroing.is_empty() && zeroing.len() == total;
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(TableKind::Wdl, board, 0)? {
+                Probe::Value(value) => value,
+                Probe::ChangeStm => return None,
+            }
+        };
+        if best >= value {
+            Some((best, best > 0 || no_more_moves))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    pub fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        if !self.in_range(board) {
+            return None;
+        }
+        self.search(board, false)
+            .map(|(value, _)| Wdl::from_value(value))
+    }
+
+    /*
+    Distance to the next zeroing move in plies, positive if winning
+    Tables that only store the other side to move are probed one ply deeper
+    */
+    fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(board, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(TableKind::Dtz, board, wdl)? {
+            Probe::Value(dtz) => Some((dtz + 100 * (wdl.abs() == 1) as i32) * wdl.signum()),
+            Probe::ChangeStm => {
+                let mut moves: Vec<(Move, bool)> = vec![];
+                let enemy = board.colors(!board.side_to_move());
+                board.generate_moves(|piece_moves| {
+                    for make_move in piece_moves {
+                        let zeroing = piece_moves.piece == Piece::Pawn || enemy.has(make_move.to);
+                        moves.push((make_move, zeroing));
+                    }
+                    false
+                });
+                let mut min_dtz = i32::MAX;
+                for (make_move, zeroing) in moves {
+                    let mut child = board.clone();
+                    child.play_unchecked(make_move);
+                    let mut dtz = if zeroing {
+                        -dtz_before_zeroing(self.search(&child, false)?.0)
+                    } else {
+                        -self.probe_dtz(&child)?
+                    };
+                    if dtz == 1 && child.status() == GameStatus::Won {
+                        min_dtz = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                        min_dtz = dtz;
+                    }
+                }
+                Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+            }
+        }
+    }
+
+    fn root_dtz(&self, position: &Position) -> Option<i32> {
+        let board = position.board();
+        let mut dtz = if board.halfmove_clock() == 0 {
+            dtz_before_zeroing(-self.search(board, false)?.0)
+        } else if position.forced_draw(1) {
+            0
+        } else {
+            let dtz = -self.probe_dtz(board)?;
+            dtz + dtz.signum()
+        };
+        if dtz == 2 && board.status() == GameStatus::Won {
+            dtz = 1;
+        }
+        Some(dtz)
+    }
+
+    /*
+    Root moves that keep the best tablebase result
+    Wins that can't be converted before the fifty move rule are ranked by their distance,
+    as are losses where the fifty move rule may still save the game
+    */
+    pub fn root_moves(&self, position: &mut Position) -> Option<Vec<Move>> {
+        if self.dtz.is_empty() || !self.in_range(position.board()) {
+            return None;
+        }
+        let half_ply = position.half_ply() as i32;
+        let repeated = position.has_repeated();
+        let mut moves = vec![];
+        position.board().generate_moves(|piece_moves| {
+            moves.extend(piece_moves);
+            false
+        });
+
+        let mut ranked = vec![];
+        for make_move in moves {
+            position.make_move(make_move);
+            let dtz = self.root_dtz(position);
+            position.unmake_move();
+            let dtz = dtz?;
+            let rank = if dtz > 0 {
+                if dtz + half_ply <= 99 && !repeated {
+                    MAX_DTZ
+                } else {
+                    MAX_DTZ - (dtz + half_ply)
+                }
+            } else if dtz < 0 {
+                if -dtz * 2 + half_ply < 100 {
+                    -MAX_DTZ
+                } else {
+                    -MAX_DTZ + (-dtz + half_ply)
+                }
+            } else {
+                0
+            };
+            ranked.push((make_move, rank));
+        }
+        let best = ranked.iter().map(|&(_, rank)| rank).max()?;
+        Some(
+            ranked
+                .into_iter()
+                .filter(|&(_, rank)| rank == best)
+                .map(|(make_move, _)| make_move)
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn encoding_maps() {
+    let maps = maps();
+    let king_pairs = maps.kk.iter().flatten().max().unwrap() + 1;
+    assert_eq!(king_pairs, 462);
+
+    let mut pawn_codes = (8..56).map(|sq| maps.pawns[sq]).collect::<Vec<_>>();
+    pawn_codes.sort_unstable();
+    assert_eq!(pawn_codes, (0..48).collect::<Vec<_>>());
+
+    assert_eq!(maps.binomial[2][4], 6);
+    assert_eq!(maps.lead_pawns_size[1], [6; 4]);
+}
+
+#[test]
+fn corrupt_tables() {
+    /*
+    A KQvK table storing the same value for every position of both sides to move
+    */
+    let pieces = [
+        piece_code(Piece::King, Color::White),
+        piece_code(Piece::Queen, Color::White),
+        piece_code(Piece::King, Color::Black),
+    ];
+    let mut table = WDL_MAGIC.to_vec();
+    table.extend([0, 0]);
+    table.extend(pieces.map(|code| code | code << 4));
+    table.extend([0, SINGLE_VALUE, 4, SINGLE_VALUE, 4]);
+    table.resize(64, 0);
+
+    let dir = std::env::temp_dir().join(format!("tb_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("KQvK{}", WDL_SUFFIX));
+    let probe = |bytes: &[u8]| {
+        std::fs::write(&path, bytes).unwrap();
+        let tablebase = Tablebase::load(dir.to_str().unwrap());
+        tablebase.probe_wdl(&"8/8/8/4k3/8/8/8/KQ6 w - - 0 1".parse().unwrap())
+    };
+    assert_eq!(probe(&table), Some(Wdl::Win));
+    for len in 0..table.len() {
+        assert_eq!(probe(&table[..len]), None, "truncated to {} bytes", len);
+    }
+    /*
+    Corrupt headers are either rejected or probed without panicking
+    */
+    for index in 4..16 {
+        for value in [0x00, 0x3F, 0x7F, 0xFF] {
+            let mut corrupt = table.clone();
+            corrupt[index] = value;
+            probe(&corrupt);
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+Fails instead of skipping without the 3 and 4 piece tables, run it with:
+SYZYGY_PATH=<tables> cargo test probe_small_tables -- --ignored
+*/
+#[test]
+#[ignore = "needs the 3 and 4 piece Syzygy tables in SYZYGY_PATH"]
+fn probe_small_tables() {
+    let path = std::env::var("SYZYGY_PATH").expect("SYZYGY_PATH isn't set");
+    let tablebase = Tablebase::load(&path);
+    assert!(tablebase.table_cnt() > 0, "no tables found in {}", path);
+    let wdl = |fen: &str| tablebase.probe_wdl(&fen.parse().unwrap());
+    assert_eq!(wdl("8/8/8/4k3/8/8/8/KQ6 w - - 0 1"), Some(Wdl::Win));
+    assert_eq!(wdl("8/8/8/4k3/8/8/8/KQ6 b - - 0 1"), Some(Wdl::Loss));
+    assert_eq!(wdl("8/8/8/4k3/8/8/8/KB6 w - - 0 1"), Some(Wdl::Draw));
+    assert_eq!(wdl("8/8/8/8/8/8/4k3/KR6 b - - 0 1"), Some(Wdl::Loss));
+    assert_eq!(wdl("8/8/4k3/8/8/8/P7/K7 w - - 0 1"), Some(Wdl::Draw));
+    assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"), Some(Wdl::Win));
+    assert_eq!(wdl("8/8/8/3qk3/8/8/8/KR6 w - - 0 1"), Some(Wdl::Loss));
+}
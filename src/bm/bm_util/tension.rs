@@ -0,0 +1,72 @@
+use cozy_chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_rook_moves, Board, Color, Move, Piece,
+};
+
+const ATTACK_WEIGHTS: [i32; 6] = [0, 2, 2, 3, 5, 0];
+const PIECE_VALUES: [i32; 6] = [1, 3, 3, 5, 9, 0];
+
+/*
+Material given up in the best line before it's considered a sacrifice, in pawns
+*/
+const SACRIFICE_MARGIN: i32 = 2;
+
+/*
+Weighted count of enemy piece attacks on the squares around the king
+This is much cheaper than a real king safety term and only meant as a signal
+*/
+pub fn king_danger(board: &Board, color: Color) -> i32 {
+    let king = board.king(color);
+    let zone = get_king_moves(king) | king.bitboard();
+    let occupied = board.occupied();
+    let mut danger = 0;
+    for sq in board.colors(!color) & !board.pieces(Piece::Pawn) {
+        let piece = board.piece_on(sq).unwrap();
+        let attacks = match piece {
+            Piece::Knight => get_knight_moves(sq),
+            Piece::Bishop => get_bishop_moves(sq, occupied),
+            Piece::Rook => get_rook_moves(sq, occupied),
+            Piece::Queen => get_bishop_moves(sq, occupied) | get_rook_moves(sq, occupied),
+            _ => continue,
+        };
+        danger += ATTACK_WEIGHTS[piece as usize] * (attacks & zone).popcnt() as i32;
+    }
+    danger
+}
+
+fn material(board: &Board, color: Color) -> i32 {
+    Piece::ALL
+        .iter()
+        .map(|&piece| {
+            PIECE_VALUES[piece as usize]
+                * (board.colors(color) & board.pieces(piece)).popcnt() as i32
+        })
+        .sum()
+}
+
+/*
+How sharp the best line is from the point of view of the side to move at the root
+*/
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Tension {
+    pub king_danger: i32,
+    pub sacrifice: bool,
+}
+
+impl Tension {
+    pub fn of_line(board: &Board, line: &[Option<Move>]) -> Self {
+        let stm = board.side_to_move();
+        let balance = |board: &Board| material(board, stm) - material(board, !stm);
+        let root_balance = balance(board);
+        let mut board = board.clone();
+        for make_move in line.iter().map_while(|make_move| *make_move) {
+            if !board.is_legal(make_move) {
+                break;
+            }
+            board.play_unchecked(make_move);
+        }
+        Self {
+            king_danger: king_danger(&board, stm) - king_danger(&board, !stm),
+            sacrifice: balance(&board) <= root_balance - SACRIFICE_MARGIN,
+        }
+    }
+}
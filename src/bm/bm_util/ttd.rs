@@ -0,0 +1,57 @@
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+/*
+Time to depth of a run over the bench positions
+ms[n] is the total milliseconds it took the positions to complete depth n + 1
+and nodes is the bench signature, runs with different nodes searched different trees
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TtdRun {
+    pub unix_time: u64,
+    pub depth: u32,
+    pub nodes: u64,
+    pub ms: Vec<f64>,
+}
+
+/*
+Runs stored in the file as a JSON array, a missing file has no runs
+*/
+pub fn load(path: &str) -> Result<Vec<TtdRun>, String> {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).map_err(|err| format!("{}: {}", path, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(format!("{}: {}", path, err)),
+    }
+}
+
+/*
+One run per line so the file stays readable and diffs well
+*/
+pub fn save(path: &str, runs: &[TtdRun]) -> io::Result<()> {
+    let runs = runs
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    fs::write(path, format!("[\n{}\n]\n", runs.join(",\n")))
+}
+
+#[test]
+fn ttd_json() {
+    let run = TtdRun {
+        unix_time: 1_700_000_000,
+        depth: 3,
+        nodes: 7957595,
+        ms: vec![0.125, 1.5, 12.0],
+    };
+    let path = std::env::temp_dir().join(format!("ttd_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    save(path, &[run.clone(), run.clone()]).unwrap();
+    assert_eq!(load(path), Ok(vec![run.clone(), run]));
+    fs::write(path, "[").unwrap();
+    assert!(load(path).is_err());
+    fs::remove_file(path).unwrap();
+    assert_eq!(load(path), Ok(vec![]));
+}
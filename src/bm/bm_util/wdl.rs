@@ -0,0 +1,66 @@
+use cozy_chess::{Board, Color};
+
+use super::eval::Evaluation;
+
+/*
+Win/Draw/Loss model in the style of Stockfish
+The win rate is a logistic function of the score,
+its center and spread are polynomials of the game ply
+*/
+const AS: [f64; 4] = [0.38036525, -2.82015070, 23.17882135, 307.36768407];
+const BS: [f64; 4] = [-2.29434733, 13.27689788, -14.26828904, 63.45318330];
+
+//Model units per centipawn
+const SCALE: f64 = 3.28;
+
+const MAX_PLY: u32 = 240;
+
+const PERMILL: u32 = 1000;
+
+fn poly(coefficients: &[f64; 4], x: f64) -> f64 {
+    coefficients.iter().fold(0.0, |acc, &c| acc * x + c)
+}
+
+fn win_rate(cp: i16, ply: u32) -> u32 {
+    let m = ply.min(MAX_PLY) as f64 / 64.0;
+    let a = poly(&AS, m);
+    let b = poly(&BS, m);
+    let x = (cp as f64 * SCALE).clamp(-4000.0, 4000.0);
+    (0.5 + PERMILL as f64 / (1.0 + ((a - x) / b).exp())) as u32
+}
+
+pub fn game_ply(board: &Board) -> u32 {
+    (board.fullmove_number() as u32 - 1) * 2 + (board.side_to_move() == Color::Black) as u32
+}
+
+/*
+Returns win, draw and loss probabilities in permill
+*/
+pub fn wdl(eval: Evaluation, ply: u32) -> (u32, u32, u32) {
+    match eval.mate_in() {
+        Some(mate_in) if mate_in > 0 => (PERMILL, 0, 0),
+        Some(_) => (0, 0, PERMILL),
+        None => {
+            let win = win_rate(eval.raw(), ply);
+            let loss = win_rate(-eval.raw(), ply);
+            (win, PERMILL - win - loss, loss)
+        }
+    }
+}
+
+#[test]
+fn wdl_model() {
+    let (win, draw, loss) = wdl(Evaluation::new(0), 60);
+    assert_eq!(win, loss);
+    assert!(draw > 900);
+
+    let (win, draw, loss) = wdl(Evaluation::new(100), 64);
+    assert!((480..=520).contains(&win));
+    assert_eq!(win + draw + loss, PERMILL);
+
+    let (win, _, loss) = wdl(Evaluation::new(-300), 30);
+    assert!(loss > 900 && win == 0);
+
+    assert_eq!(wdl(Evaluation::new_checkmate(3), 10), (PERMILL, 0, 0));
+    assert_eq!(wdl(Evaluation::new_checkmate(-2), 10), (0, 0, PERMILL));
+}
@@ -0,0 +1,145 @@
+use cozy_chess::{Board, Color, File, Move, Piece, Rank, Square};
+
+/*
+The keys behind Board::hash, cozy-chess doesn't expose them so they are generated
+the same way: a Pcg64Mcg stream filling en passant, then white's and black's
+castling and piece keys, then the side to move
+*/
+struct Keys {
+    en_passant: [u64; File::NUM],
+    castle_rights: [[u64; File::NUM]; Color::NUM],
+    pieces: [[[u64; Square::NUM]; Piece::NUM]; Color::NUM],
+    black_to_move: u64,
+}
+
+static KEYS: Keys = {
+    let mut state = 0x7369787465656E2062797465206E756D_u128 | 1;
+    macro_rules! rand {
+        () => {{
+            state = state.wrapping_mul(0x2360ED051FC65DA44385DF649FCCF645);
+            let rot = (state >> 122) as u32;
+            let xsl = ((state >> 64) as u64) ^ (state as u64);
+            xsl.rotate_right(rot)
+        }};
+    }
+
+    let mut keys = Keys {
+        en_passant: [0; File::NUM],
+        castle_rights: [[0; File::NUM]; Color::NUM],
+        pieces: [[[0; Square::NUM]; Piece::NUM]; Color::NUM],
+        black_to_move: 0,
+    };
+    let mut file = 0;
+    while file < File::NUM {
+        keys.en_passant[file] = rand!();
+        file += 1;
+    }
+    let mut color = 0;
+    while color < Color::NUM {
+        let mut file = 0;
+        while file < File::NUM {
+            keys.castle_rights[color][file] = rand!();
+            file += 1;
+        }
+        let mut piece = 0;
+        while piece < Piece::NUM {
+            let mut square = 0;
+            while square < Square::NUM {
+                keys.pieces[color][piece][square] = rand!();
+                square += 1;
+            }
+            piece += 1;
+        }
+        color += 1;
+    }
+    keys.black_to_move = rand!();
+    keys
+};
+
+/*
+Hash of the position after a reversible move without playing it
+Captures, pawn moves, castling and moves that give up castling rights can't lead back to
+an earlier position, so None is returned for them
+*/
+pub fn reversible_child_hash(board: &Board, make_move: Move) -> Option<u64> {
+    let stm = board.side_to_move();
+    let piece = board.piece_on(make_move.from)?;
+    if piece == Piece::Pawn || board.occupied().has(make_move.to) {
+        return None;
+    }
+    let rights = board.castle_rights(stm);
+    let has_rights = rights.short.is_some() || rights.long.is_some();
+    let back_rank = Rank::First.relative_to(stm);
+    let loses_rights = match piece {
+        Piece::King => has_rights,
+        Piece::Rook => {
+            make_move.from.rank() == back_rank
+                && [rights.short, rights.long].contains(&Some(make_move.from.file()))
+        }
+        _ => false,
+    };
+    if loses_rights {
+        return None;
+    }
+    let keys = &KEYS.pieces[stm as usize][piece as usize];
+    let mut hash = board.hash()
+        ^ keys[make_move.from as usize]
+        ^ keys[make_move.to as usize]
+        ^ KEYS.black_to_move;
+    if let Some(file) = board.en_passant() {
+        hash ^= KEYS.en_passant[file as usize];
+    }
+    Some(hash)
+}
+
+#[test]
+fn zobrist_keys() {
+    use std::str::FromStr;
+
+    let hash_of = |board: &Board| {
+        let mut hash = 0;
+        for color in Color::ALL {
+            for piece in Piece::ALL {
+                for square in board.pieces(piece) & board.colors(color) {
+                    hash ^= KEYS.pieces[color as usize][piece as usize][square as usize];
+                }
+            }
+            let rights = board.castle_rights(color);
+            for file in [rights.short, rights.long].into_iter().flatten() {
+                hash ^= KEYS.castle_rights[color as usize][file as usize];
+            }
+        }
+        if let Some(file) = board.en_passant() {
+            hash ^= KEYS.en_passant[file as usize];
+        }
+        if board.side_to_move() == Color::Black {
+            hash ^= KEYS.black_to_move;
+        }
+        hash
+    };
+
+    for fen in [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+    ] {
+        let board = Board::from_str(fen).unwrap();
+        assert_eq!(hash_of(&board), board.hash());
+
+        let mut reversible = 0;
+        board.generate_moves(|moves| {
+            for make_move in moves {
+                let mut child = board.clone();
+                child.play_unchecked(make_move);
+                assert_eq!(hash_of(&child), child.hash());
+                if let Some(hash) = reversible_child_hash(&board, make_move) {
+                    assert_eq!(hash, child.hash(), "{} {}", fen, make_move);
+                    reversible += 1;
+                }
+            }
+            false
+        });
+        assert!(reversible > 0);
+    }
+}
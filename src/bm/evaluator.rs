@@ -0,0 +1,84 @@
+use cozy_chess::{Board, Color, Move};
+
+#[cfg(not(embedded_net))]
+pub use super::basic_eval::BasicEval as DefaultEvaluator;
+#[cfg(embedded_net)]
+pub use super::nnue::Nnue as DefaultEvaluator;
+
+/*
+Evaluator output before (raw) and after scaling to centipawns
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct NnOutput {
+    pub raw: i32,
+    pub eval: i16,
+}
+
+/*
+Evaluator outputs with each side to move, from that side's perspective
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct Perspectives {
+    pub white: NnOutput,
+    pub black: NnOutput,
+}
+
+/*
+Raw output of the side to move by source, only the first output is used for evaluation
+*/
+#[derive(Debug, Clone)]
+pub struct NnBreakdown {
+    pub outputs: Vec<i32>,
+    pub stm: i32,
+    pub nstm: i32,
+    pub bias: i32,
+}
+
+/*
+Incrementally updated static evaluation, positions keep one in sync with their board
+The network is used when it's embedded at build time, otherwise the material based BasicEval
+*/
+pub trait Evaluator: std::fmt::Debug + Clone {
+    fn new() -> Self;
+
+    fn full_reset(&mut self, board: &Board);
+
+    fn null_move(&mut self);
+
+    /*
+    Board is the position before the move is played
+    */
+    fn make_move(&mut self, board: &Board, make_move: Move);
+
+    fn unmake_move(&mut self);
+
+    fn feed_forward(&mut self, stm: Color) -> i16;
+
+    fn feed_forward_both(&mut self) -> Perspectives;
+
+    fn breakdown(&mut self, stm: Color) -> NnBreakdown;
+
+    /*
+    Centipawn value of a raw output
+    */
+    fn to_centipawns(raw: i32) -> i16;
+}
+
+/*
+EvalFile value that stands for the network embedded at build time, GUIs send the default back
+*/
+pub const EMBEDDED_NET: &str = "<embedded>";
+
+#[cfg(embedded_net)]
+pub use super::nnue::load_network;
+
+#[cfg(not(embedded_net))]
+pub fn load_network(path: &str) -> Result<(), String> {
+    if path == EMBEDDED_NET {
+        return Ok(());
+    }
+    Err(format!(
+        "can't load {}, built without an embedded network so NNUE is unavailable",
+        path
+    ))
+}
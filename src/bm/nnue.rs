@@ -1,18 +1,128 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
+use arrayvec::ArrayVec;
 use cozy_chess::{Board, Color, File, Move, Piece, Rank, Square};
 
 use self::layers::{Dense, Incremental};
 
 use super::bm_runner::ab_runner;
+use super::evaluator::{Evaluator, NnBreakdown, NnOutput, Perspectives, EMBEDDED_NET};
 
 mod include;
 mod layers;
+mod simd;
 
 include!(concat!(env!("OUT_DIR"), "/arch.rs"));
 
 const NN_BYTES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/eval.bin"));
 
+const HEADER_LEN: usize = 12;
+
+/*
+Network weights shared by all evaluators
+The embedded network is used until another one is loaded with load_network
+*/
+#[derive(Debug)]
+struct Network {
+    incremental: Arc<[[i16; MID]; INPUT]>,
+    incremental_bias: [i16; MID],
+    out: Arc<[[i8; MID * 2]; OUTPUT]>,
+    out_bias: [i32; OUTPUT],
+}
+
+impl Network {
+    /*
+    Networks are only accepted if their architecture matches the compiled one
+    */
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN {
+            return Err("missing network header".to_string());
+        }
+        let mut arch = [0; 3];
+        for (bytes, layer) in bytes.chunks(4).zip(&mut arch) {
+            *layer = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        }
+        if arch != [INPUT, MID, OUTPUT] {
+            return Err(format!(
+                "architecture {:?} doesn't match the expected {:?}",
+                arch,
+                [INPUT, MID, OUTPUT]
+            ));
+        }
+        let expected = HEADER_LEN + INPUT * MID * 2 + MID * 2 + MID * OUTPUT * 2 + OUTPUT * 2;
+        if bytes.len() != expected {
+            return Err(format!(
+                "expected {} bytes, found {}",
+                expected,
+                bytes.len()
+            ));
+        }
+
+        let mut bytes = &bytes[HEADER_LEN..];
+        let incremental = Arc::from(include::sparse_from_bytes_i16::<i16, INPUT, MID>(bytes));
+        bytes = &bytes[INPUT * MID * 2..];
+        let incremental_bias = include::bias_from_bytes_i16::<i16, MID>(bytes);
+        bytes = &bytes[MID * 2..];
+        let out = Arc::from(include::dense_from_bytes_i8::<i8, { MID * 2 }, OUTPUT>(
+            bytes,
+        ));
+        bytes = &bytes[MID * OUTPUT * 2..];
+        let out_bias = include::bias_from_bytes_i16::<i32, OUTPUT>(bytes);
+        Ok(Self {
+            incremental,
+            incremental_bias,
+            out,
+            out_bias,
+        })
+    }
+}
+
+static NETWORK: RwLock<Option<Arc<Network>>> = RwLock::new(None);
+
+fn network() -> Arc<Network> {
+    if let Some(network) = &*NETWORK.read().unwrap() {
+        return network.clone();
+    }
+    let mut network = NETWORK.write().unwrap();
+    network
+        .get_or_insert_with(|| Arc::new(Network::from_bytes(NN_BYTES).unwrap()))
+        .clone()
+}
+
+/*
+Replaces the network used by evaluators created from now on,
+EMBEDDED_NET switches back to the network embedded at build time
+*/
+pub fn load_network(path: &str) -> Result<(), String> {
+    if path == EMBEDDED_NET {
+        *NETWORK.write().unwrap() = None;
+        return Ok(());
+    }
+    let bytes = std::fs::read(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+    let network = Network::from_bytes(&bytes)?;
+    *NETWORK.write().unwrap() = Some(Arc::new(network));
+    Ok(())
+}
+
+/*
+Input feature of a piece from white's and black's perspective
+*/
+#[inline]
+fn feature_indices(
+    w_king: Square,
+    b_king: Square,
+    sq: Square,
+    piece: Piece,
+    color: Color,
+) -> (usize, usize) {
+    let w_piece_index = color as usize * 6 + piece as usize;
+    let b_piece_index = (!color) as usize * 6 + piece as usize;
+
+    let w_index = w_king as usize * 768 + w_piece_index * 64 + sq as usize;
+    let b_index = ((b_king as usize ^ 56) * 768 + b_piece_index * 64 + sq as usize) ^ 56;
+    (w_index, b_index)
+}
+
 #[derive(Debug, Clone)]
 pub struct Accumulator {
     w_input_layer: Incremental<INPUT, MID>,
@@ -28,12 +138,7 @@ impl Accumulator {
         piece: Piece,
         color: Color,
     ) {
-        let w_piece_index = color as usize * 6 + piece as usize;
-        let b_piece_index = (!color) as usize * 6 + piece as usize;
-
-        let w_index = w_king as usize * 768 + w_piece_index * 64 + sq as usize;
-        let b_index = (b_king as usize ^ 56) * 768 + b_piece_index * 64 + sq as usize ^ 56;
-
+        let (w_index, b_index) = feature_indices(w_king, b_king, sq, piece, color);
         if INCR {
             self.w_input_layer.incr_ff::<1>(w_index);
             self.b_input_layer.incr_ff::<1>(b_index);
@@ -44,7 +149,128 @@ impl Accumulator {
     }
 }
 
-#[derive(Debug, Clone)]
+/*
+Pieces removed from and added to the board by a move,
+every feature update of a move is derived from these
+*/
+#[derive(Debug, Clone, Default)]
+pub struct DirtyPieces {
+    pub removed: ArrayVec<(Square, Piece, Color), 2>,
+    pub added: ArrayVec<(Square, Piece, Color), 2>,
+}
+
+impl DirtyPieces {
+    pub fn new(board: &Board, make_move: Move) -> Self {
+        let mut dirty = Self::default();
+        let stm = board.side_to_move();
+        let from_sq = make_move.from;
+        let to_sq = make_move.to;
+        let from_type = board.piece_on(from_sq).unwrap();
+        dirty.removed.push((from_sq, from_type, stm));
+
+        /*
+        Castling is encoded as the king capturing its own rook
+        */
+        if board.color_on(to_sq) == Some(stm) {
+            let stm_first = match stm {
+                Color::White => Rank::First,
+                Color::Black => Rank::Eighth,
+            };
+            let (king_file, rook_file) = if to_sq.file() > from_sq.file() {
+                (File::G, File::F)
+            } else {
+                (File::C, File::D)
+            };
+            dirty.removed.push((to_sq, Piece::Rook, stm));
+            dirty
+                .added
+                .push((Square::new(king_file, stm_first), Piece::King, stm));
+            dirty
+                .added
+                .push((Square::new(rook_file, stm_first), Piece::Rook, stm));
+            return dirty;
+        }
+
+        if let Some(captured) = board.piece_on(to_sq) {
+            dirty.removed.push((to_sq, captured, !stm));
+        } else if let Some(ep) = board.en_passant() {
+            let (stm_fifth, stm_sixth) = match stm {
+                Color::White => (Rank::Fifth, Rank::Sixth),
+                Color::Black => (Rank::Fourth, Rank::Third),
+            };
+            if from_type == Piece::Pawn && to_sq == Square::new(ep, stm_sixth) {
+                dirty
+                    .removed
+                    .push((Square::new(ep, stm_fifth), Piece::Pawn, !stm));
+            }
+        }
+        dirty
+            .added
+            .push((to_sq, make_move.promotion.unwrap_or(from_type), stm));
+        dirty
+    }
+}
+
+#[test]
+fn dirty_pieces() {
+    use std::str::FromStr;
+
+    let board = Board::from_str("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+    let dirty = |from: Square, to: Square, promotion: Option<Piece>| {
+        DirtyPieces::new(
+            &board,
+            Move {
+                from,
+                to,
+                promotion,
+            },
+        )
+    };
+
+    let castle = dirty(Square::E1, Square::H1, None);
+    assert_eq!(
+        castle.removed.as_slice(),
+        &[
+            (Square::E1, Piece::King, Color::White),
+            (Square::H1, Piece::Rook, Color::White)
+        ]
+    );
+    assert_eq!(
+        castle.added.as_slice(),
+        &[
+            (Square::G1, Piece::King, Color::White),
+            (Square::F1, Piece::Rook, Color::White)
+        ]
+    );
+
+    let ep = dirty(Square::E5, Square::D6, None);
+    assert_eq!(
+        ep.removed.as_slice(),
+        &[
+            (Square::E5, Piece::Pawn, Color::White),
+            (Square::D5, Piece::Pawn, Color::Black)
+        ]
+    );
+    assert_eq!(
+        ep.added.as_slice(),
+        &[(Square::D6, Piece::Pawn, Color::White)]
+    );
+
+    let promotion = dirty(Square::B7, Square::A8, Some(Piece::Queen));
+    assert_eq!(
+        promotion.removed.as_slice(),
+        &[
+            (Square::B7, Piece::Pawn, Color::White),
+            (Square::A8, Piece::Rook, Color::Black)
+        ]
+    );
+    assert_eq!(
+        promotion.added.as_slice(),
+        &[(Square::A8, Piece::Queen, Color::White)]
+    );
+}
+
+#[derive(Debug)]
 pub struct Nnue {
     accumulator: Vec<Accumulator>,
     bias: Arc<[i16; MID]>,
@@ -52,39 +278,30 @@ pub struct Nnue {
     out_layer: Dense<{ MID * 2 }, OUTPUT>,
 }
 
-impl Nnue {
-    pub fn new() -> Self {
-        let mut bytes = &NN_BYTES[12..];
-        let incremental = Arc::new(*include::sparse_from_bytes_i16::<i16, INPUT, MID>(bytes));
-        bytes = &bytes[INPUT * MID * 2..];
-        let incremental_bias = include::bias_from_bytes_i16::<i16, MID>(bytes);
-        bytes = &bytes[MID * 2..];
-        let out = Arc::new(*include::dense_from_bytes_i8::<i8, { MID * 2 }, OUTPUT>(
-            bytes,
-        ));
-        bytes = &bytes[MID * OUTPUT * 2..];
-        let out_bias = include::bias_from_bytes_i16::<i32, OUTPUT>(bytes);
-        bytes = &bytes[OUTPUT * 2..];
-        assert!(bytes.is_empty(), "{}", bytes.len());
-
-        let input_layer = Incremental::new(incremental, incremental_bias);
-        let out_layer = Dense::new(out, out_bias);
-
+impl Clone for Nnue {
+    fn clone(&self) -> Self {
         Self {
-            accumulator: vec![
-                Accumulator {
-                    w_input_layer: input_layer.clone(),
-                    b_input_layer: input_layer,
-                };
-                ab_runner::MAX_PLY as usize + 1
-            ],
-            bias: Arc::new(incremental_bias),
-            out_layer,
-            head: 0,
+            accumulator: self.accumulator.clone(),
+            bias: self.bias.clone(),
+            head: self.head,
+            out_layer: self.out_layer.clone(),
         }
     }
 
-    pub fn reset(&mut self, board: &Board) {
+    /*
+    Search threads copy the runner's evaluator every search,
+    the accumulator stack is copied into the existing one
+    */
+    fn clone_from(&mut self, source: &Self) {
+        self.accumulator.clone_from(&source.accumulator);
+        self.bias.clone_from(&source.bias);
+        self.head = source.head;
+        self.out_layer.clone_from(&source.out_layer);
+    }
+}
+
+impl Nnue {
+    fn reset(&mut self, board: &Board) {
         let w_king = board.king(Color::White);
         let b_king = board.king(Color::Black);
         let acc = &mut self.accumulator[self.head];
@@ -99,11 +316,6 @@ impl Nnue {
         }
     }
 
-    pub fn full_reset(&mut self, board: &Board) {
-        self.head = 0;
-        self.reset(board);
-    }
-
     fn push_accumulator(&mut self) {
         let w_out = *self.accumulator[self.head].w_input_layer.get();
         let b_out = *self.accumulator[self.head].b_input_layer.get();
@@ -112,109 +324,211 @@ impl Nnue {
         self.head += 1;
     }
 
-    pub fn null_move(&mut self) {
+    #[inline]
+    fn activations(&self, stm: Color) -> [u8; MID * 2] {
+        let acc = &self.accumulator[self.head];
+        let mut incr = [0; MID * 2];
+        let (stm, nstm) = match stm {
+            Color::White => (&acc.w_input_layer, &acc.b_input_layer),
+            Color::Black => (&acc.b_input_layer, &acc.w_input_layer),
+        };
+        layers::sq_clipped_relu(*stm.get(), &mut incr);
+        layers::sq_clipped_relu(*nstm.get(), &mut incr[MID..]);
+        incr
+    }
+
+    #[inline]
+    fn feed_forward_raw(&mut self, stm: Color) -> i32 {
+        self.out_layer.ff(&self.activations(stm))[0]
+    }
+}
+
+impl Evaluator for Nnue {
+    fn new() -> Self {
+        let network = network();
+        let incremental_bias = network.incremental_bias;
+        let input_layer = Incremental::new(network.incremental.clone(), incremental_bias);
+        let out_layer = Dense::new(network.out.clone(), network.out_bias);
+
+        Self {
+            accumulator: vec![
+                Accumulator {
+                    w_input_layer: input_layer.clone(),
+                    b_input_layer: input_layer,
+                };
+                ab_runner::MAX_PLY as usize + 1
+            ],
+            bias: Arc::new(incremental_bias),
+            out_layer,
+            head: 0,
+        }
+    }
+
+    fn full_reset(&mut self, board: &Board) {
+        self.head = 0;
+        self.reset(board);
+    }
+
+    fn null_move(&mut self) {
         self.push_accumulator();
     }
 
-    pub fn make_move(&mut self, board: &Board, make_move: Move) {
+    fn make_move(&mut self, board: &Board, make_move: Move) {
         self.push_accumulator();
-        let from_sq = make_move.from;
-        let from_type = board.piece_on(from_sq).unwrap();
-        let stm = board.side_to_move();
-        let w_king = board.king(Color::White);
-        let b_king = board.king(Color::Black);
-        if from_type == Piece::King {
+        if board.piece_on(make_move.from) == Some(Piece::King) {
             let mut board_clone = board.clone();
             board_clone.play_unchecked(make_move);
             self.reset(&board_clone);
             return;
         }
+        let w_king = board.king(Color::White);
+        let b_king = board.king(Color::Black);
         let acc = &mut self.accumulator[self.head];
+        let dirty = DirtyPieces::new(board, make_move);
+        for &(sq, piece, color) in &dirty.removed {
+            acc.update::<false>(w_king, b_king, sq, piece, color);
+        }
+        for &(sq, piece, color) in &dirty.added {
+            acc.update::<true>(w_king, b_king, sq, piece, color);
+        }
+    }
 
-        acc.update::<false>(w_king, b_king, from_sq, from_type, stm);
+    fn unmake_move(&mut self) {
+        self.head -= 1;
+    }
 
-        let to_sq = make_move.to;
-        if let Some((captured, color)) = board.piece_on(to_sq).zip(board.color_on(to_sq)) {
-            acc.update::<false>(w_king, b_king, to_sq, captured, color);
+    /*
+    Splits the output of the side to move into the parts coming from
+    each half of the accumulator and the output bias
+    */
+    fn breakdown(&mut self, stm: Color) -> NnBreakdown {
+        let incr = self.activations(stm);
+        let outputs = self.out_layer.ff(&incr);
+        let bias = network().out_bias[0];
+        let mut stm_half = incr;
+        stm_half[MID..].fill(0);
+        let mut nstm_half = incr;
+        nstm_half[..MID].fill(0);
+        NnBreakdown {
+            outputs: outputs.to_vec(),
+            stm: self.out_layer.ff(&stm_half)[0] - bias,
+            nstm: self.out_layer.ff(&nstm_half)[0] - bias,
+            bias,
         }
+    }
 
-        if let Some(ep) = board.en_passant() {
-            let (stm_fifth, stm_sixth) = match stm {
-                Color::White => (Rank::Fifth, Rank::Sixth),
-                Color::Black => (Rank::Fourth, Rank::Third),
-            };
-            if from_type == Piece::Pawn && to_sq == Square::new(ep, stm_sixth) {
-                acc.update::<false>(
-                    w_king,
-                    b_king,
-                    Square::new(ep, stm_fifth),
-                    Piece::Pawn,
-                    !stm,
-                );
-            }
-        }
-        if Some(stm) == board.color_on(to_sq) {
-            let stm_first = match stm {
-                Color::White => Rank::First,
-                Color::Black => Rank::Eighth,
-            };
-            if to_sq.file() > from_sq.file() {
-                acc.update::<true>(
-                    w_king,
-                    b_king,
-                    Square::new(File::G, stm_first),
-                    Piece::King,
-                    stm,
-                );
-                acc.update::<true>(
-                    w_king,
-                    b_king,
-                    Square::new(File::F, stm_first),
-                    Piece::Rook,
-                    stm,
-                );
-            } else {
-                acc.update::<true>(
-                    w_king,
-                    b_king,
-                    Square::new(File::C, stm_first),
-                    Piece::King,
-                    stm,
-                );
-                acc.update::<true>(
-                    w_king,
-                    b_king,
-                    Square::new(File::D, stm_first),
-                    Piece::Rook,
-                    stm,
-                );
+    #[inline]
+    fn feed_forward(&mut self, stm: Color) -> i16 {
+        layers::out(self.feed_forward_raw(stm))
+    }
+
+    fn feed_forward_both(&mut self) -> Perspectives {
+        let mut output = |stm| {
+            let raw = self.feed_forward_raw(stm);
+            NnOutput {
+                raw,
+                eval: layers::out(raw),
             }
-        } else {
-            acc.update::<true>(
-                w_king,
-                b_king,
-                to_sq,
-                make_move.promotion.unwrap_or(from_type),
-                stm,
-            );
+        };
+        Perspectives {
+            white: output(Color::White),
+            black: output(Color::Black),
         }
     }
+    fn to_centipawns(raw: i32) -> i16 {
+        layers::out(raw)
+    }
+}
 
-    pub fn unmake_move(&mut self) {
-        self.head -= 1;
+/*
+Evaluates the loaded network from scratch in i32 without incremental updates,
+a reference for the quantized evaluator that can't overflow the i16 accumulator
+*/
+pub fn reference_output(board: &Board, stm: Color) -> i32 {
+    let network = network();
+    let w_king = board.king(Color::White);
+    let b_king = board.king(Color::Black);
+    let mut w_acc = network.incremental_bias.map(|bias| bias as i32);
+    let mut b_acc = w_acc;
+    for sq in board.occupied() {
+        let piece = board.piece_on(sq).unwrap();
+        let color = board.color_on(sq).unwrap();
+        let (w_index, b_index) = feature_indices(w_king, b_king, sq, piece, color);
+        for (acc, &weight) in w_acc.iter_mut().zip(&network.incremental[w_index]) {
+            *acc += weight as i32;
+        }
+        for (acc, &weight) in b_acc.iter_mut().zip(&network.incremental[b_index]) {
+            *acc += weight as i32;
+        }
     }
+    let (stm_acc, nstm_acc) = match stm {
+        Color::White => (&w_acc, &b_acc),
+        Color::Black => (&b_acc, &w_acc),
+    };
+    stm_acc
+        .iter()
+        .chain(nstm_acc)
+        .zip(&network.out[0])
+        .map(|(&acc, &weight)| layers::sq_clipped_relu_ref(acc) * weight as i32)
+        .sum::<i32>()
+        + network.out_bias[0]
+}
 
-    #[inline]
-    pub fn feed_forward(&mut self, stm: Color) -> i16 {
-        let acc = &mut self.accumulator[self.head];
-        let mut incr = [0; MID * 2];
-        let (stm, nstm) = match stm {
-            Color::White => (&acc.w_input_layer, &acc.b_input_layer),
-            Color::Black => (&acc.b_input_layer, &acc.w_input_layer),
-        };
-        layers::sq_clipped_relu(*stm.get(), &mut incr);
-        layers::sq_clipped_relu(*nstm.get(), &mut incr[MID..]);
+/*
+Name of the instruction set the network's inner loops run on
+*/
+pub fn kernels() -> &'static str {
+    simd::kernels().name()
+}
 
-        layers::out(self.out_layer.ff(&incr)[0])
+/*
+Output of the evaluator that differs from the reference
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct Mismatch {
+    pub make_move: Option<Move>,
+    pub stm: Color,
+    pub output: i32,
+    pub reference: i32,
+}
+
+/*
+Checks the incremental evaluator against the reference on a position and
+every position one move away, both sides to move are compared
+Returns the number of compared outputs along with the mismatches
+*/
+pub fn verify_position(board: &Board) -> (usize, Vec<Mismatch>) {
+    let mut nnue = Nnue::new();
+    nnue.full_reset(board);
+    let mut checked = 0;
+    let mut mismatches = vec![];
+    let mut compare = |nnue: &mut Nnue, board: &Board, make_move: Option<Move>| {
+        for stm in [Color::White, Color::Black] {
+            let output = nnue.feed_forward_raw(stm);
+            let reference = reference_output(board, stm);
+            checked += 1;
+            if output != reference {
+                mismatches.push(Mismatch {
+                    make_move,
+                    stm,
+                    output,
+                    reference,
+                });
+            }
+        }
+    };
+    compare(&mut nnue, board, None);
+    let mut moves = vec![];
+    board.generate_moves(|piece_moves| {
+        moves.extend(piece_moves);
+        false
+    });
+    for make_move in moves {
+        let mut child = board.clone();
+        child.play_unchecked(make_move);
+        nnue.make_move(board, make_move);
+        compare(&mut nnue, &child, Some(make_move));
+        nnue.unmake_move();
     }
+    (checked, mismatches)
 }
@@ -1,11 +1,13 @@
 use std::sync::Arc;
 
+use super::simd;
+
 const UNITS: i16 = 400_i16;
 const FT_SCALE: i16 = 255;
 const SCALE: i16 = 64;
-const MIN: i16 = 0;
-const MAX: i16 = FT_SCALE;
-const SHIFT: i16 = 8;
+pub const MIN: i16 = 0;
+pub const MAX: i16 = FT_SCALE;
+pub const SHIFT: i16 = 8;
 
 #[derive(Debug, Clone)]
 pub struct Incremental<const INPUT: usize, const OUTPUT: usize> {
@@ -13,7 +15,7 @@ pub struct Incremental<const INPUT: usize, const OUTPUT: usize> {
     out: [i16; OUTPUT],
 }
 
-impl<'a, const INPUT: usize, const OUTPUT: usize> Incremental<INPUT, OUTPUT> {
+impl<const INPUT: usize, const OUTPUT: usize> Incremental<INPUT, OUTPUT> {
     pub fn new(weights: Arc<[[i16; OUTPUT]; INPUT]>, bias: [i16; OUTPUT]) -> Self {
         Self { weights, out: bias }
     }
@@ -24,9 +26,7 @@ impl<'a, const INPUT: usize, const OUTPUT: usize> Incremental<INPUT, OUTPUT> {
 
     #[inline]
     pub fn incr_ff<const CHANGE: i16>(&mut self, index: usize) {
-        for (out, &weight) in self.out.iter_mut().zip(&self.weights[index]) {
-            *out += weight * CHANGE;
-        }
+        simd::update_i16::<CHANGE>(&mut self.out, &self.weights[index]);
     }
 
     pub fn get(&self) -> &[i16; OUTPUT] {
@@ -49,9 +49,7 @@ impl<const INPUT: usize, const OUTPUT: usize> Dense<INPUT, OUTPUT> {
     pub fn ff(&self, inputs: &[u8; INPUT]) -> [i32; OUTPUT] {
         let mut out = self.bias;
         for (out, weights) in out.iter_mut().zip(&*self.weights) {
-            for (&input, &weight) in inputs.iter().zip(weights.iter()) {
-                *out += weight as i32 * input as i32;
-            }
+            *out += simd::dot_u8_i8(inputs, weights);
         }
         out
     }
@@ -64,8 +62,14 @@ pub fn out(x: i32) -> i16 {
 
 #[inline]
 pub fn sq_clipped_relu<const N: usize>(array: [i16; N], out: &mut [u8]) {
-    for (&x, clipped) in array.iter().zip(out.iter_mut()) {
-        let tmp = x.max(MIN).min(MAX) as u16;
-        *clipped = ((tmp * tmp) >> SHIFT) as u8;
-    }
+    simd::sq_clipped_relu(&array, out);
+}
+
+/*
+Scalar activation on an unclipped accumulator value, used by the reference evaluator
+*/
+#[inline]
+pub fn sq_clipped_relu_ref(x: i32) -> i32 {
+    let x = x.clamp(MIN as i32, MAX as i32);
+    (x * x) >> SHIFT
 }
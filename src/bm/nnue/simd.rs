@@ -0,0 +1,590 @@
+use std::sync::OnceLock;
+
+/*
+Inner loops of the network layers
+The kernels for the widest instruction set the CPU supports are picked the first time
+they're used, so a single binary runs the vectorized loops wherever they're available
+Vectorized kernels handle whole vectors and leave what's left over to the next narrower one
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kernels {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Ssse3,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    #[cfg(target_arch = "x86_64")]
+    Avx512,
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+}
+
+impl Kernels {
+    /*
+    Widest first
+    */
+    const ALL: &'static [Kernels] = &[
+        #[cfg(target_arch = "x86_64")]
+        Kernels::Avx512,
+        #[cfg(target_arch = "x86_64")]
+        Kernels::Avx2,
+        #[cfg(target_arch = "x86_64")]
+        Kernels::Ssse3,
+        #[cfg(target_arch = "aarch64")]
+        Kernels::Neon,
+        Kernels::Scalar,
+    ];
+
+    pub fn available(self) -> bool {
+        match self {
+            Kernels::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Ssse3 => is_x86_feature_detected!("ssse3"),
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx512 => {
+                is_x86_feature_detected!("avx512f")
+                    && is_x86_feature_detected!("avx512bw")
+                    && is_x86_feature_detected!("avx512vnni")
+            }
+            #[cfg(target_arch = "aarch64")]
+            Kernels::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+        }
+    }
+
+    fn detect() -> Self {
+        *Self::ALL
+            .iter()
+            .find(|kernels| kernels.available())
+            .unwrap()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kernels::Scalar => "scalar",
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Ssse3 => "ssse3",
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx2 => "avx2",
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx512 => "avx512",
+            #[cfg(target_arch = "aarch64")]
+            Kernels::Neon => "neon",
+        }
+    }
+
+    /*
+    Only kernels that are available may be used, the dispatch assumes the CPU supports them
+    */
+    #[inline]
+    fn update_i16<const CHANGE: i16>(self, out: &mut [i16], weights: &[i16]) {
+        match self {
+            Kernels::Scalar => scalar::update_i16::<CHANGE>(out, weights),
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Ssse3 => unsafe { ssse3::update_i16::<CHANGE>(out, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx2 => unsafe { avx2::update_i16::<CHANGE>(out, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx512 => unsafe { avx512::update_i16::<CHANGE>(out, weights) },
+            #[cfg(target_arch = "aarch64")]
+            Kernels::Neon => neon::update_i16::<CHANGE>(out, weights),
+        }
+    }
+
+    #[inline]
+    fn sq_clipped_relu(self, inputs: &[i16], out: &mut [u8]) {
+        match self {
+            Kernels::Scalar => scalar::sq_clipped_relu(inputs, out),
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Ssse3 => unsafe { ssse3::sq_clipped_relu(inputs, out) },
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx2 => unsafe { avx2::sq_clipped_relu(inputs, out) },
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx512 => unsafe { avx512::sq_clipped_relu(inputs, out) },
+            #[cfg(target_arch = "aarch64")]
+            Kernels::Neon => neon::sq_clipped_relu(inputs, out),
+        }
+    }
+
+    #[inline]
+    fn dot_u8_i8(self, inputs: &[u8], weights: &[i8]) -> i32 {
+        match self {
+            Kernels::Scalar => scalar::dot_u8_i8(inputs, weights),
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Ssse3 => unsafe { ssse3::dot_u8_i8(inputs, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx2 => unsafe { avx2::dot_u8_i8(inputs, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx512 => unsafe { avx512::dot_u8_i8(inputs, weights) },
+            #[cfg(target_arch = "aarch64")]
+            Kernels::Neon => neon::dot_u8_i8(inputs, weights),
+        }
+    }
+}
+
+static KERNELS: OnceLock<Kernels> = OnceLock::new();
+
+pub fn kernels() -> Kernels {
+    *KERNELS.get_or_init(Kernels::detect)
+}
+
+#[inline]
+pub fn update_i16<const CHANGE: i16>(out: &mut [i16], weights: &[i16]) {
+    kernels().update_i16::<CHANGE>(out, weights);
+}
+
+#[inline]
+pub fn sq_clipped_relu(inputs: &[i16], out: &mut [u8]) {
+    kernels().sq_clipped_relu(inputs, out);
+}
+
+#[inline]
+pub fn dot_u8_i8(inputs: &[u8], weights: &[i8]) -> i32 {
+    kernels().dot_u8_i8(inputs, weights)
+}
+
+/*
+The vector activations square the clipped value in 16 bit lanes and shift it as unsigned,
+which matches the i32 scalar path only while MAX² fits in a u16 and the result in a u8
+*/
+const _: () = assert!((super::layers::MAX as u32).pow(2) < 1 << 16);
+const _: () = assert!((super::layers::MAX as u32).pow(2) >> super::layers::SHIFT < 1 << 8);
+
+mod scalar {
+    use super::super::layers::{MAX, MIN, SHIFT};
+
+    #[inline]
+    pub fn update_i16<const CHANGE: i16>(out: &mut [i16], weights: &[i16]) {
+        for (out, &weight) in out.iter_mut().zip(weights) {
+            *out += weight * CHANGE;
+        }
+    }
+
+    #[inline]
+    pub fn sq_clipped_relu(inputs: &[i16], out: &mut [u8]) {
+        for (&x, clipped) in inputs.iter().zip(out.iter_mut()) {
+            let tmp = x.clamp(MIN, MAX) as u16;
+            *clipped = ((tmp * tmp) >> SHIFT) as u8;
+        }
+    }
+
+    #[inline]
+    pub fn dot_u8_i8(inputs: &[u8], weights: &[i8]) -> i32 {
+        inputs
+            .iter()
+            .zip(weights)
+            .map(|(&input, &weight)| weight as i32 * input as i32)
+            .sum()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod ssse3 {
+    use std::arch::x86_64::*;
+
+    use super::super::layers::{MAX, MIN, SHIFT};
+    use super::scalar;
+
+    /*
+    8 i16 or 8 u8 widened to i16
+    */
+    const LANES: usize = 8;
+
+    #[inline]
+    fn vector_len(len: usize) -> usize {
+        len / LANES * LANES
+    }
+
+    #[inline]
+    #[target_feature(enable = "ssse3")]
+    pub fn update_i16<const CHANGE: i16>(out: &mut [i16], weights: &[i16]) {
+        let len = vector_len(out.len().min(weights.len()));
+        for (out, weights) in out[..len]
+            .chunks_exact_mut(LANES)
+            .zip(weights[..len].chunks_exact(LANES))
+        {
+            unsafe {
+                let acc = _mm_loadu_si128(out.as_ptr() as *const __m128i);
+                let weights = _mm_loadu_si128(weights.as_ptr() as *const __m128i);
+                let acc = if CHANGE > 0 {
+                    _mm_add_epi16(acc, weights)
+                } else {
+                    _mm_sub_epi16(acc, weights)
+                };
+                _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, acc);
+            }
+        }
+        scalar::update_i16::<CHANGE>(&mut out[len..], &weights[len..]);
+    }
+
+    #[inline]
+    #[target_feature(enable = "ssse3")]
+    pub fn sq_clipped_relu(inputs: &[i16], out: &mut [u8]) {
+        let len = vector_len(inputs.len().min(out.len()));
+        for (inputs, out) in inputs[..len]
+            .chunks_exact(LANES)
+            .zip(out[..len].chunks_exact_mut(LANES))
+        {
+            unsafe {
+                let x = _mm_loadu_si128(inputs.as_ptr() as *const __m128i);
+                let x = _mm_min_epi16(_mm_max_epi16(x, _mm_set1_epi16(MIN)), _mm_set1_epi16(MAX));
+                let x = _mm_srli_epi16::<{ SHIFT as i32 }>(_mm_mullo_epi16(x, x));
+                _mm_storel_epi64(out.as_mut_ptr() as *mut __m128i, _mm_packus_epi16(x, x));
+            }
+        }
+        scalar::sq_clipped_relu(&inputs[len..], &mut out[len..]);
+    }
+
+    /*
+    Bytes are widened by unpacking, the weights are sign extended by unpacking them
+    with themselves and shifting arithmetically
+    */
+    #[inline]
+    #[target_feature(enable = "ssse3")]
+    pub fn dot_u8_i8(inputs: &[u8], weights: &[i8]) -> i32 {
+        let len = vector_len(inputs.len().min(weights.len()));
+        let sum = unsafe {
+            let mut acc = _mm_setzero_si128();
+            for (inputs, weights) in inputs[..len]
+                .chunks_exact(LANES)
+                .zip(weights[..len].chunks_exact(LANES))
+            {
+                let inputs = _mm_loadl_epi64(inputs.as_ptr() as *const __m128i);
+                let inputs = _mm_unpacklo_epi8(inputs, _mm_setzero_si128());
+                let weights = _mm_loadl_epi64(weights.as_ptr() as *const __m128i);
+                let weights = _mm_srai_epi16::<8>(_mm_unpacklo_epi8(weights, weights));
+                acc = _mm_add_epi32(acc, _mm_madd_epi16(inputs, weights));
+            }
+            let acc = _mm_hadd_epi32(acc, acc);
+            _mm_cvtsi128_si32(_mm_hadd_epi32(acc, acc))
+        };
+        sum + scalar::dot_u8_i8(&inputs[len..], &weights[len..])
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::super::layers::{MAX, MIN, SHIFT};
+    use super::scalar;
+
+    /*
+    16 i16 or 16 u8 widened to i16
+    */
+    const LANES: usize = 16;
+
+    #[inline]
+    fn vector_len(len: usize) -> usize {
+        len / LANES * LANES
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    pub fn update_i16<const CHANGE: i16>(out: &mut [i16], weights: &[i16]) {
+        let len = vector_len(out.len().min(weights.len()));
+        for (out, weights) in out[..len]
+            .chunks_exact_mut(LANES)
+            .zip(weights[..len].chunks_exact(LANES))
+        {
+            unsafe {
+                let acc = _mm256_loadu_si256(out.as_ptr() as *const __m256i);
+                let weights = _mm256_loadu_si256(weights.as_ptr() as *const __m256i);
+                let acc = if CHANGE > 0 {
+                    _mm256_add_epi16(acc, weights)
+                } else {
+                    _mm256_sub_epi16(acc, weights)
+                };
+                _mm256_storeu_si256(out.as_mut_ptr() as *mut __m256i, acc);
+            }
+        }
+        scalar::update_i16::<CHANGE>(&mut out[len..], &weights[len..]);
+    }
+
+    /*
+    Squares fit in u16, packing them to bytes interleaves the 128 bit lanes
+    so the 64 bit halves holding the results are moved next to each other
+    */
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    pub fn sq_clipped_relu(inputs: &[i16], out: &mut [u8]) {
+        let len = vector_len(inputs.len().min(out.len()));
+        for (inputs, out) in inputs[..len]
+            .chunks_exact(LANES)
+            .zip(out[..len].chunks_exact_mut(LANES))
+        {
+            unsafe {
+                let x = _mm256_loadu_si256(inputs.as_ptr() as *const __m256i);
+                let x = _mm256_min_epi16(
+                    _mm256_max_epi16(x, _mm256_set1_epi16(MIN)),
+                    _mm256_set1_epi16(MAX),
+                );
+                let x = _mm256_srli_epi16::<{ SHIFT as i32 }>(_mm256_mullo_epi16(x, x));
+                let packed = _mm256_permute4x64_epi64::<0b11_01_10_00>(_mm256_packus_epi16(x, x));
+                _mm_storeu_si128(
+                    out.as_mut_ptr() as *mut __m128i,
+                    _mm256_castsi256_si128(packed),
+                );
+            }
+        }
+        scalar::sq_clipped_relu(&inputs[len..], &mut out[len..]);
+    }
+
+    /*
+    Products are summed in i32 after widening, multiplying the bytes directly
+    saturates the sums of pairs
+    */
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    pub fn dot_u8_i8(inputs: &[u8], weights: &[i8]) -> i32 {
+        let len = vector_len(inputs.len().min(weights.len()));
+        let mut sum = 0;
+        unsafe {
+            let mut acc = _mm256_setzero_si256();
+            for (inputs, weights) in inputs[..len]
+                .chunks_exact(LANES)
+                .zip(weights[..len].chunks_exact(LANES))
+            {
+                let inputs =
+                    _mm256_cvtepu8_epi16(_mm_loadu_si128(inputs.as_ptr() as *const __m128i));
+                let weights =
+                    _mm256_cvtepi8_epi16(_mm_loadu_si128(weights.as_ptr() as *const __m128i));
+                acc = _mm256_add_epi32(acc, _mm256_madd_epi16(inputs, weights));
+            }
+            let acc = _mm_add_epi32(
+                _mm256_castsi256_si128(acc),
+                _mm256_extracti128_si256::<1>(acc),
+            );
+            let acc = _mm_add_epi32(acc, _mm_shuffle_epi32::<0b01_00_11_10>(acc));
+            let acc = _mm_add_epi32(acc, _mm_shuffle_epi32::<0b10_11_00_01>(acc));
+            sum += _mm_cvtsi128_si32(acc);
+        }
+        sum + scalar::dot_u8_i8(&inputs[len..], &weights[len..])
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    use std::arch::x86_64::*;
+
+    use super::super::layers::{MAX, MIN, SHIFT};
+    use super::avx2;
+
+    /*
+    32 i16 or 64 u8
+    */
+    const I16_LANES: usize = 32;
+    const U8_LANES: usize = 64;
+
+    #[inline]
+    #[target_feature(enable = "avx2,avx512f,avx512bw,avx512vnni")]
+    pub fn update_i16<const CHANGE: i16>(out: &mut [i16], weights: &[i16]) {
+        let len = out.len().min(weights.len()) / I16_LANES * I16_LANES;
+        for (out, weights) in out[..len]
+            .chunks_exact_mut(I16_LANES)
+            .zip(weights[..len].chunks_exact(I16_LANES))
+        {
+            unsafe {
+                let acc = _mm512_loadu_si512(out.as_ptr() as *const __m512i);
+                let weights = _mm512_loadu_si512(weights.as_ptr() as *const __m512i);
+                let acc = if CHANGE > 0 {
+                    _mm512_add_epi16(acc, weights)
+                } else {
+                    _mm512_sub_epi16(acc, weights)
+                };
+                _mm512_storeu_si512(out.as_mut_ptr() as *mut __m512i, acc);
+            }
+        }
+        avx2::update_i16::<CHANGE>(&mut out[len..], &weights[len..]);
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,avx512f,avx512bw,avx512vnni")]
+    pub fn sq_clipped_relu(inputs: &[i16], out: &mut [u8]) {
+        let len = inputs.len().min(out.len()) / I16_LANES * I16_LANES;
+        for (inputs, out) in inputs[..len]
+            .chunks_exact(I16_LANES)
+            .zip(out[..len].chunks_exact_mut(I16_LANES))
+        {
+            unsafe {
+                let x = _mm512_loadu_si512(inputs.as_ptr() as *const __m512i);
+                let x = _mm512_min_epi16(
+                    _mm512_max_epi16(x, _mm512_set1_epi16(MIN)),
+                    _mm512_set1_epi16(MAX),
+                );
+                let x = _mm512_srli_epi16::<{ SHIFT as u32 }>(_mm512_mullo_epi16(x, x));
+                _mm256_storeu_si256(out.as_mut_ptr() as *mut __m256i, _mm512_cvtepi16_epi8(x));
+            }
+        }
+        avx2::sq_clipped_relu(&inputs[len..], &mut out[len..]);
+    }
+
+    /*
+    VNNI sums groups of 4 byte products straight into i32 without saturating
+    */
+    #[inline]
+    #[target_feature(enable = "avx2,avx512f,avx512bw,avx512vnni")]
+    pub fn dot_u8_i8(inputs: &[u8], weights: &[i8]) -> i32 {
+        let len = inputs.len().min(weights.len()) / U8_LANES * U8_LANES;
+        let sum = unsafe {
+            let mut acc = _mm512_setzero_si512();
+            for (inputs, weights) in inputs[..len]
+                .chunks_exact(U8_LANES)
+                .zip(weights[..len].chunks_exact(U8_LANES))
+            {
+                acc = _mm512_dpbusd_epi32(
+                    acc,
+                    _mm512_loadu_si512(inputs.as_ptr() as *const __m512i),
+                    _mm512_loadu_si512(weights.as_ptr() as *const __m512i),
+                );
+            }
+            _mm512_reduce_add_epi32(acc)
+        };
+        sum + avx2::dot_u8_i8(&inputs[len..], &weights[len..])
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::super::layers::{MAX, MIN, SHIFT};
+    use super::scalar;
+
+    /*
+    8 i16 or 8 u8 widened to i16
+    */
+    const LANES: usize = 8;
+
+    #[inline]
+    fn vector_len(len: usize) -> usize {
+        len / LANES * LANES
+    }
+
+    #[inline]
+    pub fn update_i16<const CHANGE: i16>(out: &mut [i16], weights: &[i16]) {
+        let len = vector_len(out.len().min(weights.len()));
+        for (out, weights) in out[..len]
+            .chunks_exact_mut(LANES)
+            .zip(weights[..len].chunks_exact(LANES))
+        {
+            unsafe {
+                let acc = vld1q_s16(out.as_ptr());
+                let weights = vld1q_s16(weights.as_ptr());
+                let acc = if CHANGE > 0 {
+                    vaddq_s16(acc, weights)
+                } else {
+                    vsubq_s16(acc, weights)
+                };
+                vst1q_s16(out.as_mut_ptr(), acc);
+            }
+        }
+        scalar::update_i16::<CHANGE>(&mut out[len..], &weights[len..]);
+    }
+
+    #[inline]
+    pub fn sq_clipped_relu(inputs: &[i16], out: &mut [u8]) {
+        let len = vector_len(inputs.len().min(out.len()));
+        for (inputs, out) in inputs[..len]
+            .chunks_exact(LANES)
+            .zip(out[..len].chunks_exact_mut(LANES))
+        {
+            unsafe {
+                let x = vld1q_s16(inputs.as_ptr());
+                let x = vminq_s16(vmaxq_s16(x, vdupq_n_s16(MIN)), vdupq_n_s16(MAX));
+                /*
+                The product wraps to negative i16 values above 181, read as u16 it's exact
+                */
+                let x = vshrq_n_u16::<{ SHIFT as i32 }>(vreinterpretq_u16_s16(vmulq_s16(x, x)));
+                vst1_u8(out.as_mut_ptr(), vmovn_u16(x));
+            }
+        }
+        scalar::sq_clipped_relu(&inputs[len..], &mut out[len..]);
+    }
+
+    /*
+    Products of the widened inputs and weights are accumulated in i32
+    */
+    #[inline]
+    pub fn dot_u8_i8(inputs: &[u8], weights: &[i8]) -> i32 {
+        let len = vector_len(inputs.len().min(weights.len()));
+        let sum = unsafe {
+            let mut acc = vdupq_n_s32(0);
+            for (inputs, weights) in inputs[..len]
+                .chunks_exact(LANES)
+                .zip(weights[..len].chunks_exact(LANES))
+            {
+                let inputs = vreinterpretq_s16_u16(vmovl_u8(vld1_u8(inputs.as_ptr())));
+                let weights = vmovl_s8(vld1_s8(weights.as_ptr()));
+                acc = vmlal_s16(acc, vget_low_s16(inputs), vget_low_s16(weights));
+                acc = vmlal_high_s16(acc, inputs, weights);
+            }
+            vaddvq_s32(acc)
+        };
+        sum + scalar::dot_u8_i8(&inputs[len..], &weights[len..])
+    }
+}
+
+#[cfg(test)]
+fn check_kernels(kernels: Kernels) {
+    let mut seed = 0x2545_f491_u32;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    let scalar = Kernels::Scalar;
+    for len in [0, 1, 7, 15, 16, 17, 32, 47, 512] {
+        let weights = (0..len).map(|_| next() as i16 / 64).collect::<Vec<_>>();
+        let acc = (0..len).map(|_| next() as i16 / 4).collect::<Vec<_>>();
+        let mut expected = acc.clone();
+        let mut out = acc.clone();
+        scalar.update_i16::<1>(&mut expected, &weights);
+        kernels.update_i16::<1>(&mut out, &weights);
+        assert_eq!(out, expected, "{}", kernels.name());
+        scalar.update_i16::<-1>(&mut expected, &weights);
+        kernels.update_i16::<-1>(&mut out, &weights);
+        assert_eq!(out, acc, "{}", kernels.name());
+
+        let activations = (0..len).map(|_| next() as i16 % 400).collect::<Vec<_>>();
+        let mut expected = vec![0; len];
+        let mut out = vec![0; len];
+        scalar.sq_clipped_relu(&activations, &mut expected);
+        kernels.sq_clipped_relu(&activations, &mut out);
+        assert_eq!(out, expected, "{}", kernels.name());
+
+        let inputs = (0..len).map(|_| next() as u8).collect::<Vec<_>>();
+        let weights = (0..len).map(|_| next() as i8).collect::<Vec<_>>();
+        assert_eq!(
+            kernels.dot_u8_i8(&inputs, &weights),
+            scalar.dot_u8_i8(&inputs, &weights),
+            "{}",
+            kernels.name()
+        );
+    }
+}
+
+/*
+Only covers the kernels the CPU running the tests supports
+*/
+#[test]
+fn kernels_match_scalar() {
+    for &kernels in Kernels::ALL.iter().filter(|kernels| kernels.available()) {
+        check_kernels(kernels);
+    }
+}
+
+/*
+Fails instead of skipping on CPUs without AVX-512 VNNI, these can run it in Intel SDE:
+cargo test --no-run
+sde64 -icx -- target/debug/deps/blackmarlin-<hash> avx512 --ignored
+*/
+#[cfg(target_arch = "x86_64")]
+#[test]
+#[ignore]
+fn avx512_kernels_match_scalar() {
+    assert!(Kernels::Avx512.available(), "AVX-512 VNNI isn't available");
+    check_kernels(Kernels::Avx512);
+}
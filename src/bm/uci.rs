@@ -1,17 +1,81 @@
+use std::io::Write;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, TryLockError};
 use std::thread::JoinHandle;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use cozy_chess::{Board, File, Move, Piece, Square};
+use cozy_chess::{Board, Color, File, Move, Piece, Square};
 
-use crate::bm::bm_runner::ab_runner::AbRunner;
+use crate::bm::bm_runner::ab_runner::{AbRunner, DEFAULT_STACK_MB, MAX_THREADS};
 use crate::bm::bm_runner::config::{NoInfo, Run, UciInfo};
+use crate::bm::bm_runner::observer::{BestMoveChangeObserver, DebugObserver, DepthTimeObserver};
+use crate::bm::bm_runner::params::SearchParams;
 
-use crate::bm::bm_runner::time::{TimeManagementInfo, TimeManager};
+use crate::bm::bm_runner::time::{SearchLimits, TimeManagementInfo, TimeManager};
+use crate::bm::bm_search::see_check;
+use crate::bm::bm_util::mirror;
+use crate::bm::bm_util::noise::EvalNoise;
+use crate::bm::bm_util::opponent::{self, Opponent};
+use crate::bm::bm_util::pgn::{GameLog, MoveComment};
+use crate::bm::bm_util::position::Position;
+use crate::bm::bm_util::render::{self, PieceStyle};
+use crate::bm::bm_util::t_table::{EntryType, DEPTH_BIN, UTILIZATION_REGION};
+use crate::bm::bm_util::tb::Tablebase;
+use crate::bm::bm_util::ttd::{self, TtdRun};
+use crate::bm::evaluator::{self, DefaultEvaluator, Evaluator};
+#[cfg(embedded_net)]
+use crate::bm::nnue;
+use crate::bm::xboard::XBoardAdapter;
 
 const VERSION: &str = "6.0";
 
+/*
+Node count of a bench at the default depth is the signature OpenBench checks builds against,
+its final line has to contain "<nodes> nodes" and "<nps> nps"
+*/
+const BENCH_DEPTH: u32 = 12;
+
+/*
+Regions of the TT sampled by tt-stats unless another count is given
+*/
+const TT_SAMPLE_REGIONS: usize = 1024;
+
+/*
+Seconds each speedtest measurement runs for unless another duration is given,
+the search measurements split it between the first SPEEDTEST_POSITIONS bench positions
+*/
+const SPEEDTEST_SECS: f64 = 2.0;
+const SPEEDTEST_POSITIONS: usize = 4;
+
+/*
+Time to depth runs are appended to this file in the working directory
+*/
+const TTD_FILE: &str = "ttd.json";
+
+const MIN_ELO: u32 = 800;
+const MAX_ELO: u32 = 2800;
+
+/*
+Node budget for a limited strength search, halved every 200 Elo below the maximum
+*/
+fn strength_nodes(elo: u32) -> u64 {
+    1000 << ((elo - MIN_ELO) / 200)
+}
+
+/*
+Root eval noise for a limited strength search, random picks between
+near equal root moves get wider as the rating goes down
+*/
+fn strength_noise(elo: u32) -> i16 {
+    ((MAX_ELO - elo) / 8) as i16
+}
+
+/*
+Moves into the game that get opening noise against a declared opponent
+*/
+const OPENING_MOVES: u16 = 10;
+
 const POSITIONS: &[&str] = &[
     "r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14",
     "4rrk1/2p1b1p1/p1p3q1/4p3/2P2n1p/1P1NR2P/PB3PP1/3R1QK1 b - - 2 24",
@@ -65,27 +129,105 @@ const POSITIONS: &[&str] = &[
     "2r2b2/5p2/5k2/p1r1pP2/P2pB3/1P3P2/K1P3R1/7R w - - 23 93",
 ];
 
+pub type Analysis = Arc<Mutex<Option<JoinHandle<()>>>>;
+
+/*
+Stops the engine from outside of the UCI loop,
+the running search is aborted and joined so it can still report its best move
+*/
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    time_manager: Arc<TimeManager>,
+    warm_up_cancelled: Arc<AtomicBool>,
+    analysis: Analysis,
+    game_log: Arc<Mutex<GameLog>>,
+}
+
+impl ShutdownHandle {
+    /*
+    Aborts the running search without waiting for it
+    */
+    pub fn stop(&self) {
+        self.warm_up_cancelled.store(true, Ordering::SeqCst);
+        self.time_manager.abort_now();
+    }
+
+    /*
+    Stops the search once it has reported and writes out the game in progress
+    */
+    pub fn shutdown(&self) {
+        self.warm_up_cancelled.store(true, Ordering::SeqCst);
+        self.time_manager.abort_now();
+        if let Some(analysis) = self.analysis.lock().unwrap().take() {
+            analysis.join().unwrap();
+        }
+        self.game_log.lock().unwrap().finish(None);
+        std::io::stdout().flush().unwrap();
+    }
+}
+
 pub struct UciAdapter {
     bm_runner: Arc<Mutex<AbRunner>>,
     time_manager: Arc<TimeManager>,
-    analysis: Option<JoinHandle<()>>,
+    analysis: Analysis,
     forced: bool,
-    threads: u8,
+    debug: bool,
+    debug_observer: Arc<DebugObserver>,
+    move_change_observer: Arc<BestMoveChangeObserver>,
+    depth_time_observer: Arc<DepthTimeObserver>,
+    /*
+    Set while the search thread warms up the TT after reporting its best move,
+    any command that needs the engine clears it to abort the warm-up
+    */
+    warming_up: Arc<AtomicBool>,
+    /*
+    Set by any command that arrives during the search, so a stop that came before the
+    warm-up's limits were set (which clears the time manager's abort) still cancels it
+    */
+    warm_up_cancelled: Arc<AtomicBool>,
+    warm_up_depth: u32,
+    ponder: bool,
+    eval_noise: i16,
+    seed: u64,
+    limit_strength: bool,
+    elo: u32,
+    limited_searches: u64,
+    opponent: Option<Opponent>,
+    games: u64,
+    game_log: Arc<Mutex<GameLog>>,
     chess960: bool,
 }
 
 impl UciAdapter {
     pub fn new() -> Self {
         let time_manager = Arc::new(TimeManager::new());
-        let bm_runner = Arc::new(Mutex::new(AbRunner::new(
-            Board::default(),
-            time_manager.clone(),
-        )));
+        let debug_observer = Arc::new(DebugObserver::default());
+        let move_change_observer = Arc::new(BestMoveChangeObserver::default());
+        let depth_time_observer = Arc::new(DepthTimeObserver::default());
+        let mut runner = AbRunner::new(Board::default(), time_manager.clone());
+        runner.add_observer(debug_observer.clone());
+        runner.add_observer(move_change_observer.clone());
+        runner.add_observer(depth_time_observer.clone());
         Self {
-            bm_runner,
-            threads: 1,
+            bm_runner: Arc::new(Mutex::new(runner)),
+            debug_observer,
+            move_change_observer,
+            depth_time_observer,
+            warming_up: Arc::new(AtomicBool::new(false)),
+            warm_up_cancelled: Arc::new(AtomicBool::new(false)),
+            warm_up_depth: 0,
+            ponder: false,
+            eval_noise: 0,
+            seed: 0,
+            limit_strength: false,
+            elo: MAX_ELO,
+            limited_searches: 0,
+            opponent: None,
+            games: 0,
+            game_log: Arc::new(Mutex::new(GameLog::new())),
             forced: false,
-            analysis: None,
+            debug: false,
+            analysis: Arc::new(Mutex::new(None)),
             time_manager,
             chess960: false,
         }
@@ -94,46 +236,145 @@ impl UciAdapter {
     pub fn input(&mut self, input: String) -> bool {
         let name = "Black Marlin".to_string();
         let command = UciCommand::new(&input, self.chess960);
+        if !matches!(
+            command,
+            UciCommand::Empty | UciCommand::IsReady | UciCommand::Debug(_)
+        ) {
+            self.stop_warm_up();
+        }
         match command {
             UciCommand::Uci => {
                 println!("id name {} {}", name, VERSION);
                 println!("id author Doruk S.");
                 println!("option name Hash type spin default 16 min 1 max 65536");
-                println!("option name Threads type spin default 1 min 1 max 255");
+                println!(
+                    "option name Threads type spin default 1 min 1 max {}",
+                    MAX_THREADS
+                );
+                println!(
+                    "option name StackSize type spin default {} min 1 max 1024",
+                    DEFAULT_STACK_MB
+                );
+                println!("option name Ponder type check default false");
+                println!("option name WarmUpDepth type spin default 0 min 0 max 64");
+                println!("option name MultiPV type spin default 1 min 1 max 218");
+                println!("option name EvalNoise type spin default 0 min 0 max 100");
+                println!("option name Seed type spin default 0 min 0 max 2147483647");
                 println!("option name UCI_Chess960 type check default false");
+                println!("option name UCI_ShowWDL type check default false");
+                println!("option name ScoreSmoothing type spin default 0 min 0 max 90");
+                println!("option name PVExtension type check default true");
+                println!("option name ShowBestMoveChanges type check default false");
+                println!("option name TTDiagnostics type check default false");
+                println!("option name UCI_AnalyseMode type check default false");
+                println!("option name UCI_LimitStrength type check default false");
+                println!(
+                    "option name UCI_Elo type spin default {} min {} max {}",
+                    MAX_ELO, MIN_ELO, MAX_ELO
+                );
+                println!("option name UCI_Opponent type string default <empty>");
+                println!("option name GameLog type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
+                println!(
+                    "option name EvalFile type string default {}",
+                    evaluator::EMBEDDED_NET
+                );
                 println!("uciok");
             }
             UciCommand::IsReady => println!("readyok"),
-            UciCommand::Move(make_move) => {
+            UciCommand::Debug(debug) => {
+                self.debug = debug;
+                self.debug_observer.set_enabled(debug);
+            }
+            UciCommand::Move(mut make_move) => {
                 let runner = &mut *self.bm_runner.lock().unwrap();
-                runner.make_move(make_move);
+                convert_move(&mut make_move, runner.get_board(), self.chess960);
+                match runner.get_position().is_legal(make_move) {
+                    Ok(()) => runner.make_move(make_move),
+                    Err(err) => println!("info string illegal move {}: {}", make_move, err),
+                }
             }
             UciCommand::Empty => {}
+            UciCommand::PonderHit => self.time_manager.ponder_hit(),
             UciCommand::Stop => {
                 self.time_manager.abort_now();
                 self.exit();
             }
             UciCommand::Quit => {
+                self.shutdown_handle().shutdown();
                 return false;
             }
             UciCommand::Eval => {
                 let runner = &mut *self.bm_runner.lock().unwrap();
 
                 println!("eval    : {}", runner.raw_eval().raw());
+                let perspectives = runner.eval_perspectives();
+                for (side, output) in [("white", perspectives.white), ("black", perspectives.black)]
+                {
+                    println!("{:<8}: {} (raw {})", side, output.eval, output.raw);
+                }
+
+                let (terms, breakdown) = runner.eval_breakdown();
+                println!("side to move:");
+                for (index, &output) in breakdown.outputs.iter().enumerate() {
+                    println!(
+                        "output {:<2}: {} (raw {}){}",
+                        index,
+                        DefaultEvaluator::to_centipawns(output),
+                        output,
+                        if index == 0 { " used" } else { "" }
+                    );
+                }
+                for (name, raw) in [
+                    ("own half", breakdown.stm),
+                    ("opp half", breakdown.nstm),
+                    ("bias", breakdown.bias),
+                ] {
+                    println!(
+                        "{:<9}: {} (raw {})",
+                        name,
+                        DefaultEvaluator::to_centipawns(raw),
+                        raw
+                    );
+                }
+                println!(
+                    "scale    : {}/{}",
+                    terms.scale,
+                    crate::bm::bm_util::endgame::SCALE
+                );
+                println!("scaled   : {}", terms.scaled_nn_eval());
+                println!("frc      : {}", terms.frc);
+                println!("final    : {}", terms.scaled_nn_eval() + terms.frc);
             }
-            UciCommand::Go(commands) => self.go(commands),
+            UciCommand::Go(commands, search_moves) => self.go(commands, search_moves),
             UciCommand::NewGame => {
+                self.games += 1;
+                self.game_log.lock().unwrap().finish(None);
+                self.time_manager.new_game();
                 let runner = &mut *self.bm_runner.lock().unwrap();
                 runner.new_game();
                 runner.set_board(Board::default());
             }
             UciCommand::Position(position, moves) => {
                 let runner = &mut *self.bm_runner.lock().unwrap();
-                runner.set_board(position);
+                runner.set_board(position.clone());
+                let mut played = vec![];
                 for mut make_move in moves {
                     convert_move(&mut make_move, runner.get_board(), self.chess960);
+                    /*
+                    Moves after an illegal one are ignored, the position stays at the last legal move
+                    */
+                    if let Err(err) = runner.get_position().is_legal(make_move) {
+                        println!("info string illegal move {}: {}", make_move, err);
+                        break;
+                    }
                     runner.make_move(make_move);
+                    played.push(make_move);
                 }
+                self.game_log
+                    .lock()
+                    .unwrap()
+                    .sync(&position, &played, self.chess960);
             }
             UciCommand::SetOption(name, value) => {
                 let name: &str = &name;
@@ -145,8 +386,126 @@ impl UciAdapter {
                             .unwrap()
                             .hash(value.parse::<usize>().unwrap());
                     }
+                    "Ponder" => {
+                        self.ponder = value.to_lowercase().parse::<bool>().unwrap();
+                    }
+                    "WarmUpDepth" => {
+                        self.warm_up_depth = value.parse::<u32>().unwrap();
+                    }
                     "Threads" => {
-                        self.threads = value.parse::<u8>().unwrap();
+                        self.bm_runner
+                            .lock()
+                            .unwrap()
+                            .set_threads(value.parse::<usize>().unwrap());
+                    }
+                    "StackSize" => {
+                        self.bm_runner
+                            .lock()
+                            .unwrap()
+                            .set_stack_size(value.parse::<usize>().unwrap());
+                    }
+                    "MultiPV" => {
+                        self.bm_runner
+                            .lock()
+                            .unwrap()
+                            .set_multi_pv(value.parse::<usize>().unwrap());
+                    }
+                    "EvalNoise" => {
+                        self.eval_noise = value.parse::<i16>().unwrap();
+                        self.update_eval_noise();
+                    }
+                    "Seed" => {
+                        self.seed = value.parse::<u64>().unwrap();
+                        self.update_eval_noise();
+                    }
+                    "UCI_ShowWDL" => {
+                        let show_wdl = value.to_lowercase().parse::<bool>().unwrap();
+                        self.bm_runner.lock().unwrap().set_show_wdl(show_wdl);
+                    }
+                    "ScoreSmoothing" => {
+                        let score_smoothing = value.parse::<i16>().unwrap().clamp(0, 90);
+                        self.bm_runner
+                            .lock()
+                            .unwrap()
+                            .set_score_smoothing(score_smoothing);
+                    }
+                    "PVExtension" => {
+                        let pv_extension = value.to_lowercase().parse::<bool>().unwrap();
+                        self.bm_runner
+                            .lock()
+                            .unwrap()
+                            .set_pv_extension(pv_extension);
+                    }
+                    "TTDiagnostics" => {
+                        let enabled = value.to_lowercase().parse::<bool>().unwrap();
+                        self.bm_runner
+                            .lock()
+                            .unwrap()
+                            .get_t_table()
+                            .set_diagnostics(enabled);
+                    }
+                    "ShowBestMoveChanges" => {
+                        let enabled = value.to_lowercase().parse::<bool>().unwrap();
+                        self.move_change_observer.set_enabled(enabled);
+                    }
+                    "UCI_AnalyseMode" => {
+                        let analyse_mode = value.to_lowercase().parse::<bool>().unwrap();
+                        self.bm_runner
+                            .lock()
+                            .unwrap()
+                            .set_analyse_mode(analyse_mode);
+                    }
+                    "EvalFile" => match evaluator::load_network(&value) {
+                        Ok(()) => {
+                            self.bm_runner.lock().unwrap().reload_network();
+                            println!("info string loaded network {}", value);
+                        }
+                        Err(err) => println!("info string failed to load network: {}", err),
+                    },
+                    "SyzygyPath" => {
+                        let tablebase = if value == "<empty>" {
+                            Tablebase::new()
+                        } else {
+                            Tablebase::load(&value)
+                        };
+                        println!("info string found {} tablebases", tablebase.table_cnt());
+                        self.bm_runner.lock().unwrap().set_tablebase(tablebase);
+                    }
+                    "UCI_LimitStrength" => {
+                        self.limit_strength = value.to_lowercase().parse::<bool>().unwrap();
+                        self.update_eval_noise();
+                    }
+                    "UCI_Elo" => {
+                        self.elo = value.parse::<u32>().unwrap().clamp(MIN_ELO, MAX_ELO);
+                        self.update_eval_noise();
+                    }
+                    "GameLog" => {
+                        let path = Some(value).filter(|path| path != "<empty>" && !path.is_empty());
+                        self.game_log.lock().unwrap().set_path(path);
+                    }
+                    "UCI_Opponent" => {
+                        self.opponent = Opponent::parse(&value);
+                        self.game_log.lock().unwrap().set_opponent(
+                            self.opponent
+                                .as_ref()
+                                .map(|opponent| opponent.name.clone())
+                                .filter(|name| !name.is_empty()),
+                        );
+                        if let Some(opponent) = &self.opponent {
+                            println!(
+                                "info string opponent {} ({}) rated {}, contempt {}",
+                                opponent.name,
+                                if opponent.computer {
+                                    "computer"
+                                } else {
+                                    "human"
+                                },
+                                opponent
+                                    .estimated_rating()
+                                    .map_or("unknown".to_string(), |rating| rating.to_string()),
+                                opponent.contempt(self.engine_rating())
+                            );
+                        }
                     }
                     "UCI_Chess960" => {
                         self.chess960 = value.to_lowercase().parse::<bool>().unwrap();
@@ -155,7 +514,7 @@ impl UciAdapter {
                     _ => {}
                 }
             }
-            UciCommand::Bench => {
+            UciCommand::Bench(depth) => {
                 self.exit();
 
                 let mut bench_data = vec![];
@@ -167,12 +526,11 @@ impl UciAdapter {
                     let board = cozy_chess::Board::from_str(position).unwrap();
                     bm_runner.new_game();
                     bm_runner.set_board(board.clone());
-                    let options = [TimeManagementInfo::MaxDepth(12)];
                     let start = Instant::now();
 
-                    self.time_manager.initiate(&board, &options);
-                    let (make_move, eval, _, node_cnt) =
-                        bm_runner.search::<Run, NoInfo>(self.threads);
+                    self.time_manager
+                        .initiate_limits(&board, SearchLimits::new().depth(depth));
+                    let (make_move, eval, _, node_cnt) = bm_runner.search::<Run, NoInfo>();
                     self.time_manager.clear();
                     let elapsed = start.elapsed();
                     bench_data.push((
@@ -206,37 +564,749 @@ impl UciAdapter {
                 );
                 println!("{}", buffer);
             }
+            UciCommand::Display(style) => {
+                let runner = &mut *self.bm_runner.lock().unwrap();
+                let board = runner.get_board().clone();
+                let fen = runner.get_position().to_fen(self.chess960);
+                println!("{}", render::board_diagram(&board, style));
+                println!("Fen       : {}", fen);
+                println!("Hash      : {:016X}", board.hash());
+                println!("To move   : {:?}", board.side_to_move());
+                println!("Castling  : {}", fen.split(' ').nth(2).unwrap_or("-"));
+                println!("Static    : {}", runner.raw_eval().raw());
+                let perspectives = runner.eval_perspectives();
+                let output = match board.side_to_move() {
+                    Color::White => perspectives.white,
+                    Color::Black => perspectives.black,
+                };
+                println!("NNUE      : {} (raw {})", output.eval, output.raw);
+            }
             UciCommand::Static => {
                 let runner = &mut *self.bm_runner.lock().unwrap();
                 println!("{}", runner.raw_eval().raw());
             }
+            UciCommand::Diversity(runs, depth, noise) => self.diversity(runs, depth, noise),
+            UciCommand::Mirror(depth) => self.mirror(depth),
+            UciCommand::VerifyNet => self.verify_net(),
+            UciCommand::SeeCheck => self.see_check(),
+            UciCommand::TtStats(regions) => self.tt_stats(regions),
+            UciCommand::SpeedTest(duration) => self.speed_test(duration),
+            UciCommand::TimeToDepth(depth) => self.time_to_depth(depth),
         }
         true
     }
 
-    fn go(&mut self, commands: Vec<TimeManagementInfo>) {
+    /*
+    Searches the current position once per seed with the given eval noise
+    and reports how often each move was chosen along with its average score
+    */
+    fn diversity(&mut self, runs: u64, depth: u32, noise: Option<i16>) {
+        self.exit();
+        let noise = noise.unwrap_or(self.eval_noise);
+        let mut choices: Vec<(Move, u64, i64)> = vec![];
+        {
+            let runner = &mut *self.bm_runner.lock().unwrap();
+            let board = runner.get_board().clone();
+            for seed in 0..runs {
+                runner.new_game();
+                runner.set_eval_noise(EvalNoise::new(noise, self.seed.wrapping_add(seed)));
+                self.time_manager
+                    .initiate_limits(&board, SearchLimits::new().depth(depth));
+                let (mut make_move, eval, _, _) = runner.search::<Run, NoInfo>();
+                self.time_manager.clear();
+                convert_move_to_uci(&mut make_move, &board, self.chess960);
+                match choices
+                    .iter_mut()
+                    .find(|(choice, _, _)| *choice == make_move)
+                {
+                    Some((_, count, score_sum)) => {
+                        *count += 1;
+                        *score_sum += eval.raw() as i64;
+                    }
+                    None => choices.push((make_move, 1, eval.raw() as i64)),
+                }
+            }
+        }
+        self.update_eval_noise();
+
+        choices.sort_by_key(|&(_, count, _)| std::cmp::Reverse(count));
+        println!("{:<8}{:>8}{:>8}{:>10}", "move", "count", "share", "avg cp");
+        for (make_move, count, score_sum) in choices {
+            println!(
+                "{:<8}{:>8}{:>7.1}%{:>10}",
+                make_move.to_string(),
+                count,
+                count as f64 * 100.0 / runs as f64,
+                score_sum / count as i64
+            );
+        }
+    }
+
+    /*
+    Evaluates and searches every bench position and its color flipped mirror,
+    both should give the same result from the side to move's point of view
+    A depth of 0 only compares the network outputs
+    */
+    fn mirror(&mut self, depth: u32) {
+        self.exit();
+        /*
+        A single threaded runner of its own leaves the game's board and tables untouched,
+        helpers would make the searches nondeterministic
+        */
+        let time_manager = Arc::new(TimeManager::new());
+        let mut runner = AbRunner::new(Board::default(), time_manager.clone());
+        runner.set_params(self.bm_runner.lock().unwrap().get_params().clone());
+        let mut asymmetries = 0;
+        for (index, position) in POSITIONS.iter().enumerate() {
+            let board = Board::from_str(position).unwrap();
+            let flipped = mirror::color_flip(&board);
+            let mut results = vec![];
+            for board in [&board, &flipped] {
+                runner.new_game();
+                runner.set_board(board.clone());
+                let perspectives = runner.eval_perspectives();
+                let output = match board.side_to_move() {
+                    Color::White => perspectives.white,
+                    Color::Black => perspectives.black,
+                };
+                let search = (depth > 0).then(|| {
+                    time_manager.initiate_limits(board, SearchLimits::new().depth(depth));
+                    let (make_move, eval, _, _) = runner.search::<Run, NoInfo>();
+                    time_manager.clear();
+                    (make_move, eval.raw())
+                });
+                results.push((output.raw, search));
+            }
+            let (raw, search) = results[0];
+            let (flipped_raw, flipped_search) = results[1];
+            if raw != flipped_raw {
+                asymmetries += 1;
+                println!(
+                    "[#{:>3}] nnue {} mirrored {} {}",
+                    index + 1,
+                    raw,
+                    flipped_raw,
+                    position
+                );
+            }
+            if let (Some((make_move, eval)), Some((flipped_move, flipped_eval))) =
+                (search, flipped_search)
+            {
+                if make_move != mirror::flip_move(flipped_move) || eval != flipped_eval {
+                    asymmetries += 1;
+                    println!(
+                        "[#{:>3}] search {} {} mirrored {} {} {}",
+                        index + 1,
+                        make_move,
+                        eval,
+                        flipped_move,
+                        flipped_eval,
+                        position
+                    );
+                }
+            }
+        }
+        println!(
+            "{} asymmetries in {} positions",
+            asymmetries,
+            POSITIONS.len()
+        );
+    }
+
+    /*
+    Checks the loaded network before it's trusted with games:
+    The quantized incremental evaluator has to match a from scratch i32 reference on
+    every bench position and its children, which catches broken accumulator updates
+    and overflows, and a few positions need plausible evals, which catches
+    networks that parsed but were trained or exported wrong
+    */
+    fn verify_net(&mut self) {
+        self.exit();
+        let mismatch_cnt = match verify_incremental() {
+            Ok(mismatch_cnt) => mismatch_cnt,
+            Err(err) => {
+                println!("network failed verification: {}", err);
+                return;
+            }
+        };
+
+        let eval = |fen: &str| {
+            let board = Board::from_str(fen).unwrap();
+            Position::new(board).eval_perspectives().white.eval
+        };
+        let start = eval("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let queen_odds = eval("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq - 0 1");
+        let start_ok = start.abs() <= 150;
+        let queen_odds_ok = queen_odds <= -300;
+        println!(
+            "start position {} cp ({})",
+            start,
+            if start_ok { "ok" } else { "expected near 0" }
+        );
+        println!(
+            "queen odds {} cp ({})",
+            queen_odds,
+            if queen_odds_ok {
+                "ok"
+            } else {
+                "expected below -300"
+            }
+        );
+        if mismatch_cnt == 0 && start_ok && queen_odds_ok {
+            println!("network ok");
+        } else {
+            println!("network failed verification");
+        }
+    }
+
+    /*
+    Measures evaluation throughput, then search speed with each power of two up to the
+    number of available cores as the thread count
+    Scaling efficiency is the nps per thread relative to the single threaded nps
+    */
+    fn speed_test(&mut self, duration: Duration) {
+        self.exit();
+        let boards = POSITIONS[..SPEEDTEST_POSITIONS]
+            .iter()
+            .map(|fen| Board::from_str(fen).unwrap())
+            .collect::<Vec<_>>();
+
+        let eval_params = self.bm_runner.lock().unwrap().get_params().eval_params();
+        let mut positions = boards
+            .iter()
+            .map(|board| {
+                let mut moves = vec![];
+                board.generate_moves(|piece_moves| {
+                    moves.extend(piece_moves);
+                    false
+                });
+                (Position::new(board.clone()), moves)
+            })
+            .collect::<Vec<_>>();
+        let mut evals = 0_u64;
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            for (position, moves) in &mut positions {
+                for &make_move in moves.iter() {
+                    position.make_move(make_move);
+                    std::hint::black_box(position.static_eval(&eval_params));
+                    position.unmake_move();
+                }
+                evals += moves.len() as u64;
+            }
+        }
+        println!(
+            "eval {:.0} evals/s (make move, evaluate, unmake move)",
+            evals as f64 / start.elapsed().as_secs_f64()
+        );
+
+        let max_threads = std::thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(MAX_THREADS);
+        let mut thread_cnts = (0..8)
+            .map(|power| 1 << power)
+            .take_while(|&threads| threads < max_threads)
+            .collect::<Vec<_>>();
+        thread_cnts.push(max_threads);
+
+        println!(
+            "{:>7}{:>12}{:>12}{:>12}",
+            "threads", "nps", "nps/thread", "efficiency"
+        );
+        let runner = &mut *self.bm_runner.lock().unwrap();
+        let move_time = duration / SPEEDTEST_POSITIONS as u32;
+        let mut single_nps = None;
+        let configured_threads = runner.threads();
+        for threads in thread_cnts {
+            runner.set_threads(threads);
+            let mut nodes = 0;
+            let start = Instant::now();
+            for board in &boards {
+                runner.new_game();
+                runner.set_board(board.clone());
+                self.time_manager
+                    .initiate(board, &[TimeManagementInfo::MoveTime(move_time)]);
+                nodes += runner.search::<Run, NoInfo>().3;
+                self.time_manager.clear();
+            }
+            let nps = nodes as f64 / start.elapsed().as_secs_f64();
+            let single_nps = *single_nps.get_or_insert(nps);
+            println!(
+                "{:>7}{:>12.0}{:>12.0}{:>11.1}%",
+                threads,
+                nps,
+                nps / threads as f64,
+                nps * 100.0 / (single_nps * threads as f64)
+            );
+        }
+        runner.set_threads(configured_threads);
+    }
+
+    /*
+    Searches the bench positions to the depth, recording when each depth completed
+    The run is compared with the last run to the same depth in TTD_FILE and appended to it
+    */
+    fn time_to_depth(&mut self, depth: u32) {
+        self.exit();
+        let runner = &mut *self.bm_runner.lock().unwrap();
+        let mut ms = vec![0.0; depth as usize];
+        let mut nodes = 0;
+        for position in POSITIONS {
+            let board = Board::from_str(position).unwrap();
+            runner.new_game();
+            runner.set_board(board.clone());
+            self.depth_time_observer.set_enabled(true);
+            self.time_manager
+                .initiate_limits(&board, SearchLimits::new().depth(depth));
+            nodes += runner.search::<Run, NoInfo>().3;
+            self.time_manager.clear();
+            for (total, time) in ms.iter_mut().zip(self.depth_time_observer.times()) {
+                *total += time.as_secs_f64() * 1000.0;
+            }
+        }
+        self.depth_time_observer.set_enabled(false);
+        let run = TtdRun {
+            unix_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            depth,
+            nodes,
+            ms,
+        };
+
+        /*
+        A file that can't be read isn't overwritten so earlier runs aren't lost
+        */
+        let (mut runs, save) = match ttd::load(TTD_FILE) {
+            Ok(runs) => (runs, true),
+            Err(err) => {
+                println!("{}, this run won't be saved", err);
+                (vec![], false)
+            }
+        };
+        let previous = runs.iter().rev().find(|previous| previous.depth == depth);
+        println!(
+            "{:>5}{:>12}{:>12}{:>9}",
+            "depth", "ms", "previous", "change"
+        );
+        for (index, &ms) in run.ms.iter().enumerate() {
+            match previous.and_then(|previous| previous.ms.get(index)) {
+                Some(&previous_ms) => println!(
+                    "{:>5}{:>12.1}{:>12.1}{:>+8.1}%",
+                    index + 1,
+                    ms,
+                    previous_ms,
+                    (ms / previous_ms.max(0.001) - 1.0) * 100.0
+                ),
+                None => println!("{:>5}{:>12.1}", index + 1, ms),
+            }
+        }
+        match previous {
+            Some(previous) if previous.nodes != nodes => println!(
+                "{} nodes, the previous run searched {} nodes",
+                nodes, previous.nodes
+            ),
+            _ => println!("{} nodes", nodes),
+        }
+        runs.push(run);
+        if !save {
+            return;
+        }
+        if let Err(err) = ttd::save(TTD_FILE, &runs) {
+            println!("failed to save {}: {}", TTD_FILE, err);
+        }
+    }
+
+    /*
+    Reports how the TT is filled from a sample of its regions,
+    along with collision and overwrite rates counted since TTDiagnostics was enabled
+    */
+    fn tt_stats(&mut self, regions: usize) {
+        /*
+        Searches hold the runner, waiting for it would block until the search ends
+        */
+        let runner = match self.bm_runner.try_lock() {
+            Ok(runner) => runner,
+            Err(TryLockError::WouldBlock) => {
+                println!("info string tt-stats isn't available while searching");
+                return;
+            }
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
+        };
+        let t_table = runner.get_t_table();
+        let sample = t_table.sample(regions);
+        let percent = |count: usize| count as f64 * 100.0 / sample.sampled.max(1) as f64;
+        println!(
+            "sampled {} entries: {:.1}% used, {:.1}% from the last search",
+            sample.sampled,
+            percent(sample.used),
+            percent(sample.last_search)
+        );
+        println!(
+            "region utilization ({} entries per region):",
+            UTILIZATION_REGION
+        );
+        for (eighths, &count) in sample.utilization.iter().enumerate() {
+            println!(
+                "<={:>5.1}% {:>8} {}",
+                eighths as f64 * 12.5,
+                count,
+                "#".repeat(count * 50 / sample.utilization.iter().sum::<usize>().max(1))
+            );
+        }
+        println!("depths:");
+        for (bin, &count) in sample.depths.iter().enumerate() {
+            let low = bin * DEPTH_BIN;
+            let range = if bin == sample.depths.len() - 1 {
+                format!("{}+", low)
+            } else {
+                format!("{}-{}", low, low + DEPTH_BIN - 1)
+            };
+            println!("{:>6} {:>8} {:>5.1}%", range, count, percent(count));
+        }
+        println!(
+            "lower {} exact {} upper {}",
+            sample.entry_types[EntryType::LowerBound as usize],
+            sample.entry_types[EntryType::Exact as usize],
+            sample.entry_types[EntryType::UpperBound as usize]
+        );
+        if t_table.diagnostics() {
+            let counters = t_table.counters();
+            println!(
+                "probes {} hits {:.1}% collisions {:.2}%",
+                counters.probes,
+                counters.hit_rate() * 100.0,
+                counters.collision_rate() * 100.0
+            );
+            println!(
+                "stores {} overwrites {:.1}% rejected {}",
+                counters.stores,
+                counters.overwrite_rate() * 100.0,
+                counters.rejected
+            );
+        } else {
+            println!("enable TTDiagnostics to count collisions");
+        }
+    }
+
+    /*
+    Cross-checks SEE with an exhaustive exchange search on every move of the bench positions
+    and the positions one move away from them, using the pruning piece values
+    Some mismatches are expected since SEE ignores pins, sign mismatches are the ones
+    that change pruning decisions
+    */
+    fn see_check(&mut self) {
+        self.exit();
+        let values = self
+            .bm_runner
+            .lock()
+            .unwrap()
+            .get_params()
+            .see_prune_values();
+        let mut boards = vec![];
+        for position in POSITIONS {
+            let board = Board::from_str(position).unwrap();
+            board.generate_moves(|piece_moves| {
+                for make_move in piece_moves {
+                    let mut child = board.clone();
+                    child.play_unchecked(make_move);
+                    boards.push(child);
+                }
+                false
+            });
+            boards.push(board);
+        }
+        let mut checked = 0;
+        let mut mismatch_cnt = 0;
+        let mut sign_mismatch_cnt = 0;
+        for board in &boards {
+            let (board_checked, mismatches) = see_check::check(board, &values);
+            checked += board_checked;
+            mismatch_cnt += mismatches.len();
+            for mismatch in mismatches.iter().filter(|mismatch| mismatch.sign_differs()) {
+                sign_mismatch_cnt += 1;
+                if sign_mismatch_cnt <= 10 {
+                    println!(
+                        "{} see {} exact {} {}",
+                        mismatch.make_move, mismatch.see, mismatch.exact, board
+                    );
+                }
+            }
+        }
+        println!(
+            "{} moves in {} positions, {} mismatches, {} with the wrong sign",
+            checked,
+            boards.len(),
+            mismatch_cnt,
+            sign_mismatch_cnt
+        );
+    }
+
+    fn engine_rating(&self) -> u32 {
+        if self.limit_strength {
+            self.elo
+        } else {
+            opponent::ENGINE_RATING
+        }
+    }
+
+    /*
+    Every stochastic part of the engine is derived from the seed
+    */
+    fn update_eval_noise(&mut self) {
+        /*
+        Limited strength searches get a fresh seed each time so the same
+        position doesn't always lead to the same mistake
+        */
+        let (mut noise, mut seed) = if self.limit_strength {
+            (
+                self.eval_noise.max(strength_noise(self.elo)),
+                self.seed.wrapping_add(self.limited_searches),
+            )
+        } else {
+            (self.eval_noise, self.seed)
+        };
+        let runner = &mut *self.bm_runner.lock().unwrap();
+        /*
+        Openings against a declared opponent vary from game to game
+        */
+        if let Some(opponent) = &self.opponent {
+            if runner.get_board().fullmove_number() <= OPENING_MOVES {
+                let opening_noise = opponent.opening_noise(self.engine_rating());
+                if opening_noise > noise {
+                    noise = opening_noise;
+                    seed = seed.wrapping_add(self.games << 32);
+                }
+            }
+        }
+        runner.set_eval_noise(EvalNoise::new(noise, seed));
+    }
+
+    fn update_contempt(&mut self) {
+        let contempt = self
+            .opponent
+            .as_ref()
+            .map_or(0, |opponent| opponent.contempt(self.engine_rating()));
+        self.bm_runner.lock().unwrap().set_contempt(contempt);
+    }
+
+    pub fn set_params(&mut self, params: SearchParams) {
+        self.bm_runner.lock().unwrap().set_params(params);
+    }
+
+    fn go(&mut self, mut commands: Vec<TimeManagementInfo>, search_moves: Vec<Move>) {
         self.exit();
         self.forced = false;
+        {
+            let runner = &mut *self.bm_runner.lock().unwrap();
+            let restricted = !search_moves.is_empty();
+            let search_moves = search_moves
+                .into_iter()
+                .map(|mut make_move| {
+                    convert_move(&mut make_move, runner.get_board(), self.chess960);
+                    make_move
+                })
+                .collect();
+            runner.set_search_moves(search_moves);
+            /*
+            Searching every move instead would play a move the GUI excluded
+            */
+            if restricted && runner.get_search_moves().is_empty() {
+                println!("info string none of the searchmoves are legal");
+                println!("bestmove 0000");
+                return;
+            }
+        }
+        if self.limit_strength {
+            self.limited_searches += 1;
+            commands.push(TimeManagementInfo::MaxNodes(strength_nodes(self.elo)));
+        }
+        self.update_eval_noise();
+        self.update_contempt();
+        let stm = self.bm_runner.lock().unwrap().get_board().side_to_move();
+        /*
+        Time left on the engine's clock once the increment of the move is added
+        */
+        let clock = commands.iter().fold(None, |clock, info| match (stm, info) {
+            (Color::White, TimeManagementInfo::WTime(time))
+            | (Color::Black, TimeManagementInfo::BTime(time)) => {
+                Some(clock.unwrap_or_default() + *time)
+            }
+            (Color::White, TimeManagementInfo::WInc(inc))
+            | (Color::Black, TimeManagementInfo::BInc(inc)) => {
+                Some(clock.unwrap_or_default() + *inc)
+            }
+            _ => clock,
+        });
         self.time_manager
             .initiate(self.bm_runner.lock().unwrap().get_board(), &commands);
         let bm_runner = self.bm_runner.clone();
-        let threads = self.threads;
+        let game_log = self.game_log.clone();
+        let time_manager = self.time_manager.clone();
+        let debug = self.debug;
         let chess960 = self.chess960;
-        self.analysis = Some(std::thread::spawn(move || {
-            let mut bm_runner = bm_runner.lock().unwrap();
-            let (mut best_move, _, _, _) = bm_runner.search::<Run, UciInfo>(threads);
-            convert_move_to_uci(&mut best_move, bm_runner.get_board(), chess960);
-            println!("bestmove {}", best_move);
-        }));
+        let warming_up = self.warming_up.clone();
+        self.warm_up_cancelled.store(false, Ordering::SeqCst);
+        let warm_up_cancelled = self.warm_up_cancelled.clone();
+        let warm_up_depth = if self.ponder { 0 } else { self.warm_up_depth };
+        let stack_size = self.bm_runner.lock().unwrap().get_stack_size();
+        let analysis = std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let mut bm_runner = bm_runner.lock().unwrap();
+                let start = Instant::now();
+                let (mut best_move, eval, depth, _) = bm_runner.search::<Run, UciInfo>();
+                /*
+                Untimed searches are analysis and aren't logged
+                */
+                if time_manager.allocated().is_some() {
+                    let time = start.elapsed();
+                    game_log.lock().unwrap().annotate(
+                        best_move,
+                        MoveComment {
+                            eval,
+                            depth,
+                            time,
+                            clock: clock.map(|clock: Duration| clock.saturating_sub(time)),
+                        },
+                    );
+                }
+                if debug {
+                    let used = start.elapsed().as_millis();
+                    match time_manager.allocated() {
+                        Some((hard, soft)) => println!(
+                            "info string time used {} ms of {} allocated (soft {})",
+                            used, hard, soft
+                        ),
+                        None => println!("info string time used {} ms of unlimited", used),
+                    }
+                }
+                let expected_line = bm_runner
+                    .get_ponder_move()
+                    .map(|ponder_move| [best_move, ponder_move]);
+                let warm_up = warm_up_depth > 0 && expected_line.is_some();
+                /*
+                Has to be set before the best move is sent, the GUI may answer right away
+                */
+                warming_up.store(warm_up, Ordering::SeqCst);
+                let ponder_move = bm_runner.get_ponder_move().map(|mut ponder_move| {
+                    let mut ponder_board = bm_runner.get_board().clone();
+                    ponder_board.play_unchecked(best_move);
+                    convert_move_to_uci(&mut ponder_move, &ponder_board, chess960);
+                    ponder_move
+                });
+                convert_move_to_uci(&mut best_move, bm_runner.get_board(), chess960);
+                if let Some(ponder_move) = ponder_move {
+                    println!("bestmove {} ponder {}", best_move, ponder_move);
+                } else {
+                    println!("bestmove {}", best_move);
+                }
+                if let (true, Some(expected_line)) = (warm_up, expected_line) {
+                    std::io::stdout().flush().unwrap();
+                    let mut board = bm_runner.get_board().clone();
+                    for make_move in expected_line {
+                        board.play_unchecked(make_move);
+                    }
+                    time_manager.initiate_limits(&board, SearchLimits::new().depth(warm_up_depth));
+                    /*
+                    A command that arrived before the limits were set has already cancelled it,
+                    one that arrives after aborts the warm-up through the time manager
+                    */
+                    if !warm_up_cancelled.load(Ordering::SeqCst) {
+                        bm_runner.warm_up::<Run>(&expected_line);
+                    }
+                    time_manager.clear();
+                    warming_up.store(false, Ordering::SeqCst);
+                }
+            });
+        *self.analysis.lock().unwrap() = Some(analysis.unwrap());
+    }
+
+    /*
+    Hands the engine over to the Chess Engine Communication Protocol
+    */
+    pub fn xboard(&mut self) -> XBoardAdapter {
+        self.exit();
+        XBoardAdapter::new(
+            self.bm_runner.clone(),
+            self.time_manager.clone(),
+            self.analysis.clone(),
+            self.game_log.clone(),
+        )
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            time_manager: self.time_manager.clone(),
+            warm_up_cancelled: self.warm_up_cancelled.clone(),
+            analysis: self.analysis.clone(),
+            game_log: self.game_log.clone(),
+        }
+    }
+
+    /*
+    Aborts the TT warm-up so the engine is free for the next command
+    */
+    fn stop_warm_up(&mut self) {
+        self.warm_up_cancelled.store(true, Ordering::SeqCst);
+        if self.warming_up.swap(false, Ordering::SeqCst) {
+            self.time_manager.abort_now();
+            self.exit();
+        }
     }
 
     fn exit(&mut self) {
-        if let Some(analysis) = self.analysis.take() {
+        if let Some(analysis) = self.analysis.lock().unwrap().take() {
             analysis.join().unwrap();
         }
     }
 }
 
+/*
+Compares the incremental evaluator with the reference on every bench position and its children,
+returns the number of mismatches
+*/
+#[cfg(embedded_net)]
+fn verify_incremental() -> Result<usize, String> {
+    println!("using {} kernels", nnue::kernels());
+    let mut checked = 0;
+    let mut mismatch_cnt = 0;
+    for (index, position) in POSITIONS.iter().enumerate() {
+        let board = Board::from_str(position).unwrap();
+        let (position_checked, mismatches) = nnue::verify_position(&board);
+        checked += position_checked;
+        mismatch_cnt += mismatches.len();
+        if let Some(mismatch) = mismatches.first() {
+            let make_move = mismatch
+                .make_move
+                .map_or("-".to_string(), |make_move| make_move.to_string());
+            println!(
+                "[#{:>3}] {} mismatches, first after {} with {:?} to move: {} expected {}",
+                index + 1,
+                mismatches.len(),
+                make_move,
+                mismatch.stm,
+                mismatch.output,
+                mismatch.reference
+            );
+        }
+    }
+    println!(
+        "{} of {} outputs match the reference",
+        checked - mismatch_cnt,
+        checked
+    );
+    Ok(mismatch_cnt)
+}
+
+/*
+The basic evaluation isn't a network, there is nothing to verify
+*/
+#[cfg(not(embedded_net))]
+fn verify_incremental() -> Result<usize, String> {
+    Err("no network is embedded, built with the basic evaluation".to_string())
+}
+
 pub fn convert_move_to_uci(make_move: &mut Move, board: &Board, chess960: bool) {
     if !chess960 && board.color_on(make_move.from) == board.color_on(make_move.to) {
         let rights = board.castle_rights(board.side_to_move());
@@ -249,7 +1319,7 @@ pub fn convert_move_to_uci(make_move: &mut Move, board: &Board, chess960: bool)
     }
 }
 
-fn convert_move(make_move: &mut Move, board: &Board, chess960: bool) {
+pub fn convert_move(make_move: &mut Move, board: &Board, chess960: bool) {
     let convert_castle = !chess960
         && board.piece_on(make_move.from) == Some(Piece::King)
         && make_move.from.file() == File::E
@@ -267,17 +1337,27 @@ fn convert_move(make_move: &mut Move, board: &Board, chess960: bool) {
 enum UciCommand {
     Uci,
     IsReady,
+    Debug(bool),
     NewGame,
     Position(Board, Vec<Move>),
-    Go(Vec<TimeManagementInfo>),
+    Go(Vec<TimeManagementInfo>, Vec<Move>),
     SetOption(String, String),
     Move(Move),
-    Bench,
+    Bench(u32),
     Empty,
+    PonderHit,
     Stop,
     Quit,
     Eval,
     Static,
+    Display(PieceStyle),
+    Diversity(u64, u32, Option<i16>),
+    Mirror(u32),
+    VerifyNet,
+    SeeCheck,
+    TtStats(usize),
+    SpeedTest(Duration),
+    TimeToDepth(u32),
 }
 
 impl UciCommand {
@@ -299,7 +1379,7 @@ impl UciCommand {
             "position" => {
                 let mut board = "".to_string();
                 let mut chess_board = None;
-                let split = split.into_iter().collect::<Vec<_>>();
+                let split = split.collect::<Vec<_>>();
 
                 let mut board_end = 0;
                 for (index, token) in split.iter().enumerate() {
@@ -334,7 +1414,18 @@ impl UciCommand {
             }
             "go" => {
                 let mut commands = vec![];
+                let mut search_moves = vec![];
+                let mut split = split.peekable();
                 while let Some(option) = split.next() {
+                    if option == "searchmoves" {
+                        while let Some(Ok(make_move)) =
+                            split.peek().map(|token| Move::from_str(token))
+                        {
+                            search_moves.push(make_move);
+                            split.next();
+                        }
+                        continue;
+                    }
                     commands.push(match option {
                         "wtime" => {
                             let millis = split.next().unwrap().parse::<i64>().unwrap();
@@ -370,25 +1461,113 @@ impl UciCommand {
                             let nodes = split.next().unwrap().parse::<u64>().unwrap();
                             TimeManagementInfo::MaxNodes(nodes)
                         }
+                        "mate" => {
+                            let mate = split.next().unwrap().parse::<u32>().unwrap();
+                            TimeManagementInfo::Mate(mate)
+                        }
+                        "ponder" => TimeManagementInfo::Ponder,
                         _ => TimeManagementInfo::Unknown,
                     });
                 }
-                UciCommand::Go(commands)
+                UciCommand::Go(commands, search_moves)
             }
+            "ponderhit" => UciCommand::PonderHit,
             "stop" => UciCommand::Stop,
             "quit" => UciCommand::Quit,
             "eval" => UciCommand::Eval,
             "isready" => UciCommand::IsReady,
-            "bench" => UciCommand::Bench,
+            "debug" => UciCommand::Debug(split.next() == Some("on")),
+            "bench" => UciCommand::Bench(
+                split
+                    .next()
+                    .and_then(|depth| depth.parse().ok())
+                    .unwrap_or(BENCH_DEPTH),
+            ),
             "static" => UciCommand::Static,
+            "d" => match split.next() {
+                Some("unicode") => UciCommand::Display(PieceStyle::Unicode),
+                _ => UciCommand::Display(PieceStyle::Ascii),
+            },
+            "diversity" => {
+                let runs = split
+                    .next()
+                    .and_then(|runs| runs.parse().ok())
+                    .unwrap_or(16);
+                let depth = split
+                    .next()
+                    .and_then(|depth| depth.parse().ok())
+                    .unwrap_or(10);
+                let noise = split.next().and_then(|noise| noise.parse().ok());
+                UciCommand::Diversity(runs.max(1), depth, noise)
+            }
+            "mirror" => UciCommand::Mirror(
+                split
+                    .next()
+                    .and_then(|depth| depth.parse().ok())
+                    .unwrap_or(8),
+            ),
+            "verify-net" => UciCommand::VerifyNet,
+            "see-check" => UciCommand::SeeCheck,
+            "tt-stats" => UciCommand::TtStats(
+                split
+                    .next()
+                    .and_then(|regions| regions.parse().ok())
+                    .unwrap_or(TT_SAMPLE_REGIONS),
+            ),
+            "speedtest" => UciCommand::SpeedTest(Duration::from_secs_f64(
+                split
+                    .next()
+                    .and_then(|secs| secs.parse::<f64>().ok())
+                    .filter(|&secs| secs > 0.0)
+                    .unwrap_or(SPEEDTEST_SECS),
+            )),
+            "ttd" => UciCommand::TimeToDepth(
+                split
+                    .next()
+                    .and_then(|depth| depth.parse().ok())
+                    .unwrap_or(BENCH_DEPTH)
+                    .max(1),
+            ),
             "setoption" => {
                 split.next();
                 let name = split.next().unwrap().to_string();
                 split.next();
-                let value = split.next().unwrap().to_string();
+                let value = split.collect::<Vec<_>>().join(" ");
                 UciCommand::SetOption(name, value)
             }
             _ => UciCommand::Empty,
         }
     }
 }
+
+#[test]
+fn stop_during_warm_up() {
+    use std::sync::mpsc;
+
+    for command in ["stop", "quit"] {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut uci = UciAdapter::new();
+            uci.input("setoption name WarmUpDepth value 64".to_string());
+            uci.input("position startpos".to_string());
+            uci.input("go depth 64".to_string());
+            /*
+            Long enough for a ponder move, the warm-up would search to depth 64
+            */
+            std::thread::sleep(Duration::from_millis(200));
+            uci.input(command.to_string());
+            sender.send(()).unwrap();
+        });
+        assert!(
+            receiver.recv_timeout(Duration::from_secs(10)).is_ok(),
+            "{} hangs",
+            command
+        );
+    }
+}
+
+#[cfg(not(embedded_net))]
+#[test]
+fn verify_without_network() {
+    assert!(verify_incremental().is_err());
+}
@@ -0,0 +1,587 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cozy_chess::{Board, Color, Move, Square};
+
+use crate::bm::bm_runner::ab_runner::AbRunner;
+use crate::bm::bm_runner::config::{NoInfo, Run, XBoardInfo};
+use crate::bm::bm_runner::time::{TimeManagementInfo, TimeManager};
+use crate::bm::bm_util::pgn::{self, GameLog, MoveComment, Outcome};
+use crate::bm::bm_util::tb::Tablebase;
+use crate::bm::uci::{self, Analysis};
+
+const VERSION: &str = "6.0";
+
+/*
+Number of searches in a row that have to score below the resign threshold before resigning
+*/
+const RESIGN_MOVES: u32 = 3;
+
+/*
+Moves played since the last new or setboard, undo and remove replay them from the start
+*/
+#[derive(Debug, Clone)]
+struct Game {
+    start: Board,
+    moves: Vec<Move>,
+    engine: Option<Color>,
+    outcome: Option<Outcome>,
+    losing_searches: u32,
+}
+
+impl Game {
+    fn new(start: Board) -> Self {
+        Self {
+            start,
+            moves: vec![],
+            engine: None,
+            outcome: None,
+            losing_searches: 0,
+        }
+    }
+
+    /*
+    Result the side to move can claim, draws by rule are claimed as soon as they happen
+    */
+    fn claim(&self) -> Option<(Outcome, &'static str)> {
+        pgn::adjudicate(&self.start, &self.moves)
+    }
+}
+
+/*
+Results of the games finished in this session from the engine's point of view,
+games the engine didn't play a move in aren't counted
+*/
+#[derive(Debug, Clone, Default)]
+struct Session {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl Session {
+    /*
+    Only the first result of a game counts, the GUI usually echoes claims and resignations
+    */
+    fn record(&mut self, game: &mut Game, outcome: Outcome) {
+        if game.outcome.is_some() {
+            return;
+        }
+        game.outcome = Some(outcome);
+        let engine = match game.engine {
+            Some(engine) => engine,
+            None => return,
+        };
+        match outcome {
+            Outcome::Win(winner) if winner == engine => self.wins += 1,
+            Outcome::Win(_) => self.losses += 1,
+            Outcome::Draw => self.draws += 1,
+        }
+        println!(
+            "# session {} games +{} ={} -{}",
+            self.wins + self.draws + self.losses,
+            self.wins,
+            self.draws,
+            self.losses
+        );
+    }
+}
+
+/*
+Time control set with level, st and sd along with the clocks sent by time and otim
+*/
+#[derive(Debug, Clone, Default)]
+struct Clock {
+    moves_per_session: u32,
+    increment: Duration,
+    move_time: Option<Duration>,
+    depth: Option<u32>,
+    time: Option<Duration>,
+    opponent_time: Option<Duration>,
+}
+
+impl Clock {
+    fn limits(&self, board: &Board) -> Vec<TimeManagementInfo> {
+        let mut limits = vec![];
+        if let Some(depth) = self.depth {
+            limits.push(TimeManagementInfo::MaxDepth(depth));
+        }
+        if let Some(move_time) = self.move_time {
+            limits.push(TimeManagementInfo::MoveTime(move_time));
+            return limits;
+        }
+        let (white_time, black_time) = match board.side_to_move() {
+            Color::White => (self.time, self.opponent_time),
+            Color::Black => (self.opponent_time, self.time),
+        };
+        if let Some(white_time) = white_time {
+            limits.push(TimeManagementInfo::WTime(white_time));
+            limits.push(TimeManagementInfo::WInc(self.increment));
+        }
+        if let Some(black_time) = black_time {
+            limits.push(TimeManagementInfo::BTime(black_time));
+            limits.push(TimeManagementInfo::BInc(self.increment));
+        }
+        if self.moves_per_session > 0 {
+            let moves_played = board.fullmove_number() as u32 - 1;
+            limits.push(TimeManagementInfo::MovesToGo(
+                self.moves_per_session - moves_played % self.moves_per_session,
+            ));
+        }
+        limits
+    }
+}
+
+/*
+Chess Engine Communication Protocol (version 2) adapter, picked by sending xboard
+It shares the engine with the UCI adapter so interrupts and stop still reach the search
+*/
+pub struct XBoardAdapter {
+    bm_runner: Arc<Mutex<AbRunner>>,
+    time_manager: Arc<TimeManager>,
+    analysis: Analysis,
+    game: Arc<Mutex<Game>>,
+    session: Arc<Mutex<Session>>,
+    game_log: Arc<Mutex<GameLog>>,
+    cancelled: Arc<AtomicBool>,
+    engine_side: Option<Color>,
+    clock: Clock,
+    post: bool,
+    chess960: bool,
+    clear_hash: bool,
+    resign_score: i16,
+}
+
+impl XBoardAdapter {
+    pub fn new(
+        bm_runner: Arc<Mutex<AbRunner>>,
+        time_manager: Arc<TimeManager>,
+        analysis: Analysis,
+        game_log: Arc<Mutex<GameLog>>,
+    ) -> Self {
+        let board = bm_runner.lock().unwrap().get_board().clone();
+        Self {
+            bm_runner,
+            time_manager,
+            analysis,
+            game: Arc::new(Mutex::new(Game::new(board))),
+            session: Arc::new(Mutex::new(Session::default())),
+            game_log,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            engine_side: None,
+            clock: Clock::default(),
+            post: false,
+            chess960: false,
+            clear_hash: true,
+            resign_score: 0,
+        }
+    }
+
+    pub fn input(&mut self, input: String) -> bool {
+        let mut split = input.split_ascii_whitespace();
+        let command = match split.next() {
+            Some(command) => command,
+            None => return true,
+        };
+        match command {
+            "protover" => {
+                println!(
+                    "feature myname=\"Black Marlin {}\" setboard=1 usermove=1 ping=1 \
+                     memory=1 smp=1 egt=\"syzygy\" variants=\"normal,fischerandom\" \
+                     sigint=0 sigterm=0 colors=0 analyze=0 reuse=1 \
+                     option=\"Clear Hash -check 1\" option=\"Resign Score -spin 0 0 10000\" \
+                     option=\"GameLog -string \" done=1",
+                    VERSION
+                );
+            }
+            "ping" => println!("pong {}", split.next().unwrap_or_default()),
+            "new" => {
+                self.cancel();
+                self.engine_side = Some(Color::Black);
+                self.clock.depth = None;
+                self.chess960 = false;
+                self.time_manager.new_game();
+                {
+                    let runner = &mut *self.bm_runner.lock().unwrap();
+                    runner.set_chess960(false);
+                    if self.clear_hash {
+                        runner.new_game();
+                    } else {
+                        runner.clear_history();
+                    }
+                }
+                self.set_board(Board::default());
+            }
+            "variant" => {
+                self.chess960 = split.next() == Some("fischerandom");
+                self.bm_runner.lock().unwrap().set_chess960(self.chess960);
+            }
+            "setboard" => {
+                self.cancel();
+                let fen = split.collect::<Vec<_>>().join(" ");
+                match Board::from_fen(&fen, self.chess960) {
+                    Ok(board) => self.set_board(board),
+                    Err(_) => println!("tellusererror Illegal position"),
+                }
+            }
+            "force" => {
+                self.cancel();
+                self.engine_side = None;
+            }
+            "result" => {
+                self.cancel();
+                self.engine_side = None;
+                if let Some(outcome) = split.next().and_then(Outcome::parse) {
+                    let game = &mut *self.game.lock().unwrap();
+                    self.session.lock().unwrap().record(game, outcome);
+                    self.game_log.lock().unwrap().finish(Some(outcome));
+                }
+            }
+            "go" => {
+                self.exit();
+                let board = self.bm_runner.lock().unwrap().get_board().clone();
+                self.engine_side = Some(board.side_to_move());
+                self.think();
+            }
+            "playother" => {
+                self.exit();
+                let board = self.bm_runner.lock().unwrap().get_board().clone();
+                self.engine_side = Some(!board.side_to_move());
+            }
+            "usermove" => {
+                self.exit();
+                if let Some(token) = split.next() {
+                    self.user_move(token);
+                }
+            }
+            "?" => {
+                self.time_manager.abort_now();
+                self.exit();
+            }
+            "undo" => self.undo(1),
+            "remove" => self.undo(2),
+            "level" => {
+                let moves_per_session = split.next().and_then(|mps| mps.parse().ok());
+                let base = split.next();
+                let increment = split.next().and_then(|inc| inc.parse::<f64>().ok());
+                self.clock.moves_per_session = moves_per_session.unwrap_or(0);
+                self.clock.increment = Duration::from_secs_f64(increment.unwrap_or(0.0));
+                self.clock.move_time = None;
+                self.clock.time = base.and_then(parse_base).or(self.clock.time);
+            }
+            "st" => {
+                if let Some(seconds) = split.next().and_then(|st| st.parse::<f64>().ok()) {
+                    self.clock.move_time = Some(Duration::from_secs_f64(seconds));
+                }
+            }
+            "sd" => self.clock.depth = split.next().and_then(|sd| sd.parse().ok()),
+            "time" => self.clock.time = split.next().and_then(parse_centis),
+            "otim" => self.clock.opponent_time = split.next().and_then(parse_centis),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "memory" => {
+                if let Some(hash_mb) = split.next().and_then(|mb| mb.parse::<usize>().ok()) {
+                    self.exit();
+                    self.bm_runner.lock().unwrap().hash(hash_mb.max(1));
+                }
+            }
+            "cores" => {
+                if let Some(cores) = split.next().and_then(|cores| cores.parse::<u32>().ok()) {
+                    self.bm_runner.lock().unwrap().set_threads(cores as usize);
+                }
+            }
+            "egtpath" if split.next() == Some("syzygy") => {
+                let path = split.collect::<Vec<_>>().join(" ");
+                self.exit();
+                let tablebase = Tablebase::load(&path);
+                println!("# found {} tablebases", tablebase.table_cnt());
+                self.bm_runner.lock().unwrap().set_tablebase(tablebase);
+            }
+            "option" => {
+                let option = split.collect::<Vec<_>>().join(" ");
+                let (name, value) = option.split_once('=').unwrap_or((&option, ""));
+                match name {
+                    "Clear Hash" => self.clear_hash = value == "1",
+                    "Resign Score" => {
+                        if let Ok(score) = value.parse::<i16>() {
+                            self.resign_score = score.max(0);
+                        }
+                    }
+                    "GameLog" => {
+                        let path = Some(value.to_string()).filter(|path| !path.is_empty());
+                        self.game_log.lock().unwrap().set_path(path);
+                    }
+                    _ => {}
+                }
+            }
+            "quit" => {
+                self.cancel();
+                self.game_log.lock().unwrap().finish(None);
+                return false;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn set_board(&mut self, board: Board) {
+        let runner = &mut *self.bm_runner.lock().unwrap();
+        runner.set_board(board.clone());
+        self.game_log
+            .lock()
+            .unwrap()
+            .sync(&board, &[], self.chess960);
+        *self.game.lock().unwrap() = Game::new(board);
+    }
+
+    fn user_move(&mut self, token: &str) {
+        let stm = {
+            let runner = &mut *self.bm_runner.lock().unwrap();
+            let make_move = match parse_move(token, runner.get_board(), self.chess960) {
+                Some(make_move) => make_move,
+                None => {
+                    println!("Illegal move (unknown notation): {}", token);
+                    return;
+                }
+            };
+            if let Err(err) = runner.get_position().is_legal(make_move) {
+                println!("Illegal move ({}): {}", err, token);
+                return;
+            }
+            runner.make_move(make_move);
+            let game = &mut *self.game.lock().unwrap();
+            game.moves.push(make_move);
+            self.game_log
+                .lock()
+                .unwrap()
+                .sync(&game.start, &game.moves, self.chess960);
+            runner.get_board().side_to_move()
+        };
+        if self.engine_side == Some(stm) {
+            self.think();
+        }
+    }
+
+    fn undo(&mut self, moves: usize) {
+        self.cancel();
+        let game = &mut *self.game.lock().unwrap();
+        let len = game.moves.len().saturating_sub(moves);
+        game.moves.truncate(len);
+        self.game_log.lock().unwrap().take_back(moves);
+        let runner = &mut *self.bm_runner.lock().unwrap();
+        runner.set_board(game.start.clone());
+        for &make_move in &game.moves {
+            runner.make_move(make_move);
+        }
+    }
+
+    fn think(&mut self) {
+        let board = self.bm_runner.lock().unwrap().get_board().clone();
+        {
+            let game = &mut *self.game.lock().unwrap();
+            if game.outcome.is_some() {
+                return;
+            }
+            if let Some((outcome, reason)) = game.claim() {
+                println!("{} {{{}}}", outcome.score(), reason);
+                self.session.lock().unwrap().record(game, outcome);
+                self.game_log.lock().unwrap().finish(Some(outcome));
+                return;
+            }
+            game.engine = Some(board.side_to_move());
+        }
+        self.cancelled.store(false, Ordering::SeqCst);
+        self.time_manager
+            .initiate(&board, &self.clock.limits(&board));
+        let bm_runner = self.bm_runner.clone();
+        let time_manager = self.time_manager.clone();
+        let game = self.game.clone();
+        let session = self.session.clone();
+        let game_log = self.game_log.clone();
+        /*
+        Time left on the engine's clock once the increment of the move is added,
+        untimed searches aren't logged
+        */
+        let clock = match (self.clock.move_time, self.clock.time) {
+            (None, Some(time)) => Some(Some(time + self.clock.increment)),
+            (Some(_), _) => Some(None),
+            (None, None) => None,
+        };
+        let cancelled = self.cancelled.clone();
+        let post = self.post;
+        let chess960 = self.chess960;
+        let resign_score = self.resign_score;
+        let stack_size = self.bm_runner.lock().unwrap().get_stack_size();
+        let analysis = std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let mut bm_runner = bm_runner.lock().unwrap();
+                let start = Instant::now();
+                let (best_move, eval, depth, _) = if post {
+                    bm_runner.search::<Run, XBoardInfo>()
+                } else {
+                    bm_runner.search::<Run, NoInfo>()
+                };
+                time_manager.clear();
+                if cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+                let board = bm_runner.get_board().clone();
+                let game = &mut *game.lock().unwrap();
+                if resign_score > 0 && eval.raw() <= -resign_score {
+                    game.losing_searches += 1;
+                } else {
+                    game.losing_searches = 0;
+                }
+                if game.losing_searches >= RESIGN_MOVES {
+                    println!("resign");
+                    let winner = Outcome::Win(!board.side_to_move());
+                    session.lock().unwrap().record(game, winner);
+                    game_log.lock().unwrap().finish(Some(winner));
+                    return;
+                }
+                println!("move {}", format_move(best_move, &board, chess960));
+                let game_log = &mut *game_log.lock().unwrap();
+                if let Some(clock) = clock {
+                    let time = start.elapsed();
+                    game_log.annotate(
+                        best_move,
+                        MoveComment {
+                            eval,
+                            depth,
+                            time,
+                            clock: clock.map(|clock| clock.saturating_sub(time)),
+                        },
+                    );
+                }
+                bm_runner.make_move(best_move);
+                game.moves.push(best_move);
+                game_log.sync(&game.start, &game.moves, chess960);
+                if let Some((outcome, reason)) = game.claim() {
+                    println!("{} {{{}}}", outcome.score(), reason);
+                    session.lock().unwrap().record(game, outcome);
+                    game_log.finish(Some(outcome));
+                }
+            });
+        *self.analysis.lock().unwrap() = Some(analysis.unwrap());
+    }
+
+    /*
+    Aborts the running search without playing its move
+    */
+    fn cancel(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.time_manager.abort_now();
+        self.exit();
+    }
+
+    fn exit(&mut self) {
+        if let Some(analysis) = self.analysis.lock().unwrap().take() {
+            analysis.join().unwrap();
+        }
+    }
+}
+
+fn parse_centis(token: &str) -> Option<Duration> {
+    token
+        .parse::<i64>()
+        .ok()
+        .map(|centis| Duration::from_millis(centis.max(0) as u64 * 10))
+}
+
+/*
+Base time of level is given either in minutes or as minutes:seconds
+*/
+fn parse_base(token: &str) -> Option<Duration> {
+    let (minutes, seconds) = token.split_once(':').unwrap_or((token, "0"));
+    let seconds = minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/*
+Castling is sent as O-O and O-O-O in Chess960 and as the king's two square move otherwise
+*/
+fn parse_move(token: &str, board: &Board, chess960: bool) -> Option<Move> {
+    let stm = board.side_to_move();
+    let rights = board.castle_rights(stm);
+    let rook_file = match token {
+        "O-O" | "0-0" => Some(rights.short?),
+        "O-O-O" | "0-0-0" => Some(rights.long?),
+        _ => None,
+    };
+    if let Some(rook_file) = rook_file {
+        let king = board.king(stm);
+        return Some(Move {
+            from: king,
+            to: Square::new(rook_file, king.rank()),
+            promotion: None,
+        });
+    }
+    let mut make_move = Move::from_str(token).ok()?;
+    uci::convert_move(&mut make_move, board, chess960);
+    Some(make_move)
+}
+
+fn format_move(mut make_move: Move, board: &Board, chess960: bool) -> String {
+    if chess960 && board.color_on(make_move.from) == board.color_on(make_move.to) {
+        return if make_move.to.file() > make_move.from.file() {
+            "O-O".to_string()
+        } else {
+            "O-O-O".to_string()
+        };
+    }
+    uci::convert_move_to_uci(&mut make_move, board, chess960);
+    make_move.to_string()
+}
+
+#[test]
+fn castling_notation() {
+    let board =
+        Board::from_fen("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1", false).unwrap();
+    let short = parse_move("e1g1", &board, false).unwrap();
+    assert_eq!(short, parse_move("O-O", &board, false).unwrap());
+    assert_eq!(short.to, Square::H1);
+    assert_eq!(format_move(short, &board, false), "e1g1");
+    assert_eq!(format_move(short, &board, true), "O-O");
+    let long = parse_move("O-O-O", &board, true).unwrap();
+    assert_eq!(format_move(long, &board, false), "e1c1");
+    assert_eq!(parse_base("0:30"), Some(Duration::from_secs(30)));
+    assert_eq!(parse_base("5"), Some(Duration::from_secs(300)));
+}
+
+#[test]
+fn claims() {
+    let mut game = Game::new(Board::default());
+    assert_eq!(game.claim(), None);
+    for make_move in ["g1f3", "g8f6", "f3g1", "f6g8"].iter().cycle().take(8) {
+        game.moves.push(make_move.parse().unwrap());
+    }
+    assert_eq!(game.claim(), Some((Outcome::Draw, "Draw by repetition")));
+    game.moves.pop();
+    assert_eq!(game.claim(), None);
+
+    let board: Board = "7k/8/6QK/8/8/8/8/8 w - - 0 1".parse().unwrap();
+    let mut game = Game::new(board);
+    game.moves.push("g6g7".parse().unwrap());
+    assert_eq!(
+        game.claim(),
+        Some((Outcome::Win(Color::White), "White mates"))
+    );
+
+    let board: Board = "7k/8/8/8/8/8/8/5N1K w - - 0 1".parse().unwrap();
+    let mut game = Game::new(board);
+    assert_eq!(
+        game.claim(),
+        Some((Outcome::Draw, "Draw by insufficient material"))
+    );
+
+    game.engine = Some(Color::White);
+    let mut session = Session::default();
+    session.record(&mut game, Outcome::Win(Color::Black));
+    session.record(&mut game, Outcome::Draw);
+    assert_eq!((session.wins, session.draws, session.losses), (0, 0, 1));
+    assert_eq!(Outcome::parse("1/2-1/2"), Some(Outcome::Draw));
+    assert_eq!(Outcome::parse("*"), None);
+}
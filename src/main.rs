@@ -1,15 +1,31 @@
+use crate::bm::bm_console::cli::CliArgs;
 use crate::bm::bm_console::BmConsole;
-use text_io::read;
 
 mod bm;
 
 fn main() {
-    let mut bm_console = BmConsole::new();
-    for arg in std::env::args() {
-        if arg.trim() == "bench" {
-            bm_console.input("bench".to_string());
-            return;
+    let cli = CliArgs::parse(std::env::args().skip(1)).unwrap_or_else(|err| exit(&err));
+    let params = cli.params().unwrap_or_else(|err| exit(&err));
+    if cli.one_shot() {
+        if let Err(err) = cli.run(params) {
+            exit(&err);
         }
+        return;
+    }
+    let mut bm_console = BmConsole::new();
+    bm_console.set_params(params);
+    for option in cli.uci_options() {
+        bm_console.input(option);
     }
-    while bm_console.input(read!("{}\n")) {}
+    if let Some(bench) = cli.bench() {
+        bm_console.input(bench);
+        return;
+    }
+    bm_console.handle_interrupts();
+    bm_console.run();
+}
+
+fn exit(err: &str) -> ! {
+    eprintln!("# {}", err);
+    std::process::exit(1);
 }